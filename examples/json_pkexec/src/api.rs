@@ -71,8 +71,6 @@ pub enum SendyMessage {
     DoThings(i8),
     /// Request a list of all available software packages
     ListThePackages,
-    /// Query the server process's user ID to verify privilege escalation
-    WhatsYourUID,
 }
 
 /// Messages that can be sent from the privileged server process back to the client
@@ -83,6 +81,6 @@ pub enum RecvyMessage {
     /// Response containing a single package's metadata
     HereIsOnePackage(Package),
     EndOfPackages,
-    /// Response containing the server process's user ID (should be 0/root)
-    HereIsYourUID(u32),
+    /// The server failed to handle a request
+    Failed(String),
 }
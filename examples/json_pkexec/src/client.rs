@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use nix::unistd::Uid;
 use privileged_ipc::{IpcClient, PkexecExecutor};
 
 use crate::api::{RecvyMessage, SendyMessage};
@@ -11,6 +12,7 @@ use crate::api::{RecvyMessage, SendyMessage};
 /// This function shows how to:
 /// - Establish a privileged connection using `IpcClient`
 /// - Send multiple serialized messages to the server
+/// - Verify the server really runs as root before trusting its answers
 /// - Handle responses asynchronously using type-safe message types
 /// - Proper error handling with the IpcError type
 ///
@@ -25,12 +27,14 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     log::info!("🚀 Sending messages to server...");
     conn.send(&SendyMessage::DoThings(42))?;
     conn.send(&SendyMessage::ListThePackages)?;
-    conn.send(&SendyMessage::WhatsYourUID)?;
 
     conn.shutdown(std::net::Shutdown::Write)?;
 
+    conn.expect_peer_uid(Uid::from_raw(0))?;
+    log::info!("🎫 Server runs as root");
+
     log::info!("⏳ Waiting for server responses...");
-    for message in conn.incoming()? {
+    for message in conn.messages() {
         match message {
             Ok(RecvyMessage::GotThings(s)) => {
                 log::info!("📬 Received: {}", s);
@@ -42,8 +46,8 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
                     .unwrap_or_else(|e| log::error!("JSON error: {}", e));
             }
             Ok(RecvyMessage::EndOfPackages) => break,
-            Ok(RecvyMessage::HereIsYourUID(uid)) => {
-                log::info!("🎫 Received UID: {}", uid);
+            Ok(RecvyMessage::Failed(reason)) => {
+                log::error!("💥 Server failed: {}", reason);
             }
             Err(e) => {
                 log::error!("💥 Error: {:?}", e);
@@ -2,7 +2,6 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use nix::unistd::getuid;
 use privileged_ipc::{IpcError, IpcServer};
 
 use crate::api::{Package, RecvyMessage, SendyMessage};
@@ -16,7 +15,6 @@ use crate::api::{Package, RecvyMessage, SendyMessage};
 /// - Responding to various message types:
 ///   - Basic string messages (`DoThings`)
 ///   - Package listing requests (`ListThePackages`)
-///   - System information queries (`WhatsYourUID`)
 ///
 /// # Errors
 ///
@@ -26,36 +24,28 @@ use crate::api::{Package, RecvyMessage, SendyMessage};
 /// - Message processing fails
 pub fn run() -> Result<(), IpcError> {
     log::info!("🚀 Starting server...");
-    let server = IpcServer::<RecvyMessage, SendyMessage>::new()?;
-
-    let mut connection = server.accept()?;
-    log::trace!("🔌 accepted client connection");
-
-    let mut incoming = connection.incoming()?;
-
-    while let Some(message) = incoming.next() {
-        match message? {
-            SendyMessage::DoThings(i) => {
-                log::info!("📬 Received: {:?}", i);
-                let reply = RecvyMessage::GotThings(format!("I got your message: {}", i));
-                connection.send(&reply)?;
-            }
-            SendyMessage::ListThePackages => {
-                log::info!("📦 Received: ListThePackages");
-                for package in Package::get_sample_packages() {
-                    connection.send(&RecvyMessage::HereIsOnePackage(package))?;
-                }
-                connection.send(&RecvyMessage::EndOfPackages)?;
-            }
-            SendyMessage::WhatsYourUID => {
-                log::info!("🔑 Received: WhatsYourUID");
-                let reply = RecvyMessage::HereIsYourUID(getuid().into());
-                connection.send(&reply)?;
-            }
-        }
+    privileged_ipc::define_service! {
+        server: IpcServer::<RecvyMessage, SendyMessage>::new()?,
+        errors: |e: IpcError| RecvyMessage::Failed(e.to_string()),
+        SendyMessage::DoThings(i) => do_things(i),
+        SendyMessage::ListThePackages => list_the_packages(),
     }
+}
 
-    connection.shutdown(std::net::Shutdown::Both)?;
+fn do_things(i: i8) -> Result<RecvyMessage, IpcError> {
+    log::info!("📬 Received: {:?}", i);
+    Ok(RecvyMessage::GotThings(format!(
+        "I got your message: {}",
+        i
+    )))
+}
 
-    Ok(())
+fn list_the_packages() -> Result<Vec<RecvyMessage>, IpcError> {
+    log::info!("📦 Received: ListThePackages");
+    let mut replies = Package::get_sample_packages()
+        .into_iter()
+        .map(RecvyMessage::HereIsOnePackage)
+        .collect::<Vec<_>>();
+    replies.push(RecvyMessage::EndOfPackages);
+    Ok(replies)
 }
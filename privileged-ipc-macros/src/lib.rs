@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Procedural macros for privileged-ipc
+//!
+//! These are used through privileged-ipc with its `macros` and `typescript`
+//! features, as the code they generate refers to it by name.
+
+use proc_macro::TokenStream;
+
+mod protocol;
+mod typescript;
+
+/// Generates the messages, client and dispatch of a request/response protocol from a trait
+///
+/// Each method of the trait is a call. It takes `&mut self` followed by its
+/// arguments, and returns what the call answers with. A call returning a
+/// `Result` whose error implements `Display` can fail, and its error is passed to
+/// the client. Alongside the trait, which the helper implements to answer
+/// calls, the attribute generates:
+///
+/// - a `<Trait>Request` enum with a variant per call, named after the method in
+///   CamelCase and holding its arguments by name
+/// - a `<Trait>Response` enum with a variant per call, holding its result, and `Failed`
+/// - a `<Trait>Client` with one method per call, which sends the request and checks
+///   the response is the matching one, failing with `IpcError::CallFailed` if the
+///   handler failed it
+/// - `dispatch` and `serve` methods on the trait, answering a single request and
+///   answering requests until the client leaves
+///
+/// ```ignore
+/// /// Talking to the disks helper
+/// #[privileged_ipc::ipc_protocol]
+/// pub trait Disks {
+///     /// Mounts `source` at `target`, returning the mount's id
+///     fn mount(&mut self, source: String, target: String) -> Result<u32, String>;
+///     /// Lists the ids of the current mounts
+///     fn mounts(&mut self) -> Vec<u32>;
+/// }
+/// ```
+///
+/// The messages derive `Debug`, `Serialize` and `Deserialize`, so the crate using
+/// the attribute must depend on `serde` and `serde_derive`, and every argument
+/// and result must implement those. The doc comments of a call document its
+/// request and response variants and its client method alike.
+#[proc_macro_attribute]
+pub fn ipc_protocol(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = syn::parse_macro_input!(item as syn::ItemTrait);
+    protocol::expand(attr.into(), item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `TypeScript`, declaring the type as serde_json writes it
+///
+/// The declaration follows the serde attributes of the type, and types it
+/// refers to must implement `TypeScript` too. Type parameters are declared as
+/// generic parameters of the TypeScript type. `#[typescript(rename = "...")]`
+/// declares the type under another name, for types that share theirs with
+/// another type in a different module.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, TypeScript)]
+/// #[serde(tag = "type", rename_all = "kebab-case")]
+/// #[typescript(rename = "DisksRequest")]
+/// pub enum Request {
+///     CreateNamespace,
+///     Unmount { target: String },
+/// }
+/// ```
+///
+/// declares
+///
+/// ```text
+/// export type DisksRequest =
+///   | { type: "create-namespace" }
+///   | { type: "unmount"; target: string };
+/// ```
+#[proc_macro_derive(TypeScript, attributes(typescript))]
+pub fn typescript(item: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(item as syn::DeriveInput);
+    typescript::expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
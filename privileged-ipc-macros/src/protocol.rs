@@ -0,0 +1,290 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Expansion of `#[ipc_protocol]`
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse_quote, Attribute, FnArg, GenericArgument, Ident, ItemTrait, Pat, PathArguments,
+    ReturnType, TraitItem, TraitItemFn, Type,
+};
+
+/// Names the generated trait methods take, which calls can't
+const RESERVED: [&str; 2] = ["dispatch", "serve"];
+
+/// A method of the protocol trait, which becomes a call
+struct Call {
+    /// Doc comments, which document everything generated for the call
+    docs: Vec<Attribute>,
+    method: Ident,
+    /// Names the request and response variants
+    variant: Ident,
+    args: Vec<(Ident, Type)>,
+    /// What the call answers with
+    output: Type,
+    /// Set if the handler may fail the call
+    fallible: bool,
+}
+
+impl Call {
+    fn parse(item: &TraitItem) -> syn::Result<Self> {
+        let TraitItem::Fn(TraitItemFn {
+            attrs,
+            sig,
+            default: None,
+            ..
+        }) = item
+        else {
+            return Err(syn::Error::new_spanned(
+                item,
+                "protocol traits may only declare calls, as methods without a body",
+            ));
+        };
+        if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+            return Err(syn::Error::new_spanned(
+                &sig.generics,
+                "calls can't be generic",
+            ));
+        }
+        if let Some(token) = sig.asyncness {
+            return Err(syn::Error::new_spanned(token, "calls can't be async"));
+        }
+        if RESERVED.iter().any(|reserved| sig.ident == reserved) {
+            return Err(syn::Error::new_spanned(
+                &sig.ident,
+                format!("`{}` is generated, and can't be a call", sig.ident),
+            ));
+        }
+
+        let mut inputs = sig.inputs.iter();
+        match inputs.next() {
+            Some(FnArg::Receiver(receiver))
+                if receiver.reference.is_some() && receiver.mutability.is_some() => {}
+            _ => {
+                return Err(syn::Error::new(
+                    sig.paren_token.span.join(),
+                    "calls take `&mut self` first",
+                ))
+            }
+        }
+        let args = inputs
+            .map(|input| match input {
+                FnArg::Typed(arg) => match &*arg.pat {
+                    Pat::Ident(pat) if pat.subpat.is_none() && pat.by_ref.is_none() => {
+                        Ok((pat.ident.clone(), (*arg.ty).clone()))
+                    }
+                    pat => Err(syn::Error::new_spanned(pat, "arguments must be named")),
+                },
+                FnArg::Receiver(receiver) => {
+                    Err(syn::Error::new_spanned(receiver, "unexpected receiver"))
+                }
+            })
+            .collect::<syn::Result<_>>()?;
+
+        let returned = match &sig.output {
+            ReturnType::Default => parse_quote!(()),
+            ReturnType::Type(_, ty) => (**ty).clone(),
+        };
+        let (output, fallible) = match result_value(&returned) {
+            Some(value) => (value, true),
+            None => (returned, false),
+        };
+
+        Ok(Self {
+            docs: attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc"))
+                .cloned()
+                .collect(),
+            method: sig.ident.clone(),
+            variant: Ident::new(&camel_case(&sig.ident.to_string()), sig.ident.span()),
+            args,
+            output,
+            fallible,
+        })
+    }
+}
+
+/// Returns the value type of `Result<T, E>`, if `ty` is one
+fn result_value(ty: &Type) -> Option<Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let last = path.path.segments.last()?;
+    let PathArguments::AngleBracketed(args) = &last.arguments else {
+        return None;
+    };
+    match (last.ident == "Result", args.args.len(), args.args.first()) {
+        (true, 2, Some(GenericArgument::Type(value))) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Converts a method name to the name of its variant, `list_disks` becoming `ListDisks`
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars))
+                .into_iter()
+                .flatten()
+        })
+        .collect()
+}
+
+pub(crate) fn expand(attr: TokenStream, mut item: ItemTrait) -> syn::Result<TokenStream> {
+    if !attr.is_empty() {
+        return Err(syn::Error::new_spanned(
+            attr,
+            "#[ipc_protocol] takes no arguments",
+        ));
+    }
+    if !item.generics.params.is_empty() || item.generics.where_clause.is_some() {
+        return Err(syn::Error::new_spanned(
+            &item.generics,
+            "protocol traits can't be generic",
+        ));
+    }
+    let calls = item
+        .items
+        .iter()
+        .map(Call::parse)
+        .collect::<syn::Result<Vec<_>>>()?;
+    for (i, call) in calls.iter().enumerate() {
+        if call.variant == "Failed" || calls[..i].iter().any(|c| c.variant == call.variant) {
+            return Err(syn::Error::new(
+                call.method.span(),
+                format!("`{}` is already a response variant", call.variant),
+            ));
+        }
+    }
+
+    let vis = item.vis.clone();
+    let name = item.ident.clone();
+    let request = format_ident!("{name}Request");
+    let response = format_ident!("{name}Response");
+    let client = format_ident!("{name}Client");
+    let ipc = quote!(::privileged_ipc);
+
+    let docs = calls.iter().map(|call| &call.docs).collect::<Vec<_>>();
+    let methods = calls.iter().map(|call| &call.method).collect::<Vec<_>>();
+    let variants = calls.iter().map(|call| &call.variant).collect::<Vec<_>>();
+    let outputs = calls.iter().map(|call| &call.output).collect::<Vec<_>>();
+    let arg_names = calls
+        .iter()
+        .map(|call| call.args.iter().map(|(name, _)| name).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let arg_types = calls
+        .iter()
+        .map(|call| call.args.iter().map(|(_, ty)| ty).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let answers = calls.iter().map(|call| {
+        let (method, variant) = (&call.method, &call.variant);
+        let args = call.args.iter().map(|(name, _)| name);
+        let answer = quote!(self.#method(#(#args),*));
+        match call.fallible {
+            true => quote! {
+                match #answer {
+                    ::std::result::Result::Ok(result) => #response::#variant(result),
+                    ::std::result::Result::Err(error) => {
+                        #response::Failed(::std::string::ToString::to_string(&error))
+                    }
+                }
+            },
+            false => quote!(#response::#variant(#answer)),
+        }
+    });
+
+    let request_doc = format!("A call of [`{name}`], as sent by a [`{client}`]");
+    let client_doc =
+        format!("Makes the calls of [`{name}`] over a connection, waiting for the answer to each");
+    item.items.push(parse_quote! {
+        /// Answers `request` with the matching method, passing any error to the client
+        fn dispatch(&mut self, request: #request) -> #response {
+            match request {
+                #(#request::#variants { #(#arg_names),* } => #answers,)*
+            }
+        }
+    });
+    item.items.push(parse_quote! {
+        /// Answers requests on `connection` until the client leaves
+        fn serve<C: #ipc::Codec>(
+            &mut self,
+            connection: &mut #ipc::IpcConnection<#response, #request, C>,
+        ) -> ::std::result::Result<(), #ipc::IpcError>
+        where
+            Self: Sized,
+        {
+            while let ::std::option::Option::Some(request) = connection.recv()? {
+                connection.send(&self.dispatch(request))?;
+            }
+            ::std::result::Result::Ok(())
+        }
+    });
+
+    Ok(quote! {
+        #item
+
+        #[doc = #request_doc]
+        #[derive(Debug, ::serde_derive::Serialize, ::serde_derive::Deserialize)]
+        #vis enum #request {
+            #(#(#docs)* #variants { #(#arg_names: #arg_types),* },)*
+        }
+
+        /// The answer to a call
+        #[derive(Debug, ::serde_derive::Serialize, ::serde_derive::Deserialize)]
+        #vis enum #response {
+            #(#(#docs)* #variants(#outputs),)*
+            /// The handler failed the call, saying why
+            Failed(::std::string::String),
+        }
+
+        #[doc = #client_doc]
+        #vis struct #client<C = #ipc::JsonCodec> {
+            connection: #ipc::IpcConnection<#request, #response, C>,
+        }
+
+        impl<C: #ipc::Codec> #client<C> {
+            /// Makes calls over `connection`
+            pub fn new(connection: #ipc::IpcConnection<#request, #response, C>) -> Self {
+                Self { connection }
+            }
+
+            /// Returns the underlying connection
+            pub fn into_inner(self) -> #ipc::IpcConnection<#request, #response, C> {
+                self.connection
+            }
+
+            #(
+                #(#docs)*
+                pub fn #methods(
+                    &mut self,
+                    #(#arg_names: #arg_types),*
+                ) -> ::std::result::Result<#outputs, #ipc::IpcError> {
+                    self.connection.send(&#request::#variants { #(#arg_names),* })?;
+                    match self.connection.recv()? {
+                        ::std::option::Option::Some(#response::#variants(result)) => {
+                            ::std::result::Result::Ok(result)
+                        }
+                        ::std::option::Option::Some(#response::Failed(reason)) => {
+                            ::std::result::Result::Err(#ipc::IpcError::CallFailed(reason))
+                        }
+                        ::std::option::Option::Some(other) => ::std::result::Result::Err(
+                            #ipc::IpcError::ProtocolViolation(::std::format!(
+                                "expected a {} response, got {other:?}",
+                                ::std::stringify!(#variants),
+                            )),
+                        ),
+                        ::std::option::Option::None => {
+                            ::std::result::Result::Err(#ipc::IpcError::ConnectionClosed)
+                        }
+                    }
+                }
+            )*
+        }
+    })
+}
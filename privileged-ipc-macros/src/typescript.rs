@@ -0,0 +1,667 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Expansion of `#[derive(TypeScript)]`
+//!
+//! The declaration follows the serde attributes of the type, so it describes the
+//! JSON serde_json writes for it. Attributes that change that JSON in ways the
+//! derive can't see, such as `with`, are rejected.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    meta::ParseNestedMeta, parse_quote, spanned::Spanned, visit_mut::VisitMut, Attribute, Data,
+    DeriveInput, Expr, Fields, GenericParam, Ident, Lit, LitStr, Meta, Token, Type,
+};
+
+/// A `rename_all` rule, converting names as serde does
+#[derive(Clone, Copy)]
+enum Case {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl Case {
+    fn parse(rule: &LitStr) -> syn::Result<Self> {
+        Ok(match rule.value().as_str() {
+            "lowercase" => Self::Lower,
+            "UPPERCASE" => Self::Upper,
+            "PascalCase" => Self::Pascal,
+            "camelCase" => Self::Camel,
+            "snake_case" => Self::Snake,
+            "SCREAMING_SNAKE_CASE" => Self::ScreamingSnake,
+            "kebab-case" => Self::Kebab,
+            "SCREAMING-KEBAB-CASE" => Self::ScreamingKebab,
+            _ => return Err(syn::Error::new_spanned(rule, "unknown rename_all rule")),
+        })
+    }
+
+    /// Renames a variant, written in PascalCase
+    fn variant(self, name: &str) -> String {
+        let snake = || {
+            let mut snake = String::new();
+            for (i, c) in name.char_indices() {
+                if i > 0 && c.is_uppercase() {
+                    snake.push('_');
+                }
+                snake.push(c.to_ascii_lowercase());
+            }
+            snake
+        };
+        match self {
+            Self::Lower => name.to_ascii_lowercase(),
+            Self::Upper => name.to_ascii_uppercase(),
+            Self::Pascal => name.to_owned(),
+            Self::Camel => name[..1].to_ascii_lowercase() + &name[1..],
+            Self::Snake => snake(),
+            Self::ScreamingSnake => snake().to_ascii_uppercase(),
+            Self::Kebab => snake().replace('_', "-"),
+            Self::ScreamingKebab => snake().to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+
+    /// Renames a field, written in snake_case
+    fn field(self, name: &str) -> String {
+        let pascal = || {
+            let mut pascal = String::new();
+            let mut capitalize = true;
+            for c in name.chars() {
+                match c {
+                    '_' => capitalize = true,
+                    c if capitalize => {
+                        pascal.push(c.to_ascii_uppercase());
+                        capitalize = false;
+                    }
+                    c => pascal.push(c),
+                }
+            }
+            pascal
+        };
+        match self {
+            Self::Lower | Self::Snake => name.to_owned(),
+            Self::Upper | Self::ScreamingSnake => name.to_ascii_uppercase(),
+            Self::Pascal => pascal(),
+            Self::Camel => {
+                let pascal = pascal();
+                pascal[..1].to_ascii_lowercase() + &pascal[1..]
+            }
+            Self::Kebab => name.replace('_', "-"),
+            Self::ScreamingKebab => name.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+/// The serde attributes of a type, variant or field that shape its JSON
+#[derive(Default)]
+struct Attrs {
+    rename: Option<String>,
+    rename_all: Option<Case>,
+    rename_all_fields: Option<Case>,
+    tag: Option<String>,
+    content: Option<String>,
+    untagged: bool,
+    transparent: bool,
+    skip: bool,
+    /// Set by `skip_serializing_if`, as the field may be left out
+    optional: bool,
+    flatten: bool,
+    /// Name of the declaration, from `#[typescript(rename = "...")]`
+    declared_as: Option<String>,
+}
+
+impl Attrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+        for attr in attrs {
+            if attr.path().is_ident("serde") {
+                attr.parse_nested_meta(|meta| parsed.serde(meta))?;
+            } else if attr.path().is_ident("typescript") {
+                attr.parse_nested_meta(|meta| match meta.path.is_ident("rename") {
+                    true => {
+                        parsed.declared_as = Some(meta.value()?.parse::<LitStr>()?.value());
+                        Ok(())
+                    }
+                    false => Err(meta.error("expected `rename`")),
+                })?;
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn serde(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        let Some(key) = meta.path.get_ident().map(Ident::to_string) else {
+            return Err(meta.error("unknown serde attribute"));
+        };
+        match key.as_str() {
+            "rename" => self.rename = Some(serialized(&meta)?.value()),
+            "rename_all" => self.rename_all = Some(Case::parse(&serialized(&meta)?)?),
+            "rename_all_fields" => self.rename_all_fields = Some(Case::parse(&serialized(&meta)?)?),
+            "tag" => self.tag = Some(meta.value()?.parse::<LitStr>()?.value()),
+            "content" => self.content = Some(meta.value()?.parse::<LitStr>()?.value()),
+            "untagged" => self.untagged = true,
+            "transparent" => self.transparent = true,
+            "flatten" => self.flatten = true,
+            "skip" | "skip_serializing" => self.skip = true,
+            "skip_serializing_if" => {
+                self.optional = true;
+                skip_value(&meta)?;
+            }
+            // Only deserializing is affected by these
+            "alias"
+            | "borrow"
+            | "bound"
+            | "crate"
+            | "default"
+            | "deny_unknown_fields"
+            | "deserialize_with"
+            | "expecting"
+            | "field_identifier"
+            | "from"
+            | "other"
+            | "skip_deserializing"
+            | "try_from"
+            | "variant_identifier" => skip_value(&meta)?,
+            _ => {
+                return Err(meta.error(format!(
+                    "`serde({key})` isn't supported by #[derive(TypeScript)]"
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses `key = "name"` or `key(serialize = "name")`, returning the serialized name
+fn serialized(meta: &ParseNestedMeta) -> syn::Result<LitStr> {
+    if meta.input.peek(Token![=]) {
+        return meta.value()?.parse();
+    }
+    let mut name = None;
+    meta.parse_nested_meta(|nested| {
+        match nested.path.is_ident("serialize") {
+            true => name = Some(nested.value()?.parse()?),
+            false => skip_value(&nested)?,
+        }
+        Ok(())
+    })?;
+    name.ok_or_else(|| meta.error("expected a serialized name"))
+}
+
+/// Consumes the value of an attribute that doesn't affect the declaration
+fn skip_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|nested| skip_value(&nested))?;
+    }
+    Ok(())
+}
+
+/// Part of a TypeScript type, which is only known in full once the Rust types it uses are
+enum Piece {
+    Text(String),
+    Type(Type),
+    /// A type that is wrapped in parentheses if it is a union or intersection
+    Grouped(Type),
+}
+
+/// A TypeScript type under construction
+#[derive(Default)]
+struct Ts(Vec<Piece>);
+
+impl Ts {
+    fn text(text: impl Into<String>) -> Self {
+        Self(vec![Piece::Text(text.into())])
+    }
+
+    fn ty(ty: &Type) -> Self {
+        Self(vec![Piece::Type(ty.clone())])
+    }
+
+    fn push(&mut self, text: &str) {
+        self.0.push(Piece::Text(text.to_owned()));
+    }
+
+    fn append(&mut self, other: Ts) {
+        self.0.extend(other.0);
+    }
+
+    /// Joins `parts` with `separator`
+    fn join(parts: impl IntoIterator<Item = Ts>, separator: &str) -> Self {
+        let mut joined = Ts::default();
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                joined.push(separator);
+            }
+            joined.append(part);
+        }
+        joined
+    }
+
+    /// Returns an expression building the type as a `String`
+    fn build(&self) -> TokenStream {
+        let pieces = self.0.iter().map(|piece| match piece {
+            Piece::Text(text) => quote!(ts.push_str(#text);),
+            Piece::Type(ty) => quote! {
+                ts.push_str(&<#ty as ::privileged_ipc::TypeScript>::ts_type());
+            },
+            Piece::Grouped(ty) => quote! {
+                let inner = <#ty as ::privileged_ipc::TypeScript>::ts_type();
+                match inner.contains(['|', '&']) {
+                    true => ts.push_str(&::std::format!("({inner})")),
+                    false => ts.push_str(&inner),
+                }
+            },
+        });
+        quote! {{
+            let mut ts = ::std::string::String::new();
+            #(#pieces)*
+            ts
+        }}
+    }
+}
+
+/// Writes `name` as a string literal
+fn literal(name: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in name.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes `name` as a property key, quoting it unless it is an identifier
+fn key(name: &str) -> String {
+    let mut chars = name.chars();
+    let identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    match identifier {
+        true => name.to_owned(),
+        false => literal(name),
+    }
+}
+
+/// Replaces the type parameters of the derived type with stand-ins declaring them by name
+struct Generic<'a> {
+    params: &'a [Ident],
+    this: Type,
+}
+
+impl VisitMut for Generic<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(path) = ty {
+            if path.qself.is_none() {
+                if path.path.is_ident("Self") {
+                    *ty = self.this.clone();
+                    return;
+                }
+                if let Some(param) = self.params.iter().find(|p| path.path.is_ident(*p)) {
+                    let stand_in = stand_in(param);
+                    *ty = parse_quote!(#stand_in);
+                    return;
+                }
+            }
+        }
+        syn::visit_mut::visit_type_mut(self, ty);
+    }
+}
+
+fn stand_in(param: &Ident) -> Ident {
+    format_ident!("__{param}")
+}
+
+/// Builds the declaration, collecting the types it refers to
+struct Declaring<'a> {
+    generic: Generic<'a>,
+    /// Every type used, to declare along with this one
+    used: Vec<Type>,
+}
+
+impl Declaring<'_> {
+    /// Returns the type as written in the declaration, noting that it is used
+    fn ty(&mut self, ty: &Type) -> Type {
+        self.used.push(ty.clone());
+        let mut declared = ty.clone();
+        self.generic.visit_type_mut(&mut declared);
+        declared
+    }
+
+    /// Declares named fields as an object, after the members in `leading`
+    fn object(
+        &mut self,
+        fields: &Fields,
+        rename_all: Option<Case>,
+        leading: Vec<Ts>,
+    ) -> syn::Result<Ts> {
+        let mut members = leading;
+        let mut flattened = Vec::new();
+        for field in fields {
+            let attrs = Attrs::parse(&field.attrs)?;
+            if attrs.skip {
+                continue;
+            }
+            let ty = self.ty(&field.ty);
+            if attrs.flatten {
+                flattened.push(Ts(vec![Piece::Grouped(ty)]));
+                continue;
+            }
+            let ident = field.ident.as_ref().expect("named field").to_string();
+            let ident = ident.strip_prefix("r#").unwrap_or(&ident);
+            let name = match (attrs.rename, rename_all) {
+                (Some(name), _) => name,
+                (None, Some(case)) => case.field(ident),
+                (None, None) => ident.to_owned(),
+            };
+            let mut member = Ts::text(key(&name));
+            member.push(if attrs.optional { "?: " } else { ": " });
+            member.append(Ts::ty(&ty));
+            members.push(member);
+        }
+
+        if members.is_empty() && !flattened.is_empty() {
+            return Ok(Ts::join(flattened, " & "));
+        }
+        let mut object = match members.is_empty() {
+            true => Ts::text("Record<string, never>"),
+            false => {
+                let mut object = Ts::text("{ ");
+                object.append(Ts::join(members, "; "));
+                object.push(" }");
+                object
+            }
+        };
+        for flattened in flattened {
+            object.push(" & ");
+            object.append(flattened);
+        }
+        Ok(object)
+    }
+
+    /// Declares the fields of a tuple struct or variant, a newtype being its field alone
+    fn tuple(&mut self, fields: &Fields) -> syn::Result<Ts> {
+        let mut elements = Vec::new();
+        for field in fields {
+            if !Attrs::parse(&field.attrs)?.skip {
+                elements.push(Ts::ty(&self.ty(&field.ty)));
+            }
+        }
+        if fields.len() == 1 {
+            return Ok(elements.pop().unwrap_or_else(|| Ts::text("null")));
+        }
+        let mut tuple = Ts::text("[");
+        tuple.append(Ts::join(elements, ", "));
+        tuple.push("]");
+        Ok(tuple)
+    }
+
+    fn structure(&mut self, name: &str, fields: &Fields, attrs: &Attrs) -> syn::Result<Ts> {
+        if attrs.transparent {
+            let field = fields
+                .iter()
+                .find(|field| !Attrs::parse(&field.attrs).is_ok_and(|attrs| attrs.skip))
+                .ok_or_else(|| syn::Error::new_spanned(fields, "expected a field"))?;
+            return Ok(Ts::ty(&self.ty(&field.ty)));
+        }
+        match fields {
+            Fields::Named(_) => {
+                let tag = attrs
+                    .tag
+                    .iter()
+                    .map(|tag| Ts::text(format!("{}: {}", key(tag), literal(name))));
+                self.object(fields, attrs.rename_all, tag.collect())
+            }
+            Fields::Unnamed(_) => self.tuple(fields),
+            Fields::Unit => Ok(Ts::text("null")),
+        }
+    }
+
+    fn enumeration(&mut self, data: &syn::DataEnum, attrs: &Attrs) -> syn::Result<Ts> {
+        let mut variants = Vec::new();
+        for variant in &data.variants {
+            let variant_attrs = Attrs::parse(&variant.attrs)?;
+            if variant_attrs.skip {
+                continue;
+            }
+            if variant_attrs.untagged {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "untagged variants aren't supported by #[derive(TypeScript)]",
+                ));
+            }
+            let ident = variant.ident.to_string();
+            let name = match (variant_attrs.rename, attrs.rename_all) {
+                (Some(name), _) => name,
+                (None, Some(case)) => case.variant(&ident),
+                (None, None) => ident,
+            };
+            let field_case = variant_attrs.rename_all.or(attrs.rename_all_fields);
+            let tag = |tag: &str| Ts::text(format!("{}: {}", key(tag), literal(&name)));
+
+            let declared = match (&attrs.tag, &attrs.content, attrs.untagged) {
+                (None, _, false) => match &variant.fields {
+                    Fields::Unit => Ts::text(literal(&name)),
+                    fields => {
+                        let content = match fields {
+                            Fields::Named(_) => self.object(fields, field_case, Vec::new())?,
+                            _ => self.tuple(fields)?,
+                        };
+                        let mut external = Ts::text(format!("{{ {}: ", key(&name)));
+                        external.append(content);
+                        external.push(" }");
+                        external
+                    }
+                },
+                (Some(tag_name), None, _) => match &variant.fields {
+                    Fields::Unit => Ts::join([Ts::text("{ "), tag(tag_name), Ts::text(" }")], ""),
+                    Fields::Named(_) => {
+                        self.object(&variant.fields, field_case, vec![tag(tag_name)])?
+                    }
+                    Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                        let ty = self.ty(&fields.unnamed[0].ty);
+                        let mut internal = Ts::join([Ts::text("{ "), tag(tag_name)], "");
+                        internal.push(" } & ");
+                        internal.0.push(Piece::Grouped(ty));
+                        internal
+                    }
+                    Fields::Unnamed(_) => {
+                        return Err(syn::Error::new_spanned(
+                            variant,
+                            "serde can't write tuple variants of internally tagged enums",
+                        ))
+                    }
+                },
+                (Some(tag_name), Some(content_name), _) => {
+                    let mut adjacent = Ts::join([Ts::text("{ "), tag(tag_name)], "");
+                    match &variant.fields {
+                        Fields::Unit => {}
+                        fields => {
+                            adjacent.push(&format!("; {}: ", key(content_name)));
+                            adjacent.append(match fields {
+                                Fields::Named(_) => self.object(fields, field_case, Vec::new())?,
+                                _ => self.tuple(fields)?,
+                            });
+                        }
+                    }
+                    adjacent.push(" }");
+                    adjacent
+                }
+                (None, _, true) => match &variant.fields {
+                    Fields::Named(_) => self.object(&variant.fields, field_case, Vec::new())?,
+                    Fields::Unnamed(_) => self.tuple(&variant.fields)?,
+                    Fields::Unit => Ts::text("null"),
+                },
+            };
+            variants.push(declared);
+        }
+
+        Ok(match variants.len() {
+            0 => Ts::text("never"),
+            1 => variants.pop().expect("one variant"),
+            _ => {
+                let mut union = Ts::text("\n  | ");
+                union.append(Ts::join(variants, "\n  | "));
+                union
+            }
+        })
+    }
+}
+
+/// Returns the doc comments in `attrs` as a JSDoc comment, or nothing if there are none
+fn js_doc(attrs: &[Attribute]) -> String {
+    let lines = attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(doc) if doc.path.is_ident("doc") => match &doc.value {
+                Expr::Lit(lit) => match &lit.lit {
+                    Lit::Str(line) => Some(line.value()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        return String::new();
+    }
+    let mut doc = String::from("/**\n");
+    for line in lines.iter().flat_map(|line| line.split('\n')) {
+        let line = line.strip_prefix(' ').unwrap_or(line).replace("*/", "*\\/");
+        match line.is_empty() {
+            true => doc.push_str(" *\n"),
+            false => doc.push_str(&format!(" * {line}\n")),
+        }
+    }
+    doc.push_str(" */\n");
+    doc
+}
+
+pub(crate) fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let attrs = Attrs::parse(&input.attrs)?;
+    let mut params = Vec::new();
+    for param in &input.generics.params {
+        match param {
+            GenericParam::Type(ty) => params.push(ty.ident.clone()),
+            GenericParam::Lifetime(_) => {}
+            GenericParam::Const(param) => {
+                return Err(syn::Error::new_spanned(
+                    param,
+                    "const generics aren't supported by #[derive(TypeScript)]",
+                ))
+            }
+        }
+    }
+
+    let ident = &input.ident;
+    let name = attrs
+        .declared_as
+        .clone()
+        .unwrap_or_else(|| ident.to_string());
+    let serialized_name = attrs.rename.clone().unwrap_or_else(|| ident.to_string());
+    let stand_ins = params.iter().map(stand_in).collect::<Vec<_>>();
+    let lifetimes = input.generics.lifetimes().map(|_| quote!('_));
+    let this = parse_quote!(#ident<#(#lifetimes,)* #(#stand_ins),*>);
+
+    let mut declaring = Declaring {
+        generic: Generic {
+            params: &params,
+            this,
+        },
+        used: Vec::new(),
+    };
+    let body = match &input.data {
+        Data::Struct(data) => declaring.structure(&serialized_name, &data.fields, &attrs)?,
+        Data::Enum(data) => declaring.enumeration(data, &attrs)?,
+        Data::Union(data) => {
+            return Err(syn::Error::new(
+                data.union_token.span(),
+                "unions can't be declared in TypeScript",
+            ))
+        }
+    }
+    .build();
+
+    let declared_params = match params.is_empty() {
+        true => String::new(),
+        false => format!(
+            "<{}>",
+            params
+                .iter()
+                .map(Ident::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let prefix = format!(
+        "{}export type {name}{declared_params} =",
+        js_doc(&input.attrs)
+    );
+    let used = &declaring.used;
+    let ts_type = match params.is_empty() {
+        true => quote!(::std::string::String::from(#name)),
+        false => quote! {
+            ::std::format!(
+                "{}<{}>",
+                #name,
+                [#(<#params as ::privileged_ipc::TypeScript>::ts_type()),*].join(", "),
+            )
+        },
+    };
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::privileged_ipc::TypeScript));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let param_names = params.iter().map(Ident::to_string);
+
+    Ok(quote! {
+        const _: () = {
+            #(
+                #[allow(non_camel_case_types)]
+                struct #stand_ins;
+
+                impl ::privileged_ipc::TypeScript for #stand_ins {
+                    fn ts_type() -> ::std::string::String {
+                        ::std::string::String::from(#param_names)
+                    }
+                }
+            )*
+
+            impl #impl_generics ::privileged_ipc::TypeScript for #ident #ty_generics #where_clause {
+                fn ts_type() -> ::std::string::String {
+                    #ts_type
+                }
+
+                fn declare(declarations: &mut ::privileged_ipc::Declarations) {
+                    let mut declaration = ::std::string::String::from(#prefix);
+                    let body = #body;
+                    // Unions start on a line of their own
+                    if !body.starts_with('\n') {
+                        declaration.push(' ');
+                    }
+                    declaration.push_str(&body);
+                    declaration.push(';');
+                    if declarations.insert(#name, declaration) {
+                        #(<#used as ::privileged_ipc::TypeScript>::declare(declarations);)*
+                    }
+                }
+            }
+        };
+    })
+}
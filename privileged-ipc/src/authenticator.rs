@@ -0,0 +1,309 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Optional authentication of frames with HMAC-SHA256
+//!
+//! Filesystem and TCP sockets can be reached by processes other than the intended
+//! peer. A [`MessageAuthenticator`] tags every outgoing frame and rejects incoming
+//! frames whose tag doesn't verify before they are deserialized, so frames injected
+//! by anyone without the key are never acted upon.
+//!
+//! Each tag covers the direction and position of its frame in the stream, so frames
+//! can't be replayed, reordered or reflected back at their sender either. The key
+//! is typically generated by the client and handed to the helper at spawn time,
+//! see [`IpcClient::new_authenticated`](crate::IpcClient::new_authenticated).
+
+use std::{
+    io::{self, Read},
+    mem::ManuallyDrop,
+    os::fd::{FromRawFd, OwnedFd},
+};
+
+use crate::{FrameCipher, IpcError};
+
+/// Length of keys created by [`generate_key`], and of every tag
+pub const KEY_LEN: usize = 32;
+
+/// Which end of the connection an authenticator is used on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// The end that launched or connected to the service
+    Client,
+    /// The service
+    Server,
+}
+
+/// A [`FrameCipher`] that authenticates frames with HMAC-SHA256 without encrypting them
+pub struct MessageAuthenticator {
+    key: Vec<u8>,
+    role: Role,
+    sent: u64,
+    received: u64,
+}
+
+impl MessageAuthenticator {
+    /// Creates an authenticator for one connection, with a key shared by both ends
+    pub fn new(key: &[u8], role: Role) -> Self {
+        Self {
+            key: key.to_vec(),
+            role,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    fn tag(&self, sender: Role, sequence: u64, payload: &[u8]) -> [u8; KEY_LEN] {
+        let direction = match sender {
+            Role::Client => 0u8,
+            Role::Server => 1u8,
+        };
+        hmac_sha256(&self.key, &[&[direction], &sequence.to_be_bytes(), payload])
+    }
+}
+
+impl FrameCipher for MessageAuthenticator {
+    fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, IpcError> {
+        let tag = self.tag(self.role, self.sent, plaintext);
+        self.sent += 1;
+
+        let mut frame = Vec::with_capacity(KEY_LEN + plaintext.len());
+        frame.extend_from_slice(&tag);
+        frame.extend_from_slice(plaintext);
+        Ok(frame)
+    }
+
+    fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, IpcError> {
+        let Some((tag, payload)) = ciphertext.split_first_chunk::<KEY_LEN>() else {
+            return Err(IpcError::Codec(
+                "frame too short to be authenticated".into(),
+            ));
+        };
+        let peer = match self.role {
+            Role::Client => Role::Server,
+            Role::Server => Role::Client,
+        };
+        let expected = self.tag(peer, self.received, payload);
+
+        // Compare in constant time, so the tag can't be guessed byte by byte
+        if tag
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            != 0
+        {
+            return Err(IpcError::Codec("frame failed authentication".into()));
+        }
+        self.received += 1;
+        Ok(payload.to_vec())
+    }
+}
+
+/// Creates a random key from the kernel's random number generator
+pub fn generate_key() -> io::Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    let mut filled = 0;
+    while filled < KEY_LEN {
+        // SAFETY: the pointer and length describe the unfilled part of `key`
+        let n = unsafe { libc::getrandom(key[filled..].as_mut_ptr().cast(), KEY_LEN - filled, 0) };
+        match n {
+            n if n >= 0 => filled += n as usize,
+            _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            _ => return Err(io::Error::last_os_error()),
+        }
+    }
+    Ok(key)
+}
+
+/// Reads the key handed over by [`IpcClient::new_authenticated`](crate::IpcClient::new_authenticated)
+///
+/// The token of [`IpcClient::new_with_token`](crate::IpcClient::new_with_token) is
+/// handed over the same way.
+///
+/// The key arrives on the helper's stdin, which the client keeps open afterwards
+/// for [`dump_on_request`](crate::dump_on_request). This must be called once,
+/// early in the helper and before stdin is used for anything else, and blocks
+/// until the client goes away if it handed over no key.
+pub fn read_spawn_key() -> io::Result<Vec<u8>> {
+    // SAFETY: stdin is open in a spawned helper, and only borrowed here
+    let stdin = ManuallyDrop::new(std::fs::File::from(unsafe { OwnedFd::from_raw_fd(0) }));
+    let mut key = vec![];
+    (&*stdin).take(KEY_LEN as u64).read_to_end(&mut key)?;
+    if key.len() != KEY_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no authentication key was handed over on stdin",
+        ));
+    }
+    Ok(key)
+}
+
+/// Computes the HMAC-SHA256 of the concatenated `parts` (RFC 2104)
+pub(crate) fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; KEY_LEN] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..KEY_LEN].copy_from_slice(&sha256(&[key]));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner_pad = block.map(|b| b ^ 0x36);
+    let outer_pad = block.map(|b| b ^ 0x5c);
+
+    let mut inner = vec![&inner_pad[..]];
+    inner.extend_from_slice(parts);
+    let inner = sha256(&inner);
+    sha256(&[&outer_pad, &inner])
+}
+
+/// Round constants of SHA-256
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Computes the SHA-256 digest of the concatenated `parts` (FIPS 180-4)
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    let mut message = Vec::with_capacity(len + 72);
+    for part in parts {
+        message.extend_from_slice(part);
+    }
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((len as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hmac_sha256, sha256};
+
+    fn hex(digest: [u8; 32]) -> String {
+        digest.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Examples from FIPS 180-4, along with the empty message
+    #[test]
+    fn sha256_known_answers() {
+        let cases: [(&[u8], &str); 4] = [
+            (
+                b"",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            (
+                &[b'a'; 1_000_000],
+                "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+            ),
+        ];
+        for (message, digest) in cases {
+            assert_eq!(hex(sha256(&[message])), digest);
+        }
+    }
+
+    #[test]
+    fn sha256_hashes_parts_as_one_message() {
+        let message = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        for split in 0..=message.len() {
+            let (first, second) = message.split_at(split);
+            assert_eq!(sha256(&[first, second]), sha256(&[message]));
+        }
+    }
+
+    /// Test cases 1, 2, 6 and 7 of RFC 4231, the last two with keys longer than a block
+    #[test]
+    fn hmac_sha256_known_answers() {
+        let cases: [(&[u8], &[u8], &str); 4] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (key, data, tag) in cases {
+            assert_eq!(hex(hmac_sha256(key, &[data])), tag);
+        }
+    }
+}
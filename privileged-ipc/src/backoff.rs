@@ -0,0 +1,165 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! How long to wait before trying again
+//!
+//! Everything that retries, such as [`ReconnectingClient`](crate::ReconnectingClient)
+//! and [`Discovery`](crate::Discovery) waiting on a busy daemon, asks a [`Backoff`]
+//! for the delay after each failure and gives up once it returns `None`. The
+//! delay only depends on the number of failures so far, so a strategy can be
+//! shared and its schedule is reproducible: [`Fixed`] with a zero delay retries
+//! without sleeping, [`Jittered::seed`] makes the jitter repeatable, and any
+//! `Fn(u32) -> Option<Duration>` can script the delays outright.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// Decides how long to wait before retrying something that failed
+pub trait Backoff: Send + Sync {
+    /// Returns how long to wait after `failures` consecutive failures, or `None` to give up
+    ///
+    /// `failures` starts at 1 for the first failure.
+    fn delay(&self, failures: u32) -> Option<Duration>;
+}
+
+impl<F> Backoff for F
+where
+    F: Fn(u32) -> Option<Duration> + Send + Sync,
+{
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        self(failures)
+    }
+}
+
+impl fmt::Debug for dyn Backoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Backoff")
+    }
+}
+
+/// A delay starting at `initial` and doubling after every failure, up to `max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exponential {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Exponential {
+    /// Creates a backoff starting at `initial` and growing to at most `max`
+    ///
+    /// Eight attempts are made by default.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            attempts: 8,
+        }
+    }
+
+    /// Sets how many attempts are made before giving up
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts;
+        self
+    }
+}
+
+impl Default for Exponential {
+    /// Starts at 100ms, backing off to at most 5s
+    fn default() -> Self {
+        Self::new(Duration::from_millis(100), Duration::from_secs(5))
+    }
+}
+
+impl Backoff for Exponential {
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        if failures >= self.attempts {
+            return None;
+        }
+        let factor = 1u32
+            .checked_shl(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        Some(self.initial.saturating_mul(factor).min(self.max))
+    }
+}
+
+/// The same delay after every failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixed {
+    delay: Duration,
+    attempts: Option<u32>,
+}
+
+impl Fixed {
+    /// Waits `delay` after every failure, without ever giving up
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            attempts: None,
+        }
+    }
+
+    /// Sets how many attempts are made before giving up
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = Some(attempts);
+        self
+    }
+}
+
+impl Backoff for Fixed {
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        match self.attempts {
+            Some(attempts) if failures >= attempts => None,
+            _ => Some(self.delay),
+        }
+    }
+}
+
+/// Another backoff with each delay shortened by up to half, at random
+///
+/// Clients that failed together, such as when a daemon restarts, then don't all
+/// retry at the same moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jittered<B> {
+    inner: B,
+    seed: u64,
+}
+
+impl<B: Backoff> Jittered<B> {
+    /// Adds jitter to the delays of `inner`
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            seed: RandomState::new().build_hasher().finish(),
+        }
+    }
+
+    /// Draws the jitter from `seed`, so the same delays come out every time
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl<B: Backoff> Backoff for Jittered<B> {
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        let delay = self.inner.delay(failures)?;
+        // A fraction in [0, 1) from the top 53 bits of the mixed seed
+        let fraction =
+            (splitmix64(self.seed ^ u64::from(failures)) >> 11) as f64 / (1u64 << 53) as f64;
+        Some(delay.mul_f64(1.0 - fraction / 2.0))
+    }
+}
+
+/// Scrambles `state` into a well-distributed value
+fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
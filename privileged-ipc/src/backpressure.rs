@@ -0,0 +1,210 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! What sending does when the peer stops reading
+//!
+//! Once the socket buffer is full, a plain write waits for the peer to drain it,
+//! which a wedged frontend never does. A privileged daemon replying to it would
+//! then hang with every other client it serves. A [`WritePolicy`] bounds that
+//! wait, or keeps the unsent bytes on the side up to a limit, so only the
+//! connection to the misbehaving peer is affected.
+//!
+//! Bytes that didn't fit are queued behind the frame they belong to and are
+//! written before anything sent later, so a frame is never interleaved with
+//! another; either policy leaves the stream intact.
+
+use std::{
+    io,
+    os::fd::BorrowedFd,
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    poll::{PollFd, PollFlags, PollTimeout},
+};
+
+use crate::{IpcConnection, IpcError};
+
+/// How sending behaves while the peer isn't reading, see [`IpcConnection::set_write_policy`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Wait for as long as it takes the peer to read
+    #[default]
+    Block,
+    /// Wait at most this long for each message to be written
+    ///
+    /// If the peer doesn't read in time, sending fails with
+    /// [`IpcError::WriteTimeout`]. When part of the message was written already,
+    /// the rest is queued and written before the next message.
+    Deadline(Duration),
+    /// Never wait, and queue whatever can't be written straight away
+    ///
+    /// Sending fails with [`IpcError::SendBufferFull`] once the queue would grow
+    /// beyond `limit` bytes. The queue is written as later messages are sent, or
+    /// with [`IpcConnection::try_flush`] and [`IpcConnection::flush`].
+    Buffer { limit: usize },
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Sets what sending does when the peer stops reading
+    ///
+    /// Policies other than [`WritePolicy::Block`] need a transport that supports
+    /// non-blocking writes, such as a Unix socket or a pipe.
+    pub fn set_write_policy(&mut self, policy: WritePolicy) {
+        self.write_policy = policy;
+    }
+
+    /// Returns what sending does when the peer stops reading
+    pub fn write_policy(&self) -> WritePolicy {
+        self.write_policy
+    }
+
+    /// Returns the number of bytes sent but not yet written to the transport
+    pub fn pending_bytes(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    /// Writes as much of the queued bytes as the transport takes without waiting
+    ///
+    /// Returns true once nothing remains queued.
+    pub fn try_flush(&mut self) -> Result<bool, IpcError> {
+        self.drain(Some(Instant::now()))
+    }
+
+    /// Writes every queued byte, waiting for the peer to read them
+    ///
+    /// Also waits for the writer thread to empty the send queue, if there is one.
+    /// Waits indefinitely, except under [`WritePolicy::Deadline`], which fails
+    /// with [`IpcError::WriteTimeout`] once the deadline passes.
+    pub fn flush(&mut self) -> Result<(), IpcError> {
+        let deadline = match self.write_policy {
+            WritePolicy::Deadline(timeout) => Some(Instant::now() + timeout),
+            WritePolicy::Block | WritePolicy::Buffer { .. } => None,
+        };
+        if let Some(queue) = &self.send_queue {
+            if !queue.wait_idle(deadline)? {
+                return Err(IpcError::WriteTimeout);
+            }
+        }
+        match self.drain(deadline)? {
+            true => Ok(()),
+            false => Err(IpcError::WriteTimeout),
+        }
+    }
+
+    /// Writes `frames` according to the write policy, behind any queued bytes
+    pub(crate) fn write_policed(
+        &mut self,
+        frames: &[u8],
+        fds: &[BorrowedFd<'_>],
+    ) -> Result<(), IpcError> {
+        let deadline = match self.write_policy {
+            WritePolicy::Block => None,
+            WritePolicy::Deadline(timeout) => Some(Instant::now() + timeout),
+            WritePolicy::Buffer { .. } => Some(Instant::now()),
+        };
+        let drained = self.drain(deadline)?;
+
+        if let WritePolicy::Buffer { limit } = self.write_policy {
+            if !drained {
+                // Descriptors must go with the first byte of their frame, so can't wait in the queue
+                if !fds.is_empty() || self.outgoing.len() + frames.len() > limit {
+                    return Err(IpcError::SendBufferFull { limit });
+                }
+                self.outgoing.extend_from_slice(frames);
+                return Ok(());
+            }
+        } else if !drained {
+            return Err(IpcError::WriteTimeout);
+        }
+
+        let written = self.write_until(frames, fds, deadline)?;
+        if written == frames.len() {
+            return Ok(());
+        }
+        match self.write_policy {
+            WritePolicy::Buffer { limit } if written == 0 && frames.len() > limit => {
+                Err(IpcError::SendBufferFull { limit })
+            }
+            WritePolicy::Buffer { .. } => {
+                self.outgoing.extend_from_slice(&frames[written..]);
+                Ok(())
+            }
+            WritePolicy::Block | WritePolicy::Deadline(_) => {
+                // The rest of a partly written frame has to follow, or the stream is corrupt
+                if written > 0 {
+                    self.outgoing.extend_from_slice(&frames[written..]);
+                }
+                Err(IpcError::WriteTimeout)
+            }
+        }
+    }
+
+    /// Writes queued bytes until none remain or `deadline` passes, returning true if none remain
+    fn drain(&mut self, deadline: Option<Instant>) -> Result<bool, IpcError> {
+        if self.outgoing.is_empty() {
+            return Ok(true);
+        }
+        let outgoing = std::mem::take(&mut self.outgoing);
+        let written = self.write_until(&outgoing, &[], deadline);
+        self.outgoing = outgoing;
+        self.outgoing.drain(..written?);
+        Ok(self.outgoing.is_empty())
+    }
+
+    /// Writes `buf` until it is all written or `deadline` passes, returning how much was
+    ///
+    /// `fds` are passed with the first byte written.
+    fn write_until(
+        &mut self,
+        buf: &[u8],
+        fds: &[BorrowedFd<'_>],
+        deadline: Option<Instant>,
+    ) -> Result<usize, IpcError> {
+        let mut written = 0;
+        while written < buf.len() {
+            let fds = if written == 0 { fds } else { &[] };
+            match self.transport.try_write_with_fds(&buf[written..], fds) {
+                Ok(0) => return Err(IpcError::Io(io::ErrorKind::WriteZero.into())),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    let remaining = match deadline {
+                        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                            Some(remaining) if !remaining.is_zero() => Some(remaining),
+                            _ => break,
+                        },
+                        None => None,
+                    };
+                    let fd = self.transport.write_fd().ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            "waiting to write not supported by this transport",
+                        )
+                    })?;
+                    wait_writable(fd, remaining)?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    return Err(IpcError::ConnectionClosed)
+                }
+                Err(e) => return Err(IpcError::Io(e)),
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Waits until `fd` can be written to, or `timeout` elapses
+fn wait_writable(fd: BorrowedFd<'_>, timeout: Option<Duration>) -> io::Result<()> {
+    let poll_timeout = match timeout {
+        Some(timeout) => PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX),
+        None => PollTimeout::NONE,
+    };
+    let mut fds = [PollFd::new(fd, PollFlags::POLLOUT)];
+    match nix::poll::poll(&mut fds, poll_timeout) {
+        Ok(_) | Err(Errno::EINTR) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,340 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Bulk data passed as sealed memory files
+//!
+//! Encoding large payloads such as package archives or disk images into messages
+//! is wasteful. A [`Blob`] instead holds its data in a sealed `memfd`, and only an
+//! index and length are encoded into the message; the file descriptor itself is
+//! passed alongside the frame with `SCM_RIGHTS`.
+//!
+//! Seals make the contents immutable, and receivers reject blobs that aren't
+//! sealed, so a privileged receiver can't be raced into seeing data change after
+//! validating it. Blobs can only be sent over Unix socket transports.
+
+use std::{
+    cell::RefCell,
+    ffi::c_int,
+    fs::File,
+    io::{self, IoSlice, IoSliceMut, Read},
+    mem,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::FileExt,
+    },
+    ptr,
+};
+
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{credentials, PeerCredentials};
+
+/// Seals that make a memory file's contents immutable
+const CONTENT_SEALS: c_int = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+
+/// Most blobs a single message may carry
+pub(crate) const MAX_BLOBS_PER_MESSAGE: usize = 64;
+
+/// The kernel dropped descriptors passed with a read, as more came than fit
+///
+/// The data read is lost along with them, so the stream can't be resynchronised.
+#[derive(Debug, thiserror::Error)]
+#[error("too many file descriptors passed at once")]
+pub(crate) struct Truncated;
+
+thread_local! {
+    /// Descriptors of the blobs encountered while encoding a message
+    static OUTGOING: RefCell<Option<Vec<RawFd>>> = const { RefCell::new(None) };
+    /// Descriptors passed with the frame being decoded, taken by its blobs
+    static INCOMING: RefCell<Option<Vec<Option<OwnedFd>>>> = const { RefCell::new(None) };
+}
+
+/// Immutable bulk data that is passed by file descriptor rather than encoded
+#[derive(Debug)]
+pub struct Blob {
+    file: File,
+    len: u64,
+}
+
+impl Blob {
+    /// Creates a blob holding a copy of `data`
+    pub fn new(data: &[u8]) -> io::Result<Self> {
+        Self::from_reader(data)
+    }
+
+    /// Creates a blob holding everything read from `reader`
+    pub fn from_reader(mut reader: impl Read) -> io::Result<Self> {
+        // SAFETY: the name is NUL terminated
+        let fd = unsafe {
+            libc::memfd_create(
+                c"privileged-ipc-blob".as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just created and is owned by nothing else
+        let mut file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        let len = io::copy(&mut reader, &mut file)?;
+
+        // SAFETY: plain fcntl on a file descriptor we own
+        if unsafe {
+            libc::fcntl(
+                file.as_raw_fd(),
+                libc::F_ADD_SEALS,
+                CONTENT_SEALS | libc::F_SEAL_SEAL,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { file, len })
+    }
+
+    /// Length of the data in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the blob holds no data
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads from the data at `offset` into `buf`, returning how many bytes were read
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.file.read_at(buf, offset)
+    }
+
+    /// Returns a reader over the data from its start
+    pub fn reader(&self) -> BlobReader<'_> {
+        BlobReader {
+            blob: self,
+            offset: 0,
+        }
+    }
+
+    /// Copies all of the data into memory
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.len as usize);
+        self.reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Adopts a descriptor received from a peer, checking it is a sealed file of `len` bytes
+    fn from_received(fd: OwnedFd, len: u64) -> io::Result<Self> {
+        let invalid = |reason| io::Error::new(io::ErrorKind::InvalidData, reason);
+
+        // SAFETY: plain fcntl on a file descriptor we own
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        if seals < 0 || seals & CONTENT_SEALS != CONTENT_SEALS {
+            return Err(invalid("blob is not a sealed memory file"));
+        }
+        let file = File::from(fd);
+        if file.metadata()?.len() != len {
+            return Err(invalid("blob length doesn't match its file"));
+        }
+        Ok(Self { file, len })
+    }
+}
+
+impl AsFd for Blob {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl Serialize for Blob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let index = pass_fd(self.file.as_raw_fd())
+            .ok_or_else(|| ser::Error::custom("a Blob can only be sent over an IpcConnection"))?;
+        (index, self.len).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Blob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (index, len) = <(usize, u64)>::deserialize(deserializer)?;
+        let fd = take_passed_fd(index).ok_or_else(|| {
+            de::Error::custom("Blob refers to a file descriptor that wasn't passed")
+        })?;
+        Blob::from_received(fd, len).map_err(de::Error::custom)
+    }
+}
+
+/// Reads the data of a [`Blob`] sequentially
+pub struct BlobReader<'a> {
+    blob: &'a Blob,
+    offset: u64,
+}
+
+impl Read for BlobReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.blob.read_at(buf, self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// Passes `fd` along with the message being encoded, returning its index among those passed
+///
+/// Returns `None` if no message is being encoded for a connection.
+pub(crate) fn pass_fd(fd: RawFd) -> Option<usize> {
+    OUTGOING.with_borrow_mut(|fds| {
+        fds.as_mut().map(|fds| {
+            fds.push(fd);
+            fds.len() - 1
+        })
+    })
+}
+
+/// Takes the descriptor at `index` among those passed with the frame being decoded
+///
+/// Returns `None` if there is none, or it was already taken.
+pub(crate) fn take_passed_fd(index: usize) -> Option<OwnedFd> {
+    INCOMING.with_borrow_mut(|fds| {
+        fds.as_mut()
+            .and_then(|fds| fds.get_mut(index))
+            .and_then(Option::take)
+    })
+}
+
+/// Runs `encode`, returning the descriptors of any blobs it serialized
+pub(crate) fn collect_fds<T>(encode: impl FnOnce() -> T) -> (T, Vec<RawFd>) {
+    let previous = OUTGOING.replace(Some(vec![]));
+    let result = encode();
+    let fds = OUTGOING.replace(previous).unwrap_or_default();
+    (result, fds)
+}
+
+/// Runs `decode` with the descriptors passed alongside the frame it decodes
+///
+/// Descriptors not taken by a blob are closed.
+pub(crate) fn provide_fds<T>(fds: Vec<OwnedFd>, decode: impl FnOnce() -> T) -> T {
+    let previous = INCOMING.replace(Some(fds.into_iter().map(Some).collect()));
+    let result = decode();
+    INCOMING.set(previous);
+    result
+}
+
+/// Writes all of `buf` to a Unix socket, passing `fds` along with its first byte
+///
+/// `flags` are passed to `sendmsg(2)` in addition to `MSG_NOSIGNAL`.
+pub(crate) fn send_with_fds(
+    socket: BorrowedFd<'_>,
+    buf: &[u8],
+    fds: &[BorrowedFd<'_>],
+    flags: libc::c_int,
+) -> io::Result<usize> {
+    let raw = fds.iter().map(AsRawFd::as_raw_fd).collect::<Vec<_>>();
+    let payload_len = mem::size_of_val(raw.as_slice());
+    // SAFETY: CMSG_SPACE only computes a size
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(payload_len as u32) } as usize];
+
+    let mut iov = [IoSlice::new(buf)];
+    // SAFETY: an all-zero msghdr is valid, and the fields set point at live buffers
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = iov.as_mut_ptr().cast();
+    msg.msg_iovlen = iov.len();
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len();
+
+    // SAFETY: `control` has room for one header carrying `raw`
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&msg);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(payload_len as u32) as usize;
+        ptr::copy_nonoverlapping(
+            raw.as_ptr().cast::<u8>(),
+            libc::CMSG_DATA(header),
+            payload_len,
+        );
+    }
+
+    loop {
+        // SAFETY: `msg` and everything it points to outlive the call
+        let sent = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL | flags) };
+        if sent >= 0 {
+            return Ok(sent as usize);
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+/// Reads from a Unix socket into `buf`, collecting any descriptors passed with the data
+pub(crate) fn recv_with_fds(
+    socket: BorrowedFd<'_>,
+    buf: &mut [u8],
+    fds: &mut Vec<OwnedFd>,
+) -> io::Result<usize> {
+    recv_with_credentials(socket, buf, fds).map(|(received, _)| received)
+}
+
+/// Receives like [`recv_with_fds`], also returning the sender's credentials if `SO_PASSCRED` is set
+pub(crate) fn recv_with_credentials(
+    socket: BorrowedFd<'_>,
+    buf: &mut [u8],
+    fds: &mut Vec<OwnedFd>,
+) -> io::Result<(usize, Option<PeerCredentials>)> {
+    // Leaves room for the credentials too, which the kernel passes with every read under SO_PASSCRED
+    // SAFETY: CMSG_SPACE only computes a size
+    let control_len = unsafe {
+        libc::CMSG_SPACE((MAX_BLOBS_PER_MESSAGE * mem::size_of::<RawFd>()) as u32)
+            + libc::CMSG_SPACE(mem::size_of::<libc::ucred>() as u32)
+    };
+    let mut control = vec![0u8; control_len as usize];
+
+    let mut iov = [IoSliceMut::new(buf)];
+    // SAFETY: an all-zero msghdr is valid, and the fields set point at live buffers
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = iov.as_mut_ptr().cast();
+    msg.msg_iovlen = iov.len();
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len();
+
+    let received = loop {
+        // SAFETY: `msg` and everything it points to outlive the call
+        let received =
+            unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+        if received >= 0 {
+            break received as usize;
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    };
+
+    let mut credentials = None;
+    // SAFETY: the kernel filled in `msg_controllen` bytes of well formed headers
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&msg);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(header);
+                let len = (*header).cmsg_len - (data as usize - header as usize);
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    let fd = ptr::read_unaligned(data.cast::<RawFd>().add(i));
+                    fds.push(OwnedFd::from_raw_fd(fd));
+                }
+            }
+            if (*header).cmsg_level == libc::SOL_SOCKET
+                && (*header).cmsg_type == libc::SCM_CREDENTIALS
+            {
+                let cred = ptr::read_unaligned(libc::CMSG_DATA(header).cast::<libc::ucred>());
+                credentials = credentials::from_ucred(cred);
+            }
+            header = libc::CMSG_NXTHDR(&msg, header);
+        }
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::other(Truncated));
+    }
+    Ok((received, credentials))
+}
@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Optional encryption of frames on the wire
+//!
+//! Daemons listening on filesystem sockets shared between users may want traffic
+//! dumps to be useless to anyone without the key. A [`FrameCipher`] installed on
+//! a connection seals every outgoing frame and opens every incoming one, with the
+//! key typically fetched from the kernel keyring via [`Keyring::read_key`].
+
+use std::{ffi::CString, io};
+
+use crate::IpcError;
+
+/// Seals and opens individual frames
+///
+/// Implementations wrap an authenticated cipher of the application's choosing and
+/// are responsible for nonce management. Both sides of a connection must install
+/// matching ciphers.
+pub trait FrameCipher: Send {
+    /// Encrypts an encoded message into a sealed frame
+    fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, IpcError>;
+
+    /// Decrypts and authenticates a sealed frame
+    fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, IpcError>;
+}
+
+/// A kernel keyring to search for frame keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyring {
+    /// The keyring of the current login session
+    Session,
+    /// The keyring shared by all processes of the current user
+    User,
+}
+
+impl Keyring {
+    /// Reads the payload of the `user` type key with the given description
+    pub fn read_key(self, description: &str) -> io::Result<Vec<u8>> {
+        let keyring = match self {
+            Keyring::Session => libc::KEY_SPEC_SESSION_KEYRING,
+            Keyring::User => libc::KEY_SPEC_USER_KEYRING,
+        };
+        let description = CString::new(description)?;
+
+        // SAFETY: both strings are NUL terminated and outlive the call
+        let serial = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                libc::KEYCTL_SEARCH,
+                keyring,
+                c"user".as_ptr(),
+                description.as_ptr(),
+                0,
+            )
+        };
+        if serial < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The key could be updated between calls, so retry until the buffer is large enough
+        let mut payload = vec![];
+        loop {
+            // SAFETY: `payload` is valid for writes of its length
+            let len = unsafe {
+                libc::syscall(
+                    libc::SYS_keyctl,
+                    libc::KEYCTL_READ,
+                    serial,
+                    payload.as_mut_ptr(),
+                    payload.len(),
+                )
+            };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let len = len as usize;
+            if len <= payload.len() {
+                payload.truncate(len);
+                return Ok(payload);
+            }
+            payload.resize(len, 0);
+        }
+    }
+}
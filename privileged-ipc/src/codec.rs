@@ -0,0 +1,190 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Wire formats for messages
+//!
+//! Connections are generic over a [`Codec`], defaulting to [`JsonCodec`], so
+//! other serde formats can be used without changing the connection types.
+
+use std::io::{Read, Write};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::IpcError;
+
+/// Converts messages to and from their encoded form
+pub trait Codec: Default {
+    /// Encodes a single message onto `writer`
+    fn encode<T: Serialize>(&self, message: &T, writer: impl Write) -> Result<(), IpcError>;
+
+    /// Decodes a single message, which must make up all of `reader`
+    fn decode<T: DeserializeOwned>(&self, reader: impl Read) -> Result<T, IpcError>;
+
+    /// Finds the length of the first complete encoded message at the start of `buffer`
+    ///
+    /// This is needed by [`Framing::Stream`](crate::Framing::Stream), where messages
+    /// must delimit themselves. Returns `Ok(None)` if more data is needed. Codecs whose
+    /// output isn't self-delimiting should return an error here, so that they are
+    /// only used with [`Framing::LengthPrefixed`](crate::Framing::LengthPrefixed).
+    fn message_len(&self, buffer: &[u8]) -> Result<Option<usize>, IpcError>;
+
+    /// Finds the length of the first complete message like [`Codec::message_len`], carrying on from `scan`
+    ///
+    /// Connections look for the end of a message again each time more of it
+    /// arrives, so a codec that records its progress in `scan` goes over every
+    /// byte once, rather than over the whole message on every read. `scan` must
+    /// be left as it was when a message is found, as the same message may be
+    /// looked for again before it is taken. By default, the whole buffer is
+    /// searched every time.
+    fn resume_message_len(
+        &self,
+        buffer: &[u8],
+        scan: &mut StreamScan,
+    ) -> Result<Option<usize>, IpcError> {
+        let _ = scan;
+        self.message_len(buffer)
+    }
+}
+
+/// How far the search for the end of a partly received message got
+///
+/// Connections keep one of these, starting over from the default whenever a
+/// message is taken from the front of the buffer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StreamScan {
+    /// Bytes at the start of the buffer already looked at
+    pub offset: usize,
+    /// Whatever else the codec needs to carry on from `offset`
+    pub state: u64,
+}
+
+/// Encodes messages as JSON using serde_json
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: Serialize>(&self, message: &T, writer: impl Write) -> Result<(), IpcError> {
+        Ok(serde_json::to_writer(writer, message)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, reader: impl Read) -> Result<T, IpcError> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    fn message_len(&self, buffer: &[u8]) -> Result<Option<usize>, IpcError> {
+        self.resume_message_len(buffer, &mut StreamScan::default())
+    }
+
+    fn resume_message_len(
+        &self,
+        buffer: &[u8],
+        scan: &mut StreamScan,
+    ) -> Result<Option<usize>, IpcError> {
+        let mut json = JsonScan::unpack(scan.state);
+        for (offset, byte) in buffer.iter().enumerate().skip(scan.offset) {
+            if let Some(end) = json.step(offset, *byte)? {
+                return Ok(Some(end));
+            }
+        }
+        *scan = StreamScan {
+            offset: buffer.len(),
+            state: json.pack(),
+        };
+        Ok(None)
+    }
+}
+
+/// Where a scan for the end of a JSON value is within it
+///
+/// Only the structure is followed, the value is validated when decoding it.
+#[derive(Debug, Default)]
+struct JsonScan {
+    /// Objects and arrays opened but not yet closed
+    depth: u32,
+    in_string: bool,
+    /// Whether the previous byte of a string was an unescaped backslash
+    escaped: bool,
+    /// First byte of the number or literal making up the whole value, if it is one
+    word_start: u8,
+    /// Bytes of that number or literal so far, saturating
+    word_len: u8,
+}
+
+impl JsonScan {
+    fn unpack(state: u64) -> Self {
+        Self {
+            depth: (state >> 32) as u32,
+            in_string: state & 1 != 0,
+            escaped: state & 2 != 0,
+            word_start: (state >> 8) as u8,
+            word_len: (state >> 16) as u8,
+        }
+    }
+
+    fn pack(&self) -> u64 {
+        (self.depth as u64) << 32
+            | (self.word_len as u64) << 16
+            | (self.word_start as u64) << 8
+            | (self.escaped as u64) << 1
+            | self.in_string as u64
+    }
+
+    /// Takes in the byte at `offset`, returning the end of the value once it is complete
+    fn step(&mut self, offset: usize, byte: u8) -> Result<Option<usize>, IpcError> {
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if byte == b'\\' {
+                self.escaped = true;
+            } else if byte == b'"' {
+                self.in_string = false;
+                if self.depth == 0 {
+                    return Ok(Some(offset + 1));
+                }
+            }
+            return Ok(None);
+        }
+        if self.word_len > 0 {
+            if !matches!(byte, b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'+' | b'-' | b'.') {
+                // A number only ends where something else starts
+                return Ok(Some(offset));
+            }
+            self.word_len = self.word_len.saturating_add(1);
+            return Ok(self.literal_complete().then_some(offset + 1));
+        }
+        match byte {
+            b' ' | b'\t' | b'\n' | b'\r' => {}
+            b'"' => self.in_string = true,
+            b'{' | b'[' => self.depth += 1,
+            b'}' | b']' => {
+                let Some(depth) = self.depth.checked_sub(1) else {
+                    return Err(unexpected(byte));
+                };
+                self.depth = depth;
+                if depth == 0 {
+                    return Ok(Some(offset + 1));
+                }
+            }
+            b'-' | b'0'..=b'9' | b't' | b'f' | b'n' if self.depth == 0 => {
+                self.word_start = byte;
+                self.word_len = 1;
+            }
+            _ if self.depth == 0 => return Err(unexpected(byte)),
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Returns true once a whole `true`, `false` or `null` has been read
+    fn literal_complete(&self) -> bool {
+        matches!(
+            (self.word_start, self.word_len),
+            (b't' | b'n', 4) | (b'f', 5)
+        )
+    }
+}
+
+fn unexpected(byte: u8) -> IpcError {
+    IpcError::Codec(format!("unexpected byte {byte:#04x} outside of a JSON value").into())
+}
@@ -0,0 +1,191 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Client-side protocol conformance checking
+//!
+//! A [`CheckedConnection`] wraps an [`IpcConnection`] and validates the traffic it
+//! observes against the rules of a request/response protocol: every call is
+//! answered, interim messages such as progress only arrive while a call is being
+//! answered, and nothing follows a goodbye. Messages declare their role through
+//! [`ProtocolMessage`].
+//!
+//! This is meant for tests and debug builds of clients, to catch protocol bugs in
+//! services early. By default a violation panics when debug assertions are enabled
+//! and is logged otherwise.
+
+use std::ops::{Deref, DerefMut};
+
+use thiserror::Error;
+
+use crate::{Codec, IpcConnection, IpcError, JsonCodec};
+
+/// The role of a message within a request/response protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// A request the peer must answer with a reply
+    Call,
+    /// Part of the answer to the oldest unanswered call, such as progress or a stream item
+    Interim,
+    /// The final answer to the oldest unanswered call
+    Reply,
+    /// A message that neither expects nor gives an answer
+    Notification,
+    /// The last message its sender will send
+    Goodbye,
+}
+
+/// A message whose role in the protocol is known
+pub trait ProtocolMessage {
+    /// Returns the role of this message
+    fn kind(&self) -> MessageKind;
+}
+
+/// A breach of the protocol rules observed by a [`CheckedConnection`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Violation {
+    /// A reply or interim message arrived while no call was outstanding
+    #[error("received {0:?} message with no call outstanding")]
+    Unsolicited(MessageKind),
+    /// The service sent a call, which clients don't answer
+    #[error("received a call from the service")]
+    UnexpectedCall,
+    /// The connection closed before every call was answered
+    #[error("connection closed with {0} unanswered call(s)")]
+    Unanswered(usize),
+    /// A message arrived after the service said goodbye
+    #[error("received a message after goodbye")]
+    ReceivedAfterGoodbye,
+    /// A message was sent after the client said goodbye
+    #[error("sent a message after goodbye")]
+    SentAfterGoodbye,
+}
+
+/// An [`IpcConnection`] that checks the traffic it carries against the protocol rules
+///
+/// Only messages passing through [`CheckedConnection::send`], [`CheckedConnection::recv`]
+/// and [`CheckedConnection::try_recv`] are checked.
+pub struct CheckedConnection<S, R, C = JsonCodec> {
+    connection: IpcConnection<S, R, C>,
+    outstanding: usize,
+    sent_goodbye: bool,
+    received_goodbye: bool,
+    panic: bool,
+    violations: Vec<Violation>,
+}
+
+impl<S, R, C> CheckedConnection<S, R, C>
+where
+    S: serde::Serialize + ProtocolMessage,
+    R: serde::de::DeserializeOwned + ProtocolMessage,
+    C: Codec,
+{
+    /// Starts checking the traffic on `connection`
+    pub fn new(connection: IpcConnection<S, R, C>) -> Self {
+        Self {
+            connection,
+            outstanding: 0,
+            sent_goodbye: false,
+            received_goodbye: false,
+            panic: cfg!(debug_assertions),
+            violations: vec![],
+        }
+    }
+
+    /// Sets whether a violation panics, rather than only being logged and recorded
+    pub fn set_panic(&mut self, panic: bool) {
+        self.panic = panic;
+    }
+
+    /// Returns the violations observed so far
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Stops checking, returning the underlying connection
+    pub fn into_inner(self) -> IpcConnection<S, R, C> {
+        self.connection
+    }
+
+    /// Sends a message, checking it may still be sent
+    pub fn send(&mut self, message: &S) -> Result<(), IpcError> {
+        if self.sent_goodbye {
+            self.violation(Violation::SentAfterGoodbye);
+        }
+        self.connection.send(message)?;
+        match message.kind() {
+            MessageKind::Call => self.outstanding += 1,
+            MessageKind::Goodbye => self.sent_goodbye = true,
+            MessageKind::Interim | MessageKind::Reply | MessageKind::Notification => {}
+        }
+        Ok(())
+    }
+
+    /// Receives a message, checking it is one the service may send
+    pub fn recv(&mut self) -> Result<Option<R>, IpcError> {
+        let message = self.connection.recv()?;
+        self.observe(message.as_ref());
+        Ok(message)
+    }
+
+    /// Receives a message without blocking, checking it is one the service may send
+    pub fn try_recv(&mut self) -> Result<Option<R>, IpcError> {
+        let message = self.connection.try_recv()?;
+        if let Some(message) = &message {
+            self.observe(Some(message));
+        }
+        Ok(message)
+    }
+
+    /// Checks a received message, or the end of the connection if `None`
+    fn observe(&mut self, message: Option<&R>) {
+        let Some(message) = message else {
+            if self.outstanding > 0 {
+                self.violation(Violation::Unanswered(self.outstanding));
+                self.outstanding = 0;
+            }
+            return;
+        };
+
+        if self.received_goodbye {
+            self.violation(Violation::ReceivedAfterGoodbye);
+        }
+        match message.kind() {
+            MessageKind::Call => self.violation(Violation::UnexpectedCall),
+            kind @ (MessageKind::Interim | MessageKind::Reply) if self.outstanding == 0 => {
+                self.violation(Violation::Unsolicited(kind))
+            }
+            MessageKind::Reply => self.outstanding -= 1,
+            MessageKind::Goodbye => {
+                self.received_goodbye = true;
+                if self.outstanding > 0 {
+                    self.violation(Violation::Unanswered(self.outstanding));
+                    self.outstanding = 0;
+                }
+            }
+            MessageKind::Interim | MessageKind::Notification => {}
+        }
+    }
+
+    fn violation(&mut self, violation: Violation) {
+        self.violations.push(violation.clone());
+        if self.panic {
+            panic!("protocol violation: {violation}");
+        }
+        log::error!("protocol violation: {violation}");
+    }
+}
+
+impl<S, R, C> Deref for CheckedConnection<S, R, C> {
+    type Target = IpcConnection<S, R, C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.connection
+    }
+}
+
+impl<S, R, C> DerefMut for CheckedConnection<S, R, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.connection
+    }
+}
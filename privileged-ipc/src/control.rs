@@ -0,0 +1,261 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Control frames exchanged below the message layer
+//!
+//! With [`Framing::LengthPrefixed`], a length header with its top bit set, which
+//! no real message can have, is a control frame carrying no payload. Control
+//! frames never reach the application and are neither timestamped nor sealed by
+//! a cipher. They carry [heartbeats](crate::heartbeat), the close handshake of
+//! [`IpcConnection::close`] and [queue updates](crate::JobQueue). Usage reports
+//! are the one kind followed by a payload, of fixed size.
+//!
+//! Closing by shutting down the write half and waiting for end of file leaves the
+//! peer unable to tell a finished client from a crashed one, and a server that
+//! shuts down once it reads end of file can cut off responses still being written.
+//! The handshake makes both explicit: the closing side sends a close frame and
+//! keeps receiving, the peer handles every message that preceded it and sends its
+//! responses, then acknowledges, and only then do both sides shut down.
+
+use std::{
+    io,
+    net::Shutdown,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    usage::USAGE_LEN, Codec, ConnectionState, Framing, IpcConnection, IpcError, Queued,
+    ResourceUsage, FRAME_HEADER_LEN,
+};
+
+/// Set in the length header of control frames
+const CONTROL_FLAG: u32 = 1 << 31;
+
+/// Asks the peer to prove it is still responsive
+pub(crate) const PING: u32 = CONTROL_FLAG | 1;
+
+/// Answers a ping
+pub(crate) const PONG: u32 = CONTROL_FLAG | 2;
+
+/// Announces that no more messages will be sent
+const CLOSE: u32 = CONTROL_FLAG | 3;
+
+/// Confirms that every response to messages before a close frame has been sent
+pub(crate) const CLOSE_ACK: u32 = CONTROL_FLAG | 4;
+
+/// Reports the sender's resource usage, in the payload that follows
+pub(crate) const USAGE: u32 = CONTROL_FLAG | 5;
+
+/// Marks a queue update, whose remaining bits hold the position and estimated wait
+const QUEUED: u32 = CONTROL_FLAG | 1 << 30;
+
+/// Largest queue position an update can hold
+const MAX_POSITION: u32 = (1 << 14) - 1;
+
+/// Estimated wait in seconds meaning there is no estimate
+const UNKNOWN_WAIT: u32 = 0xffff;
+
+/// Encodes a queue update as a control frame
+///
+/// The position takes the 14 bits above the low 16, which hold the estimated
+/// wait in whole seconds, rounded up.
+pub(crate) fn queued(update: Queued) -> u32 {
+    let wait = match update.est_wait {
+        Some(wait) => {
+            let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            secs.min(u64::from(UNKNOWN_WAIT - 1)) as u32
+        }
+        None => UNKNOWN_WAIT,
+    };
+    QUEUED | update.position.min(MAX_POSITION) << 16 | wait
+}
+
+fn decode_queued(frame: u32) -> Queued {
+    let wait = frame & 0xffff;
+    Queued {
+        position: (frame >> 16) & MAX_POSITION,
+        est_wait: (wait != UNKNOWN_WAIT).then(|| Duration::from_secs(wait.into())),
+    }
+}
+
+/// Progress of the close handshake
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Closing {
+    #[default]
+    Open,
+    /// The peer sent a close frame and awaits our acknowledgement
+    PeerRequested,
+    /// The peer acknowledged our close frame
+    Acknowledged,
+    /// The handshake finished, or the connection was closed without one
+    Closed,
+}
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Closes the connection once both sides have finished, returning any messages still in flight
+    ///
+    /// On the side that calls this first, a close frame is sent and messages keep
+    /// being received until the peer acknowledges it, so responses to requests
+    /// already sent aren't lost. The peer sees [`IpcConnection::recv`] return
+    /// `Ok(None)` after the last message sent before the close frame, and calls
+    /// this in turn to acknowledge once it has sent its responses. Dropping the
+    /// peer's connection acknowledges as well.
+    ///
+    /// Fails with [`IpcError::ConnectionClosed`] if the peer disconnects without
+    /// acknowledging, as responses may have been cut off. Without
+    /// [`Framing::LengthPrefixed`] there is no handshake, and this shuts down the
+    /// write half of the connection and receives until the peer closes its own.
+    pub fn close(&mut self) -> Result<Vec<R>, IpcError> {
+        let mut awaiting_ack = false;
+        self.enter_state(ConnectionState::Draining);
+        self.flush()?;
+        match self.closing {
+            Closing::Open if !self.eof => {
+                if self.framing == Framing::LengthPrefixed {
+                    self.write_control(CLOSE)?;
+                    awaiting_ack = true;
+                } else {
+                    self.transport.shutdown(Shutdown::Write)?;
+                }
+            }
+            Closing::PeerRequested => self.write_control(CLOSE_ACK)?,
+            Closing::Open | Closing::Acknowledged | Closing::Closed => {}
+        }
+
+        let mut pending = vec![];
+        while let Some(message) = self.recv()? {
+            pending.push(message);
+        }
+        let acknowledged = !awaiting_ack || self.closing == Closing::Acknowledged;
+        self.closing = Closing::Closed;
+        // The peer may already be gone, which is fine
+        let _ = self.transport.shutdown(Shutdown::Both);
+        self.enter_state(ConnectionState::Closed);
+        if !acknowledged {
+            return Err(IpcError::ConnectionClosed);
+        }
+        Ok(pending)
+    }
+
+    /// Pings the peer and waits up to `timeout` for its answer, returning the round trip time
+    ///
+    /// Only works with [`Framing::LengthPrefixed`], and before the peer sends
+    /// anything unprompted: a message arriving ahead of the answer fails the ping
+    /// and stays buffered for the next receive. Fails with
+    /// [`IpcError::DeadlineExceeded`] if the peer doesn't answer in time.
+    pub fn ping(&mut self, timeout: Duration) -> Result<Duration, IpcError> {
+        if self.framing != Framing::LengthPrefixed {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "pings need length-prefixed framing",
+            )
+            .into());
+        }
+        let sent = Instant::now();
+        let deadline = sent + timeout;
+        let answered = self.pongs + 1;
+        self.write_control(PING)?;
+        loop {
+            self.take_hello()?;
+            self.take_control_frames();
+            if self.pongs >= answered {
+                return Ok(sent.elapsed());
+            }
+            if self.has_buffered_message() {
+                return Err(io::Error::other("a message arrived ahead of the pong").into());
+            }
+            if self.eof {
+                return Err(IpcError::ConnectionClosed);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(IpcError::DeadlineExceeded);
+            }
+            let Some(fd) = self.transport.poll_fd() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "deadlines not supported by this transport",
+                )
+                .into());
+            };
+            if crate::wait_readable(fd, remaining)? {
+                self.read_chunk()?;
+            }
+        }
+    }
+
+    /// Handles any control frames at the front of the buffer
+    pub(crate) fn take_control_frames(&mut self) {
+        // Control frames can only follow the peer's hello
+        if self.framing != Framing::LengthPrefixed || self.handshake.awaiting_peer() {
+            return;
+        }
+        while let Some(header) = self.buffer.first_chunk::<FRAME_HEADER_LEN>() {
+            let header = u32::from_be_bytes(*header);
+            if header & CONTROL_FLAG == 0 {
+                break;
+            }
+            if header == USAGE {
+                let Some(payload) = self.buffer[FRAME_HEADER_LEN..].first_chunk::<USAGE_LEN>()
+                else {
+                    // The rest of the report has yet to arrive
+                    break;
+                };
+                let usage = ResourceUsage::decode(payload);
+                self.buffer.drain(..FRAME_HEADER_LEN + USAGE_LEN);
+                self.take_fds(FRAME_HEADER_LEN + USAGE_LEN);
+                self.settle_pool();
+                self.usage_reported(usage);
+                continue;
+            }
+            self.buffer.drain(..FRAME_HEADER_LEN);
+            self.take_fds(FRAME_HEADER_LEN);
+            self.settle_pool();
+            match header {
+                // A peer that can't be answered is gone, which the next read reports
+                PING => {
+                    let _ = self.write_control(PONG);
+                }
+                PONG => self.pongs += 1,
+                // Nothing follows either of these, so reading can stop
+                CLOSE => {
+                    self.closing = Closing::PeerRequested;
+                    self.eof = true;
+                    self.enter_state(ConnectionState::Draining);
+                }
+                CLOSE_ACK => {
+                    self.closing = Closing::Acknowledged;
+                    self.eof = true;
+                }
+                update if update & QUEUED == QUEUED => {
+                    if let Some(observer) = self.queue_observer.as_mut() {
+                        observer(decode_queued(update));
+                    }
+                }
+                unknown => log::debug!("ignoring unknown control frame {unknown:#x}"),
+            }
+        }
+    }
+
+    pub(crate) fn write_control(&mut self, frame: u32) -> Result<(), IpcError> {
+        self.write_frames(&frame.to_be_bytes(), &[])
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Returns true if the buffer starts with a control frame still missing its payload
+    pub(crate) fn partial_control_frame(&self) -> bool {
+        self.framing == Framing::LengthPrefixed
+            && !self.handshake.awaiting_peer()
+            && self
+                .buffer
+                .first_chunk::<FRAME_HEADER_LEN>()
+                .is_some_and(|header| u32::from_be_bytes(*header) & CONTROL_FLAG != 0)
+    }
+}
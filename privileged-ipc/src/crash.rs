@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Postmortems of helpers that failed
+//!
+//! A helper that crashes usually only leaves the client with a closed
+//! connection. Every launched helper is reaped by a thread in the client, which
+//! also passes its stderr through and keeps the tail of it. Once the helper
+//! exits, the thread has how it ended along with that tail, which
+//! [`IpcClient::wait`] turns into a [`CrashReport`] for the user to file.
+//!
+//! Under pkexec the listener takes the place of stderr, so the pipe is handed
+//! over on stdout instead and [`service_init`](crate::service_init) moves it
+//! back. Such a helper's stdout ends up in the tail as well.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{self, Read, Write},
+    os::{fd::OwnedFd, unix::process::ExitStatusExt},
+    process::Child,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use crate::{transport, Codec, IpcClient, IpcConnection, IpcError, ServiceConnection};
+
+/// How much of the end of a helper's stderr is kept
+const STDERR_TAIL: usize = 64 * 1024;
+
+/// What went wrong with a helper that exited unsuccessfully
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrashReport {
+    /// The helper's exit code, unless it was killed by a signal
+    pub code: Option<i32>,
+    /// The signal that killed the helper
+    pub signal: Option<i32>,
+    /// The last 64 KiB the helper wrote to stderr
+    pub stderr: Vec<u8>,
+    /// The last frames received from the helper, oldest first
+    ///
+    /// Only recorded after [`IpcConnection::set_frame_history`].
+    pub frames: Vec<Vec<u8>>,
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.signal, self.code) {
+            (Some(signal), _) => write!(f, "helper was killed by signal {signal}")?,
+            (None, Some(code)) => write!(f, "helper exited with code {code}")?,
+            (None, None) => f.write_str("helper exited abnormally")?,
+        }
+        if !self.stderr.is_empty() {
+            write!(f, "\n{}", String::from_utf8_lossy(&self.stderr).trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// The thread reaping a helper
+#[derive(Debug)]
+pub(crate) struct Supervisor {
+    pid: u32,
+    /// Yields how the helper ended, once it has
+    thread: Option<JoinHandle<io::Result<CrashReport>>>,
+    /// Set once the helper has been waited on
+    finished: Option<Option<CrashReport>>,
+    /// The client's end of the helper's stdin, if it was launched over a socket
+    control: Option<Arc<File>>,
+}
+
+impl Supervisor {
+    /// Supervises `child`, passing through and keeping the tail of `stderr` if it was captured
+    ///
+    /// `control` is kept open until the supervisor is dropped.
+    pub(crate) fn spawn(
+        mut child: Child,
+        stderr: Option<OwnedFd>,
+        control: Option<File>,
+    ) -> io::Result<Self> {
+        let pid = child.id();
+        let thread = thread::Builder::new()
+            .name("privileged-ipc-supervisor".into())
+            .spawn(move || {
+                let stderr = stderr.map(pass_through).unwrap_or_default();
+                let status = child.wait()?;
+                Ok(CrashReport {
+                    code: status.code(),
+                    signal: status.signal(),
+                    stderr,
+                    frames: vec![],
+                })
+            })?;
+        Ok(Self {
+            pid,
+            thread: Some(thread),
+            finished: None,
+            control: control.map(Arc::new),
+        })
+    }
+
+    /// The process id of the helper, which pkexec keeps when it runs it
+    pub(crate) fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// The client's end of the helper's stdin, see [`dump_on_request`](crate::dump_on_request)
+    pub(crate) fn control(&self) -> Option<Arc<File>> {
+        self.control.clone()
+    }
+
+    /// Waits for the helper to exit, returning a report unless it succeeded
+    fn wait(&mut self) -> io::Result<Option<CrashReport>> {
+        if let Some(report) = &self.finished {
+            return Ok(report.clone());
+        }
+        let Some(thread) = self.thread.take() else {
+            return Ok(None);
+        };
+        let report = thread
+            .join()
+            .map_err(|_| io::Error::other("helper supervisor panicked"))??;
+        let report = (report.code != Some(0)).then_some(report);
+        self.finished = Some(report.clone());
+        Ok(report)
+    }
+}
+
+impl ServiceConnection {
+    /// Waits for the helper to exit, returning what went wrong unless it succeeded
+    ///
+    /// A connection to a service that was already running has no helper of its
+    /// own, and returns `None` straight away.
+    pub fn wait(&mut self) -> Result<Option<CrashReport>, crate::Error> {
+        match self.supervisor.as_mut() {
+            Some(supervisor) => Ok(supervisor.wait()?),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<S, R, C> IpcClient<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Waits for the helper to exit, returning what went wrong unless it succeeded
+    ///
+    /// Intended for once the connection has closed unexpectedly: a helper that is
+    /// still running is waited on until it exits. The report includes the frames
+    /// recorded by [`IpcConnection::set_frame_history`]. Clients of a service
+    /// that was already running get `None` straight away.
+    pub fn wait(&mut self) -> Result<Option<CrashReport>, IpcError> {
+        let Some(supervisor) = self.supervisor.as_mut() else {
+            return Ok(None);
+        };
+        let report = supervisor.wait().map_err(crate::Error::from)?;
+        Ok(report.map(|report| CrashReport {
+            frames: self.connection.recent_frames(),
+            ..report
+        }))
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Keeps the last `frames` frames received, for [`IpcClient::wait`] to report
+    ///
+    /// Frames are kept as they arrived, encoded and sealed. They may contain
+    /// whatever the peer sends, such as credentials, so this is off by default
+    /// and `0` turns it off again.
+    pub fn set_frame_history(&mut self, frames: usize) {
+        self.history_len = frames;
+        while self.history.len() > frames {
+            self.history.pop_front();
+        }
+    }
+
+    /// Returns the frames kept since [`IpcConnection::set_frame_history`], oldest first
+    pub fn recent_frames(&self) -> Vec<Vec<u8>> {
+        self.history.iter().cloned().collect()
+    }
+
+    /// Records a received frame, if frames are being kept
+    pub(crate) fn remember_frame(&mut self, len: usize) {
+        if self.history_len == 0 {
+            return;
+        }
+        if self.history.len() == self.history_len {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.buffer[..len].to_vec());
+    }
+}
+
+/// Passes the helper's stderr through until it closes, returning the tail of it
+///
+/// Best effort, as the client's own stderr may well be gone.
+fn pass_through(stderr: OwnedFd) -> Vec<u8> {
+    let mut stderr = File::from(stderr);
+    let mut tail = VecDeque::<u8>::with_capacity(STDERR_TAIL);
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = match stderr.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        let _ = transport::suppress_sigpipe(|| io::stderr().write_all(&chunk[..n]));
+        tail.extend(&chunk[..n]);
+        let excess = tail.len().saturating_sub(STDERR_TAIL);
+        tail.drain(..excess);
+    }
+    tail.into()
+}
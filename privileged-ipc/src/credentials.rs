@@ -0,0 +1,215 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Peer credentials of connected Unix domain sockets
+
+use std::{
+    io::{self, IoSliceMut},
+    mem,
+    os::fd::{AsRawFd, BorrowedFd},
+    ptr,
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    poll::{PollFd, PollFlags, PollTimeout},
+    unistd::{Gid, Pid, Uid},
+};
+
+use crate::{IpcConnection, IpcError};
+
+/// How long [`IpcConnection::expect_peer_uid`] waits for the peer to send something
+const SENDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Credentials of the process on the other end of a connection, as recorded by the kernel
+///
+/// These are captured when the connection is established, so they describe the
+/// peer as it was at connect time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// Process ID of the peer
+    pub pid: Pid,
+    /// Effective user ID of the peer
+    pub uid: Uid,
+    /// Effective group ID of the peer
+    pub gid: Gid,
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Fails with [`IpcError::UnexpectedPeerUid`] unless the peer sending to us runs as `expected`
+    ///
+    /// A client launching its helper through [`PkexecExecutor`](crate::PkexecExecutor)
+    /// expects root, and checking proves escalation took place before trusting
+    /// what the helper answers. `SO_PEERCRED` can't tell, as it records whoever
+    /// created the listener, which was the client itself, so this checks the
+    /// credentials the kernel attaches to the first bytes the peer sent instead.
+    /// Unless those were received already, that waits up to 30 seconds for the
+    /// peer to send something, such as the answer to the first request, which is
+    /// left to be received as usual.
+    ///
+    /// Only works on Unix sockets. Fails with [`IpcError::ConnectionClosed`] if
+    /// the peer leaves without sending anything, and [`IpcError::DeadlineExceeded`]
+    /// if it sends nothing in time. Connections other than those of a
+    /// [`ServiceConnection`](crate::ServiceConnection) must check before receiving
+    /// anything, as the kernel only passes credentials along once asked to.
+    pub fn expect_peer_uid(&self, expected: Uid) -> Result<(), IpcError> {
+        let credentials = match self.sender {
+            Some(credentials) => Some(credentials),
+            None if !self.buffer.is_empty() => {
+                return Err(IpcError::Io(io::Error::other(
+                    "the bytes already received carry no credentials of the peer",
+                )))
+            }
+            None => {
+                let fd = self.transport.poll_fd().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "only Unix sockets carry the credentials of the peer",
+                    )
+                })?;
+                sender_credentials(fd, Instant::now() + SENDER_TIMEOUT)?
+            }
+        };
+        let uid = credentials.ok_or(IpcError::ConnectionClosed)?.uid;
+        if uid != expected {
+            log::warn!(target: "privileged_ipc::audit", "🚨 peer runs as uid {uid}, not the expected uid {expected}");
+            return Err(IpcError::UnexpectedPeerUid { uid, expected });
+        }
+        Ok(())
+    }
+}
+
+/// Queries `SO_PEERCRED` for the given socket
+pub(crate) fn peer_credentials(fd: BorrowedFd<'_>) -> io::Result<PeerCredentials> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    // SAFETY: `cred` and `len` are valid for writes and `len` matches the size of `cred`
+    let ret = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(PeerCredentials {
+        pid: Pid::from_raw(cred.pid),
+        uid: Uid::from_raw(cred.uid),
+        gid: Gid::from_raw(cred.gid),
+    })
+}
+
+/// Returns the credentials of the sender of the next bytes waiting on the socket, leaving them unread
+///
+/// Waits for the peer to send something until `deadline`, returning `Ok(None)`
+/// if it leaves first. `SO_PASSCRED` is left set, so that the reads that follow
+/// are passed credentials as well.
+fn sender_credentials(
+    fd: BorrowedFd<'_>,
+    deadline: Instant,
+) -> Result<Option<PeerCredentials>, IpcError> {
+    set_pass_credentials(fd, true)?;
+    let mut byte = [0u8; 1];
+    // SAFETY: CMSG_SPACE only computes a size
+    let control_len = unsafe { libc::CMSG_SPACE(mem::size_of::<libc::ucred>() as u32) };
+    let mut control = vec![0u8; control_len as usize];
+
+    let mut iov = [IoSliceMut::new(&mut byte)];
+    // SAFETY: an all-zero msghdr is valid, and the fields set point at live buffers
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = iov.as_mut_ptr().cast();
+    msg.msg_iovlen = iov.len();
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len();
+
+    let received = loop {
+        // SAFETY: `msg` and everything it points to outlive the call
+        let received = unsafe { libc::recvmsg(fd.as_raw_fd(), &mut msg, libc::MSG_PEEK) };
+        if received >= 0 {
+            break received;
+        }
+        let error = io::Error::last_os_error();
+        match error.kind() {
+            io::ErrorKind::Interrupted => {}
+            // The socket is non-blocking, so wait for the peer here
+            io::ErrorKind::WouldBlock => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(IpcError::DeadlineExceeded);
+                }
+                let timeout = PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX);
+                let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+                match nix::poll::poll(&mut fds, timeout) {
+                    Ok(_) | Err(Errno::EINTR) => {}
+                    Err(e) => return Err(io::Error::from(e).into()),
+                }
+            }
+            _ => return Err(error.into()),
+        }
+    };
+    if received == 0 {
+        return Ok(None);
+    }
+
+    // SAFETY: the kernel filled in `msg_controllen` bytes of well formed headers
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&msg);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET
+                && (*header).cmsg_type == libc::SCM_CREDENTIALS
+            {
+                let cred = ptr::read_unaligned(libc::CMSG_DATA(header).cast::<libc::ucred>());
+                if let Some(credentials) = from_ucred(cred) {
+                    return Ok(Some(credentials));
+                }
+            }
+            header = libc::CMSG_NXTHDR(&msg, header);
+        }
+    }
+    Err(IpcError::Io(io::Error::other(
+        "the peer sent something before it could be passed its credentials",
+    )))
+}
+
+/// Converts credentials passed with `SCM_CREDENTIALS`, unless the bytes were sent without any
+///
+/// Bytes sent before `SO_PASSCRED` was set are passed pid 0 and the overflow
+/// uid and gid in place of their sender's.
+pub(crate) fn from_ucred(cred: libc::ucred) -> Option<PeerCredentials> {
+    (cred.pid != 0).then(|| PeerCredentials {
+        pid: Pid::from_raw(cred.pid),
+        uid: Uid::from_raw(cred.uid),
+        gid: Gid::from_raw(cred.gid),
+    })
+}
+
+/// Sets `SO_PASSCRED`, which has the kernel pass the sender's credentials along with what is read
+pub(crate) fn set_pass_credentials(fd: BorrowedFd<'_>, enabled: bool) -> io::Result<()> {
+    let value = libc::c_int::from(enabled);
+    // SAFETY: `value` is valid for reads of the size given
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
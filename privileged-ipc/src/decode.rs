@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Pure decoding of received bytes
+//!
+//! [`IpcConnection`](crate::IpcConnection) locates and decodes frames with these
+//! functions, which need no socket. They are exposed so that downstream projects
+//! can fuzz and property test the parsing a privileged helper performs on
+//! untrusted input, for example:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     let _ = decode_message::<Request, _>(data, Framing::LengthPrefixed, &JsonCodec);
+//! });
+//! ```
+
+use std::ops::Range;
+
+use serde::de::DeserializeOwned;
+
+use crate::{Codec, Framing, IpcError, StreamScan, FRAME_HEADER_LEN};
+
+/// Largest header block accepted by [`Framing::ContentLength`]
+const MAX_HEADER_BLOCK_LEN: usize = 1024;
+
+/// A complete frame found at the start of a buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Location of the encoded message within the buffer
+    pub payload: Range<usize>,
+    /// Length of the whole frame, which is where the next frame starts
+    pub len: usize,
+}
+
+/// Locates the first complete frame in `buffer`
+///
+/// Returns `Ok(None)` if more data is needed. The extent of a frame is found
+/// without decoding its payload, except with [`Framing::Stream`] where `codec`
+/// must delimit messages itself.
+pub fn decode_frame<C: Codec>(
+    buffer: &[u8],
+    framing: Framing,
+    codec: &C,
+) -> Result<Option<Frame>, IpcError> {
+    resume_frame(buffer, framing, codec, &mut StreamScan::default())
+}
+
+/// Locates the first complete frame like [`decode_frame`], carrying on from where `scan` stopped
+///
+/// Framings that are searched for the end of a frame record how far they got in
+/// `scan`, so that each byte is looked at once however many reads a frame takes.
+/// `scan` must come from earlier calls on the same buffer, with nothing taken
+/// from its front since.
+pub(crate) fn resume_frame<C: Codec>(
+    buffer: &[u8],
+    framing: Framing,
+    codec: &C,
+    scan: &mut StreamScan,
+) -> Result<Option<Frame>, IpcError> {
+    match framing {
+        Framing::LengthPrefixed | Framing::ContentLength => Ok(frame_header(buffer, framing)?
+            .filter(|(_, end)| buffer.len() >= *end)
+            .map(|(payload, end)| Frame {
+                payload: payload..end,
+                len: end,
+            })),
+        Framing::Ndjson => Ok(line(buffer, scan)),
+        Framing::NulTerminated => {
+            let Some(end) = buffer[scan.offset..].iter().position(|b| *b == 0) else {
+                scan.offset = buffer.len();
+                return Ok(None);
+            };
+            let end = scan.offset + end;
+            Ok(Some(Frame {
+                payload: 0..end,
+                len: end + 1,
+            }))
+        }
+        Framing::Stream => Ok(codec.resume_message_len(buffer, scan)?.map(|end| Frame {
+            payload: 0..end,
+            len: end,
+        })),
+    }
+}
+
+/// Decodes the first complete message in `buffer`, along with the length of its frame
+///
+/// Returns `Ok(None)` if more data is needed. Frames are decoded as received without
+/// encryption, and without the size limit a connection applies.
+pub fn decode_message<R: DeserializeOwned, C: Codec>(
+    buffer: &[u8],
+    framing: Framing,
+    codec: &C,
+) -> Result<Option<(R, usize)>, IpcError> {
+    let Some(frame) = decode_frame(buffer, framing, codec)? else {
+        return Ok(None);
+    };
+    let message = codec.decode(&buffer[frame.payload])?;
+    Ok(Some((message, frame.len)))
+}
+
+/// Parses the header of the first frame, for framings that declare the payload length
+///
+/// Returns the offset of the payload and the offset just past the frame, once the
+/// whole header has arrived.
+pub(crate) fn frame_header(
+    buffer: &[u8],
+    framing: Framing,
+) -> Result<Option<(usize, usize)>, IpcError> {
+    match framing {
+        Framing::LengthPrefixed => Ok(buffer.first_chunk::<FRAME_HEADER_LEN>().map(|header| {
+            (
+                FRAME_HEADER_LEN,
+                FRAME_HEADER_LEN + u32::from_be_bytes(*header) as usize,
+            )
+        })),
+        Framing::ContentLength => content_length_header(buffer),
+        Framing::Stream | Framing::Ndjson | Framing::NulTerminated => Ok(None),
+    }
+}
+
+/// Parses an LSP-style header block from the front of the buffer
+fn content_length_header(buffer: &[u8]) -> Result<Option<(usize, usize)>, IpcError> {
+    let malformed = |reason: &str| IpcError::Codec(format!("malformed header: {reason}").into());
+
+    let searched = &buffer[..buffer.len().min(MAX_HEADER_BLOCK_LEN)];
+    let Some(header_len) = searched.windows(4).position(|w| w == b"\r\n\r\n") else {
+        if searched.len() == MAX_HEADER_BLOCK_LEN {
+            return Err(malformed("header block too long"));
+        }
+        return Ok(None);
+    };
+    let headers = std::str::from_utf8(&buffer[..header_len])
+        .map_err(|_| malformed("headers are not valid UTF-8"))?;
+
+    let mut content_length = None;
+    for header in headers.split("\r\n") {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| malformed("header has no value"))?;
+        if name.trim().eq_ignore_ascii_case("Content-Length") {
+            let len = value
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| malformed("invalid Content-Length"))?;
+            content_length = Some(len);
+        }
+    }
+    let content_length = content_length.ok_or_else(|| malformed("missing Content-Length"))?;
+
+    let payload = header_len + 4;
+    Ok(Some((payload, payload.saturating_add(content_length))))
+}
+
+/// Locates the first non-blank line in the buffer, excluding its line ending, carrying on from `scan`
+fn line(buffer: &[u8], scan: &mut StreamScan) -> Option<Frame> {
+    // Once the line is known to start somewhere, `state` holds one past that offset
+    let start = match scan.state.checked_sub(1) {
+        Some(start) => start as usize,
+        None => {
+            let Some(start) = buffer[scan.offset..]
+                .iter()
+                .position(|b| !matches!(b, b'\r' | b'\n'))
+            else {
+                scan.offset = buffer.len();
+                return None;
+            };
+            scan.offset + start
+        }
+    };
+    let searched = scan.offset.max(start);
+    let Some(newline) = buffer[searched..].iter().position(|b| *b == b'\n') else {
+        *scan = StreamScan {
+            offset: buffer.len(),
+            state: start as u64 + 1,
+        };
+        return None;
+    };
+    let newline = searched + newline;
+    let payload_end = match buffer[newline - 1] {
+        b'\r' => newline - 1,
+        _ => newline,
+    };
+    Some(Frame {
+        payload: start..payload_end,
+        len: newline + 1,
+    })
+}
@@ -0,0 +1,74 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! File descriptors passed within messages
+//!
+//! Some answers are an open file rather than data, such as a filesystem the
+//! helper mounted in a namespace of its own, whose path means nothing to the
+//! client. A [`Descriptor`] is passed the same way as a [`Blob`](crate::Blob):
+//! only its index is encoded into the message, and the descriptor itself goes
+//! alongside the frame with `SCM_RIGHTS`.
+//!
+//! Nothing is checked about what a received descriptor refers to, so receivers
+//! must not trust it further than they trust the peer. Descriptors can only be
+//! sent over Unix socket transports.
+
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::blob;
+
+/// An open file descriptor that is passed to the peer rather than encoded
+#[derive(Debug)]
+pub struct Descriptor(OwnedFd);
+
+impl Descriptor {
+    /// Wraps `fd` for sending
+    pub fn new(fd: impl Into<OwnedFd>) -> Self {
+        Self(fd.into())
+    }
+
+    /// Returns the descriptor
+    pub fn into_inner(self) -> OwnedFd {
+        self.0
+    }
+}
+
+impl From<OwnedFd> for Descriptor {
+    fn from(fd: OwnedFd) -> Self {
+        Self(fd)
+    }
+}
+
+impl From<Descriptor> for OwnedFd {
+    fn from(descriptor: Descriptor) -> Self {
+        descriptor.0
+    }
+}
+
+impl AsFd for Descriptor {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl Serialize for Descriptor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let index = blob::pass_fd(self.0.as_raw_fd()).ok_or_else(|| {
+            ser::Error::custom("a Descriptor can only be sent over an IpcConnection")
+        })?;
+        index.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Descriptor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let index = usize::deserialize(deserializer)?;
+        let fd = blob::take_passed_fd(index).ok_or_else(|| {
+            de::Error::custom("Descriptor refers to a file descriptor that wasn't passed")
+        })?;
+        Ok(Self(fd))
+    }
+}
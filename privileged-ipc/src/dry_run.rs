@@ -0,0 +1,120 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Showing what launching a helper would run, without running it
+//!
+//! Whatever runs under pkexec runs as root, so packagers and reviewers want to
+//! know exactly what that is. [`ServiceConnection::dry_run`] resolves the command
+//! an executor would spawn, the changes made to the environment it inherits and
+//! the descriptors it is handed, without binding a socket or forking.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
+    fmt,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    escalation_fd, launch_command, watches_escalation, AddressIdentifier, AddressScheme,
+    ServiceConnection, SocketExecutor,
+};
+
+/// What launching a helper would run, see [`ServiceConnection::dry_run`]
+///
+/// Displays as a report suited for review.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpawnPlan {
+    /// The program that would be run, resolved through `PATH`
+    pub program: PathBuf,
+    /// The arguments it would be given, not including the program itself
+    pub args: Vec<OsString>,
+    /// Variables set in the inherited environment, or removed from it if `None`
+    pub env: Vec<(OsString, Option<OsString>)>,
+    /// What each descriptor handed to the program would be, by number
+    pub fds: BTreeMap<i32, String>,
+}
+
+impl ServiceConnection {
+    /// Returns what [`ServiceConnection::new_with_scheme`] would run, without running it
+    ///
+    /// The address is made up afresh, so an anonymous or prefixed one won't match
+    /// that of a later launch. Whether an escalating executor is watched for
+    /// authorization depends on the hooks set at the time.
+    pub fn dry_run<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        scheme: &AddressScheme,
+    ) -> SpawnPlan {
+        let exec = T::default();
+        let command = launch_command(&exec, executable, args);
+
+        let mut fds = BTreeMap::from([
+            (0, "stdin, a pipe for requesting a dump".to_owned()),
+            (1, "stdout, inherited".to_owned()),
+            (2, "stderr, a pipe kept for crash reports".to_owned()),
+        ]);
+        // Arranged after the standard streams, replacing them if need be
+        fds.insert(
+            exec.child_fd(),
+            format!("a socket listening on @{}", AddressIdentifier::new(scheme)),
+        );
+        if watches_escalation(&exec) {
+            fds.insert(
+                escalation_fd(&exec),
+                "a pipe reporting when authorization is done".to_owned(),
+            );
+        }
+
+        SpawnPlan {
+            program: resolve(command.get_program()),
+            args: command.get_args().map(OsStr::to_owned).collect(),
+            env: command
+                .get_envs()
+                .map(|(name, value)| (name.to_owned(), value.map(OsStr::to_owned)))
+                .collect(),
+            fds,
+        }
+    }
+}
+
+/// Finds `program` on `PATH` the way spawning does, leaving it as it is if it isn't there
+fn resolve(program: &OsStr) -> PathBuf {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.to_owned();
+    }
+    env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(path))
+        .find(|candidate| {
+            candidate
+                .metadata()
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+        .unwrap_or_else(|| path.to_owned())
+}
+
+impl fmt::Display for SpawnPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program.display())?;
+        for arg in &self.args {
+            write!(f, " {arg:?}")?;
+        }
+        writeln!(f)?;
+        for (name, value) in &self.env {
+            match value {
+                Some(value) => writeln!(f, "  set {}={value:?}", name.to_string_lossy())?,
+                None => writeln!(f, "  unset {}", name.to_string_lossy())?,
+            }
+        }
+        for (fd, what) in &self.fds {
+            writeln!(f, "  fd {fd}: {what}")?;
+        }
+        Ok(())
+    }
+}
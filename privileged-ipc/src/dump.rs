@@ -0,0 +1,137 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Postmortems of helpers that stopped answering
+//!
+//! A [heartbeat](crate::IpcConnection::set_heartbeat) tells the client that its
+//! helper is wedged, but not where. With [`IpcClient::dump_on_unresponsive`],
+//! giving up on the helper also asks it to dump what each of its threads is
+//! doing to its log, and to abort, leaving a core dump and a [`CrashReport`](crate::CrashReport)
+//! whose stderr ends with the dump.
+//!
+//! The request goes over the helper's stdin rather than the socket, which the
+//! wedged helper isn't reading, and rather than as a signal, which a client
+//! can't send to a helper running as root under pkexec. The client keeps its end
+//! of stdin open for as long as the helper runs. A helper opts in by calling
+//! [`dump_on_request`] early, which answers from a thread of its own.
+//!
+//! Without an unwinder the dump can't include the userspace stacks of other
+//! threads. It has each thread's name, state, the syscall it is in and where
+//! the kernel has it waiting, along with its kernel stack when the helper may
+//! read it, as root can. The core dump has the rest.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    mem::ManuallyDrop,
+    os::fd::{FromRawFd, OwnedFd},
+    sync::Arc,
+    thread,
+};
+
+use crate::{transport, Codec, IpcClient, IpcConnection};
+
+/// Asks the helper on the other end of its stdin for a dump
+const DUMP_REQUEST: u8 = b'D';
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Calls `hook` when the connection gives up on its peer, just before failing with [`IpcError::PeerUnresponsive`](crate::IpcError::PeerUnresponsive)
+    ///
+    /// Replaces any previous hook. Only a [heartbeat](IpcConnection::set_heartbeat)
+    /// gives up on a peer this way.
+    pub fn on_unresponsive(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.unresponsive_hook = Some(Box::new(hook));
+    }
+}
+
+impl<S, R, C> IpcClient<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Asks the helper to dump its threads and abort once it stops answering heartbeats
+    ///
+    /// The helper must call [`dump_on_request`] to take part. Afterwards
+    /// [`IpcClient::wait`] returns a report with the dump at the end of its stderr.
+    /// Returns false, doing nothing, if this client didn't launch its helper
+    /// over a socket.
+    pub fn dump_on_unresponsive(&mut self) -> bool {
+        let Some(control) = self.supervisor.as_ref().and_then(|s| s.control()) else {
+            return false;
+        };
+        self.on_unresponsive(move || request_dump(&control));
+        true
+    }
+}
+
+/// Writes a dump request to the helper's stdin
+///
+/// Best effort, as the helper may have closed its stdin without ever dumping.
+fn request_dump(control: &Arc<File>) {
+    log::warn!("🩻 asking the unresponsive helper to dump its threads");
+    if let Err(e) = transport::suppress_sigpipe(|| control.as_ref().write_all(&[DUMP_REQUEST])) {
+        log::warn!("🩻 couldn't ask the helper for a dump: {e}");
+    }
+}
+
+/// Dumps what every thread is doing and aborts when the client finds the helper unresponsive
+///
+/// Reads requests from stdin on a thread of its own, so it must be called after
+/// [`read_spawn_key`](crate::read_spawn_key), if the helper reads a key, and
+/// stdin mustn't be used for anything else. The thread returns quietly once the
+/// client has gone. See [`IpcClient::dump_on_unresponsive`].
+pub fn dump_on_request() -> io::Result<()> {
+    thread::Builder::new()
+        .name("privileged-ipc-dump".into())
+        .spawn(|| {
+            // SAFETY: stdin is open in a spawned helper, and only borrowed here
+            let stdin = ManuallyDrop::new(File::from(unsafe { OwnedFd::from_raw_fd(0) }));
+            let mut requests = [0; 16];
+            loop {
+                match (&*stdin).read(&mut requests) {
+                    Ok(0) => return,
+                    Ok(n) if requests[..n].contains(&DUMP_REQUEST) => {
+                        dump_threads();
+                        std::process::abort();
+                    }
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => return,
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Logs the name, state, syscall, wait channel and kernel stack of each thread
+fn dump_threads() {
+    log::error!("🩻 the client found this helper unresponsive, dumping its threads");
+    let Ok(tasks) = fs::read_dir("/proc/self/task") else {
+        return;
+    };
+    for task in tasks.flatten() {
+        let path = task.path();
+        let read = |name: &str| fs::read_to_string(path.join(name)).ok();
+        let tid = task.file_name().to_string_lossy().into_owned();
+        let name = read("comm").unwrap_or_default();
+        // The state follows the name, which may itself contain ")"
+        let state = read("stat")
+            .and_then(|stat| stat.rsplit_once(')')?.1.trim().chars().next())
+            .unwrap_or('?');
+        let syscall = read("syscall").unwrap_or_default();
+        let syscall = syscall.split_whitespace().next().unwrap_or("?");
+        let wchan = read("wchan").unwrap_or_default();
+        let stack = read("stack").unwrap_or_default();
+        log::error!(
+            "🩻 thread {tid} ({}): state {state}, syscall {syscall}, waiting in {}\n{}",
+            name.trim(),
+            match wchan.as_str() {
+                "" | "0" => "nothing",
+                wchan => wchan,
+            },
+            stack.trim_end(),
+        );
+    }
+}
@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Named, persistent service endpoints
+//!
+//! An [`Endpoint`] is a well-known socket path such as `$XDG_RUNTIME_DIR/moss/ipc.sock`.
+//! Unlike the one-shot sockets used by [`ServiceConnection::new`](crate::ServiceConnection::new),
+//! it outlives the first connection, so clients can reach a helper that is already
+//! running instead of launching one each time.
+
+use std::{
+    env, fs, io,
+    os::unix::{fs::FileTypeExt, net::UnixStream},
+    path::PathBuf,
+};
+
+use crate::{path_socket, PathSocket};
+
+/// File name extension of endpoint sockets
+const EXTENSION: &str = "sock";
+
+/// A named socket a service can be reached at across connections
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    socket: PathSocket,
+}
+
+impl Endpoint {
+    /// The default endpoint of `app`, at `<runtime dir>/<app>/ipc.sock`
+    pub fn new(app: &str) -> Self {
+        Self::named(app, "ipc")
+    }
+
+    /// The endpoint `name` of `app`, at `<runtime dir>/<app>/<name>.sock`
+    ///
+    /// The runtime directory is `$XDG_RUNTIME_DIR`, falling back to
+    /// `/run/user/<uid>`, or `/run` for root.
+    pub fn named(app: &str, name: &str) -> Self {
+        Self::from_socket(
+            PathSocket::new(runtime_dir().join(app)).name(format!("{name}.{EXTENSION}")),
+        )
+    }
+
+    /// An endpoint at an explicit location, such as a system-wide socket under `/run`
+    pub fn from_socket(socket: PathSocket) -> Self {
+        Self { socket }
+    }
+
+    /// Lists the endpoints of `app` that have a service listening on them
+    ///
+    /// Stale sockets left behind by services that are gone are skipped, but not
+    /// removed; see [`Endpoint::cleanup`].
+    pub fn discover(app: &str) -> io::Result<Vec<Self>> {
+        let directory = runtime_dir().join(app);
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut endpoints = vec![];
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            // Dotfiles are sockets still being bound
+            if name.starts_with('.') || !name.ends_with(&format!(".{EXTENSION}")) {
+                continue;
+            }
+            if !entry.file_type()?.is_socket() {
+                continue;
+            }
+            let endpoint = Self::from_socket(PathSocket::new(&directory).name(name));
+            if endpoint.is_running() {
+                endpoints.push(endpoint);
+            }
+        }
+        endpoints.sort_by_key(Endpoint::path);
+        Ok(endpoints)
+    }
+
+    /// The socket the endpoint is bound with, and its permissions
+    pub fn socket(&self) -> &PathSocket {
+        &self.socket
+    }
+
+    /// The full path of the endpoint
+    pub fn path(&self) -> PathBuf {
+        self.socket.path()
+    }
+
+    /// Returns whether a service is listening on the endpoint
+    ///
+    /// This is checked by connecting, so the service sees one connection that closes
+    /// without sending anything.
+    pub fn is_running(&self) -> bool {
+        UnixStream::connect(self.path()).is_ok()
+    }
+
+    /// Removes the endpoint if the service that was listening on it is gone
+    ///
+    /// Returns whether a stale socket was removed. An endpoint that is still in use
+    /// is left alone.
+    pub fn cleanup(&self) -> io::Result<bool> {
+        let path = self.path();
+        if fs::symlink_metadata(&path).is_err() {
+            return Ok(false);
+        }
+        match path_socket::remove_stale(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Directory for the invoking user's runtime files, such as sockets
+pub(crate) fn runtime_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    let uid = nix::unistd::getuid();
+    if uid.is_root() {
+        PathBuf::from("/run")
+    } else {
+        PathBuf::from(format!("/run/user/{uid}"))
+    }
+}
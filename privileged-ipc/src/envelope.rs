@@ -0,0 +1,220 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Versioned envelopes for protocols that change over time
+//!
+//! A helper installed alongside an older frontend, or the other way around,
+//! fails on the first message it doesn't understand. Wrapping every message in
+//! an [`Envelope`] carries the sender's protocol version and a message id next
+//! to the payload, and an [`EnvelopedConnection`] does the wrapping and
+//! unwrapping, numbering the messages it sends.
+//!
+//! Envelopes ignore fields they don't know, so later versions can add to them.
+//! A payload of [`MaybeKnown`] survives variants and shapes this version doesn't
+//! know either, keeping them as they were received rather than failing the
+//! connection, so a handler can skip them or answer that they are unsupported.
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use serde::{
+    de::{self, DeserializeOwned, IgnoredAny, MapAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{Codec, IpcConnection, IpcError, JsonCodec};
+
+/// A message along with the protocol version of its sender and a message id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope<T> {
+    /// The protocol version the sender speaks
+    pub version: u32,
+    /// Numbers the messages of each sender, starting from 1
+    pub id: u64,
+    /// The message itself
+    pub payload: T,
+}
+
+impl<T> Envelope<T> {
+    /// Wraps `payload`
+    pub fn new(version: u32, id: u64, payload: T) -> Self {
+        Self {
+            version,
+            id,
+            payload,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Envelope<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut envelope = serializer.serialize_struct("Envelope", 3)?;
+        envelope.serialize_field("version", &self.version)?;
+        envelope.serialize_field("id", &self.id)?;
+        envelope.serialize_field("payload", &self.payload)?;
+        envelope.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Envelope<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(
+            "Envelope",
+            &["version", "id", "payload"],
+            EnvelopeVisitor(PhantomData),
+        )
+    }
+}
+
+struct EnvelopeVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for EnvelopeVisitor<T> {
+    type Value = Envelope<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a message envelope")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut version, mut id, mut payload) = (None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" => version = Some(map.next_value()?),
+                "id" => id = Some(map.next_value()?),
+                "payload" => payload = Some(map.next_value()?),
+                // Added by a later version
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Envelope {
+            version: version.ok_or_else(|| de::Error::missing_field("version"))?,
+            id: id.ok_or_else(|| de::Error::missing_field("id"))?,
+            payload: payload.ok_or_else(|| de::Error::missing_field("payload"))?,
+        })
+    }
+}
+
+/// A payload that is either a message this version understands, or kept as it was received
+///
+/// Decoding a `T` that has, say, an unknown variant or a field of the wrong
+/// type gives [`MaybeKnown::Unknown`] with the message as a JSON value,
+/// whatever the codec. Sending it again passes it on unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaybeKnown<T> {
+    /// A message this version understands
+    Known(T),
+    /// A message from a version that isn't understood, as received
+    Unknown(serde_json::Value),
+}
+
+impl<T> MaybeKnown<T> {
+    /// Returns the message, if this version understands it
+    pub fn known(self) -> Option<T> {
+        match self {
+            Self::Known(message) => Some(message),
+            Self::Unknown(_) => None,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for MaybeKnown<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Known(message) => message.serialize(serializer),
+            Self::Unknown(value) => value.serialize(serializer),
+        }
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for MaybeKnown<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        Ok(match T::deserialize(&value) {
+            Ok(message) => Self::Known(message),
+            Err(e) => {
+                log::debug!("📭 keeping a message this version doesn't understand: {e}");
+                Self::Unknown(value)
+            }
+        })
+    }
+}
+
+/// An [`IpcConnection`] that wraps every message it sends in an [`Envelope`], and unwraps those it receives
+pub struct EnvelopedConnection<S, R, C = JsonCodec> {
+    connection: IpcConnection<Envelope<S>, Envelope<R>, C>,
+    version: u32,
+    last_id: u64,
+}
+
+impl<S, R, C> EnvelopedConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Sends messages over `connection` as protocol `version`
+    pub fn new(connection: IpcConnection<Envelope<S>, Envelope<R>, C>, version: u32) -> Self {
+        Self {
+            connection,
+            version,
+            last_id: 0,
+        }
+    }
+
+    /// Returns the protocol version messages are sent as
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Stops wrapping messages, returning the underlying connection
+    pub fn into_inner(self) -> IpcConnection<Envelope<S>, Envelope<R>, C> {
+        self.connection
+    }
+
+    /// Sends a message in the next envelope, returning its id
+    pub fn send(&mut self, message: S) -> Result<u64, IpcError> {
+        let id = self.last_id + 1;
+        self.connection
+            .send(&Envelope::new(self.version, id, message))?;
+        self.last_id = id;
+        Ok(id)
+    }
+
+    /// Receives the next envelope, so the sender's version and message id can be inspected
+    ///
+    /// Returns `Ok(None)` if the connection was closed.
+    pub fn recv(&mut self) -> Result<Option<Envelope<R>>, IpcError> {
+        self.connection.recv()
+    }
+
+    /// Receives the payload of the next envelope, returning `Ok(None)` if the connection was closed
+    pub fn recv_payload(&mut self) -> Result<Option<R>, IpcError> {
+        Ok(self.recv()?.map(|envelope| envelope.payload))
+    }
+
+    /// Receives the next envelope without blocking, returning `Ok(None)` if none is ready
+    pub fn try_recv(&mut self) -> Result<Option<Envelope<R>>, IpcError> {
+        self.connection.try_recv()
+    }
+}
+
+impl<S, R, C> Deref for EnvelopedConnection<S, R, C> {
+    type Target = IpcConnection<Envelope<S>, Envelope<R>, C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.connection
+    }
+}
+
+impl<S, R, C> DerefMut for EnvelopedConnection<S, R, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.connection
+    }
+}
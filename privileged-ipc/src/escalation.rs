@@ -0,0 +1,154 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Notifications around privilege escalation
+//!
+//! pkexec's authentication dialog otherwise appears out of nowhere, with the
+//! application still looking responsive behind it. A hook installed with
+//! [`set_escalation_hook`] is told when a client is about to escalate and how
+//! the attempt ended, so a GUI can explain the prompt first and react to the
+//! user dismissing it. A dialog left unanswered can also be given up on after
+//! [`set_authorization_timeout`].
+//!
+//! pkexec marks every inherited descriptor above stderr close-on-exec, so a pipe
+//! handed to it closes the moment it either runs the helper or gives up. Which of
+//! the two happened is told apart by whether pkexec exits with one of its
+//! authorization failure codes straight afterwards.
+
+use std::{
+    fmt,
+    fs::File,
+    io::Read,
+    os::fd::{AsFd, OwnedFd},
+    process::Child,
+    sync::{Arc, PoisonError, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Exit code of pkexec when the user dismissed the authentication dialog
+const PKEXEC_DISMISSED: i32 = 126;
+
+/// Exit code of pkexec when authorization was refused or couldn't be obtained
+const PKEXEC_NOT_AUTHORIZED: i32 = 127;
+
+/// How long pkexec may take to exit once it has stopped holding the pipe
+const EXIT_GRACE: Duration = Duration::from_millis(100);
+
+type Hook = Arc<dyn Fn(Escalation) + Send + Sync>;
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+static TIMEOUT: RwLock<Option<Duration>> = RwLock::new(None);
+
+/// The progress of a privilege escalation, as reported to the escalation hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalation {
+    /// The helper is about to be launched, which may prompt the user to authenticate
+    ///
+    /// No prompt appears if the user is already authorized, such as when polkit
+    /// remembers a recent authentication.
+    Prompting,
+    /// Authorization was obtained and the helper is running
+    Granted,
+    /// Authorization was refused, or couldn't be obtained
+    Denied,
+    /// The user dismissed the authentication dialog
+    Dismissed,
+    /// The dialog went unanswered for longer than the authorization timeout, and was closed
+    TimedOut,
+}
+
+impl fmt::Display for Escalation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Prompting => "prompting",
+            Self::Granted => "granted",
+            Self::Denied => "denied",
+            Self::Dismissed => "dismissed",
+            Self::TimedOut => "timed out",
+        })
+    }
+}
+
+/// Calls `hook` before and after every privilege escalation by clients in this process
+///
+/// While a hook is installed, creating a client with an escalating executor such
+/// as [`PkexecExecutor`](crate::PkexecExecutor) blocks until the escalation is
+/// resolved, and fails with [`Error::Escalation`](crate::Error::Escalation) if it
+/// was denied or dismissed. The hook runs on the thread creating the client.
+pub fn set_escalation_hook(hook: impl Fn(Escalation) + Send + Sync + 'static) {
+    *HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(hook));
+}
+
+/// Removes the hook installed with [`set_escalation_hook`]
+pub fn clear_escalation_hook() {
+    *HOOK.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Gives up on authentication dialogs that stay unanswered for longer than `timeout`
+///
+/// Creating a client with an escalating executor then fails with
+/// [`Error::AuthorizationTimeout`](crate::Error::AuthorizationTimeout) once the
+/// timeout passes, rather than blocking for as long as the dialog is left
+/// open. pkexec is killed, which closes its dialog. `None` waits indefinitely
+/// again, which is the default.
+pub fn set_authorization_timeout(timeout: Option<Duration>) {
+    *TIMEOUT.write().unwrap_or_else(PoisonError::into_inner) = timeout;
+}
+
+/// Returns how long authentication dialogs are left unanswered before giving up
+pub(crate) fn authorization_timeout() -> Option<Duration> {
+    *TIMEOUT.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns true if a hook wants to hear about escalations
+pub(crate) fn hooked() -> bool {
+    HOOK.read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_some()
+}
+
+/// Reports an escalation event to the hook, if there is one
+pub(crate) fn notify(event: Escalation) {
+    let hook = HOOK.read().unwrap_or_else(PoisonError::into_inner).clone();
+    if let Some(hook) = hook {
+        hook(event);
+    }
+}
+
+/// Waits for pkexec to stop holding `ready`, and works out whether it was authorized
+///
+/// Kills pkexec if that takes longer than `timeout`.
+pub(crate) fn await_authorization(
+    child: &mut Child,
+    ready: OwnedFd,
+    timeout: Option<Duration>,
+) -> Escalation {
+    // Nothing is ever written, so the pipe only becomes readable once pkexec has let go of it
+    if let Some(timeout) = timeout {
+        if let Ok(false) = crate::wait_readable(ready.as_fd(), timeout) {
+            let _ = child.kill();
+            return Escalation::TimedOut;
+        }
+    }
+    let _ = File::from(ready).read(&mut [0u8; 1]);
+
+    let deadline = Instant::now() + EXIT_GRACE;
+    while Instant::now() < deadline {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                return match status.code() {
+                    Some(PKEXEC_DISMISSED) => Escalation::Dismissed,
+                    Some(PKEXEC_NOT_AUTHORIZED) => Escalation::Denied,
+                    // The helper ran, but exited straight away
+                    _ => Escalation::Granted,
+                };
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(5)),
+            Err(_) => break,
+        }
+    }
+    Escalation::Granted
+}
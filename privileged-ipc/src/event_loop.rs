@@ -0,0 +1,213 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Serving every client from a single thread
+//!
+//! [`IpcServer::serve_with`] dedicates a thread to each client, which stops
+//! scaling once a daemon has hundreds of them. [`IpcServer::serve_events`]
+//! instead registers the listener and every accepted connection with one epoll
+//! instance, and reads from whichever are ready without blocking. Messages that
+//! arrive in pieces stay buffered in their connection until they are complete.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::Shutdown,
+    os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    time::Duration,
+};
+
+use crate::{serve::SHUTDOWN_POLL_INTERVAL, Codec, IpcConnection, IpcError, IpcServer, Pollable};
+
+/// How many readiness events are collected per wait
+const MAX_EVENTS: usize = 64;
+
+/// Identifies the listener among the registered descriptors
+const LISTENER: u64 = 0;
+
+impl<S, R, C> IpcServer<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Accepts and serves clients on the calling thread until shutdown is requested
+    ///
+    /// Behaves like [`IpcServer::serve_with`], except that `handler` is called
+    /// on this thread for the messages of every client, so it needn't be `Send`
+    /// and mustn't block. Replies to a client that stops reading would still
+    /// block everyone else, which a [`WritePolicy`](crate::WritePolicy) set with
+    /// [`IpcServer::set_write_policy`] prevents. Once shutdown is requested no new
+    /// clients are accepted, and this returns after the connected ones have left.
+    pub fn serve_events<F>(&self, mut handler: F) -> Result<(), IpcError>
+    where
+        F: FnMut(&mut IpcConnection<S, R, C>, R) -> Result<(), IpcError>,
+    {
+        let epoll = Epoll::new()?;
+        epoll.add(self.listener.as_fd(), LISTENER)?;
+        let mut listening = true;
+        let mut clients = HashMap::new();
+        let mut next_token = LISTENER + 1;
+        let mut events = Vec::with_capacity(MAX_EVENTS);
+
+        while listening || !clients.is_empty() {
+            if listening && self.shutdown.is_shutdown() {
+                epoll.delete(self.listener.as_fd())?;
+                listening = false;
+            }
+            epoll.wait(&mut events, SHUTDOWN_POLL_INTERVAL)?;
+
+            for &token in &events {
+                if token == LISTENER {
+                    if !listening {
+                        continue;
+                    }
+                    let connection = match self.accept() {
+                        Ok(connection) => connection,
+                        Err(
+                            e @ (IpcError::UnauthorizedPeer { .. }
+                            | IpcError::ForeignClient { .. }
+                            | IpcError::TokenMismatch),
+                        ) => {
+                            log::warn!(target: "privileged_ipc::audit", "🚨 {e}");
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let Some(fd) = connection.poll_fd() else {
+                        continue;
+                    };
+                    epoll.add(fd, next_token)?;
+                    clients.insert(next_token, connection);
+                    next_token += 1;
+                    log::trace!("🔌 accepted client connection");
+                    continue;
+                }
+
+                let Some(connection) = clients.get_mut(&token) else {
+                    continue;
+                };
+                match dispatch(connection, &mut handler) {
+                    Ok(true) => continue,
+                    // Acknowledges the client's close frame, if it sent one
+                    Ok(false) => {
+                        if let Err(e) = connection.close() {
+                            log::error!("💥 failed to close client session: {e}");
+                        }
+                    }
+                    Err(e) => {
+                        match e {
+                            // Under multi_client, the token is only checked once the client sends
+                            IpcError::TokenMismatch => {
+                                log::warn!(target: "privileged_ipc::audit", "🚨 {e}")
+                            }
+                            e => log::error!("💥 client session failed: {e}"),
+                        }
+                        // The peer may already be gone, which is fine
+                        let _ = connection.shutdown(Shutdown::Both);
+                    }
+                }
+                if let Some(connection) = clients.remove(&token) {
+                    if let Some(fd) = connection.poll_fd() {
+                        let _ = epoll.delete(fd);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Passes every message a client has sent so far to the handler
+///
+/// Returns false once the client has disconnected.
+fn dispatch<S, R, C, F>(
+    connection: &mut IpcConnection<S, R, C>,
+    handler: &mut F,
+) -> Result<bool, IpcError>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+    F: FnMut(&mut IpcConnection<S, R, C>, R) -> Result<(), IpcError>,
+{
+    loop {
+        match connection.try_recv() {
+            Ok(Some(message)) => handler(connection, message)?,
+            Ok(None) => return Ok(true),
+            Err(IpcError::ConnectionClosed) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// An epoll instance watching descriptors for readability
+struct Epoll(OwnedFd);
+
+impl Epoll {
+    fn new() -> io::Result<Self> {
+        // SAFETY: no pointers are involved
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just created, and nothing else owns it
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Reports `fd` under `token` whenever it is readable or has hung up
+    fn add(&self, fd: BorrowedFd<'_>, token: u64) -> io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: token,
+        };
+        self.control(libc::EPOLL_CTL_ADD, fd, &mut event)
+    }
+
+    fn delete(&self, fd: BorrowedFd<'_>) -> io::Result<()> {
+        // Kernels before 2.6.9 insist on an event even though it is ignored
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        self.control(libc::EPOLL_CTL_DEL, fd, &mut event)
+    }
+
+    fn control(
+        &self,
+        op: libc::c_int,
+        fd: BorrowedFd<'_>,
+        event: &mut libc::epoll_event,
+    ) -> io::Result<()> {
+        // SAFETY: `event` is valid for the duration of the call
+        let result = unsafe { libc::epoll_ctl(self.0.as_raw_fd(), op, fd.as_raw_fd(), event) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for readiness, replacing `tokens` with those of the ready descriptors
+    fn wait(&self, tokens: &mut Vec<u64>, timeout: Duration) -> io::Result<()> {
+        tokens.clear();
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
+        let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+        // SAFETY: `events` has room for `MAX_EVENTS` entries
+        let ready = unsafe {
+            libc::epoll_wait(
+                self.0.as_raw_fd(),
+                events.as_mut_ptr(),
+                MAX_EVENTS as libc::c_int,
+                timeout,
+            )
+        };
+        if ready < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::Interrupted => Ok(()),
+                _ => Err(error),
+            };
+        }
+        tokens.extend(events[..ready as usize].iter().map(|event| event.u64));
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Raw file transfer between messages
+//!
+//! Logs and build artifacts produced on the privileged side can be large, and
+//! encoding them into messages costs both a serde round trip and a copy through
+//! userspace. [`IpcConnection::send_file`] instead writes the file straight into
+//! the socket with `sendfile(2)`, preceded only by its length, and the peer
+//! collects it with [`IpcConnection::recv_file`].
+//!
+//! The contents bypass framing, so the protocol must tell the receiver that a file
+//! follows, typically with a message sent just before it. Files are never
+//! encrypted or authenticated, so both calls fail on connections with a cipher.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    net::Shutdown,
+    num::NonZeroU64,
+    os::{
+        fd::{AsRawFd, BorrowedFd},
+        unix::fs::FileExt,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{transport, Codec, ConnectionState, IpcConnection, IpcError, Transport};
+
+/// Length of the size header sent before a file's contents
+const FILE_HEADER_LEN: usize = 8;
+
+/// Size of the buffer used when a transport can't use `sendfile`
+const COPY_BUFFER_LEN: usize = 64 * 1024;
+
+/// Bounds on how much a throttled transfer sends between pauses
+const MIN_THROTTLED_CHUNK: u64 = 4 * 1024;
+const MAX_THROTTLED_CHUNK: u64 = 1024 * 1024;
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Sends the whole contents of `file`, returning how many bytes were sent
+    ///
+    /// The file is read from its start without moving its position. The peer must
+    /// call [`IpcConnection::recv_file`] before receiving its next message.
+    /// Opens the connection with the hello first if nothing has been sent yet, as
+    /// [`IpcConnection::send`] does.
+    pub fn send_file(&mut self, file: &File) -> Result<u64, IpcError> {
+        self.check_unsealed()?;
+        if let Some(hello) = self.handshake.take_unsent() {
+            self.write_frames(&hello, &[])?;
+        }
+        self.flush()?;
+        let len = file.metadata()?.len();
+        if len > self.max_file_size {
+            return Err(IpcError::FileTooLarge {
+                size: len,
+                limit: self.max_file_size,
+            });
+        }
+
+        let mut throttle = self.transfer_rate.map(Throttle::new);
+        let sent = self.transport.write_all(&len.to_be_bytes()).and_then(|_| {
+            let Some(throttle) = throttle.as_mut() else {
+                return self.transport.send_file(file, 0, len);
+            };
+            let mut offset = 0;
+            while offset < len {
+                let chunk = throttle.chunk_len().min(len - offset);
+                self.transport.send_file(file, offset, chunk)?;
+                offset += chunk;
+                throttle.wait(chunk);
+            }
+            Ok(())
+        });
+        match sent {
+            Ok(()) => Ok(len),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Err(IpcError::ConnectionClosed),
+            Err(e) => Err(IpcError::Io(e)),
+        }
+    }
+
+    /// Receives a file sent with [`IpcConnection::send_file`] into `writer`
+    ///
+    /// Returns the number of bytes received. Fails with [`IpcError::ConnectionClosed`]
+    /// if the peer disconnects before the whole file has arrived. The peer's token
+    /// and hello are checked first if they are still expected, as when receiving a
+    /// message.
+    ///
+    /// A file larger than [`IpcConnection::set_max_file_size`] allows fails with
+    /// [`IpcError::FileTooLarge`] before anything is written, and closes the
+    /// connection, as the stream can't be resynchronised without reading all of it.
+    pub fn recv_file(&mut self, writer: &mut impl Write) -> Result<u64, IpcError> {
+        self.check_unsealed()?;
+
+        while !self.take_token()? || !self.take_hello()? {
+            self.read_more()?;
+        }
+        while self.buffer.len() < FILE_HEADER_LEN {
+            self.read_more()?;
+        }
+        let header = self.buffer[..FILE_HEADER_LEN]
+            .try_into()
+            .expect("header has a fixed length");
+        let len = u64::from_be_bytes(header);
+        if len > self.max_file_size {
+            self.buffer.clear();
+            self.fds.clear();
+            self.eof = true;
+            self.settle_pool();
+            // The peer may already be gone, which is fine
+            let _ = self.transport.shutdown(Shutdown::Both);
+            self.enter_state(ConnectionState::Closed);
+            return Err(IpcError::FileTooLarge {
+                size: len,
+                limit: self.max_file_size,
+            });
+        }
+        self.consume(FILE_HEADER_LEN);
+
+        let mut throttle = self.transfer_rate.map(Throttle::new);
+        let mut remaining = len;
+        loop {
+            let available = self
+                .buffer
+                .len()
+                .min(remaining.try_into().unwrap_or(usize::MAX));
+            writer.write_all(&self.buffer[..available])?;
+            self.consume(available);
+            remaining -= available as u64;
+            if let Some(throttle) = throttle.as_mut() {
+                throttle.wait(available as u64);
+            }
+            if remaining == 0 {
+                break;
+            }
+            self.read_more()?;
+        }
+        writer.flush()?;
+        Ok(len)
+    }
+
+    /// Limits the rate of [`IpcConnection::send_file`] and [`IpcConnection::recv_file`]
+    ///
+    /// Throttling a bulk transfer keeps it from saturating the socket and delaying
+    /// the control messages that follow. A receiver reading slowly also slows the
+    /// sender, once the socket's buffers fill up. `None` removes the limit.
+    pub fn set_transfer_rate(&mut self, bytes_per_second: Option<NonZeroU64>) {
+        self.transfer_rate = bytes_per_second;
+    }
+
+    /// Sets the largest file, in bytes, that may be sent or received, [`DEFAULT_MAX_FILE_SIZE`](crate::DEFAULT_MAX_FILE_SIZE) by default
+    ///
+    /// Bounds how much a peer can make [`IpcConnection::recv_file`] write, which a
+    /// privileged helper receiving files from unprivileged clients relies on.
+    /// Sending a larger file fails without writing anything.
+    pub fn set_max_file_size(&mut self, limit: u64) {
+        self.max_file_size = limit;
+    }
+
+    /// Returns the largest file that may be sent or received
+    pub fn max_file_size(&self) -> u64 {
+        self.max_file_size
+    }
+
+    /// Refuses to transfer files over connections whose frames are sealed
+    fn check_unsealed(&self) -> Result<(), IpcError> {
+        if self.cipher.is_some() {
+            return Err(IpcError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "files can't be transferred over a connection with a cipher",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reads another chunk into the buffer, failing once the peer has closed the connection
+    fn read_more(&mut self) -> Result<(), IpcError> {
+        if self.eof {
+            return Err(IpcError::ConnectionClosed);
+        }
+        self.read_chunk()?;
+        Ok(())
+    }
+
+    /// Discards the first `len` bytes of the buffer, along with any descriptors sent with them
+    fn consume(&mut self, len: usize) {
+        self.buffer.drain(..len);
+        self.take_fds(len);
+        self.settle_pool();
+    }
+}
+
+/// Paces a transfer to a fixed number of bytes per second
+struct Throttle {
+    rate: NonZeroU64,
+    started: Instant,
+    transferred: u64,
+}
+
+impl Throttle {
+    fn new(rate: NonZeroU64) -> Self {
+        Self {
+            rate,
+            started: Instant::now(),
+            transferred: 0,
+        }
+    }
+
+    /// Bytes to send at once, about a tenth of a second's worth
+    fn chunk_len(&self) -> u64 {
+        (self.rate.get() / 10).clamp(MIN_THROTTLED_CHUNK, MAX_THROTTLED_CHUNK)
+    }
+
+    /// Records `bytes` as transferred, sleeping until the rate allows for them
+    fn wait(&mut self, bytes: u64) {
+        self.transferred += bytes;
+        let due = Duration::from_secs_f64(self.transferred as f64 / self.rate.get() as f64);
+        if let Some(early) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(early);
+        }
+    }
+}
+
+/// Writes `len` bytes of `file` from `start` to `socket` with `sendfile(2)`
+///
+/// Falls back to copying through userspace if the file can't be used with
+/// `sendfile`, such as when it is a pipe.
+pub(crate) fn sendfile(
+    socket: BorrowedFd<'_>,
+    file: &File,
+    start: u64,
+    len: u64,
+) -> io::Result<()> {
+    transport::suppress_sigpipe(|| sendfile_unguarded(socket, file, start, len))
+}
+
+fn sendfile_unguarded(socket: BorrowedFd<'_>, file: &File, start: u64, len: u64) -> io::Result<()> {
+    let end = start + len;
+    let mut offset = start as libc::off_t;
+    while (offset as u64) < end {
+        let count = (end - offset as u64).min(isize::MAX as u64) as usize;
+        // SAFETY: both descriptors are open, and `offset` is valid for reads and writes
+        let sent =
+            unsafe { libc::sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut offset, count) };
+        match sent {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n if n > 0 => {}
+            _ => {
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) => {}
+                    Some(libc::EINVAL | libc::ENOSYS) if offset as u64 == start => {
+                        let mut socket = File::from(socket.try_clone_to_owned()?);
+                        return copy_file(&mut socket, file, start, len);
+                    }
+                    _ => return Err(error),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes `len` bytes of `file` from `start` by reading them into memory
+pub(crate) fn copy_file(
+    writer: &mut impl Write,
+    file: &File,
+    start: u64,
+    len: u64,
+) -> io::Result<()> {
+    let mut buffer = vec![0u8; COPY_BUFFER_LEN];
+    let end = start + len;
+    let mut offset = start;
+    while offset < end {
+        let want = (end - offset).min(buffer.len() as u64) as usize;
+        let n = file.read_at(&mut buffer[..want], offset)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        writer.write_all(&buffer[..n])?;
+        offset += n as u64;
+    }
+    writer.flush()
+}
+
+/// Adapts a transport to [`Write`] for [`copy_file`]
+pub(crate) struct TransportWriter<'a, T: ?Sized>(pub(crate) &'a mut T);
+
+impl<T: Transport + ?Sized> Write for TransportWriter<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Agreeing on the protocol version before any message is exchanged
+//!
+//! A client and helper built from different versions of a tool otherwise only
+//! notice when a message fails to deserialize, often well into a session. Once
+//! both sides call [`IpcConnection::set_protocol`], each opens the connection
+//! with a hello carrying the application's protocol version and the version of
+//! this library, ahead of its first frame. Receiving checks the peer's hello
+//! before anything else, failing with [`IpcError::ProtocolMismatch`] if the
+//! protocols differ or the peer sent none.
+//!
+//! The hello also advertises the [`Capabilities`] each side set with
+//! [`IpcConnection::set_capabilities`], and those both advertised are what
+//! [`IpcConnection::capabilities`] returns afterwards, so either side can fall
+//! back when the other lacks a feature rather than failing halfway through.
+//!
+//! The hello is written raw, whatever the framing, and is neither timestamped
+//! nor sealed by a cipher. A side that receives the peer's hello before sending
+//! anything answers with its own straight away, so a mismatch is reported on
+//! both ends rather than as a closed connection on one of them.
+
+use std::{
+    fmt,
+    net::Shutdown,
+    ops::{BitAnd, BitOr},
+};
+
+use crate::{Codec, ConnectionState, IpcConnection, IpcError};
+
+/// Opens every hello
+const MAGIC: [u8; 4] = *b"PIPC";
+
+/// The magic, the library's major and minor version, the protocol version and the capabilities
+const HELLO_LEN: usize = 16;
+
+/// Features a side of a connection supports, advertised in the hello
+///
+/// The low 16 bits are features of this library, and [`Capabilities::custom`]
+/// names the high 16 for the application, such as alternative codecs or
+/// optional requests.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// Passing file descriptors, as [`Blob`](crate::Blob)s need
+    pub const FD_PASSING: Self = Self(1 << 0);
+    /// Sealing frames with a [`FrameCipher`](crate::FrameCipher)
+    pub const ENCRYPTION: Self = Self(1 << 1);
+    /// Timestamped frames, see [`IpcConnection::set_timestamps`]
+    pub const TIMESTAMPS: Self = Self(1 << 2);
+    /// Resource usage reports, see [`IpcConnection::set_usage_reports`]
+    pub const USAGE_REPORTS: Self = Self(1 << 3);
+    /// Queue position updates, see [`JobQueue`](crate::JobQueue)
+    pub const QUEUE_UPDATES: Self = Self(1 << 4);
+    /// Answering with a stream of messages, see [`IpcConnection::send_stream`]
+    pub const STREAMING: Self = Self(1 << 5);
+
+    /// No capabilities at all
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The application-defined capability numbered `index`, which must be below 16
+    pub const fn custom(index: u32) -> Self {
+        assert!(index < 16, "there are 16 application-defined capabilities");
+        Self(1 << (16 + index))
+    }
+
+    /// Returns true if every capability in `other` is also in `self`
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if there are no capabilities
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The capabilities as they go on the wire
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Capabilities as they went on the wire, including any this version doesn't know
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let named = [
+            (Self::FD_PASSING, "FD_PASSING"),
+            (Self::ENCRYPTION, "ENCRYPTION"),
+            (Self::TIMESTAMPS, "TIMESTAMPS"),
+            (Self::USAGE_REPORTS, "USAGE_REPORTS"),
+            (Self::QUEUE_UPDATES, "QUEUE_UPDATES"),
+            (Self::STREAMING, "STREAMING"),
+        ];
+        let mut set = f.debug_set();
+        let mut rest = self.0;
+        for (capability, name) in named {
+            if self.contains(capability) {
+                set.entry(&format_args!("{name}"));
+                rest &= !capability.0;
+            }
+        }
+        for bit in (0..32).filter(|bit| rest & 1 << bit != 0) {
+            match bit {
+                16.. => set.entry(&format_args!("custom({})", bit - 16)),
+                _ => set.entry(&format_args!("unknown({bit})")),
+            };
+        }
+        set.finish()
+    }
+}
+
+/// Progress of the hello exchange
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Handshake {
+    /// The protocol this side speaks, if it takes part in the exchange
+    protocol: Option<u32>,
+    /// Set once this side's hello has been written
+    sent: bool,
+    /// The protocol the peer announced, once its hello has been received
+    peer: Option<u32>,
+    /// What this side advertises
+    advertised: Capabilities,
+    /// What both sides advertised, once the peer's hello has been received
+    negotiated: Capabilities,
+}
+
+impl Handshake {
+    /// Returns the hello to write ahead of the first frame, if it is still due
+    pub(crate) fn take_unsent(&mut self) -> Option<[u8; HELLO_LEN]> {
+        let protocol = self.protocol.filter(|_| !self.sent)?;
+        self.sent = true;
+        let mut hello = [0u8; HELLO_LEN];
+        hello[..4].copy_from_slice(&MAGIC);
+        hello[4..6].copy_from_slice(&library_version(env!("CARGO_PKG_VERSION_MAJOR")));
+        hello[6..8].copy_from_slice(&library_version(env!("CARGO_PKG_VERSION_MINOR")));
+        hello[8..12].copy_from_slice(&protocol.to_be_bytes());
+        hello[12..].copy_from_slice(&self.advertised.bits().to_be_bytes());
+        Some(hello)
+    }
+
+    /// Returns true while the peer's hello has yet to be received
+    pub(crate) fn awaiting_peer(&self) -> bool {
+        self.protocol.is_some() && self.peer.is_none()
+    }
+}
+
+fn library_version(component: &str) -> [u8; 2] {
+    component.parse::<u16>().unwrap_or_default().to_be_bytes()
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Opens the connection with a hello announcing `protocol`, and requires one from the peer
+    ///
+    /// Must be called on both sides before anything is sent or received. The
+    /// protocol version is defined by the application, and is usually bumped
+    /// whenever its message types change incompatibly.
+    pub fn set_protocol(&mut self, protocol: u32) {
+        self.handshake.protocol = Some(protocol);
+    }
+
+    /// Returns the protocol version the peer announced, once its hello has been received
+    pub fn peer_protocol(&self) -> Option<u32> {
+        self.handshake.peer
+    }
+
+    /// Sets the capabilities to advertise in the hello, none by default
+    ///
+    /// Only takes effect along with [`IpcConnection::set_protocol`], before the
+    /// hello is sent. Advertising a capability is a promise to handle it, and
+    /// doesn't enable anything by itself.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.handshake.advertised = capabilities;
+    }
+
+    /// Returns the capabilities both sides advertised
+    ///
+    /// Empty until the peer's hello has been received, as
+    /// [`IpcConnection::peer_protocol`] tells.
+    pub fn capabilities(&self) -> Capabilities {
+        self.handshake.negotiated
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Takes the peer's hello from the front of the buffer, returning false while it is incomplete
+    pub(crate) fn take_hello(&mut self) -> Result<bool, IpcError> {
+        if !self.handshake.awaiting_peer() {
+            return Ok(true);
+        }
+        let available = self.buffer.len().min(MAGIC.len());
+        let greeted = self.buffer[..available] == MAGIC[..available];
+        if greeted && self.buffer.len() < HELLO_LEN {
+            return Ok(false);
+        }
+
+        let expected = self.handshake.protocol.unwrap_or_default();
+        let found = match self.buffer.first_chunk::<HELLO_LEN>() {
+            Some(&[_, _, _, _, m0, m1, n0, n1, p0, p1, p2, p3, c0, c1, c2, c3]) if greeted => {
+                let protocol = u32::from_be_bytes([p0, p1, p2, p3]);
+                let (major, minor) = (u16::from_be_bytes([m0, m1]), u16::from_be_bytes([n0, n1]));
+                let capabilities = Capabilities::from_bits(u32::from_be_bytes([c0, c1, c2, c3]));
+                log::trace!(
+                    "🤝 peer speaks protocol {protocol} with privileged-ipc {major}.{minor}, supporting {capabilities:?}"
+                );
+                self.handshake.negotiated = self.handshake.advertised & capabilities;
+                Some(protocol)
+            }
+            _ => None,
+        };
+        if found.is_some() {
+            self.buffer.drain(..HELLO_LEN);
+            self.take_fds(HELLO_LEN);
+            self.settle_pool();
+        }
+        // Lets the peer tell what went wrong too, and proceed if nothing did
+        if let Some(hello) = self.handshake.take_unsent() {
+            let _ = self.write_frames(&hello, &[]);
+        }
+        if found == Some(expected) {
+            self.handshake.peer = found;
+            return Ok(true);
+        }
+
+        self.buffer.clear();
+        self.fds.clear();
+        self.eof = true;
+        // The peer may already be gone, which is fine
+        let _ = self.transport.shutdown(Shutdown::Both);
+        self.enter_state(ConnectionState::Closed);
+        Err(IpcError::ProtocolMismatch { expected, found })
+    }
+}
@@ -0,0 +1,134 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Liveness detection for long-lived connections
+//!
+//! A helper that deadlocks or is stopped keeps its socket open, so a client
+//! blocked in [`IpcConnection::recv`] would wait on it forever. With a heartbeat
+//! enabled, a connection that hears nothing from its peer for an interval sends it
+//! a ping, and gives up with [`IpcError::PeerUnresponsive`] once too many pings in
+//! a row went unanswered.
+//!
+//! Pings and pongs are [control frames](crate::control), which every
+//! length-prefixed connection answers while receiving, so only the side wanting
+//! to detect a wedged peer needs to enable a heartbeat. As a peer only
+//! answers while it is receiving, the interval times the threshold must exceed the
+//! longest it may spend handling a request without sending anything.
+
+use std::{
+    net::Shutdown,
+    time::{Duration, Instant},
+};
+
+use crate::{control, Codec, ConnectionState, Framing, IpcConnection, IpcError};
+
+/// Heartbeat settings and progress of a connection
+pub(crate) struct Heartbeat {
+    interval: Duration,
+    max_missed: u32,
+    /// Pings sent since anything was last heard from the peer
+    missed: u32,
+    /// When the peer was last heard from, or the last ping was sent
+    last_event: Instant,
+}
+
+impl Heartbeat {
+    /// Records that something arrived from the peer
+    pub(crate) fn heard(&mut self) {
+        self.missed = 0;
+        self.last_event = Instant::now();
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Pings the peer after `interval` of silence, failing after `max_missed` unanswered pings
+    ///
+    /// Anything received from the peer counts as a sign of life, not just pongs.
+    /// Heartbeats are checked while receiving, so a connection that is only sending
+    /// never fails this way. Enabling them switches the connection to
+    /// [`Framing::LengthPrefixed`], which the peer must use as well.
+    pub fn set_heartbeat(&mut self, interval: Duration, max_missed: u32) {
+        self.heartbeat = Some(Heartbeat {
+            interval,
+            max_missed,
+            missed: 0,
+            last_event: Instant::now(),
+        });
+        self.framing = Framing::LengthPrefixed;
+    }
+
+    /// Stops pinging the peer, which is the default
+    ///
+    /// The connection still answers the peer's pings.
+    pub fn clear_heartbeat(&mut self) {
+        self.heartbeat = None;
+    }
+
+    /// Waits until the transport is readable, pinging the peer while it stays silent
+    ///
+    /// Returns straight away without a heartbeat, or if the transport can't be polled,
+    /// leaving the following read to block.
+    pub(crate) fn wait_for_peer(&mut self) -> Result<(), IpcError> {
+        while let Some(timeout) = self.heartbeat_tick()? {
+            let readable = match self.transport.poll_fd() {
+                Some(fd) => crate::wait_readable(fd, timeout)?,
+                None => return Ok(()),
+            };
+            if readable {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends whatever periodic control frames are due, returning the time until the next check
+    ///
+    /// Fails once the peer has let too many pings go unanswered.
+    pub(crate) fn heartbeat_tick(&mut self) -> Result<Option<Duration>, IpcError> {
+        let report = self.usage_tick()?;
+        let ping = self.ping_tick()?;
+        Ok(match (report, ping) {
+            (Some(report), Some(ping)) => Some(report.min(ping)),
+            (report, ping) => report.or(ping),
+        })
+    }
+
+    /// Pings the peer if it has been silent for an interval, returning the time until the next check
+    fn ping_tick(&mut self) -> Result<Option<Duration>, IpcError> {
+        let Some(heartbeat) = self.heartbeat.as_mut() else {
+            return Ok(None);
+        };
+        let due = heartbeat.last_event + heartbeat.interval;
+        let now = Instant::now();
+        if now < due {
+            return Ok(Some(due - now));
+        }
+        if heartbeat.missed >= heartbeat.max_missed {
+            return Err(self.unresponsive());
+        }
+        heartbeat.missed += 1;
+        heartbeat.last_event = now;
+        let interval = heartbeat.interval;
+        self.write_control(control::PING)?;
+        Ok(Some(interval))
+    }
+
+    /// Terminates the connection because the peer stopped answering pings
+    fn unresponsive(&mut self) -> IpcError {
+        log::warn!("💔 peer missed too many heartbeats, closing connection");
+        if let Some(hook) = self.unresponsive_hook.as_mut() {
+            hook();
+        }
+        self.eof = true;
+        // A wedged peer can't object to being disconnected
+        let _ = self.transport.shutdown(Shutdown::Both);
+        self.enter_state(ConnectionState::Closed);
+        IpcError::PeerUnresponsive
+    }
+}
@@ -0,0 +1,296 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Limiting how many requests run at once, and telling the rest where they stand
+//!
+//! Some operations can't run concurrently, such as two transactions on one
+//! package database. A daemon guards them with a [`JobQueue`], and clients whose
+//! requests have to wait would otherwise see nothing but a spinner. While a
+//! request waits in [`JobQueue::acquire`], its client is sent control frames
+//! with its position in the queue and an estimate of the wait, derived from how
+//! long recent jobs took, which arrive at the observer set with
+//! [`IpcConnection::set_queue_observer`].
+//!
+//! Updates are control frames, so both peers must use
+//! [`Framing::LengthPrefixed`]; with any other framing requests still queue,
+//! just without updates. Each update fits a control frame's header: positions
+//! beyond 16383 and waits beyond about 18 hours are reported as those limits.
+//!
+//! Requests waiting for a job are ordered by their [`Priority`], so one the user
+//! is waiting on goes ahead of background work queued before it. Clients tag
+//! their requests with a priority, which serializes as a plain string, and the
+//! handler passes it to [`JobQueue::acquire_with_priority`].
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{control, Codec, Framing, IpcConnection, IpcError};
+
+/// How often a waiting client is reminded of its position by default
+const DEFAULT_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Weight of the most recent job in the estimate of how long jobs take
+const ESTIMATE_WEIGHT: f64 = 0.25;
+
+/// Where a request stands in the queue of the peer, see [`JobQueue`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Queued {
+    /// How many requests will start before this one does, counting itself
+    ///
+    /// `0` means the request has left the queue and started.
+    pub position: u32,
+    /// How long the peer expects the request to wait, if it has an idea yet
+    pub est_wait: Option<Duration>,
+}
+
+/// How urgently a request's job should run, see [`JobQueue::acquire_with_priority`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Started at the user's request, who is waiting for it to finish
+    #[default]
+    Interactive,
+    /// Maintenance nobody is waiting on, such as refreshing metadata
+    Background,
+}
+
+impl Priority {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Background => "background",
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Priority {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Priority {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match name.as_str() {
+            "interactive" => Ok(Self::Interactive),
+            "background" => Ok(Self::Background),
+            other => Err(de::Error::unknown_variant(
+                other,
+                &["interactive", "background"],
+            )),
+        }
+    }
+}
+
+/// Admits a limited number of jobs at a time, queueing the rest by priority, then in order of arrival
+///
+/// Clones share the same queue, so one can be moved into the handler of every client.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    shared: Arc<Shared>,
+    update_interval: Duration,
+}
+
+#[derive(Debug)]
+struct Shared {
+    slots: usize,
+    state: Mutex<State>,
+    /// Signalled whenever a job finishes, or a request is queued ahead of others
+    finished: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    running: usize,
+    /// Priorities and tickets of the requests waiting, first in line at the front
+    waiting: VecDeque<(Priority, u64)>,
+    next_ticket: u64,
+    /// Moving average of how long jobs took
+    average: Option<Duration>,
+}
+
+/// A job admitted by [`JobQueue::acquire`], making room for the next once dropped
+#[derive(Debug)]
+pub struct Job {
+    shared: Arc<Shared>,
+    started: Instant,
+}
+
+impl JobQueue {
+    /// Creates a queue running at most `concurrency` jobs at once, and at least one
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                slots: concurrency.max(1),
+                state: Mutex::default(),
+                finished: Condvar::new(),
+            }),
+            update_interval: DEFAULT_UPDATE_INTERVAL,
+        }
+    }
+
+    /// Sets how often waiting clients are sent their position even if it hasn't changed, every second by default
+    pub fn update_interval(mut self, interval: Duration) -> Self {
+        self.update_interval = interval;
+        self
+    }
+
+    /// Returns how many jobs are running and how many requests are waiting
+    pub fn load(&self) -> (usize, usize) {
+        let state = self.shared.lock();
+        (state.running, state.waiting.len())
+    }
+
+    /// Waits for the job of a request received on `connection` to be admitted
+    ///
+    /// Blocks the calling thread, so suits the handlers of
+    /// [`IpcServer::serve_with`](crate::IpcServer::serve_with) rather than an
+    /// event loop. Until the job is admitted the client is kept up to date with
+    /// [`Queued`] updates, then told it left the queue. Fails if an update
+    /// can't be sent, giving up the place in the queue. The job is
+    /// [`Priority::Interactive`].
+    pub fn acquire<S, R, C>(&self, connection: &mut IpcConnection<S, R, C>) -> Result<Job, IpcError>
+    where
+        S: serde::Serialize,
+        R: serde::de::DeserializeOwned,
+        C: Codec,
+    {
+        self.acquire_with_priority(connection, Priority::default())
+    }
+
+    /// Waits for a job of `priority` to be admitted, see [`JobQueue::acquire`]
+    ///
+    /// The job is queued behind every waiting job of the same or a more urgent
+    /// priority, and ahead of the rest. Jobs already running aren't interrupted,
+    /// and a steady stream of interactive jobs keeps background ones waiting.
+    pub fn acquire_with_priority<S, R, C>(
+        &self,
+        connection: &mut IpcConnection<S, R, C>,
+        priority: Priority,
+    ) -> Result<Job, IpcError>
+    where
+        S: serde::Serialize,
+        R: serde::de::DeserializeOwned,
+        C: Codec,
+    {
+        let notify = connection.framing() == Framing::LengthPrefixed;
+        let mut state = self.shared.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        let place = state
+            .waiting
+            .partition_point(|&(waiting, _)| waiting <= priority);
+        state.waiting.insert(place, (priority, ticket));
+        if place + 1 < state.waiting.len() {
+            // Those queued behind have moved back a place
+            self.shared.finished.notify_all();
+        }
+
+        let mut reported = None;
+        let mut last_update = Instant::now();
+        loop {
+            let ahead = state
+                .waiting
+                .iter()
+                .position(|&(_, waiting)| waiting == ticket)
+                .unwrap_or_default();
+            if ahead == 0 && state.running < self.shared.slots {
+                break;
+            }
+
+            let position = ahead + 1;
+            let due = last_update.elapsed() >= self.update_interval;
+            if notify && (reported != Some(position) || due) {
+                // Rounds of jobs that have to finish before this one starts
+                let rounds = (ahead + state.running + 1)
+                    .saturating_sub(self.shared.slots)
+                    .div_ceil(self.shared.slots)
+                    .max(1) as u32;
+                let update = Queued {
+                    position: position.try_into().unwrap_or(u32::MAX),
+                    est_wait: state.average.map(|average| average.saturating_mul(rounds)),
+                };
+                drop(state);
+                let sent = connection.write_control(control::queued(update));
+                state = self.shared.lock();
+                if let Err(e) = sent {
+                    state.waiting.retain(|&(_, waiting)| waiting != ticket);
+                    self.shared.finished.notify_all();
+                    return Err(e);
+                }
+                reported = Some(position);
+                last_update = Instant::now();
+            }
+
+            let remaining = self.update_interval.saturating_sub(last_update.elapsed());
+            state = match self.shared.finished.wait_timeout(state, remaining) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+
+        state.waiting.pop_front();
+        state.running += 1;
+        drop(state);
+        // Others may now move up, or even start alongside
+        self.shared.finished.notify_all();
+
+        if reported.is_some() {
+            let started = Queued {
+                position: 0,
+                est_wait: Some(Duration::ZERO),
+            };
+            connection.write_control(control::queued(started))?;
+        }
+        Ok(Job {
+            shared: Arc::clone(&self.shared),
+            started: Instant::now(),
+        })
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        let took = self.started.elapsed();
+        let mut state = self.shared.lock();
+        state.running -= 1;
+        state.average = Some(match state.average {
+            Some(average) => average.mul_f64(1.0 - ESTIMATE_WEIGHT) + took.mul_f64(ESTIMATE_WEIGHT),
+            None => took,
+        });
+        drop(state);
+        self.shared.finished.notify_all();
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Calls `observer` with every [`Queued`] update the peer sends, see [`JobQueue`]
+    ///
+    /// Updates are handled while receiving, so a client blocked waiting for its
+    /// response still hears about them. The observer runs on the receiving
+    /// thread and shouldn't block.
+    pub fn set_queue_observer(&mut self, observer: impl FnMut(Queued) + Send + 'static) {
+        self.queue_observer = Some(Box::new(observer));
+    }
+}
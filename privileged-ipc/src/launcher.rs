@@ -0,0 +1,44 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Recognising the client that launched a helper
+//!
+//! Anyone on the machine can connect to an abstract socket, and checking the
+//! peer's uid only keeps out other users. The launching client created the
+//! listener the helper inherits, whether it spawned the helper directly or
+//! through pkexec or a wrapper such as sudo, and the kernel records the process
+//! that started listening on a socket. Until a peer from that process has
+//! connected, [`ServiceListener::accept`](crate::ServiceListener::accept)
+//! refuses every other one.
+
+use std::{
+    os::{fd::AsFd, unix::net::UnixStream},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{credentials, IpcError, ServiceListener};
+
+/// Whether the client that launched a helper has connected to its listener yet
+#[derive(Debug, Default)]
+pub(crate) struct Launcher {
+    /// Set once it has, as a helper is only launched once
+    connected: AtomicBool,
+}
+
+impl ServiceListener {
+    /// Refuses `stream` unless the launching client has connected, or is its peer
+    pub(crate) fn authorize_launcher(&self, stream: &UnixStream) -> Result<(), IpcError> {
+        if self.1.connected.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        // Reports the process that called `listen`, however the listener was passed on since
+        let launcher = credentials::peer_credentials(self.0.as_fd())?.pid;
+        let pid = credentials::peer_credentials(stream.as_fd())?.pid;
+        if pid != launcher {
+            return Err(IpcError::ForeignClient { pid });
+        }
+        self.1.connected.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
@@ -8,24 +8,137 @@
 //! with support for both direct execution and privilege escalation via pkexec.
 
 use std::{
-    env,
+    cell::Cell,
+    collections::VecDeque,
+    env, fmt,
     io::{self, Write},
     net::Shutdown,
     ops::DerefMut,
     os::{
-        fd::{FromRawFd, OwnedFd, RawFd},
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
         linux::net::SocketAddrExt,
         unix::net::{SocketAddr, UnixListener, UnixStream},
     },
-    process::Command,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
 };
 
 use command_fds::{CommandFdExt, FdMapping, FdMappingCollision};
-use nix::unistd::Pid;
-use serde_json::de::IoRead;
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    poll::{PollFd, PollFlags, PollTimeout},
+    unistd::{Pid, Uid},
+};
 use std::ops::Deref;
 use thiserror::Error;
 
+pub use authenticator::{generate_key, read_spawn_key, MessageAuthenticator, Role, KEY_LEN};
+pub use backoff::{Backoff, Exponential, Fixed, Jittered};
+pub use backpressure::WritePolicy;
+pub use blob::{Blob, BlobReader};
+pub use cipher::{FrameCipher, Keyring};
+pub use codec::{Codec, JsonCodec, StreamScan};
+pub use conformance::{CheckedConnection, MessageKind, ProtocolMessage, Violation};
+pub use crash::CrashReport;
+pub use credentials::PeerCredentials;
+pub use decode::{decode_frame, decode_message, Frame};
+pub use descriptor::Descriptor;
+pub use dry_run::SpawnPlan;
+pub use dump::dump_on_request;
+pub use endpoint::Endpoint;
+pub use envelope::{Envelope, EnvelopedConnection, MaybeKnown};
+pub use escalation::{
+    clear_escalation_hook, set_authorization_timeout, set_escalation_hook, Escalation,
+};
+pub use handshake::Capabilities;
+pub use job_queue::{Job, JobQueue, Priority, Queued};
+pub use lifecycle::{ConnectionState, Lifecycle, Transition};
+pub use path_socket::PathSocket;
+pub use polkit::{policy_document, Action, Authorization, PolkitActions};
+pub use pool::BufferPool;
+pub use preflight::Preflight;
+pub use reactor::Reactor;
+pub use reconnect::ReconnectingClient;
+#[cfg(feature = "typescript")]
+pub use reference::ProtocolReference;
+pub use registry::{Discovery, Fallback, Registry, ServiceInfo, SYSTEM_REGISTRY};
+pub use replay::{Recorder, Recording, Replay, SharedRecording};
+pub use rotation::RotatingCipher;
+pub use select::{select, Pollable};
+pub use selector::{Selected, Selector};
+pub use self_test::{Check, CheckStatus, SelfTest, SelfTestReport};
+pub use serve::ShutdownHandle;
+pub use service::IntoReplies;
+pub use session_dir::SessionDir;
+pub use timing::TransitStats;
+pub use transport::{PipeTransport, StreamTransport, Transport};
+pub use usage::ResourceUsage;
+pub use varlink::{
+    VarlinkCall, VarlinkClient, VarlinkError, VarlinkInfo, VarlinkReply, VarlinkService,
+    SERVICE_INTERFACE,
+};
+
+#[cfg(feature = "macros")]
+pub use privileged_ipc_macros::ipc_protocol;
+#[cfg(feature = "typescript")]
+pub use typescript::{Declarations, TypeScript};
+
+mod authenticator;
+mod backoff;
+mod backpressure;
+mod blob;
+mod cipher;
+mod codec;
+mod conformance;
+mod control;
+mod crash;
+mod credentials;
+mod decode;
+mod descriptor;
+mod dry_run;
+mod dump;
+mod endpoint;
+mod envelope;
+mod escalation;
+mod event_loop;
+mod file_transfer;
+mod handshake;
+mod heartbeat;
+mod job_queue;
+mod launcher;
+mod lifecycle;
+mod path_socket;
+mod polkit;
+mod pool;
+mod preflight;
+mod reactor;
+mod reconnect;
+#[cfg(feature = "typescript")]
+mod reference;
+mod registry;
+mod replay;
+mod rotation;
+mod select;
+mod selector;
+mod self_test;
+mod send_queue;
+mod serve;
+mod service;
+mod session_dir;
+mod socket_buffers;
+mod streaming;
+#[cfg(feature = "tcp")]
+mod tcp;
+mod timing;
+mod token;
+mod transport;
+#[cfg(feature = "typescript")]
+mod typescript;
+mod usage;
+mod varlink;
+
 /// Errors that can occur when working with privileged services
 #[derive(Debug, Error)]
 pub enum Error {
@@ -37,9 +150,17 @@ pub enum Error {
     #[error("mapping collision@ {0}")]
     MappingCollision(#[from] FdMappingCollision),
 
-    /// The fork operation failed
-    #[error("Failed to fork: {0}")]
+    /// A system call made while launching the worker failed
+    #[error("System call failed: {0}")]
     Nix(#[from] nix::Error),
+
+    /// The user didn't authorize the privileged worker, see [`set_escalation_hook`]
+    #[error("Privilege escalation was {0}")]
+    Escalation(Escalation),
+
+    /// The user didn't answer the authentication dialog in time, see [`set_authorization_timeout`]
+    #[error("Authorization wasn't given within {0:?}")]
+    AuthorizationTimeout(Duration),
 }
 
 /// Trait for types that can execute commands with socket file descriptor handling
@@ -52,6 +173,15 @@ pub trait SocketExecutor: Default {
 
     /// Creates a command with the given executable and arguments
     fn command(&self, executable: &str, args: &[&str]) -> Command;
+
+    /// Returns true if the command obtains privileges the way pkexec does
+    ///
+    /// Such a command must keep the descriptor following [`SocketExecutor::child_fd`]
+    /// open until it either runs the executable, which mustn't inherit it, or gives
+    /// up, exiting with pkexec's codes if authorization failed. See [`set_escalation_hook`].
+    fn escalates(&self) -> bool {
+        false
+    }
 }
 
 /// Executor that uses pkexec for privilege escalation
@@ -73,6 +203,10 @@ impl SocketExecutor for PkexecExecutor {
         command.args(args);
         command
     }
+
+    fn escalates(&self) -> bool {
+        true
+    }
 }
 
 /// Executor that runs commands directly without privilege escalation
@@ -95,56 +229,333 @@ impl SocketExecutor for DirectExecutor {
     }
 }
 
-/// A unique identifier for a socket address using a UUID
-struct AddressIdentifier(uuid::Uuid);
+/// Executor for a privileged helper launching another helper it is privileged enough to run itself
+///
+/// The executable runs directly, without prompting the user a second time, and
+/// learns which user the chain of helpers acts for from [`invoking_uid`]. Use it
+/// with [`IpcClient::connect_or_spawn`] or [`IpcClient::discover`] to reach a
+/// peer that may already be running, such as an installer orchestrating the
+/// disks and users helpers.
+#[derive(Default)]
+pub struct PeerExecutor;
+
+impl SocketExecutor for PeerExecutor {
+    fn child_fd(&self) -> i32 {
+        3
+    }
+
+    fn parent_fd(&self) -> i32 {
+        3
+    }
+
+    fn command(&self, executable: &str, args: &[&str]) -> Command {
+        let mut command = Command::new(executable);
+        command.args(args);
+        // The helper isn't launched by pkexec, so `PKEXEC_UID` would misplace its listener
+        match invoking_uid() {
+            Some(uid) => command.env(INVOKING_UID_VAR, uid.to_string()),
+            None => command.env_remove(INVOKING_UID_VAR),
+        };
+        command
+    }
+}
+
+/// Environment variable [`PeerExecutor`] hands the invoking user's uid down in
+const INVOKING_UID_VAR: &str = "PRIVILEGED_IPC_INVOKING_UID";
+
+/// How the abstract socket address of a privileged service is named
+#[derive(Debug, Clone, Default)]
+pub enum AddressScheme {
+    /// A random UUID in its raw byte form
+    #[default]
+    Anonymous,
+    /// `<app>-<uid>-<uuid>`, with the invoking user's uid
+    ///
+    /// The name is readable in `ss -x` output, and services of different
+    /// applications or users can never collide.
+    Prefixed(String),
+    /// Exactly the given name, so that other processes can find the service
+    ///
+    /// Launching fails if another service already has the name.
+    Named(String),
+}
+
+impl AddressScheme {
+    /// Prefixes addresses with `app` and the invoking user's uid
+    pub fn prefixed(app: impl Into<String>) -> Self {
+        Self::Prefixed(app.into())
+    }
+}
+
+/// A unique identifier for a socket address
+enum AddressIdentifier {
+    /// A UUID, used in its raw byte form
+    Uuid(uuid::Uuid),
+    /// A readable name
+    Name(String),
+}
 
 /// A connection to a privileged service, maintaining both the socket and child process
 pub struct ServiceConnection {
     /// The Unix domain socket connected to the service
     pub socket: UnixStream,
-    _child: Pid,
+    address: SocketAddr,
+    supervisor: Option<crash::Supervisor>,
 }
 
 impl ServiceConnection {
     /// Creates a new connection to a privileged service using the specified executor
     pub fn new<T: SocketExecutor>(executable: &str, args: &[&str]) -> Result<Self, self::Error> {
-        let identity = AddressIdentifier::default();
+        Self::new_with_scheme::<T>(executable, args, &AddressScheme::default())
+    }
+
+    /// Creates a new connection to a privileged service, naming its address with `scheme`
+    pub fn new_with_scheme<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        scheme: &AddressScheme,
+    ) -> Result<Self, self::Error> {
+        let identity = AddressIdentifier::new(scheme);
         let socket_addr = identity.as_unix_address()?;
         let unix_socket = UnixListener::bind_addr(&socket_addr)?;
 
-        log::trace!("🔌 setting server address to: @{:?}", identity.0);
+        log::trace!("🔌 setting server address to: @{identity}");
+
+        Self::spawn::<T>(executable, args, unix_socket, &socket_addr, None)
+    }
+
+    /// Creates a new connection to a privileged service over a filesystem socket
+    ///
+    /// The socket's permissions guard the window between the helper being launched
+    /// and the connection being established, after which the path is unlinked.
+    pub fn new_with_socket<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        socket: &PathSocket,
+    ) -> Result<Self, self::Error> {
+        let bound = socket.bind()?;
+        let socket_addr = SocketAddr::from_pathname(bound.path())?;
+
+        log::trace!("🔌 setting server address to: {:?}", bound.path());
+
+        Self::spawn::<T>(
+            executable,
+            args,
+            bound.listener.try_clone()?,
+            &socket_addr,
+            None,
+        )
+    }
+
+    /// Creates a new connection to a privileged service over a filesystem socket, handing it `key`
+    ///
+    /// The key is written to the helper's stdin, which only the helper can read, for it
+    /// to fetch with [`read_spawn_key`]. See [`ServiceConnection::new_with_socket`].
+    pub fn new_with_socket_and_key<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        socket: &PathSocket,
+        key: &[u8],
+    ) -> Result<Self, self::Error> {
+        let bound = socket.bind()?;
+        let socket_addr = SocketAddr::from_pathname(bound.path())?;
+
+        log::trace!("🔌 setting server address to: {:?}", bound.path());
 
+        Self::spawn::<T>(
+            executable,
+            args,
+            bound.listener.try_clone()?,
+            &socket_addr,
+            Some(key),
+        )
+    }
+
+    /// Returns the address the service is listening on
+    ///
+    /// Other processes, such as a diagnostic client or a supervisor, can attach to the
+    /// same service with [`IpcConnection::connect_addr`] for as long as it keeps
+    /// accepting connections, which a helper only does after its launching client
+    /// connected if it called [`IpcServer::set_multi_client`]. The path of a [`ServiceConnection::new_with_socket`]
+    /// connection is unlinked once connected, so can't be reached again.
+    pub fn address(&self) -> &SocketAddr {
+        &self.address
+    }
+
+    /// Connects to the service already listening on `endpoint`, launching it if there is none
+    ///
+    /// A launched helper inherits a listener bound to the endpoint and keeps it for as
+    /// long as it runs, so later connections reach the same helper rather than forking
+    /// another. The helper uses [`IpcServer::new`] as usual, calling
+    /// [`IpcServer::set_multi_client`] so it keeps accepting connections until it has
+    /// been idle for a while.
+    pub fn connect_or_spawn<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        endpoint: &Endpoint,
+    ) -> Result<Self, self::Error> {
+        if let Some(connection) = Self::connect_existing(endpoint)? {
+            return Ok(connection);
+        }
+
+        let bound = match endpoint.socket().bind() {
+            Ok(bound) => bound,
+            // Another client launched the helper in the meantime
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                let socket = UnixStream::connect(endpoint.path())?;
+                return Ok(Self {
+                    socket,
+                    address: SocketAddr::from_pathname(endpoint.path())?,
+                    supervisor: None,
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let socket_addr = SocketAddr::from_pathname(bound.path())?;
+
+        log::trace!("🔌 launching service at: {:?}", bound.path());
+
+        Self::spawn::<T>(executable, args, bound.persist()?, &socket_addr, None)
+    }
+
+    /// Connects to the service listening on `endpoint`, if there is one
+    fn connect_existing(endpoint: &Endpoint) -> io::Result<Option<Self>> {
+        match UnixStream::connect(endpoint.path()) {
+            Ok(socket) => {
+                log::trace!("🔌 reusing service at: {:?}", endpoint.path());
+                Ok(Some(Self {
+                    socket,
+                    address: SocketAddr::from_pathname(endpoint.path())?,
+                    supervisor: None,
+                }))
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Launches the service with `listener` and connects to it at `socket_addr`
+    ///
+    /// A `key` is handed to the service through a pipe on its stdin.
+    fn spawn<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        listener: UnixListener,
+        socket_addr: &SocketAddr,
+        key: Option<&[u8]>,
+    ) -> Result<Self, self::Error> {
         let exec = T::default();
 
-        let mappings: Vec<FdMapping> = vec![FdMapping {
-            parent_fd: unix_socket.into(),
+        let mut mappings: Vec<FdMapping> = vec![FdMapping {
+            parent_fd: listener.into(),
             child_fd: exec.child_fd(),
         }];
+        // Stdin stays open for as long as the helper runs, to ask it for a dump
+        // after any key, see `dump_on_request`
+        let (reader, writer) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let mut control = std::fs::File::from(writer);
+        if let Some(key) = key {
+            // The key is far smaller than a pipe's buffer, so writing it can't block
+            control.write_all(key)?;
+        }
+        mappings.push(FdMapping {
+            parent_fd: reader,
+            child_fd: 0,
+        });
 
-        match unsafe { nix::unistd::fork() }? {
-            nix::unistd::ForkResult::Parent { child } => {
-                let socket = UnixStream::connect_addr(&socket_addr)?;
-                Ok(Self {
-                    _child: child,
-                    socket,
-                })
-            }
-            nix::unistd::ForkResult::Child => {
-                // Ensure we don't leak the listener, so failed pkexec
-                // will still result in the listener being closed, and the
-                // client connection will fail properly.
-                let mut command = exec.command(executable, args);
-                command.fd_mappings(mappings)?;
-                command.env_remove("PKEXEC_UID");
-                let st = command.status()?;
-                std::process::exit(st.code().unwrap_or(1));
+        // With a hook to notify, `ready` tells when the executor has finished authorizing
+        let mut escalation = None;
+        let timeout = escalation::authorization_timeout();
+        if watches_escalation(&exec) {
+            let (ready, ready_writer) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+            mappings.push(FdMapping {
+                parent_fd: ready_writer,
+                child_fd: escalation_fd(&exec),
+            });
+            escalation = Some(ready);
+            escalation::notify(Escalation::Prompting);
+        }
+
+        // The descriptors are only arranged between fork and exec, by std::process,
+        // so that launching is safe from threaded programs such as async runtimes
+        let (stderr, stderr_writer) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let mut command = launch_command(&exec, executable, args);
+        command.fd_mappings(mappings)?;
+        if exec.child_fd() == 2 {
+            // The listener takes the place of stderr, and pkexec closes everything
+            // above it, so stdout carries the pipe for `service_init` to move to stderr
+            command.stdout(stderr_writer);
+        } else {
+            command.stderr(stderr_writer);
+        }
+        let mut child = command.spawn()?;
+        // Ensure we don't leak the listener, so failed pkexec will still result
+        // in the listener being closed, and the client connection will fail properly.
+        // Dropping the command also closes our copies of the helper's ends of the pipes.
+        drop(command);
+
+        let outcome =
+            escalation.map(|ready| escalation::await_authorization(&mut child, ready, timeout));
+        let supervisor = crash::Supervisor::spawn(child, Some(stderr), Some(control))?;
+        if let Some(outcome) = outcome {
+            escalation::notify(outcome);
+            match (outcome, timeout) {
+                (Escalation::Granted, _) => {}
+                (Escalation::TimedOut, Some(timeout)) => {
+                    return Err(Error::AuthorizationTimeout(timeout))
+                }
+                (outcome, _) => return Err(Error::Escalation(outcome)),
             }
         }
+        let socket = UnixStream::connect_addr(socket_addr)?;
+        // Set before the helper can send anything, for `expect_peer_uid` to tell who did
+        credentials::set_pass_credentials(socket.as_fd(), true)?;
+        Ok(Self {
+            supervisor: Some(supervisor),
+            address: socket_addr.clone(),
+            socket,
+        })
     }
 }
 
+/// Returns the command launching `executable` with `exec`
+fn launch_command<T: SocketExecutor>(exec: &T, executable: &str, args: &[&str]) -> Command {
+    let mut command = exec.command(executable, args);
+    command.env_remove("PKEXEC_UID");
+    command
+}
+
+/// Returns true if launching with `exec` waits for it to report on authorization
+fn watches_escalation<T: SocketExecutor>(exec: &T) -> bool {
+    exec.escalates()
+        && (lifecycle::authenticating()
+            || escalation::hooked()
+            || escalation::authorization_timeout().is_some())
+}
+
+/// The descriptor an escalating executor reports on authorization through
+///
+/// Just above the listener, or stderr for pkexec.
+fn escalation_fd<T: SocketExecutor>(exec: &T) -> i32 {
+    exec.child_fd().max(2) + 1
+}
+
 /// An activated service listener that accepts connections from clients
-pub struct ServiceListener(pub UnixListener);
+///
+/// Under pkexec, only the user who ran pkexec and root may connect, as anyone
+/// could otherwise race the client to the abstract socket of a root helper.
+/// Other processes of that user could too, so on an abstract address the first
+/// client accepted must be the one that launched the helper. Filesystem sockets
+/// are guarded by their mode instead, and may be shared by several clients, see
+/// [`ServiceConnection::connect_or_spawn`].
+pub struct ServiceListener(pub UnixListener, launcher::Launcher);
 
 impl ServiceListener {
     /// Creates a new service listener using the appropriate executor
@@ -154,7 +565,53 @@ impl ServiceListener {
             None => DirectExecutor {}.parent_fd(),
         };
         let listener = unsafe { UnixListener::from(OwnedFd::from_raw_fd(server_fd)) };
-        Ok(ServiceListener(listener))
+        Ok(Self::from(listener))
+    }
+
+    /// Accepts a client connection, refusing peers that may not talk to this helper
+    ///
+    /// Under pkexec only the invoking user and root are accepted, and on an abstract
+    /// address no other client is until the launching one has connected. A refused peer is
+    /// disconnected and reported as [`io::ErrorKind::PermissionDenied`], after which
+    /// the next client can be accepted.
+    pub fn accept(&self) -> io::Result<(UnixStream, SocketAddr)> {
+        let (stream, address) = self.0.accept()?;
+        self.authorize(&stream)
+            .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))?;
+        Ok((stream, address))
+    }
+
+    /// Checks that the peer of an accepted connection may talk to this helper
+    fn authorize(&self, stream: &UnixStream) -> Result<(), IpcError> {
+        authorize_invoker(stream)?;
+        if self.0.local_addr()?.as_abstract_name().is_some() {
+            self.authorize_launcher(stream)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the peer of a helper launched by pkexec runs as the user who ran pkexec, or as root
+fn authorize_invoker(stream: &UnixStream) -> Result<(), IpcError> {
+    let Some(expected) = env::var_os("PKEXEC_UID") else {
+        return Ok(());
+    };
+    let expected = expected
+        .to_str()
+        .and_then(|uid| uid.parse().ok())
+        .map(Uid::from_raw)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "PKEXEC_UID is not a uid"))?;
+    let uid = credentials::peer_credentials(stream.as_fd())?.uid;
+    if uid == expected || uid.is_root() {
+        Ok(())
+    } else {
+        Err(IpcError::UnauthorizedPeer { uid, expected })
+    }
+}
+
+impl From<UnixListener> for ServiceListener {
+    fn from(listener: UnixListener) -> Self {
+        Self(listener, launcher::Launcher::default())
     }
 }
 
@@ -166,25 +623,47 @@ impl Deref for ServiceListener {
     }
 }
 
-impl Default for AddressIdentifier {
-    fn default() -> Self {
-        Self(uuid::Uuid::new_v4())
+impl AddressIdentifier {
+    fn new(scheme: &AddressScheme) -> Self {
+        match scheme {
+            AddressScheme::Anonymous => Self::Uuid(uuid::Uuid::new_v4()),
+            AddressScheme::Prefixed(app) => Self::Name(format!(
+                "{app}-{}-{}",
+                nix::unistd::getuid(),
+                uuid::Uuid::new_v4()
+            )),
+            AddressScheme::Named(name) => Self::Name(name.clone()),
+        }
     }
-}
 
-impl AddressIdentifier {
     #[inline]
     fn as_unix_address(&self) -> io::Result<SocketAddr> {
-        SocketAddr::from_abstract_name(self.0.as_bytes())
+        match self {
+            Self::Uuid(id) => SocketAddr::from_abstract_name(id.as_bytes()),
+            Self::Name(name) => SocketAddr::from_abstract_name(name),
+        }
+    }
+}
+
+impl fmt::Display for AddressIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uuid(id) => write!(f, "{id:?}"),
+            Self::Name(name) => f.write_str(name),
+        }
     }
 }
 
 /// Initializes a service by handling file descriptor redirection when running under pkexec
+///
+/// The listener arrives on stderr, and the client's stderr pipe on stdout. Both
+/// stdout and stderr go to that pipe afterwards, so whatever the helper prints
+/// ends up in its [`CrashReport`].
 pub fn service_init() -> io::Result<()> {
     match env::var_os("PKEXEC_UID") {
         None => Ok(()),
         Some(_) => {
-            // Redirect stderr to stdout
+            // Move the listener out of the way and redirect stderr to stdout
             let exec = PkexecExecutor {};
             nix::unistd::dup2(exec.child_fd(), exec.parent_fd())?;
             nix::unistd::close(exec.child_fd())?;
@@ -193,6 +672,19 @@ pub fn service_init() -> io::Result<()> {
         }
     }
 }
+
+/// Returns the user a helper acts for, if it was launched by pkexec or by another helper
+///
+/// This is the uid of whoever ran pkexec, passed along by every [`PeerExecutor`]
+/// in between. It is only as trustworthy as the process that launched this
+/// one, which for a privileged helper is pkexec or another privileged helper.
+pub fn invoking_uid() -> Option<Uid> {
+    [env::var_os("PKEXEC_UID"), env::var_os(INVOKING_UID_VAR)]
+        .into_iter()
+        .flatten()
+        .find_map(|uid| uid.to_str()?.parse().ok().map(Uid::from_raw))
+}
+
 /// Error types for IPC operations
 #[derive(Debug, Error)]
 pub enum IpcError {
@@ -202,158 +694,1364 @@ pub enum IpcError {
     Json(#[from] serde_json::Error),
     #[error("Privileged IPC error: {0}")]
     Privileged(#[from] Error),
+    #[error("Codec error: {0}")]
+    Codec(#[source] Box<dyn std::error::Error + Send + Sync>),
     #[error("Connection closed")]
     ConnectionClosed,
+    /// The peer violated the protocol while in strict mode, and the connection was terminated
+    #[error("Protocol violation: {0}")]
+    ProtocolViolation(String),
+    /// A message exceeded the connection's size limit
+    #[error("Message of {size} bytes exceeds the {limit} byte limit")]
+    MessageTooLarge { size: usize, limit: usize },
+    /// A file exceeded the connection's size limit, see [`IpcConnection::set_max_file_size`]
+    #[error("File of {size} bytes exceeds the {limit} byte limit")]
+    FileTooLarge { size: u64, limit: u64 },
+    /// The peer passed more descriptors at once than can be received, and the connection was closed
+    #[error("Too many file descriptors were passed at once")]
+    DescriptorsTruncated,
+    /// The peer stopped answering heartbeats, and the connection was terminated
+    #[error("Peer is unresponsive")]
+    PeerUnresponsive,
+    /// No message arrived, or a message couldn't be written, before the deadline passed
+    #[error("Deadline exceeded")]
+    DeadlineExceeded,
+    /// The peer didn't read a message in time, see [`WritePolicy::Deadline`]
+    #[error("Timed out writing to the peer")]
+    WriteTimeout,
+    /// The peer isn't reading and the send queue is full, see [`WritePolicy::Buffer`]
+    #[error("Send queue of {limit} bytes is full")]
+    SendBufferFull { limit: usize },
+    /// The writer thread has fallen behind by a full queue of messages, see [`IpcConnection::set_send_queue`]
+    #[error("Send queue of {capacity} messages is full")]
+    SendQueueFull { capacity: usize },
+    /// The peer announced a different protocol version, or none, see [`IpcConnection::set_protocol`]
+    #[error("Peer speaks {}, not protocol {expected}", found.map_or("no known protocol".into(), |found| format!("protocol {found}")))]
+    ProtocolMismatch { expected: u32, found: Option<u32> },
+    /// The daemon of a tool didn't accept the connection in time, see [`Discovery`]
+    #[error("The {tool} daemon is busy")]
+    DaemonBusy { tool: String },
+    /// The daemon of a tool speaks a different protocol version, see [`Discovery`]
+    #[error("The {tool} daemon speaks protocol {protocol}, not {expected}")]
+    DaemonIncompatible {
+        tool: String,
+        protocol: u32,
+        expected: u32,
+    },
+    /// A client of a helper launched by pkexec runs as neither the invoking user nor root
+    #[error("Refused a client running as uid {uid}, as only uid {expected} and root may connect")]
+    UnauthorizedPeer { uid: Uid, expected: Uid },
+    /// A client connected to a helper on an abstract address before the client launching it did
+    #[error("Refused a client in process {pid}, which didn't launch this helper")]
+    ForeignClient { pid: Pid },
+    /// A client didn't present the token set with [`IpcServer::set_connect_token`]
+    #[error("Refused a client that didn't present the connection token")]
+    TokenMismatch,
+    /// The peer runs as a different user than it was expected to, see [`IpcConnection::expect_peer_uid`]
+    #[error("Peer runs as uid {uid}, not the expected uid {expected}")]
+    UnexpectedPeerUid { uid: Uid, expected: Uid },
+    /// The peer's handler failed a call, see `#[ipc_protocol]`
+    #[error("Call failed: {0}")]
+    CallFailed(String),
+    /// A varlink call was answered with an error
+    #[error("Varlink call failed with {0}")]
+    Varlink(VarlinkError),
+}
+
+/// Default limit on the encoded size of a single message
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Default limit on the size of a file received with [`IpcConnection::recv_file`]
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Size of the length prefix used by [`Framing::LengthPrefixed`]
+const FRAME_HEADER_LEN: usize = 4;
+
+/// How message boundaries are marked on the wire
+///
+/// Both peers must use the same framing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Messages are written back to back, relying on the codec to delimit them
+    #[default]
+    Stream,
+    /// Each message is preceded by its length as a big-endian `u32`
+    ///
+    /// Message boundaries and sizes are explicit, so oversized messages are
+    /// rejected as soon as their header arrives.
+    LengthPrefixed,
+    /// Each message is written on a single line, terminated by `\n`
+    ///
+    /// This eases interop with shell tools and other languages. Compact JSON
+    /// always escapes newlines within strings, so every line is exactly one
+    /// message; sending a message whose encoding contains a raw newline fails.
+    /// Blank lines and `\r\n` line endings are tolerated when receiving.
+    Ndjson,
+    /// Each message is preceded by LSP-style headers: `Content-Length: N\r\n\r\n`
+    ///
+    /// Suited to helpers talking over stdin/stdout, see [`IpcConnection::from_stdio`].
+    /// Header names are matched case-insensitively, and headers other than
+    /// `Content-Length` are ignored when receiving.
+    ContentLength,
+    /// Each message is terminated by a NUL byte, as varlink does
+    ///
+    /// Compact JSON escapes NUL within strings, so sending a message whose
+    /// encoding contains one fails. See [`VarlinkService`].
+    NulTerminated,
 }
 
 /// A type-safe IPC connection for sending and receiving messages
-pub struct IpcConnection<S, R> {
-    connection: ServiceConnection,
+pub struct IpcConnection<S, R, C = JsonCodec> {
+    transport: Box<dyn Transport>,
+    /// Bytes read from the transport that have not yet been decoded
+    buffer: Vec<u8>,
+    /// How far the search for the end of the first buffered frame got
+    scan: Cell<StreamScan>,
+    /// File descriptors received for blobs, keyed by the buffer offset of the last byte
+    /// read along with them, which lies within the frame they were sent with
+    fds: VecDeque<(usize, Vec<OwnedFd>)>,
+    /// Set once the peer has closed its end of the transport
+    eof: bool,
+    /// Terminate the connection on any protocol violation instead of skipping the message
+    strict: bool,
+    /// Largest encoded message that may be sent or received
+    max_message_size: usize,
+    /// How message boundaries are marked on the wire
+    framing: Framing,
+    /// Cipher sealing every frame, if encryption is enabled
+    cipher: Option<Box<dyn FrameCipher>>,
+    /// Whether frames carry the sender's clock reading
+    timestamps: bool,
+    /// Transit timing of received timestamped frames
+    transit: TransitStats,
+    /// Budget shared with other connections that the buffer is charged against
+    pool: Option<BufferPool>,
+    /// Bytes currently charged to the pool, at least the length of the buffer
+    charged: usize,
+    /// Limit on the rate of file transfers, in bytes per second
+    transfer_rate: Option<std::num::NonZeroU64>,
+    /// Limit on the size of files sent or received
+    max_file_size: u64,
+    /// Liveness checking of the peer, if enabled
+    heartbeat: Option<heartbeat::Heartbeat>,
+    /// Progress of the close handshake
+    closing: control::Closing,
+    /// Answers to pings received, for telling when a ping has been answered
+    pongs: u64,
+    /// Progress of the hello exchange, if the protocol version is checked
+    handshake: handshake::Handshake,
+    /// The token the peer has yet to present before its first frame
+    connect_token: Option<Vec<u8>>,
+    /// Credentials the kernel passed with the earliest bytes received that carried any
+    sender: Option<PeerCredentials>,
+    /// Periodic reporting of this process's resource usage to the peer, if enabled
+    usage_reports: Option<usage::UsageReports>,
+    /// The resource usage the peer last reported
+    peer_usage: Option<ResourceUsage>,
+    /// Where resource usage reports from the peer are reported
+    usage_observer: Option<Box<dyn FnMut(ResourceUsage) + Send>>,
+    /// Where queue updates from the peer are reported
+    queue_observer: Option<Box<dyn FnMut(Queued) + Send>>,
+    /// Called when a heartbeat gives up on the peer
+    unresponsive_hook: Option<Box<dyn FnMut() + Send>>,
+    /// Directory for exchanging files with the peer, removed along with the connection
+    session_dir: Option<SessionDir>,
+    /// Where state transitions are reported
+    lifecycle: lifecycle::Membership,
+    /// What sending does when the peer stops reading
+    write_policy: WritePolicy,
+    /// Bytes sent but not yet written, under a non-blocking write policy
+    outgoing: Vec<u8>,
+    /// Frames handed to the writer thread, if sending is queued
+    send_queue: Option<send_queue::SendQueue>,
+    /// The most recently received frames, if any are kept
+    history: VecDeque<Vec<u8>>,
+    history_len: usize,
+    /// Wire format of messages
+    codec: C,
     _phantom: std::marker::PhantomData<(S, R)>,
 }
 
-impl<S, R> IpcConnection<S, R>
+impl<S, R, C> IpcConnection<S, R, C>
 where
     S: serde::Serialize,
     R: serde::de::DeserializeOwned,
+    C: Codec,
 {
-    /// Creates a new IPC connection from an existing ServiceConnection
-    pub fn new(connection: ServiceConnection) -> Self {
+    /// Creates a new IPC connection over a transport, such as an existing ServiceConnection
+    pub fn new(transport: impl Transport + 'static) -> Self {
+        Self::with_codec(transport, C::default())
+    }
+
+    /// Creates a new IPC connection using the given codec
+    pub fn with_codec(transport: impl Transport + 'static, codec: C) -> Self {
         Self {
-            connection,
+            transport: Box::new(transport),
+            buffer: Vec::new(),
+            scan: Cell::default(),
+            fds: VecDeque::new(),
+            eof: false,
+            strict: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            framing: Framing::default(),
+            cipher: None,
+            timestamps: false,
+            transit: TransitStats::default(),
+            pool: None,
+            charged: 0,
+            transfer_rate: None,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            heartbeat: None,
+            closing: control::Closing::Open,
+            pongs: 0,
+            handshake: handshake::Handshake::default(),
+            connect_token: None,
+            sender: None,
+            usage_reports: None,
+            peer_usage: None,
+            usage_observer: None,
+            queue_observer: None,
+            unresponsive_hook: None,
+            session_dir: None,
+            lifecycle: lifecycle::Membership::join(),
+            write_policy: WritePolicy::default(),
+            outgoing: vec![],
+            send_queue: None,
+            history: VecDeque::new(),
+            history_len: 0,
+            codec,
             _phantom: std::marker::PhantomData,
         }
     }
 
+    /// Creates a new IPC connection over a separate reader and writer, such as a child's stdout and stdin
+    ///
+    /// See [`PipeTransport`] for the limitations of pipes.
+    pub fn from_pipes(reader: impl Into<OwnedFd>, writer: impl Into<OwnedFd>) -> Self {
+        Self::new(PipeTransport::new(reader, writer))
+    }
+
+    /// Creates a connection over this process's stdin and stdout, for helpers that can't inherit a socket
+    ///
+    /// This is the helper side of [`IpcClient::new_stdio`] and uses [`Framing::ContentLength`].
+    /// Stdout is redirected to stderr afterwards so that stray prints can't corrupt the stream.
+    /// Helpers using this must not call [`service_init`], which repurposes stdout under pkexec.
+    pub fn from_stdio() -> Result<Self, IpcError> {
+        let reader = io::stdin().as_fd().try_clone_to_owned()?;
+        let writer = io::stdout().as_fd().try_clone_to_owned()?;
+        nix::unistd::dup2(2, 1).map_err(io::Error::from)?;
+        let mut connection = Self::from_pipes(reader, writer);
+        connection.set_framing(Framing::ContentLength);
+        Ok(connection)
+    }
+
+    /// Connects to an [`IpcServer`] listening on a filesystem socket
+    pub fn connect_path(path: impl AsRef<std::path::Path>) -> Result<Self, IpcError> {
+        Ok(Self::new(UnixStream::connect(path)?))
+    }
+
+    /// Connects to an [`IpcServer`] listening on an abstract or filesystem address
+    ///
+    /// See [`ServiceConnection::address`].
+    pub fn connect_addr(address: &SocketAddr) -> Result<Self, IpcError> {
+        Ok(Self::new(UnixStream::connect_addr(address)?))
+    }
+
+    /// Sets how message boundaries are marked on the wire
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Returns how message boundaries are marked on the wire
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Encrypts all subsequent frames with the given cipher
+    ///
+    /// Sealed frames are binary, so this also switches the connection to
+    /// [`Framing::LengthPrefixed`]. Both peers must install a matching cipher
+    /// before exchanging any messages.
+    pub fn set_cipher(&mut self, cipher: impl FrameCipher + 'static) {
+        self.cipher = Some(Box::new(cipher));
+        self.framing = Framing::LengthPrefixed;
+    }
+
+    /// Enables or disables timestamping of frames, for estimating transit delays
+    ///
+    /// Timestamps are binary, so enabling them also switches the connection to
+    /// [`Framing::LengthPrefixed`]. Both peers must enable them before exchanging
+    /// any messages. See [`IpcConnection::transit_stats`].
+    pub fn set_timestamps(&mut self, enabled: bool) {
+        self.timestamps = enabled;
+        if enabled {
+            self.framing = Framing::LengthPrefixed;
+        }
+    }
+
+    /// Returns the transit timing of the timestamped frames received so far
+    ///
+    /// A frame counts as received when it is decoded, so delays include time spent
+    /// buffered before [`IpcConnection::recv`] was called.
+    pub fn transit_stats(&self) -> TransitStats {
+        self.transit
+    }
+
+    /// Charges the receive buffer against a budget shared with other connections
+    ///
+    /// See [`BufferPool`]. Any previous pool is released, and bytes already
+    /// buffered are charged to the new one even if they exceed its budget.
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        if let Some(previous) = self.pool.take() {
+            previous.release(self.charged);
+        }
+        if self.buffer.capacity() == 0 {
+            self.buffer = pool.acquire();
+        }
+        self.charged = self.buffer.len();
+        pool.charge_unchecked(self.charged);
+        self.pool = Some(pool);
+    }
+
+    /// Enables or disables strict protocol mode
+    ///
+    /// By default a message that cannot be decoded as `R` is skipped and reported as an
+    /// error, leaving the connection usable. In strict mode, intended for security-sensitive
+    /// deployments, any such violation terminates the connection immediately and the reason
+    /// is logged to the `privileged_ipc::audit` target.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Sets the largest encoded message, in bytes, that may be sent or received
+    ///
+    /// Sending a larger message fails without writing anything. Receiving one fails
+    /// with `IpcError::MessageTooLarge`, and if the message is still incomplete when
+    /// the limit is reached the connection is closed, as the stream can't be resynchronised.
+    pub fn set_max_message_size(&mut self, limit: usize) {
+        self.max_message_size = limit;
+    }
+
+    /// Returns the largest encoded message that may be sent or received
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
     /// Sends a message over the connection
     pub fn send(&mut self, message: &S) -> Result<(), IpcError> {
-        match serde_json::to_writer(&self.connection.socket, message) {
-            Ok(_) => {
-                // Try to flush, but handle broken pipe gracefully
-                match self.connection.socket.flush() {
-                    Ok(_) => Ok(()),
-                    Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
-                        Err(IpcError::ConnectionClosed)
-                    }
-                    Err(e) => Err(IpcError::Io(e)),
+        let (frame, fds) = self.encode_frame(message)?;
+        self.write_frames(&frame, &fds)
+    }
+
+    /// Sends a message, failing with [`IpcError::DeadlineExceeded`] if it can't be written by `deadline`
+    ///
+    /// Writes as [`WritePolicy::Deadline`] does for this message only: when part
+    /// of it was written already, the rest is queued and written before the next
+    /// message. A connection with a send queue queues the message as
+    /// [`IpcConnection::send`] does.
+    pub fn send_with_deadline(&mut self, message: &S, deadline: Instant) -> Result<(), IpcError> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let policy = std::mem::replace(&mut self.write_policy, WritePolicy::Deadline(remaining));
+        let sent = self.send(message);
+        self.write_policy = policy;
+        match sent {
+            Err(IpcError::WriteTimeout) => Err(IpcError::DeadlineExceeded),
+            sent => sent,
+        }
+    }
+
+    /// Sends several messages, flushing them to the transport in a single write
+    ///
+    /// Every message is encoded before anything is written, so a message that
+    /// can't be encoded means none are sent. Messages carrying [`Blob`]s are
+    /// written separately, as the descriptors passed with a write must all belong
+    /// to one frame.
+    pub fn send_all(&mut self, messages: &[S]) -> Result<(), IpcError> {
+        let frames = messages
+            .iter()
+            .map(|message| self.encode_frame(message))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut batch = Vec::with_capacity(frames.iter().map(|(frame, _)| frame.len()).sum());
+        for (frame, fds) in frames {
+            if fds.is_empty() {
+                batch.extend_from_slice(&frame);
+                continue;
+            }
+            if !batch.is_empty() {
+                self.write_frames(&batch, &[])?;
+                batch.clear();
+            }
+            self.write_frames(&frame, &fds)?;
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.write_frames(&batch, &[])
+    }
+
+    /// Encodes a message into a complete frame, collecting the descriptors of its blobs
+    fn encode_frame(&mut self, message: &S) -> Result<(Vec<u8>, Vec<RawFd>), IpcError> {
+        let mut payload = vec![];
+        let (encoded, fds) = blob::collect_fds(|| self.codec.encode(message, &mut payload));
+        encoded?;
+        if fds.len() > blob::MAX_BLOBS_PER_MESSAGE {
+            return Err(IpcError::Codec(
+                format!(
+                    "message carries {} blobs, more than the limit of {}",
+                    fds.len(),
+                    blob::MAX_BLOBS_PER_MESSAGE
+                )
+                .into(),
+            ));
+        }
+        if self.timestamps {
+            payload = timing::stamp(&payload);
+        }
+        if let Some(cipher) = self.cipher.as_mut() {
+            payload = cipher.seal(&payload)?;
+        }
+        let frame = match self.framing {
+            Framing::Stream => payload,
+            Framing::Ndjson => {
+                if payload.contains(&b'\n') {
+                    return Err(IpcError::Codec(
+                        "encoded message contains a newline, which NDJSON framing can't carry"
+                            .into(),
+                    ));
+                }
+                payload.push(b'\n');
+                payload
+            }
+            Framing::NulTerminated => {
+                if payload.contains(&0) {
+                    return Err(IpcError::Codec(
+                        "encoded message contains a NUL byte, which NUL-terminated framing can't carry"
+                            .into(),
+                    ));
                 }
+                payload.push(0);
+                payload
+            }
+            Framing::LengthPrefixed => {
+                let too_large = IpcError::MessageTooLarge {
+                    size: payload.len(),
+                    limit: self.max_message_size,
+                };
+                let len = u32::try_from(payload.len()).map_err(|_| too_large)?;
+                let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+                frame.extend_from_slice(&len.to_be_bytes());
+                frame.extend_from_slice(&payload);
+                frame
+            }
+            Framing::ContentLength => {
+                let mut frame = format!("Content-Length: {}\r\n\r\n", payload.len()).into_bytes();
+                frame.extend_from_slice(&payload);
+                frame
             }
-            Err(e) if e.is_io() && e.io_error_kind() == Some(std::io::ErrorKind::BrokenPipe) => {
+        };
+        if frame.len() > self.max_message_size {
+            return Err(IpcError::MessageTooLarge {
+                size: frame.len(),
+                limit: self.max_message_size,
+            });
+        }
+        Ok((frame, fds))
+    }
+
+    /// Writes encoded frames along with the descriptors of the blobs they carry
+    ///
+    /// The descriptors must be borrowed from messages that outlive this call.
+    fn write_frames(&mut self, frames: &[u8], fds: &[RawFd]) -> Result<(), IpcError> {
+        // SAFETY: the caller still holds the blobs these descriptors belong to
+        let fds = fds
+            .iter()
+            .map(|fd| unsafe { BorrowedFd::borrow_raw(*fd) })
+            .collect::<Vec<_>>();
+
+        // Written on its own rather than with the descriptors, which the peer discards with it
+        if let Some(hello) = self.handshake.take_unsent() {
+            self.write_frames(&hello, &[])?;
+        }
+        if self.usage_reports.is_some() {
+            self.usage_tick()?;
+        }
+
+        if let Some(queue) = &self.send_queue {
+            if fds.is_empty() {
+                return queue.push(frames);
+            }
+            // Descriptors can only be passed on this thread
+            queue.wait_idle(None)?;
+        }
+
+        if self.write_policy != WritePolicy::Block || !self.outgoing.is_empty() {
+            return self.write_policed(frames, &fds);
+        }
+
+        // Handle broken pipe gracefully
+        match self.transport.write_with_fds(frames, &fds) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Err(IpcError::ConnectionClosed),
+            Err(e) => Err(IpcError::Io(e)),
+        }
+    }
+
+    /// Receives the next message, blocking until one is available
+    ///
+    /// Returns `Ok(None)` once the peer has closed the connection and all
+    /// buffered messages have been returned.
+    pub fn recv(&mut self) -> Result<Option<R>, IpcError> {
+        loop {
+            if let Some(message) = self.decode_buffered()? {
+                return Ok(Some(message));
+            }
+            if self.eof {
+                self.reached_end();
+                return Ok(None);
+            }
+            self.wait_for_peer()?;
+            self.read_chunk()?;
+        }
+    }
+
+    /// Receives the next message, failing with [`IpcError::DeadlineExceeded`] if none arrives by `deadline`
+    ///
+    /// Returns `Ok(None)` once the peer has closed the connection, as with
+    /// [`IpcConnection::recv`]. The connection remains usable after the deadline
+    /// passes, with any partial message kept for the next call. Fails if the
+    /// transport can't be polled.
+    pub fn recv_with_deadline(&mut self, deadline: Instant) -> Result<Option<R>, IpcError> {
+        loop {
+            if let Some(message) = self.decode_buffered()? {
+                return Ok(Some(message));
+            }
+            if self.eof {
+                self.reached_end();
+                return Ok(None);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(IpcError::DeadlineExceeded);
+            }
+            let timeout = match self.heartbeat_tick()? {
+                Some(due) => due.min(remaining),
+                None => remaining,
+            };
+            let Some(fd) = self.transport.poll_fd() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "deadlines not supported by this transport",
+                )
+                .into());
+            };
+            if wait_readable(fd, timeout)? {
+                self.read_chunk()?;
+            }
+        }
+    }
+
+    /// Attempts to receive a message without blocking
+    ///
+    /// Returns `Ok(None)` when no complete message has been buffered yet, making this
+    /// suitable for polling from an event loop. Once the peer has closed the connection
+    /// and all buffered messages have been returned, `IpcError::ConnectionClosed` is returned.
+    /// Fails if the transport doesn't support non-blocking reads.
+    pub fn try_recv(&mut self) -> Result<Option<R>, IpcError> {
+        if let Some(message) = self.decode_buffered()? {
+            return Ok(Some(message));
+        }
+
+        self.transport.set_nonblocking(true)?;
+        let filled = self.fill_buffer();
+        self.transport.set_nonblocking(false)?;
+        filled?;
+
+        match self.decode_buffered()? {
+            Some(message) => Ok(Some(message)),
+            None if self.eof => {
+                self.reached_end();
                 Err(IpcError::ConnectionClosed)
             }
-            Err(e) => Err(IpcError::Json(e)),
+            None => {
+                self.heartbeat_tick()?;
+                Ok(None)
+            }
         }
     }
 
+    /// Returns a borrowing iterator over incoming messages
+    ///
+    /// The iterator ends once the peer closes the connection. Messages left unread
+    /// when the iterator is dropped remain buffered for the next call to
+    /// [`IpcConnection::recv`] or [`IpcConnection::messages`].
+    pub fn messages(&mut self) -> Messages<'_, S, R, C> {
+        Messages { connection: self }
+    }
+
     /// Returns an iterator over incoming messages
-    pub fn incoming(&mut self) -> Result<IpcMessageIterator<R>, IpcError> {
-        let reader = std::io::BufReader::new(self.connection.socket.try_clone()?);
-        Ok(IpcMessageIterator {
-            deserializer: serde_json::Deserializer::from_reader(reader),
-            eof: false,
-            _phantom: std::marker::PhantomData,
-        })
+    #[deprecated(note = "use `IpcConnection::messages`, which can't fail")]
+    #[allow(deprecated)]
+    pub fn incoming(&mut self) -> Result<IpcMessageIterator<'_, S, R, C>, IpcError> {
+        Ok(self.messages())
+    }
+
+    /// Reads everything currently available on the (non-blocking) transport into the buffer
+    ///
+    /// Reading stops early once the buffer holds more than a maximum sized message,
+    /// so a flooding peer can't grow it without bound.
+    fn fill_buffer(&mut self) -> Result<(), IpcError> {
+        while !self.eof && self.buffer.len() <= self.max_message_size && self.read_chunk()? {}
+        Ok(())
+    }
+
+    /// Reads a single chunk from the transport into the buffer
+    ///
+    /// Returns `false` if the transport is non-blocking and has no data available.
+    fn read_chunk(&mut self) -> Result<bool, IpcError> {
+        let mut chunk = [0u8; 8192];
+        if !self.charge_pool(chunk.len()) {
+            // Let the messages already buffered be consumed before failing
+            if self.has_buffered_message() {
+                return Ok(false);
+            }
+            return Err(IpcError::Io(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "buffer pool exhausted",
+            )));
+        }
+        let result = self.read_into(&mut chunk);
+        self.settle_pool();
+        result
+    }
+
+    /// Reserves room in the pool for reading `len` more bytes, if there is a pool
+    fn charge_pool(&mut self, len: usize) -> bool {
+        self.settle_pool();
+        match &self.pool {
+            Some(pool) if !pool.charge(len) => false,
+            _ => {
+                self.charged += len;
+                true
+            }
+        }
+    }
+
+    /// Returns whatever the pool was charged beyond the bytes still buffered
+    fn settle_pool(&mut self) {
+        if let Some(pool) = &self.pool {
+            pool.release(self.charged - self.buffer.len());
+        }
+        self.charged = self.buffer.len();
+    }
+
+    /// Reads a single chunk from the transport into the buffer through `chunk`
+    fn read_into(&mut self, chunk: &mut [u8]) -> Result<bool, IpcError> {
+        let mut fds = vec![];
+        loop {
+            match self.transport.read_with_credentials(chunk, &mut fds) {
+                Ok((0, _)) => {
+                    self.eof = true;
+                    return Ok(true);
+                }
+                Ok((n, credentials)) => {
+                    if self.sender.is_none() {
+                        self.sender = credentials;
+                    }
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    if let Some(heartbeat) = self.heartbeat.as_mut() {
+                        heartbeat.heard();
+                    }
+                    // The kernel ends a read with the data the descriptors were sent with
+                    if !fds.is_empty() {
+                        self.fds.push_back((self.buffer.len() - 1, fds));
+                    }
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e)
+                    if e.get_ref()
+                        .is_some_and(|inner| inner.is::<blob::Truncated>()) =>
+                {
+                    // The frame the descriptors came with is incomplete for good, and
+                    // so is every frame after it
+                    self.buffer.clear();
+                    self.fds.clear();
+                    self.eof = true;
+                    // The peer may already be gone, which is fine
+                    let _ = self.transport.shutdown(Shutdown::Both);
+                    self.enter_state(ConnectionState::Closed);
+                    return Err(IpcError::DescriptorsTruncated);
+                }
+                Err(e)
+                    if e.kind() == io::ErrorKind::ConnectionReset
+                        || e.kind() == io::ErrorKind::BrokenPipe =>
+                {
+                    self.eof = true;
+                    return Ok(true);
+                }
+                Err(e) => return Err(IpcError::Io(e)),
+            }
+        }
+    }
+
+    /// Decodes a single complete message from the front of the buffer, if there is one
+    fn decode_buffered(&mut self) -> Result<Option<R>, IpcError> {
+        if !self.take_token()? || !self.take_hello()? {
+            return Ok(None);
+        }
+        self.take_control_frames();
+        if self.partial_control_frame() {
+            return Ok(None);
+        }
+        let Frame { payload, len: end } = match self.buffered_frame() {
+            Ok(Some(frame)) if frame.len > self.max_message_size => {
+                return Err(self.oversized(frame.len));
+            }
+            Ok(Some(frame)) => frame,
+            Ok(None)
+                if self.buffer.len() > self.max_message_size
+                    || decode::frame_header(&self.buffer, self.framing)
+                        .ok()
+                        .flatten()
+                        .is_some_and(|(_, end)| end > self.max_message_size) =>
+            {
+                // The partial message is already too large to ever be accepted, and
+                // the stream can't be resynchronised without reading all of it
+                self.eof = true;
+                return Err(self.oversized(self.buffer.len()));
+            }
+            Ok(None) => return Ok(None),
+            Err(e) if self.strict => return Err(self.violation(format!("malformed message: {e}"))),
+            Err(e) => {
+                // Not a valid encoding, so there is no way to find the next message boundary
+                self.buffer.clear();
+                self.fds.clear();
+                self.eof = true;
+                return Err(e);
+            }
+        };
+
+        let timestamps = self.timestamps;
+        let decode = |codec: &C, payload: &[u8]| {
+            if timestamps {
+                let (sent, payload) = timing::split_stamp(payload)?;
+                Ok((codec.decode(payload)?, Some(sent)))
+            } else {
+                Ok((codec.decode(payload)?, None))
+            }
+        };
+        self.remember_frame(end);
+        let fds = self.take_fds(end);
+        let message = blob::provide_fds(fds, || match self.cipher.as_mut() {
+            Some(cipher) => cipher
+                .open(&self.buffer[payload])
+                .and_then(|plaintext| decode(&self.codec, &plaintext)),
+            None => decode(&self.codec, &self.buffer[payload]),
+        });
+        self.buffer.drain(..end);
+        self.settle_pool();
+        match message {
+            Ok((message, sent)) => {
+                if let Some(sent) = sent {
+                    self.transit.record(sent, timing::monotonic_ns());
+                }
+                Ok(Some(message))
+            }
+            Err(e) if self.strict => Err(self.violation(format!("unexpected message: {e}"))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Discards an oversized message occupying the first `size` bytes of the buffer
+    fn oversized(&mut self, size: usize) -> IpcError {
+        if self.strict {
+            return self.violation(format!("message of {size} bytes exceeds size limit"));
+        }
+        self.buffer.drain(..size);
+        self.take_fds(size);
+        self.settle_pool();
+        IpcError::MessageTooLarge {
+            size,
+            limit: self.max_message_size,
+        }
+    }
+
+    /// Removes the file descriptors received with the first `len` bytes of the buffer
+    ///
+    /// The offsets of the remaining descriptors are adjusted for those bytes being drained,
+    /// and the search for the end of the next frame starts over.
+    fn take_fds(&mut self, len: usize) -> Vec<OwnedFd> {
+        self.scan.take();
+        let mut taken = vec![];
+        while self.fds.front().is_some_and(|(offset, _)| *offset < len) {
+            taken.extend(self.fds.pop_front().into_iter().flat_map(|(_, fds)| fds));
+        }
+        for (offset, _) in &mut self.fds {
+            *offset -= len;
+        }
+        taken
+    }
+
+    /// Terminates the connection because of a protocol violation, auditing the reason
+    fn violation(&mut self, reason: String) -> IpcError {
+        log::warn!(target: "privileged_ipc::audit", "🚨 terminating connection: {reason}");
+        self.buffer.clear();
+        self.fds.clear();
+        self.eof = true;
+        // The peer may already be gone, which is fine
+        let _ = self.transport.shutdown(Shutdown::Both);
+        self.enter_state(ConnectionState::Closed);
+        IpcError::ProtocolViolation(reason)
+    }
+
+    /// Returns the credentials of the process on the other end of the connection
+    ///
+    /// Servers can use this to log or authorize the actual caller. Note that the kernel
+    /// records the credentials of whoever called `listen()`, so on the client side of a
+    /// helper spawned by [`ServiceConnection::new`] these are the client's own credentials,
+    /// as the listener is created before being handed to the helper. Clients check who
+    /// their helper runs as with [`IpcConnection::expect_peer_uid`] instead.
+    pub fn peer_credentials(&self) -> Result<PeerCredentials, IpcError> {
+        Ok(self.transport.peer_credentials()?)
     }
 
     /// Shuts down the connection
     pub fn shutdown(&mut self, how: Shutdown) -> Result<(), IpcError> {
-        self.connection.socket.shutdown(how)?;
+        self.transport.shutdown(how)?;
+        self.enter_state(match how {
+            Shutdown::Read | Shutdown::Write => ConnectionState::Draining,
+            Shutdown::Both => ConnectionState::Closed,
+        });
         Ok(())
     }
 }
 
-/// Iterator over incoming IPC messages
-pub struct IpcMessageIterator<R> {
-    deserializer: serde_json::Deserializer<IoRead<std::io::BufReader<UnixStream>>>,
-    eof: bool,
-    _phantom: std::marker::PhantomData<R>,
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize + serde::de::DeserializeOwned,
+    R: serde::Serialize + serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Creates two connected endpoints within the current process
+    ///
+    /// Messages sent on one are received by the other, so request/response
+    /// protocols can be exercised in plain unit tests without spawning a helper.
+    /// Both endpoints use the default codec and framing.
+    pub fn pair() -> Result<(Self, IpcConnection<R, S, C>), IpcError> {
+        let (a, b) = UnixStream::pair()?;
+        Ok((Self::new(a), IpcConnection::new(b)))
+    }
 }
 
-impl<R: serde::de::DeserializeOwned> Iterator for IpcMessageIterator<R> {
+impl<S, R, C: Codec> IpcConnection<S, R, C> {
+    /// Returns true if receiving can carry on after `error`, as only a single message was skipped
+    ///
+    /// Messages that fail to decode, or exceed the size limit, are dropped from
+    /// the buffer without losing track of where the next one starts, unless the
+    /// stream couldn't be resynchronised and was ended.
+    pub(crate) fn survives(&self, error: &IpcError) -> bool {
+        !self.eof
+            && matches!(
+                error,
+                IpcError::Json(_) | IpcError::Codec(_) | IpcError::MessageTooLarge { .. }
+            )
+    }
+
+    /// Returns true if a complete message is waiting in the buffer
+    fn has_buffered_message(&self) -> bool {
+        matches!(self.buffered_frame(), Ok(Some(_)))
+    }
+
+    /// Locates the first complete frame in the buffer
+    ///
+    /// The extent of a frame is found before decoding it, so that a message which
+    /// fails to deserialize into `R` can be skipped without desynchronising the stream.
+    fn buffered_frame(&self) -> Result<Option<Frame>, IpcError> {
+        if self.connect_token.is_some()
+            || self.handshake.awaiting_peer()
+            || self.partial_control_frame()
+        {
+            return Ok(None);
+        }
+        let mut scan = self.scan.take();
+        if scan.offset > self.buffer.len() {
+            // The buffer was emptied since, along with the frame being searched for
+            scan = StreamScan::default();
+        }
+        let frame = decode::resume_frame(&self.buffer, self.framing, &self.codec, &mut scan);
+        self.scan.set(scan);
+        frame
+    }
+}
+
+impl<S, R, C> Drop for IpcConnection<S, R, C> {
+    fn drop(&mut self) {
+        // Writing behind a partly written frame would corrupt it
+        let idle = self.outgoing.is_empty() && self.send_queue.as_ref().is_none_or(|q| q.is_idle());
+        if self.closing == control::Closing::PeerRequested && idle {
+            // Best effort, the peer treats a missing acknowledgement as a failure anyway
+            let _ = self.transport.write_all(&control::CLOSE_ACK.to_be_bytes());
+        }
+        self.lifecycle.enter(ConnectionState::Closed);
+        if let Some(pool) = self.pool.take() {
+            pool.release(self.charged);
+            pool.recycle(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+/// Borrowing iterator over incoming IPC messages
+pub struct Messages<'a, S, R, C = JsonCodec> {
+    connection: &'a mut IpcConnection<S, R, C>,
+}
+
+/// Iterator over incoming IPC messages
+#[deprecated(note = "renamed to `Messages`, returned by `IpcConnection::messages`")]
+pub type IpcMessageIterator<'a, S, R, C = JsonCodec> = Messages<'a, S, R, C>;
+
+impl<S, R, C> Iterator for Messages<'_, S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
     type Item = Result<R, IpcError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.eof {
-            return None;
-        }
+        self.connection.recv().transpose()
+    }
+}
 
-        match R::deserialize(&mut self.deserializer) {
-            Ok(msg) => Some(Ok(msg)),
-            Err(e) => {
-                // Handle both EOF and broken pipe/connection reset errors
-                if e.is_eof()
-                    || e.io_error_kind() == Some(std::io::ErrorKind::BrokenPipe)
-                    || e.io_error_kind() == Some(std::io::ErrorKind::ConnectionReset)
-                    || e.io_error_kind() == Some(std::io::ErrorKind::UnexpectedEof)
-                {
-                    self.eof = true;
-                    None
-                } else {
-                    Some(Err(IpcError::Json(e)))
-                }
+/// The socket an [`IpcServer`] accepts connections on
+enum Listener {
+    Unix(ServiceListener),
+    Path(path_socket::PathListener),
+    Registered(registry::Registration),
+    #[cfg(feature = "tcp")]
+    Tcp(std::net::TcpListener),
+}
+
+impl Listener {
+    fn accept(&self) -> Result<Box<dyn Transport>, IpcError> {
+        match self {
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.0.accept()?;
+                listener.authorize(&stream)?;
+                Ok(Box::new(stream))
+            }
+            Listener::Path(bound) => Ok(Box::new(bound.listener.accept()?.0)),
+            Listener::Registered(registration) => {
+                Ok(Box::new(registration.listener.listener.accept()?.0))
+            }
+            #[cfg(feature = "tcp")]
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                // Messages are written whole, so there's nothing to gain from coalescing
+                stream.set_nodelay(true)?;
+                Ok(Box::new(stream))
             }
         }
     }
+
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Listener::Unix(listener) => listener.as_fd(),
+            Listener::Path(bound) => bound.listener.as_fd(),
+            Listener::Registered(registration) => registration.listener.listener.as_fd(),
+            #[cfg(feature = "tcp")]
+            Listener::Tcp(listener) => listener.as_fd(),
+        }
+    }
 }
 
 /// A type-safe IPC server that listens for connections
-pub struct IpcServer<S, R> {
-    listener: ServiceListener,
-    _phantom: std::marker::PhantomData<(S, R)>,
+pub struct IpcServer<S, R, C = JsonCodec> {
+    listener: Listener,
+    shutdown: ShutdownHandle,
+    max_message_size: usize,
+    framing: Framing,
+    timestamps: bool,
+    buffer_pool: Option<BufferPool>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    write_policy: WritePolicy,
+    protocol: Option<u32>,
+    capabilities: Capabilities,
+    multi_client: bool,
+    token: Option<Vec<u8>>,
+    /// Cleared once a server serving a single client has accepted it
+    listening: AtomicBool,
+    _phantom: std::marker::PhantomData<(S, R, C)>,
 }
 
-impl<S, R> IpcServer<S, R>
+impl<S, R, C> IpcServer<S, R, C>
 where
     S: serde::Serialize,
     R: serde::de::DeserializeOwned,
+    C: Codec,
 {
     /// Creates a new IPC server
+    ///
+    /// Listens on the socket the client set up, see [`ServiceListener`]. Under
+    /// pkexec, accepting a client running as another user fails with
+    /// [`IpcError::UnauthorizedPeer`], and one that beat the launching client to
+    /// an abstract address with [`IpcError::ForeignClient`]. The serve loops
+    /// carry on without either, as they do without clients refused for lacking
+    /// the token set with [`IpcServer::set_connect_token`].
     pub fn new() -> Result<Self, IpcError> {
-        Ok(Self {
-            listener: ServiceListener::new()?,
+        Ok(Self::with_listener(Listener::Unix(ServiceListener::new()?)))
+    }
+
+    /// Creates a server listening on a filesystem socket, which is unlinked when the server is dropped
+    ///
+    /// Clients connect with [`IpcConnection::connect_path`].
+    pub fn bind_path(socket: &PathSocket) -> Result<Self, IpcError> {
+        Ok(Self::with_listener(Listener::Path(socket.bind()?)))
+    }
+
+    fn with_listener(listener: Listener) -> Self {
+        Self {
+            listener,
+            shutdown: ShutdownHandle::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            framing: Framing::default(),
+            timestamps: false,
+            buffer_pool: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            write_policy: WritePolicy::default(),
+            protocol: None,
+            capabilities: Capabilities::empty(),
+            multi_client: false,
+            token: None,
+            listening: AtomicBool::new(true),
             _phantom: std::marker::PhantomData,
-        })
+        }
     }
 
     /// Accepts a new client connection
-    pub fn accept(&self) -> Result<IpcConnection<S, R>, IpcError> {
-        let (socket, _) = self.listener.accept()?;
-        let connection = ServiceConnection {
-            socket,
-            _child: nix::unistd::Pid::from_raw(0), // No child process for server side
+    ///
+    /// Unless [`IpcServer::set_multi_client`] was called, a server listening on
+    /// the socket its client set up stops listening once it has accepted that
+    /// client, so no other process can attach to the helper. Clients that connect
+    /// later are refused, and the serve loops shut down once the session ends.
+    pub fn accept(&self) -> Result<IpcConnection<S, R, C>, IpcError> {
+        if !self.listening.load(Ordering::SeqCst) {
+            return Err(IpcError::Io(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the server stopped listening once its client connected",
+            )));
+        }
+        let mut transport = self.listener.accept()?;
+        // A single client is checked before it takes the place of the one expected
+        if let (Some(token), false) = (&self.token, self.multi_client) {
+            token::verify(transport.as_mut(), token)?;
+        }
+        if !self.multi_client && matches!(self.listener, Listener::Unix(_)) {
+            self.stop_listening()?;
+        }
+        let mut connection = IpcConnection::new(transport);
+        if self.multi_client {
+            connection.connect_token = self.token.clone();
+        }
+        connection.set_max_message_size(self.max_message_size);
+        connection.set_framing(self.framing);
+        connection.set_timestamps(self.timestamps);
+        connection.set_write_policy(self.write_policy);
+        if let Some(protocol) = self.protocol {
+            connection.set_protocol(protocol);
+        }
+        connection.set_capabilities(self.capabilities);
+        if let Some(pool) = &self.buffer_pool {
+            connection.set_buffer_pool(pool.clone());
+        }
+        if let Some(size) = self.send_buffer_size {
+            connection.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            connection.set_recv_buffer_size(size)?;
+        }
+        Ok(connection)
+    }
+
+    /// Refuses clients from now on, as the one this helper was launched for has connected
+    fn stop_listening(&self) -> io::Result<()> {
+        self.listening.store(false, Ordering::SeqCst);
+        self.shutdown.shutdown();
+        let Listener::Unix(listener) = &self.listener else {
+            return Ok(());
         };
-        Ok(IpcConnection::new(connection))
+        // Later connections are refused outright, while those already waiting are dropped below
+        // SAFETY: the listener's descriptor stays open for the duration of the call
+        if unsafe { libc::shutdown(listener.as_raw_fd(), libc::SHUT_RDWR) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        listener.set_nonblocking(true)?;
+        while let Ok((stream, _)) = listener.0.accept() {
+            let pid = credentials::peer_credentials(stream.as_fd()).map(|c| c.pid);
+            log::warn!(target: "privileged_ipc::audit", "🚨 refused a client in process {pid:?}, as this helper serves a single client");
+        }
+        Ok(())
+    }
+
+    /// Keeps accepting clients after the first, which a server on the socket its client set up doesn't by default
+    ///
+    /// Needed by helpers shared through [`ServiceConnection::connect_or_spawn`], and
+    /// by those that diagnostic clients attach to. Servers created with
+    /// [`IpcServer::bind_path`] always accept several clients.
+    pub fn set_multi_client(&mut self, multi_client: bool) {
+        self.multi_client = multi_client;
+    }
+
+    /// Sets the message size limit applied to every accepted connection
+    pub fn set_max_message_size(&mut self, limit: usize) {
+        self.max_message_size = limit;
+    }
+
+    /// Sets the framing used by every accepted connection
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Enables or disables timestamping on every accepted connection
+    ///
+    /// See [`IpcConnection::set_timestamps`].
+    pub fn set_timestamps(&mut self, enabled: bool) {
+        self.timestamps = enabled;
+    }
+
+    /// Charges the receive buffers of every accepted connection against one budget
+    ///
+    /// Without a pool, each connection may buffer up to its maximum message size.
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.buffer_pool = Some(pool);
+    }
+
+    /// Sets the socket send buffer size of every accepted connection
+    ///
+    /// See [`IpcConnection::set_send_buffer_size`].
+    pub fn set_send_buffer_size(&mut self, size: usize) {
+        self.send_buffer_size = Some(size);
+    }
+
+    /// Sets the socket receive buffer size of every accepted connection
+    ///
+    /// See [`IpcConnection::set_recv_buffer_size`].
+    pub fn set_recv_buffer_size(&mut self, size: usize) {
+        self.recv_buffer_size = Some(size);
+    }
+
+    /// Sets what sending does on every accepted connection when its client stops reading
+    ///
+    /// See [`IpcConnection::set_write_policy`].
+    pub fn set_write_policy(&mut self, policy: WritePolicy) {
+        self.write_policy = policy;
+    }
+
+    /// Sets the protocol version every accepted connection announces and requires
+    ///
+    /// See [`IpcConnection::set_protocol`]. A client speaking another protocol
+    /// fails on its first receive, ending only its own session.
+    pub fn set_protocol(&mut self, protocol: u32) {
+        self.protocol = Some(protocol);
+    }
+
+    /// Sets the capabilities every accepted connection advertises, see [`IpcConnection::set_capabilities`]
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Accepts a new client connection, giving up after `timeout`
+    ///
+    /// Returns `Ok(None)` if no client connected in time, allowing a server loop
+    /// to periodically check whether it should shut down.
+    pub fn accept_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<IpcConnection<S, R, C>>, IpcError> {
+        if wait_readable(self.listener.as_fd(), timeout)? {
+            self.accept().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns an iterator over incoming client connections
+    ///
+    /// Each item is the result of [`IpcServer::accept`]. The iterator only
+    /// returns `None` once a server serving a single client has accepted it.
+    pub fn incoming(&self) -> Incoming<'_, S, R, C> {
+        Incoming { server: self }
+    }
+}
+
+/// Iterator over client connections accepted by an [`IpcServer`]
+pub struct Incoming<'a, S, R, C = JsonCodec> {
+    server: &'a IpcServer<S, R, C>,
+}
+
+impl<S, R, C> Iterator for Incoming<'_, S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    type Item = Result<IpcConnection<S, R, C>, IpcError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.server.listening.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(self.server.accept())
+    }
+}
+
+/// Waits until `fd` is readable, returning `false` if `timeout` elapses first
+fn wait_readable(fd: BorrowedFd<'_>, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let poll_timeout = PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX);
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match nix::poll::poll(&mut fds, poll_timeout) {
+            Ok(0) => return Ok(false),
+            Ok(_) => return Ok(true),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
     }
 }
 
 /// A type-safe IPC client that connects to a server
-pub struct IpcClient<S, R> {
-    connection: IpcConnection<S, R>,
+pub struct IpcClient<S, R, C = JsonCodec> {
+    connection: IpcConnection<S, R, C>,
+    address: Option<SocketAddr>,
+    supervisor: Option<crash::Supervisor>,
     _phantom: std::marker::PhantomData<(S, R)>,
 }
-impl<S, R> IpcClient<S, R>
+impl<S, R, C> IpcClient<S, R, C>
 where
     S: serde::Serialize,
     R: serde::de::DeserializeOwned,
+    C: Codec,
 {
     /// Creates a new IPC client connection using the specified executor
     pub fn new<T: SocketExecutor>(executable: &str, args: &[&str]) -> Result<Self, IpcError> {
         let connection = ServiceConnection::new::<T>(executable, args)?;
+        Ok(Self::from_service(connection))
+    }
+
+    /// Creates a new IPC client connection using the specified executor, naming its address with `scheme`
+    pub fn new_with_scheme<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        scheme: &AddressScheme,
+    ) -> Result<Self, IpcError> {
+        let connection = ServiceConnection::new_with_scheme::<T>(executable, args, scheme)?;
+        Ok(Self::from_service(connection))
+    }
+
+    /// Creates a new IPC client connection over a filesystem socket, using the specified executor
+    ///
+    /// See [`ServiceConnection::new_with_socket`].
+    pub fn new_with_socket<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        socket: &PathSocket,
+    ) -> Result<Self, IpcError> {
+        let connection = ServiceConnection::new_with_socket::<T>(executable, args, socket)?;
+        Ok(Self::from_service(connection))
+    }
+
+    /// Connects to the helper listening on `endpoint`, launching it with the specified executor if needed
+    ///
+    /// See [`ServiceConnection::connect_or_spawn`].
+    pub fn connect_or_spawn<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        endpoint: &Endpoint,
+    ) -> Result<Self, IpcError> {
+        let connection = ServiceConnection::connect_or_spawn::<T>(executable, args, endpoint)?;
+        Ok(Self::from_service(connection))
+    }
+
+    /// Creates a new IPC client connection over a filesystem socket, authenticating every frame
+    ///
+    /// A fresh key is handed to the helper at spawn time, and a [`MessageAuthenticator`]
+    /// installed with it, so frames injected by any other process that reaches the socket
+    /// are rejected. The helper reads the key with [`read_spawn_key`] and installs a
+    /// matching [`MessageAuthenticator`] on the accepted connection.
+    pub fn new_authenticated<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        socket: &PathSocket,
+    ) -> Result<Self, IpcError> {
+        let key = generate_key()?;
+        let connection =
+            ServiceConnection::new_with_socket_and_key::<T>(executable, args, socket, &key)?;
+        let mut client = Self::from_service(connection);
+        client.set_cipher(MessageAuthenticator::new(&key, Role::Client));
+        Ok(client)
+    }
+
+    /// Spawns a helper speaking over its stdin and stdout, using the specified executor
+    ///
+    /// Intended for helpers that can't inherit a socket, such as those launched through
+    /// flatpak portals or into containers. The helper should use [`IpcConnection::from_stdio`].
+    pub fn new_stdio<T: SocketExecutor>(executable: &str, args: &[&str]) -> Result<Self, IpcError> {
+        let mut command = T::default().command(executable, args);
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .env_remove("PKEXEC_UID");
+        let mut child = command.spawn().map_err(Error::IO)?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            unreachable!("stdin and stdout are piped");
+        };
+
+        let mut connection = IpcConnection::from_pipes(stdout, stdin);
+        connection.set_framing(Framing::ContentLength);
         Ok(Self {
-            connection: IpcConnection::new(connection),
+            connection,
+            address: None,
+            supervisor: Some(crash::Supervisor::spawn(child, None, None).map_err(Error::IO)?),
             _phantom: std::marker::PhantomData,
         })
     }
+
+    /// Returns the address the helper is listening on, unless it speaks over stdio
+    ///
+    /// See [`ServiceConnection::address`].
+    pub fn server_address(&self) -> Option<&SocketAddr> {
+        self.address.as_ref()
+    }
+
+    fn from_service(mut connection: ServiceConnection) -> Self {
+        Self {
+            supervisor: connection.supervisor.take(),
+            address: Some(connection.address().clone()),
+            connection: IpcConnection::new(connection),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<S, R, C> From<IpcClient<S, R, C>> for IpcConnection<S, R, C> {
+    fn from(client: IpcClient<S, R, C>) -> Self {
+        client.connection
+    }
 }
 
-impl<S, R> DerefMut for IpcClient<S, R> {
+impl<S, R, C> DerefMut for IpcClient<S, R, C> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.connection
     }
 }
 
-impl<S, R> Deref for IpcClient<S, R> {
-    type Target = IpcConnection<S, R>;
+impl<S, R, C> Deref for IpcClient<S, R, C> {
+    type Target = IpcConnection<S, R, C>;
 
     fn deref(&self) -> &Self::Target {
         &self.connection
@@ -0,0 +1,260 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Observable connection state
+//!
+//! A status indicator shouldn't have to infer from errors whether a helper is
+//! still starting, waiting on the user to authenticate, or gone. Every
+//! [`IpcConnection`] moves through the states of [`ConnectionState`] and reports
+//! each transition to whoever subscribed to its [`Lifecycle`].
+//!
+//! A connection only exists once it is established, so the states before that
+//! are reported by [`Lifecycle::connect`], which the connection created within it
+//! then joins. A [`ReconnectingClient`](crate::ReconnectingClient) keeps a single
+//! lifecycle across all of its connections.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::IpcConnection;
+
+type Subscriber = Box<dyn FnMut(Transition) + Send>;
+
+thread_local! {
+    /// The lifecycle of the connection being established on this thread, if observed
+    static CONNECTING: RefCell<Option<Lifecycle>> = const { RefCell::new(None) };
+}
+
+/// The state of a connection, as reported to [`Lifecycle`] subscribers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The helper is being launched or connected to
+    Connecting,
+    /// The helper is waiting on the user to authorize it, such as in a pkexec prompt
+    Authenticating,
+    /// Messages can be exchanged
+    Ready,
+    /// One side has started closing, and the remaining responses are being exchanged
+    Draining,
+    /// The connection was closed, lost, or couldn't be established
+    Closed,
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Connecting => "connecting",
+            Self::Authenticating => "authenticating",
+            Self::Ready => "ready",
+            Self::Draining => "draining",
+            Self::Closed => "closed",
+        })
+    }
+}
+
+/// A change from one [`ConnectionState`] to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    /// The state left
+    pub from: ConnectionState,
+    /// The state entered
+    pub to: ConnectionState,
+}
+
+struct Inner {
+    state: ConnectionState,
+    subscribers: Vec<Subscriber>,
+    /// Identifies the connection whose state this follows
+    attached: u64,
+}
+
+/// A shared handle to the state of a connection, which transitions can be subscribed to
+///
+/// Clones refer to the same lifecycle, so one can be handed to a GUI thread while
+/// the connection is used elsewhere.
+#[derive(Clone)]
+pub struct Lifecycle {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Lifecycle {
+    /// Creates a lifecycle for a connection that is about to be established
+    pub fn new() -> Self {
+        Self::starting_at(ConnectionState::Connecting)
+    }
+
+    fn starting_at(state: ConnectionState) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                state,
+                subscribers: vec![],
+                attached: 0,
+            })),
+        }
+    }
+
+    /// Returns the current state
+    pub fn state(&self) -> ConnectionState {
+        self.lock().state
+    }
+
+    /// Calls `subscriber` on every transition from now on
+    ///
+    /// Subscribers are called on whichever thread caused the transition, so should
+    /// hand the state to their own event loop rather than block.
+    pub fn subscribe(&self, subscriber: impl FnMut(Transition) + Send + 'static) {
+        self.lock().subscribers.push(Box::new(subscriber));
+    }
+
+    /// Establishes a connection with `connect`, reporting its progress
+    ///
+    /// The first [`IpcConnection`] created by `connect` on this thread joins the
+    /// lifecycle, which is [`ConnectionState::Ready`] once it returns. Helpers
+    /// launched with an escalating executor such as
+    /// [`PkexecExecutor`](crate::PkexecExecutor) are waited on while the user
+    /// authenticates, as with [`set_escalation_hook`](crate::set_escalation_hook).
+    /// If `connect` fails, the lifecycle is [`ConnectionState::Closed`].
+    pub fn connect<T, E>(&self, connect: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        self.enter(ConnectionState::Connecting);
+        let outer = CONNECTING.replace(Some(self.clone()));
+        let result = connect();
+        CONNECTING.set(outer);
+        self.enter(match result {
+            Ok(_) => ConnectionState::Ready,
+            Err(_) => ConnectionState::Closed,
+        });
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Moves to `state` and notifies subscribers, unless already there
+    fn enter(&self, state: ConnectionState) {
+        let mut inner = self.lock();
+        if inner.state == state {
+            return;
+        }
+        let transition = Transition {
+            from: inner.state,
+            to: state,
+        };
+        inner.state = state;
+        // Subscribers may well query or subscribe to this lifecycle themselves
+        let mut subscribers = std::mem::take(&mut inner.subscribers);
+        drop(inner);
+        for subscriber in &mut subscribers {
+            subscriber(transition);
+        }
+        let mut inner = self.lock();
+        let added = std::mem::replace(&mut inner.subscribers, subscribers);
+        inner.subscribers.extend(added);
+    }
+
+    /// Makes the lifecycle follow a new connection, returning the connection's id
+    fn attach(&self) -> u64 {
+        let mut inner = self.lock();
+        inner.attached += 1;
+        inner.attached
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("Lifecycle")
+            .field("state", &inner.state)
+            .field("subscribers", &inner.subscribers.len())
+            .finish()
+    }
+}
+
+/// A connection's membership of a [`Lifecycle`]
+pub(crate) struct Membership {
+    lifecycle: Lifecycle,
+    id: u64,
+    state: ConnectionState,
+}
+
+impl Membership {
+    /// Joins the lifecycle being connected on this thread, or starts a new one
+    pub(crate) fn join() -> Self {
+        let lifecycle = CONNECTING
+            .take()
+            .unwrap_or_else(|| Lifecycle::starting_at(ConnectionState::Ready));
+        Self::attach(lifecycle, ConnectionState::Ready)
+    }
+
+    fn attach(lifecycle: Lifecycle, state: ConnectionState) -> Self {
+        let id = lifecycle.attach();
+        lifecycle.enter(state);
+        Self {
+            lifecycle,
+            id,
+            state,
+        }
+    }
+
+    /// Moves the connection to `state`, reporting it unless a newer connection took over
+    pub(crate) fn enter(&mut self, state: ConnectionState) {
+        self.state = state;
+        if self.lifecycle.lock().attached == self.id {
+            self.lifecycle.enter(state);
+        }
+    }
+}
+
+/// Reports that the helper being launched on this thread awaits authorization
+///
+/// Returns false if the connection isn't being observed.
+pub(crate) fn authenticating() -> bool {
+    match CONNECTING.with_borrow(Option::clone) {
+        Some(lifecycle) => {
+            lifecycle.enter(ConnectionState::Authenticating);
+            true
+        }
+        None => false,
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Returns the state of the connection
+    pub fn state(&self) -> ConnectionState {
+        self.lifecycle.state
+    }
+
+    /// Returns the lifecycle reporting this connection's transitions
+    pub fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle.lifecycle
+    }
+
+    /// Reports this connection's transitions to `lifecycle` from now on
+    ///
+    /// The lifecycle moves to the connection's current state straight away, and
+    /// stops following any connection it followed before.
+    pub fn set_lifecycle(&mut self, lifecycle: Lifecycle) {
+        self.lifecycle = Membership::attach(lifecycle, self.lifecycle.state);
+    }
+
+    pub(crate) fn enter_state(&mut self, state: ConnectionState) {
+        self.lifecycle.enter(state);
+    }
+
+    /// Records that the peer has gone, unless it is waiting for the close to be acknowledged
+    pub(crate) fn reached_end(&mut self) {
+        if self.closing != crate::control::Closing::PeerRequested {
+            self.enter_state(ConnectionState::Closed);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Unix sockets bound to filesystem paths
+//!
+//! Abstract sockets have no filesystem permissions, so any local user who learns
+//! the name can connect. A [`PathSocket`] is instead bound within a directory,
+//! with its mode bits and ownership applied before it becomes reachable.
+
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    io,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use nix::unistd::{Gid, Uid};
+
+/// Where and how to bind a filesystem socket
+#[derive(Debug, Clone)]
+pub struct PathSocket {
+    directory: PathBuf,
+    name: String,
+    mode: u32,
+    owner: Option<Uid>,
+    group: Option<Gid>,
+}
+
+impl PathSocket {
+    /// Describes a socket within `directory`, with a random name and mode `0600`
+    ///
+    /// The directory is created with mode `0755` if it doesn't already exist.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            name: format!("{}.sock", uuid::Uuid::new_v4()),
+            mode: 0o600,
+            owner: None,
+            group: None,
+        }
+    }
+
+    /// Sets the file name of the socket within its directory
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the permission bits of the socket, such as `0660` to admit a group
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the user owning the socket, which requires privileges to change
+    pub fn owner(mut self, uid: Uid) -> Self {
+        self.owner = Some(uid);
+        self
+    }
+
+    /// Sets the group owning the socket
+    pub fn group(mut self, gid: Gid) -> Self {
+        self.group = Some(gid);
+        self
+    }
+
+    /// The full path the socket is bound to
+    pub fn path(&self) -> PathBuf {
+        self.directory.join(&self.name)
+    }
+
+    /// Binds the socket, replacing a stale socket left behind by a process that is gone
+    pub(crate) fn bind(&self) -> io::Result<PathListener> {
+        let path = self.path();
+        if !self.directory.exists() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o755)
+                .create(&self.directory)?;
+        }
+        remove_stale(&path)?;
+
+        // Bind under a temporary name and only move the socket into place once its
+        // permissions are set, so that it is never reachable with the wrong ones
+        let staging = self
+            .directory
+            .join(format!(".{}.{}", self.name, uuid::Uuid::new_v4()));
+        let listener = UnixListener::bind(&staging)?;
+        let staging = PathListener {
+            listener,
+            path: staging,
+            unlink: true,
+        };
+        fs::set_permissions(&staging.path, Permissions::from_mode(self.mode))?;
+        if self.owner.is_some() || self.group.is_some() {
+            nix::unistd::chown(&staging.path, self.owner, self.group)?;
+        }
+        fs::rename(&staging.path, &path)?;
+
+        let mut bound = staging;
+        bound.path = path;
+        Ok(bound)
+    }
+}
+
+/// A listener bound to a filesystem path, which is unlinked again on drop
+pub(crate) struct PathListener {
+    pub(crate) listener: UnixListener,
+    path: PathBuf,
+    unlink: bool,
+}
+
+impl PathListener {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Releases the listener, leaving the path in place for as long as it is listened on
+    ///
+    /// A path left behind once the listener is closed is replaced by the next bind.
+    pub(crate) fn persist(mut self) -> io::Result<UnixListener> {
+        self.unlink = false;
+        self.listener.try_clone()
+    }
+}
+
+impl Drop for PathListener {
+    fn drop(&mut self) {
+        if self.unlink {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Removes the socket at `path` if nothing is listening on it any more
+///
+/// Anything other than a socket, or a socket still in use, is left alone and
+/// causes binding to fail. Liveness is probed by connecting, so a running server
+/// sees one connection that closes without sending anything.
+pub(crate) fn remove_stale(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => match UnixStream::connect(path) {
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+            _ => Err(io::ErrorKind::AddrInUse.into()),
+        },
+        Ok(_) => Err(io::ErrorKind::AlreadyExists.into()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
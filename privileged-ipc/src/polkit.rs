@@ -0,0 +1,133 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Polkit actions guarding requests
+//!
+//! A helper's request type implements [`PolkitActions`] to declare which action
+//! each request needs and to list every action it may need. The same list feeds
+//! [`policy_document`], which writes the `.policy` file to install alongside the
+//! helper, and [`SelfTest::actions_of`](crate::SelfTest::actions_of), which checks
+//! that file is installed, so neither can drift from the code.
+
+use std::fmt::{self, Write};
+
+/// A polkit action that must be authorized before some requests are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Action {
+    /// Reverse-DNS action id, such as `com.serpentos.moss.install`
+    pub id: &'static str,
+    /// Short description of what the action allows
+    pub description: &'static str,
+    /// Shown to the user when authentication is requested
+    pub message: &'static str,
+    /// Authorization required of users in an active local session
+    ///
+    /// Inactive and remote sessions are never authorized.
+    pub allow_active: Authorization,
+}
+
+/// What polkit requires of a user before authorizing an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authorization {
+    /// Never authorized
+    No,
+    /// Always authorized
+    Yes,
+    /// The user must authenticate as themselves
+    AuthSelf,
+    /// The user must authenticate as an administrator
+    AuthAdmin,
+    /// As [`Authorization::AuthSelf`], remembered for a short while
+    AuthSelfKeep,
+    /// As [`Authorization::AuthAdmin`], remembered for a short while
+    AuthAdminKeep,
+}
+
+impl Authorization {
+    /// Returns the name used for this authorization in `.policy` files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::No => "no",
+            Self::Yes => "yes",
+            Self::AuthSelf => "auth_self",
+            Self::AuthAdmin => "auth_admin",
+            Self::AuthSelfKeep => "auth_self_keep",
+            Self::AuthAdminKeep => "auth_admin_keep",
+        }
+    }
+}
+
+impl fmt::Display for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A request type whose variants are guarded by polkit actions
+pub trait PolkitActions {
+    /// Every action that [`PolkitActions::action`] may return
+    const ACTIONS: &'static [Action];
+
+    /// Returns the action that must be authorized before the request is handled
+    ///
+    /// Requests that need no authorization return `None`.
+    fn action(&self) -> Option<&'static Action>;
+
+    /// Returns the id of the action that must be authorized before the request is handled
+    fn action_id(&self) -> Option<&'static str> {
+        self.action().map(|action| action.id)
+    }
+}
+
+/// Writes a polkit `.policy` document defining `actions`, attributed to `vendor`
+pub fn policy_document(vendor: &str, actions: &[Action]) -> String {
+    let mut document = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE policyconfig PUBLIC \"-//freedesktop//DTD PolicyKit Policy Configuration 1.0//EN\"\n",
+        " \"http://www.freedesktop.org/standards/PolicyKit/1/policyconfig.dtd\">\n",
+        "<policyconfig>\n",
+    ));
+    // Writing to a String can't fail
+    let _ = writeln!(document, "  <vendor>{}</vendor>", Escaped(vendor));
+    for action in actions {
+        let _ = write!(
+            document,
+            concat!(
+                "  <action id=\"{id}\">\n",
+                "    <description>{description}</description>\n",
+                "    <message>{message}</message>\n",
+                "    <defaults>\n",
+                "      <allow_any>no</allow_any>\n",
+                "      <allow_inactive>no</allow_inactive>\n",
+                "      <allow_active>{allow_active}</allow_active>\n",
+                "    </defaults>\n",
+                "  </action>\n",
+            ),
+            id = Escaped(action.id),
+            description = Escaped(action.description),
+            message = Escaped(action.message),
+            allow_active = action.allow_active,
+        );
+    }
+    document.push_str("</policyconfig>\n");
+    document
+}
+
+/// Displays text with the characters XML reserves escaped
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Shared budget for the receive buffers of many connections
+//!
+//! Each connection already caps its own buffer at the maximum message size, but a
+//! server with many clients could still need that much memory for every one of
+//! them in the worst case. Connections sharing a [`BufferPool`] draw from a single
+//! byte budget instead, so the server's peak buffer memory is bounded regardless
+//! of how many clients burst at once. Buffers of closed connections are kept for
+//! reuse by the next ones.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, PoisonError,
+};
+
+/// Most idle buffers kept for reuse
+const MAX_FREE_BUFFERS: usize = 32;
+
+/// Buffers that grew larger than this are freed rather than reused
+const MAX_REUSED_CAPACITY: usize = 64 * 1024;
+
+/// A byte budget shared by the receive buffers of several connections
+///
+/// Pools are cheap to clone, and clones share the same budget. Once the budget is
+/// exhausted, receiving fails with [`std::io::ErrorKind::OutOfMemory`] on whichever
+/// connection needed more, until other connections drain their buffers.
+#[derive(Clone, Debug)]
+pub struct BufferPool(Arc<PoolState>);
+
+#[derive(Debug)]
+struct PoolState {
+    limit: usize,
+    in_use: AtomicUsize,
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// Creates a pool allowing at most `limit` bytes to be buffered in total
+    pub fn new(limit: usize) -> Self {
+        Self(Arc::new(PoolState {
+            limit,
+            in_use: AtomicUsize::new(0),
+            free: Mutex::new(vec![]),
+        }))
+    }
+
+    /// Returns the total number of bytes that may be buffered
+    pub fn limit(&self) -> usize {
+        self.0.limit
+    }
+
+    /// Returns the number of bytes currently buffered or reserved for a read
+    pub fn in_use(&self) -> usize {
+        self.0.in_use.load(Ordering::Relaxed)
+    }
+
+    /// Reserves `bytes` of the budget, returning false if that would exceed it
+    pub(crate) fn charge(&self, bytes: usize) -> bool {
+        self.0
+            .in_use
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_use| {
+                in_use
+                    .checked_add(bytes)
+                    .filter(|total| *total <= self.0.limit)
+            })
+            .is_ok()
+    }
+
+    /// Reserves `bytes` of the budget even if that exceeds it
+    pub(crate) fn charge_unchecked(&self, bytes: usize) {
+        self.0.in_use.fetch_add(bytes, Ordering::AcqRel);
+    }
+
+    /// Returns `bytes` previously reserved with [`BufferPool::charge`]
+    pub(crate) fn release(&self, bytes: usize) {
+        self.0.in_use.fetch_sub(bytes, Ordering::AcqRel);
+    }
+
+    /// Takes an empty buffer, reusing one from a closed connection if possible
+    pub(crate) fn acquire(&self) -> Vec<u8> {
+        self.free().pop().unwrap_or_default()
+    }
+
+    /// Keeps the buffer of a closed connection for reuse
+    pub(crate) fn recycle(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_REUSED_CAPACITY {
+            return;
+        }
+        buffer.clear();
+        let mut free = self.free();
+        if free.len() < MAX_FREE_BUFFERS {
+            free.push(buffer);
+        }
+    }
+
+    fn free(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        self.0.free.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,193 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Checking a privileged backend is usable before relying on it
+//!
+//! A failure in the middle of the first real operation is a poor time to learn
+//! that the helper runs unprivileged or doesn't answer. An application can run
+//! [`IpcClient::preflight`] behind its splash screen instead, and turn the
+//! report into a meaningful error page:
+//!
+//! ```ignore
+//! let report = client
+//!     .preflight()
+//!     .require_root()
+//!     .probe("version", Request::Version, |response| match response {
+//!         Response::Version(1) => Ok("protocol 1".into()),
+//!         other => Err(format!("unexpected answer {other:?}")),
+//!     })
+//!     .run();
+//! ```
+
+use std::{
+    fs, io,
+    time::{Duration, Instant},
+};
+
+use nix::unistd::Uid;
+
+use crate::{Check, Codec, ConnectionState, Framing, IpcClient, SelfTestReport};
+
+/// How long each check waits for the helper by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Verify<'a, R> = Box<dyn FnOnce(&R) -> Result<String, String> + 'a>;
+
+/// The checks to run against a connected helper, created by [`IpcClient::preflight`]
+pub struct Preflight<'a, S, R, C> {
+    client: &'a mut IpcClient<S, R, C>,
+    timeout: Duration,
+    require_root: bool,
+    probes: Vec<(&'static str, S, Verify<'a, R>)>,
+}
+
+impl<S, R, C> IpcClient<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Prepares readiness checks of the helper, sending nothing until they run
+    ///
+    /// Besides any [`Preflight::probe`]s, the checks only look at the connection
+    /// and ping the helper, so they have no effect on it.
+    pub fn preflight(&mut self) -> Preflight<'_, S, R, C> {
+        Preflight {
+            client: self,
+            timeout: DEFAULT_TIMEOUT,
+            require_root: false,
+            probes: vec![],
+        }
+    }
+}
+
+impl<'a, S, R, C> Preflight<'a, S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Sets how long each check may wait for the helper, 5 seconds by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Requires the helper to run as root
+    ///
+    /// Otherwise its user is only reported. A helper this client launched is
+    /// looked up by its process, and a running service by the credentials of
+    /// its socket.
+    pub fn require_root(mut self) -> Self {
+        self.require_root = true;
+        self
+    }
+
+    /// Sends `request` and passes the response to `verify`, such as to check the protocol version
+    ///
+    /// `verify` returns a description of what it found, or why the response is
+    /// unacceptable. Probes run in the order they were added, after the
+    /// built-in checks.
+    pub fn probe(
+        mut self,
+        name: &'static str,
+        request: S,
+        verify: impl FnOnce(&R) -> Result<String, String> + 'a,
+    ) -> Self {
+        self.probes.push((name, request, Box::new(verify)));
+        self
+    }
+
+    /// Runs every check, returning the report
+    pub fn run(mut self) -> SelfTestReport {
+        let mut checks = vec![
+            self.check_connection(),
+            self.check_peer(),
+            self.check_ping(),
+        ];
+        for (name, request, verify) in self.probes {
+            checks.push(probe(self.client, name, request, verify, self.timeout));
+        }
+        SelfTestReport::new(checks)
+    }
+
+    fn check_connection(&self) -> Check {
+        match self.client.state() {
+            ConnectionState::Ready => Check::passed("connection", "connected"),
+            state => Check::failed("connection", format!("connection is {state}")),
+        }
+    }
+
+    fn check_peer(&self) -> Check {
+        // The socket of a launched helper was listened on by this process, so
+        // its credentials would be our own
+        let identity = match &self.client.supervisor {
+            Some(supervisor) => helper_identity(supervisor.pid()),
+            None => self
+                .client
+                .peer_credentials()
+                .map(|peer| (peer.pid.as_raw() as u32, peer.uid))
+                .map_err(io::Error::other),
+        };
+        let (pid, uid) = match identity {
+            Ok(identity) => identity,
+            Err(e) if self.require_root => return Check::failed("peer", e.to_string()),
+            Err(e) => return Check::skipped("peer", e.to_string()),
+        };
+        let detail = format!("helper {pid} runs as uid {uid}");
+        if self.require_root && !uid.is_root() {
+            Check::failed("peer", format!("{detail}, but root is required"))
+        } else {
+            Check::passed("peer", detail)
+        }
+    }
+
+    fn check_ping(&mut self) -> Check {
+        if self.client.framing() != Framing::LengthPrefixed {
+            return Check::skipped("ping", "pings need length-prefixed framing");
+        }
+        match self.client.ping(self.timeout) {
+            Ok(round_trip) => Check::passed("ping", format!("answered in {round_trip:?}")),
+            Err(e) => Check::failed("ping", e.to_string()),
+        }
+    }
+}
+
+/// Looks up the effective user of a running helper
+fn helper_identity(pid: u32) -> io::Result<(u32, Uid)> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status"))?;
+    // Real, effective, saved and filesystem uids, in that order
+    let euid = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|uids| uids.split_whitespace().nth(1))
+        .and_then(|euid| euid.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no uid in process status"))?;
+    Ok((pid, Uid::from_raw(euid)))
+}
+
+fn probe<S, R, C>(
+    client: &mut IpcClient<S, R, C>,
+    name: &'static str,
+    request: S,
+    verify: Verify<'_, R>,
+    timeout: Duration,
+) -> Check
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    if let Err(e) = client.send(&request) {
+        return Check::failed(name, format!("sending failed: {e}"));
+    }
+    match client.recv_with_deadline(Instant::now() + timeout) {
+        Ok(Some(response)) => match verify(&response) {
+            Ok(detail) => Check::passed(name, detail),
+            Err(reason) => Check::failed(name, reason),
+        },
+        Ok(None) => Check::failed(name, "helper closed the connection"),
+        Err(e) => Check::failed(name, e.to_string()),
+    }
+}
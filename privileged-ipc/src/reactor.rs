@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Single-threaded callback-driven multiplexing of connections
+//!
+//! A [`Reactor`] owns a set of connections, each with an `on_message` and
+//! `on_closed` callback, and dispatches to them as messages arrive. It is built
+//! on [`select`](crate::select) and requires no async runtime.
+
+use std::{io, time::Duration};
+
+use crate::{select, Codec, IpcConnection, IpcError, Pollable};
+
+/// Drives registered connections, dispatching their messages to callbacks
+#[derive(Default)]
+pub struct Reactor<'a> {
+    sources: Vec<Box<dyn Source + 'a>>,
+}
+
+impl<'a> Reactor<'a> {
+    /// Creates an empty reactor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a connection with its callbacks
+    ///
+    /// `on_message` is called for every message received, and may reply through the
+    /// connection it is given. `on_closed` is called once when the connection ends,
+    /// with the error that caused it, or `None` if the peer closed it cleanly. The
+    /// connection is dropped from the reactor after `on_closed` returns. Messages
+    /// that can't be decoded, or are too large, are logged and skipped, leaving
+    /// the connection registered.
+    pub fn register<S, R, C, M, F>(
+        &mut self,
+        connection: impl Into<IpcConnection<S, R, C>>,
+        on_message: M,
+        on_closed: F,
+    ) where
+        S: serde::Serialize + 'a,
+        R: serde::de::DeserializeOwned + 'a,
+        C: Codec + 'a,
+        M: FnMut(&mut IpcConnection<S, R, C>, R) + 'a,
+        F: FnOnce(Option<IpcError>) + 'a,
+    {
+        self.sources.push(Box::new(Registration {
+            connection: connection.into(),
+            on_message,
+            on_closed: Some(on_closed),
+        }));
+    }
+
+    /// Returns the number of connections still registered
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Returns true if no connections remain registered
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Waits up to `timeout` for activity and dispatches it
+    ///
+    /// Passing `None` waits indefinitely. Returns the number of connections that
+    /// had activity, which is zero if the timeout elapsed.
+    pub fn step(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+        if self.sources.is_empty() {
+            return Ok(0);
+        }
+
+        let ready = {
+            let mut pollables = self
+                .sources
+                .iter_mut()
+                .map(|s| s.pollable())
+                .collect::<Vec<_>>();
+            select(&mut pollables, timeout)?
+        };
+
+        // Dispatch in reverse so closed sources can be removed without shifting pending indices
+        for &index in ready.iter().rev() {
+            if !self.sources[index].dispatch() {
+                self.sources.remove(index);
+            }
+        }
+
+        Ok(ready.len())
+    }
+
+    /// Dispatches events until every registered connection has closed
+    pub fn run(&mut self) -> io::Result<()> {
+        while !self.sources.is_empty() {
+            self.step(None)?;
+        }
+        Ok(())
+    }
+}
+
+/// Type-erased view of a registered connection
+trait Source {
+    fn pollable(&mut self) -> &mut dyn Pollable;
+
+    /// Dispatches all buffered messages, returning false once the connection has closed
+    fn dispatch(&mut self) -> bool;
+}
+
+struct Registration<S, R, C, M, F> {
+    connection: IpcConnection<S, R, C>,
+    on_message: M,
+    on_closed: Option<F>,
+}
+
+impl<S, R, C, M, F> Source for Registration<S, R, C, M, F>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+    M: FnMut(&mut IpcConnection<S, R, C>, R),
+    F: FnOnce(Option<IpcError>),
+{
+    fn pollable(&mut self) -> &mut dyn Pollable {
+        &mut self.connection
+    }
+
+    fn dispatch(&mut self) -> bool {
+        loop {
+            let reason = match self.connection.try_recv() {
+                Ok(Some(message)) => {
+                    (self.on_message)(&mut self.connection, message);
+                    continue;
+                }
+                Ok(None) => return true,
+                Err(IpcError::ConnectionClosed) => None,
+                Err(e) if self.connection.survives(&e) => {
+                    log::warn!("🗑️ skipping a message that couldn't be received: {e}");
+                    continue;
+                }
+                Err(e) => Some(e),
+            };
+            if let Some(on_closed) = self.on_closed.take() {
+                on_closed(reason);
+            }
+            return false;
+        }
+    }
+}
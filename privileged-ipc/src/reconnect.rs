@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Clients that survive their service restarting
+//!
+//! A helper behind a persistent [`Endpoint`] may exit when idle, crash, or be
+//! restarted by an upgrade while a client still holds a connection to it. A
+//! [`ReconnectingClient`] notices the connection closing and connects to the
+//! endpoint again, launching the helper if needed, backing off between failed
+//! attempts. State the old session had built up on the helper is gone, so a
+//! callback can set it up again on every new connection.
+
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+    thread,
+};
+
+use crate::{
+    Backoff, Codec, Endpoint, Exponential, IpcClient, IpcConnection, IpcError, JsonCodec,
+    Lifecycle, SocketExecutor,
+};
+
+type Connector<S, R, C> = Box<dyn FnMut() -> Result<IpcClient<S, R, C>, IpcError> + Send>;
+type SessionSetup<S, R, C> =
+    Box<dyn FnMut(&mut IpcConnection<S, R, C>) -> Result<(), IpcError> + Send>;
+
+/// A client of a persistent service that reconnects whenever the connection closes
+///
+/// Other methods of the underlying [`IpcConnection`] are available through
+/// `Deref`, but only [`ReconnectingClient::send`] and [`ReconnectingClient::recv`]
+/// reconnect. Every connection joins the same [`Lifecycle`], so subscribers see
+/// the client go back to connecting rather than a new lifecycle each time.
+pub struct ReconnectingClient<S, R, C = JsonCodec> {
+    client: IpcClient<S, R, C>,
+    connect: Connector<S, R, C>,
+    backoff: Box<dyn Backoff>,
+    on_reconnect: Option<SessionSetup<S, R, C>>,
+    lifecycle: Lifecycle,
+}
+
+impl<S, R, C> ReconnectingClient<S, R, C>
+where
+    S: serde::Serialize + 'static,
+    R: serde::de::DeserializeOwned + 'static,
+    C: Codec + 'static,
+{
+    /// Connects to the helper listening on `endpoint`, launching it with the specified executor if needed
+    ///
+    /// Reconnecting does the same, so a helper that has exited is launched again.
+    /// See [`IpcClient::connect_or_spawn`].
+    pub fn connect_or_spawn<T: SocketExecutor + 'static>(
+        executable: &str,
+        args: &[&str],
+        endpoint: &Endpoint,
+    ) -> Result<Self, IpcError> {
+        let executable = executable.to_owned();
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let endpoint = endpoint.clone();
+        let mut connect: Connector<S, R, C> = Box::new(move || {
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            IpcClient::connect_or_spawn::<T>(&executable, &args, &endpoint)
+        });
+        let lifecycle = Lifecycle::new();
+        Ok(Self {
+            client: lifecycle.connect(&mut connect)?,
+            connect,
+            backoff: Box::new(Exponential::default()),
+            on_reconnect: None,
+            lifecycle,
+        })
+    }
+
+    /// Sets how long to wait between failed attempts to reconnect
+    ///
+    /// The first attempt is always made straight away. Defaults to
+    /// [`Exponential::default`], making eight attempts over about six seconds.
+    pub fn backoff(mut self, backoff: impl Backoff + 'static) -> Self {
+        self.backoff = Box::new(backoff);
+        self
+    }
+
+    /// Sets a callback run on every new connection before it is used
+    ///
+    /// The callback re-establishes whatever the session relies on, such as the
+    /// framing or a cipher, and any state held by the helper. It isn't run for the
+    /// initial connection. If it fails, reconnecting fails with its error.
+    pub fn on_reconnect(
+        mut self,
+        setup: impl FnMut(&mut IpcConnection<S, R, C>) -> Result<(), IpcError> + Send + 'static,
+    ) -> Self {
+        self.on_reconnect = Some(Box::new(setup));
+        self
+    }
+
+    /// Sends a message, reconnecting and sending it again if the connection has closed
+    pub fn send(&mut self, message: &S) -> Result<(), IpcError> {
+        match self.client.send(message) {
+            Err(e) if is_disconnect(&e) => {
+                self.reconnect()?;
+                self.client.send(message)
+            }
+            result => result,
+        }
+    }
+
+    /// Receives the next message, blocking until one is available
+    ///
+    /// If the connection closes first this reconnects, but still fails with
+    /// [`IpcError::ConnectionClosed`]: whatever the old helper was about to send is
+    /// lost, and it is up to the caller whether to retry the request.
+    pub fn recv(&mut self) -> Result<R, IpcError> {
+        match self.client.recv() {
+            Ok(Some(message)) => Ok(message),
+            Ok(None) => {
+                self.reconnect()?;
+                Err(IpcError::ConnectionClosed)
+            }
+            Err(e) if is_disconnect(&e) => {
+                self.reconnect()?;
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replaces the connection with a new one, retrying with backoff
+    ///
+    /// Returns the error of the last attempt once all have failed.
+    pub fn reconnect(&mut self) -> Result<(), IpcError> {
+        let mut attempt = 1;
+        let mut client = loop {
+            match self.lifecycle.connect(&mut self.connect) {
+                Ok(client) => break client,
+                Err(e) => {
+                    let Some(delay) = self.backoff.delay(attempt) else {
+                        return Err(e);
+                    };
+                    log::debug!(
+                        "🔌 reconnect attempt {attempt} failed, retrying in {delay:?}: {e}"
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        };
+        if let Some(setup) = self.on_reconnect.as_mut() {
+            setup(&mut client)?;
+        }
+        log::trace!("🔌 reconnected after {attempt} attempt(s)");
+        self.client = client;
+        Ok(())
+    }
+}
+
+/// Returns true for errors meaning the connection can no longer be used
+fn is_disconnect(error: &IpcError) -> bool {
+    match error {
+        IpcError::ConnectionClosed => true,
+        IpcError::Io(e) => matches!(
+            e.kind(),
+            io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::NotConnected
+        ),
+        _ => false,
+    }
+}
+
+impl<S, R, C> Deref for ReconnectingClient<S, R, C> {
+    type Target = IpcConnection<S, R, C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl<S, R, C> DerefMut for ReconnectingClient<S, R, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
@@ -0,0 +1,116 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Readable references to the protocols helpers speak
+//!
+//! A helper answers an introspection request with a [`ProtocolReference`] to
+//! its protocol, much as a varlink service answers `GetInterfaceDescription`,
+//! so a client can show what a helper it has never seen supports. The reference
+//! is rendered from the helper's own message types, so it can't drift from
+//! them: their shapes come from their [`TypeScript`] declarations and the
+//! authorization they need from their [`PolkitActions`](crate::PolkitActions).
+//! Notes cover what the types can't show, such as which responses are streamed
+//! before the final one.
+//!
+//! ```ignore
+//! let reference = ProtocolReference::new("disks")
+//!     .requests::<Request>()
+//!     .responses::<Response>()
+//!     .actions(Request::ACTIONS)
+//!     .note("Each request is answered by one response.");
+//! connection.send(&Response::Description { reference: reference.to_string() })?;
+//! ```
+
+use std::fmt;
+
+use crate::{Action, Declarations, TypeScript};
+
+/// A readable reference to a protocol, built from its message types
+///
+/// Displays as plain text, with the shapes of the messages declared in
+/// TypeScript as they are sent on the wire.
+#[derive(Debug)]
+pub struct ProtocolReference {
+    name: String,
+    requests: Option<String>,
+    responses: Option<String>,
+    declarations: Declarations,
+    actions: Vec<Action>,
+    notes: Vec<String>,
+}
+
+impl ProtocolReference {
+    /// Starts a reference to the protocol of the helper called `name`
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            requests: None,
+            responses: None,
+            declarations: Declarations::new(),
+            actions: vec![],
+            notes: vec![],
+        }
+    }
+
+    /// Declares `T` as the requests the helper answers
+    pub fn requests<T: TypeScript + ?Sized>(mut self) -> Self {
+        self.requests = Some(T::ts_type());
+        self.declarations.add::<T>();
+        self
+    }
+
+    /// Declares `T` as the responses the helper sends
+    pub fn responses<T: TypeScript + ?Sized>(mut self) -> Self {
+        self.responses = Some(T::ts_type());
+        self.declarations.add::<T>();
+        self
+    }
+
+    /// Lists the polkit actions that guard the requests, such as a request type's
+    /// [`PolkitActions::ACTIONS`](crate::PolkitActions::ACTIONS)
+    pub fn actions(mut self, actions: &[Action]) -> Self {
+        self.actions.extend_from_slice(actions);
+        self
+    }
+
+    /// Adds a note on semantics the types can't show
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl fmt::Display for ProtocolReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Protocol of {}", self.name)?;
+        if let Some(requests) = &self.requests {
+            writeln!(f, "  Requests:  {requests}")?;
+        }
+        if let Some(responses) = &self.responses {
+            writeln!(f, "  Responses: {responses}")?;
+        }
+        for note in &self.notes {
+            writeln!(f)?;
+            writeln!(f, "{note}")?;
+        }
+        if !self.actions.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Authorization")?;
+            for action in &self.actions {
+                writeln!(
+                    f,
+                    "  {}: {} ({} in active sessions)",
+                    action.id, action.description, action.allow_active
+                )?;
+            }
+        }
+        if !self.declarations.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Messages")?;
+            writeln!(f)?;
+            write!(f, "{}", self.declarations)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,440 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! A shared directory where system daemons can be discovered
+//!
+//! Daemons started by the system, rather than launched by a client, register
+//! under [`SYSTEM_REGISTRY`]: the socket goes at `<tool>.sock`, and next to it
+//! `<tool>.json` records the daemon's pid and the protocol version it speaks.
+//! Every tool shares the one directory, so a client only needs the tool's name to
+//! find its daemon with [`IpcClient::discover`], which launches a private helper
+//! when no compatible daemon is running. [`Discovery`] decides what happens when
+//! a daemon is running but can't be used, for deployments mixing both.
+
+use std::{
+    fs::{self, Permissions},
+    io::{self, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{
+            ffi::OsStrExt,
+            fs::PermissionsExt,
+            net::{SocketAddr, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    path_socket::PathListener, Backoff, Codec, Endpoint, Fixed, IpcClient, IpcConnection, IpcError,
+    IpcServer, PathSocket, SocketExecutor,
+};
+
+/// The directory system daemons register in
+pub const SYSTEM_REGISTRY: &str = "/run/serpent/ipc";
+
+/// How often a busy or incompatible daemon is tried again while waiting, by default
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a registered daemon records about itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceInfo {
+    /// Version of the protocol the daemon speaks, as defined by the tool
+    pub protocol: u32,
+    /// Process id of the daemon
+    pub pid: u32,
+}
+
+/// A directory of daemon sockets and their metadata, one of each per tool
+#[derive(Debug, Clone)]
+pub struct Registry {
+    directory: PathBuf,
+}
+
+impl Registry {
+    /// The registry at [`SYSTEM_REGISTRY`]
+    pub fn system() -> Self {
+        Self::at(SYSTEM_REGISTRY)
+    }
+
+    /// A registry in another directory, such as a per-user one for testing
+    pub fn at(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// The endpoint `tool` registers at
+    ///
+    /// Its socket has mode `0666`, as a daemon serves every user and must
+    /// authorize each request itself, such as through polkit.
+    pub fn endpoint(&self, tool: &str) -> Endpoint {
+        Endpoint::from_socket(
+            PathSocket::new(&self.directory)
+                .name(format!("{tool}.sock"))
+                .mode(0o666),
+        )
+    }
+
+    /// Returns what the daemon registered as `tool` recorded, if it is still running
+    ///
+    /// Missing or unreadable metadata, and metadata left behind by a process that
+    /// is gone, count as there being no daemon.
+    pub fn lookup(&self, tool: &str) -> io::Result<Option<ServiceInfo>> {
+        let metadata = match fs::read_to_string(self.metadata_path(tool)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let Some(info) = parse_info(&metadata) else {
+            log::debug!("🔌 ignoring malformed registration of {tool}");
+            return Ok(None);
+        };
+        Ok(is_alive(info.pid).then_some(info))
+    }
+
+    /// Connects to the daemon registered as `tool`, if it is running and speaks `protocol`
+    ///
+    /// A daemon too busy to accept the connection counts as not running. See
+    /// [`Discovery`] for telling the two apart.
+    pub fn connect<S, R, C>(
+        &self,
+        tool: &str,
+        protocol: u32,
+    ) -> Result<Option<IpcClient<S, R, C>>, IpcError>
+    where
+        S: serde::Serialize,
+        R: serde::de::DeserializeOwned,
+        C: Codec,
+    {
+        match self.probe(tool, protocol)? {
+            Probe::Connected(client) => Ok(Some(*client)),
+            Probe::Missing | Probe::Busy | Probe::Incompatible(_) => Ok(None),
+        }
+    }
+
+    /// Finds out whether the daemon registered as `tool` can be used, connecting if so
+    fn probe<S, R, C>(&self, tool: &str, protocol: u32) -> Result<Probe<S, R, C>, IpcError>
+    where
+        S: serde::Serialize,
+        R: serde::de::DeserializeOwned,
+        C: Codec,
+    {
+        let Some(info) = self.lookup(tool)? else {
+            return Ok(Probe::Missing);
+        };
+        if info.protocol != protocol {
+            log::debug!(
+                "🔌 {tool} daemon speaks protocol {}, not {protocol}",
+                info.protocol
+            );
+            return Ok(Probe::Incompatible(info.protocol));
+        }
+        let path = self.endpoint(tool).path();
+        let stream = match connect_now(&path) {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                log::debug!("🔌 {tool} daemon has no room for another connection");
+                return Ok(Probe::Busy);
+            }
+            // Registered, but not accepting connections yet or any more
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => return Ok(Probe::Busy),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Probe::Missing),
+            Err(e) => return Err(e.into()),
+        };
+        log::trace!("🔌 connected to {tool} daemon {} at {path:?}", info.pid);
+        Ok(Probe::Connected(Box::new(IpcClient {
+            connection: IpcConnection::new(stream),
+            address: Some(SocketAddr::from_pathname(&path)?),
+            supervisor: None,
+            _phantom: std::marker::PhantomData,
+        })))
+    }
+
+    fn metadata_path(&self, tool: &str) -> PathBuf {
+        self.directory.join(format!("{tool}.json"))
+    }
+}
+
+/// What a client found when trying the daemon of a tool
+enum Probe<S, R, C> {
+    Connected(Box<IpcClient<S, R, C>>),
+    /// No daemon is registered, or it is gone
+    Missing,
+    /// The daemon is running, but didn't take the connection
+    Busy,
+    /// The daemon speaks the given protocol version instead
+    Incompatible(u32),
+}
+
+/// What to do when a daemon was found but can't be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    /// Launch a private helper for this client instead
+    Spawn,
+    /// Keep trying the daemon for up to the given time, then fail
+    Wait(Duration),
+    /// Fail straight away
+    Fail,
+}
+
+/// How a client finds the daemon of a tool, and what it does when the daemon can't be used
+///
+/// Both fallbacks default to [`Fallback::Spawn`], so a client always ends up with
+/// a helper. Tools whose helpers mustn't run alongside their daemon, such as two
+/// processes writing one package database, should wait or fail instead.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    registry: Registry,
+    tool: String,
+    protocol: u32,
+    busy: Fallback,
+    incompatible: Fallback,
+    backoff: Arc<dyn Backoff>,
+}
+
+impl Discovery {
+    /// Looks for the daemon of `tool` speaking `protocol` in the system registry
+    pub fn new(tool: impl Into<String>, protocol: u32) -> Self {
+        Self {
+            registry: Registry::system(),
+            tool: tool.into(),
+            protocol,
+            busy: Fallback::Spawn,
+            incompatible: Fallback::Spawn,
+            backoff: Arc::new(Fixed::new(RETRY_INTERVAL)),
+        }
+    }
+
+    /// Looks in `registry` instead of the system registry
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Sets what to do when the daemon is running but doesn't accept the connection
+    pub fn when_busy(mut self, fallback: Fallback) -> Self {
+        self.busy = fallback;
+        self
+    }
+
+    /// Sets what to do when the daemon speaks a different protocol version
+    ///
+    /// Waiting gives an upgraded daemon the chance to be restarted.
+    pub fn when_incompatible(mut self, fallback: Fallback) -> Self {
+        self.incompatible = fallback;
+        self
+    }
+
+    /// Sets how long to wait between tries of the daemon under [`Fallback::Wait`]
+    ///
+    /// Defaults to trying every 100ms. Waiting ends early, failing as if the time
+    /// was up, once `backoff` gives up.
+    pub fn backoff(mut self, backoff: impl Backoff + 'static) -> Self {
+        self.backoff = Arc::new(backoff);
+        self
+    }
+}
+
+/// A daemon's entry in a [`Registry`], removed again when the server is dropped
+pub(crate) struct Registration {
+    pub(crate) listener: PathListener,
+    metadata: PathBuf,
+}
+
+impl Registration {
+    /// Binds the socket of `tool` and records this process as speaking `protocol`
+    fn create(registry: &Registry, tool: &str, protocol: u32) -> io::Result<Self> {
+        if tool.is_empty() || tool.starts_with('.') || tool.contains('/') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{tool:?} is not a valid tool name"),
+            ));
+        }
+        // Binding fails while another daemon is listening, so its metadata is never clobbered
+        let listener = registry.endpoint(tool).socket().bind()?;
+        let metadata = registry.metadata_path(tool);
+        let info = serde_json::json!({
+            "protocol": protocol,
+            "pid": std::process::id(),
+        });
+
+        // Written aside and renamed into place, so it is never seen half written
+        let staging = registry
+            .directory
+            .join(format!(".{tool}.json.{}", uuid::Uuid::new_v4()));
+        let written = fs::File::create(&staging).and_then(|mut file| {
+            file.set_permissions(Permissions::from_mode(0o644))?;
+            file.write_all(info.to_string().as_bytes())?;
+            fs::rename(&staging, &metadata)
+        });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&staging);
+            return Err(e);
+        }
+        Ok(Self { listener, metadata })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.listener.path()
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.metadata);
+    }
+}
+
+impl<S, R, C> IpcServer<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Creates a server registered as `tool` in `registry`, speaking `protocol`
+    ///
+    /// Intended for daemons started by the system. Fails with
+    /// [`io::ErrorKind::AddrInUse`] if another daemon is registered as `tool` and
+    /// still listening. The registration is removed when the server is dropped.
+    pub fn register(registry: &Registry, tool: &str, protocol: u32) -> Result<Self, IpcError> {
+        let registration = Registration::create(registry, tool, protocol)?;
+        log::trace!("🔌 registered {tool} at {:?}", registration.path());
+        Ok(Self::with_listener(crate::Listener::Registered(
+            registration,
+        )))
+    }
+}
+
+impl<S, R, C> IpcClient<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Connects to the system daemon of `tool`, launching a helper with the specified executor if there is none
+    ///
+    /// The daemon is only used if it speaks `protocol`. Otherwise, as when it isn't
+    /// running, `executable` is launched for this client alone, as with
+    /// [`IpcClient::new`].
+    pub fn discover<T: SocketExecutor>(
+        tool: &str,
+        protocol: u32,
+        executable: &str,
+        args: &[&str],
+    ) -> Result<Self, IpcError> {
+        Self::discover_with::<T>(&Discovery::new(tool, protocol), executable, args)
+    }
+
+    /// Connects to a daemon found as described by `discovery`, launching a helper if there is none
+    ///
+    /// If no daemon is registered, `executable` is launched for this client alone,
+    /// as with [`IpcClient::new`]. Otherwise the fallbacks of `discovery` decide
+    /// what happens when the daemon is busy or speaks another protocol version,
+    /// failing with [`IpcError::DaemonBusy`] or [`IpcError::DaemonIncompatible`].
+    pub fn discover_with<T: SocketExecutor>(
+        discovery: &Discovery,
+        executable: &str,
+        args: &[&str],
+    ) -> Result<Self, IpcError> {
+        let Discovery { registry, tool, .. } = discovery;
+        let started = Instant::now();
+        let mut failures = 0;
+        loop {
+            let (fallback, error) = match registry.probe(tool, discovery.protocol)? {
+                Probe::Connected(client) => return Ok(*client),
+                Probe::Missing => return Self::new::<T>(executable, args),
+                Probe::Busy => (discovery.busy, IpcError::DaemonBusy { tool: tool.clone() }),
+                Probe::Incompatible(protocol) => (
+                    discovery.incompatible,
+                    IpcError::DaemonIncompatible {
+                        tool: tool.clone(),
+                        protocol,
+                        expected: discovery.protocol,
+                    },
+                ),
+            };
+            match fallback {
+                Fallback::Spawn => return Self::new::<T>(executable, args),
+                Fallback::Wait(patience) if started.elapsed() < patience => {
+                    failures += 1;
+                    let Some(delay) = discovery.backoff.delay(failures) else {
+                        return Err(error);
+                    };
+                    thread::sleep(delay.min(patience.saturating_sub(started.elapsed())));
+                }
+                Fallback::Wait(_) | Fallback::Fail => return Err(error),
+            }
+        }
+    }
+}
+
+/// Connects to `path` without waiting for room in the listener's backlog
+///
+/// A daemon that has fallen behind on accepting connections makes this fail with
+/// [`io::ErrorKind::WouldBlock`], rather than block until it catches up.
+fn connect_now(path: &Path) -> io::Result<UnixStream> {
+    let bytes = path.as_os_str().as_bytes();
+    // SAFETY: sockaddr_un is plain data, for which all zeroes is a valid value
+    let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    if bytes.len() >= address.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "socket path is too long",
+        ));
+    }
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in address.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+
+    let flags = libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+    // SAFETY: plain system call without pointers
+    let fd = unsafe { libc::socket(libc::AF_UNIX, flags, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just created and is owned by nothing else
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: `address` is a fully initialised sockaddr_un of the given size
+    let ret = unsafe {
+        libc::connect(
+            socket.as_raw_fd(),
+            (&raw const address).cast(),
+            std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stream = UnixStream::from(socket);
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+fn parse_info(metadata: &str) -> Option<ServiceInfo> {
+    let value: serde_json::Value = serde_json::from_str(metadata).ok()?;
+    let field = |name| u32::try_from(value.get(name)?.as_u64()?).ok();
+    Some(ServiceInfo {
+        protocol: field("protocol")?,
+        pid: field("pid")?,
+    })
+}
+
+/// Returns whether a process with the given pid exists
+fn is_alive(pid: u32) -> bool {
+    // Zero would signal our own process group instead
+    let Ok(pid @ 1..) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process could be signalled
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // The process exists, but belongs to someone else
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
@@ -0,0 +1,283 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Recording what a peer sent, and playing it back with the original timing
+//!
+//! Testing how a client copes with a slow backend otherwise means running the
+//! privileged backend itself. A [`Recorder`] wraps the transport of a real
+//! session and notes every chunk the peer sent along with when it arrived. The
+//! [`Recording`] can be saved as a fixture, and a [`Replay`] then serves the
+//! same bytes to a client at the same moments, or proportionally sooner or later
+//! with [`Replay::scale`], without any backend at all.
+//!
+//! Only bytes are replayed. File descriptors the peer passed aren't recorded, and
+//! whatever the client sends to a replay is discarded, so a replay suits clients
+//! whose requests don't change what the backend answers.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::fd::{BorrowedFd, OwnedFd},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{PeerCredentials, Transport};
+
+/// Opens every saved recording
+const MAGIC: [u8; 4] = *b"PIPR";
+
+/// Bytes received from a peer, along with when each chunk arrived
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Chunk {
+    /// When the chunk arrived, since recording started
+    at: Duration,
+    bytes: Vec<u8>,
+}
+
+impl Recording {
+    /// Returns the number of chunks received
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Returns true if nothing was received
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns when the last chunk arrived, since recording started
+    pub fn duration(&self) -> Duration {
+        self.chunks.last().map_or(Duration::ZERO, |chunk| chunk.at)
+    }
+
+    /// Writes the recording to `writer`, to be loaded again by [`Recording::load`]
+    pub fn save(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        for chunk in &self.chunks {
+            let at = u64::try_from(chunk.at.as_nanos()).unwrap_or(u64::MAX);
+            let len = u32::try_from(chunk.bytes.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "chunk too large"))?;
+            writer.write_all(&at.to_be_bytes())?;
+            writer.write_all(&len.to_be_bytes())?;
+            writer.write_all(&chunk.bytes)?;
+        }
+        writer.flush()
+    }
+
+    /// Reads a recording written by [`Recording::save`]
+    pub fn load(mut reader: impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a privileged-ipc recording",
+            ));
+        }
+        let mut chunks = vec![];
+        let mut header = [0u8; 12];
+        loop {
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let (at, len) = header.split_at(8);
+            let at = u64::from_be_bytes(at.try_into().unwrap_or_default());
+            let len = u32::from_be_bytes(len.try_into().unwrap_or_default());
+            let mut bytes = vec![0u8; len as usize];
+            reader.read_exact(&mut bytes)?;
+            chunks.push(Chunk {
+                at: Duration::from_nanos(at),
+                bytes,
+            });
+        }
+        Ok(Self { chunks })
+    }
+}
+
+/// A recording that a [`Recorder`] is still adding to
+///
+/// Clones share the same recording, so one can be kept while the recorder is
+/// moved into a connection.
+#[derive(Debug, Clone, Default)]
+pub struct SharedRecording(Arc<Mutex<Recording>>);
+
+impl SharedRecording {
+    fn lock(&self) -> MutexGuard<'_, Recording> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns what has been recorded so far
+    pub fn snapshot(&self) -> Recording {
+        self.lock().clone()
+    }
+}
+
+/// Wraps a transport, recording every chunk read from it
+pub struct Recorder<T> {
+    inner: T,
+    recording: SharedRecording,
+    started: Instant,
+}
+
+impl<T: Transport> Recorder<T> {
+    /// Starts recording what is read from `inner`, timed from now
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            recording: SharedRecording::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Returns the recording being made
+    pub fn recording(&self) -> SharedRecording {
+        self.recording.clone()
+    }
+
+    fn note(&self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        self.recording.lock().chunks.push(Chunk {
+            at: self.started.elapsed(),
+            bytes: bytes.to_vec(),
+        });
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.note(&buf[..n]);
+        Ok(n)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_all(buf)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        self.inner.shutdown(how)
+    }
+
+    fn read_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        let n = self.inner.read_with_fds(buf, fds)?;
+        self.note(&buf[..n]);
+        Ok(n)
+    }
+
+    fn read_with_credentials(
+        &mut self,
+        buf: &mut [u8],
+        fds: &mut Vec<OwnedFd>,
+    ) -> io::Result<(usize, Option<PeerCredentials>)> {
+        let (n, credentials) = self.inner.read_with_credentials(buf, fds)?;
+        self.note(&buf[..n]);
+        Ok((n, credentials))
+    }
+
+    fn write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<()> {
+        self.inner.write_with_fds(buf, fds)
+    }
+
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        self.inner.send_file(file, offset, len)
+    }
+
+    fn try_write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        self.inner.try_write_with_fds(buf, fds)
+    }
+
+    fn write_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.inner.write_fd()
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.inner.poll_fd()
+    }
+
+    fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        self.inner.peer_credentials()
+    }
+}
+
+/// A transport serving a [`Recording`], each chunk no sooner than it originally arrived
+///
+/// Timing starts when the replay is created, as recording started when the
+/// [`Recorder`] was. A reader that falls behind gets what is due straight away,
+/// and the end of the recording reads as the peer closing the connection. Replays
+/// can't be polled, so only the blocking receive methods work.
+#[derive(Debug)]
+pub struct Replay {
+    chunks: std::vec::IntoIter<Chunk>,
+    /// What is left of a chunk that didn't fit the last read
+    pending: Vec<u8>,
+    scale: f64,
+    started: Instant,
+}
+
+impl Replay {
+    /// Replays `recording` with its original timing
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            chunks: recording.chunks.into_iter(),
+            pending: vec![],
+            scale: 1.0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Multiplies every delay by `scale`, so `0.5` replays twice as fast and `0.0` without waiting
+    pub fn scale(mut self, scale: f64) -> Self {
+        self.scale = scale.max(0.0);
+        self
+    }
+}
+
+impl Transport for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let Some(chunk) = self.chunks.next() else {
+                return Ok(0);
+            };
+            let delay = Duration::try_from_secs_f64(chunk.at.as_secs_f64() * self.scale)
+                .unwrap_or(Duration::ZERO);
+            if let Some(due) = self.started.checked_add(delay) {
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+            }
+            self.pending = chunk.bytes;
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+
+    /// Discards what the client sends
+    fn write_all(&mut self, _buf: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self, _how: Shutdown) -> io::Result<()> {
+        Ok(())
+    }
+}
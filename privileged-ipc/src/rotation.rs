@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Session key rotation on long-lived connections
+//!
+//! A [`RotatingCipher`] wraps another [`FrameCipher`] and periodically replaces its
+//! key without interrupting the connection, so a multi-hour installer session
+//! doesn't protect everything with one key. Each side rotates the key it sends
+//! with once a time or byte threshold is reached, and tags every frame with the
+//! number of rotations so far; the receiver follows along when the tag advances.
+//!
+//! New keys are derived from the previous one with HMAC-SHA256, and old keys are
+//! discarded, so a key captured late in a session can't decrypt earlier traffic.
+//! This is a one-way ratchet rather than a fresh key exchange: both sides must
+//! start from the same shared key, as with any other cipher.
+
+use std::time::{Duration, Instant};
+
+use crate::{authenticator, FrameCipher, IpcError};
+
+/// Length of the epoch tag preceding every frame
+const EPOCH_LEN: usize = 4;
+
+/// Furthest a received frame may ratchet ahead in one step
+const MAX_EPOCH_SKIP: u32 = 1024;
+
+/// Label mixed into every key derivation
+const ROTATION_LABEL: &[u8] = b"privileged-ipc key rotation";
+
+type CipherFactory = Box<dyn FnMut(&[u8]) -> Box<dyn FrameCipher> + Send>;
+
+/// A [`FrameCipher`] whose key is replaced after a configurable time or volume
+pub struct RotatingCipher {
+    make: CipherFactory,
+    sending: Direction,
+    receiving: Direction,
+    after_bytes: Option<u64>,
+    after: Option<Duration>,
+}
+
+/// The key chain of one direction of the connection
+struct Direction {
+    epoch: u32,
+    key: Vec<u8>,
+    cipher: Box<dyn FrameCipher>,
+    bytes: u64,
+    since: Instant,
+}
+
+impl Direction {
+    fn new(key: &[u8], make: &mut CipherFactory) -> Self {
+        Self {
+            epoch: 0,
+            key: key.to_vec(),
+            cipher: make(key),
+            bytes: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Moves on to the next key, forgetting the current one
+    fn ratchet(&mut self, make: &mut CipherFactory) {
+        self.key = next_key(&self.key);
+        self.cipher = make(&self.key);
+        self.epoch += 1;
+        self.bytes = 0;
+        self.since = Instant::now();
+    }
+}
+
+/// Derives the key that follows `key`
+fn next_key(key: &[u8]) -> Vec<u8> {
+    authenticator::hmac_sha256(key, &[ROTATION_LABEL]).to_vec()
+}
+
+impl RotatingCipher {
+    /// Creates a rotating cipher starting from a key shared by both ends
+    ///
+    /// `make` builds the inner cipher for each key, such as a
+    /// [`MessageAuthenticator`](crate::MessageAuthenticator). Without a threshold
+    /// set the key is never rotated.
+    pub fn new(
+        key: &[u8],
+        make: impl FnMut(&[u8]) -> Box<dyn FrameCipher> + Send + 'static,
+    ) -> Self {
+        let mut make: CipherFactory = Box::new(make);
+        Self {
+            sending: Direction::new(key, &mut make),
+            receiving: Direction::new(key, &mut make),
+            make,
+            after_bytes: None,
+            after: None,
+        }
+    }
+
+    /// Rotates the sending key once this many bytes have been sealed with it
+    pub fn rotate_after_bytes(mut self, bytes: u64) -> Self {
+        self.after_bytes = Some(bytes);
+        self
+    }
+
+    /// Rotates the sending key once it has been in use for `interval`
+    ///
+    /// Rotation happens when the next frame is sealed, so an idle connection keeps
+    /// its key until it sends again.
+    pub fn rotate_after(mut self, interval: Duration) -> Self {
+        self.after = Some(interval);
+        self
+    }
+
+    fn rotation_due(&self) -> bool {
+        self.after_bytes
+            .is_some_and(|limit| self.sending.bytes >= limit)
+            || self
+                .after
+                .is_some_and(|interval| self.sending.since.elapsed() >= interval)
+    }
+}
+
+impl FrameCipher for RotatingCipher {
+    fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, IpcError> {
+        if self.rotation_due() {
+            self.sending.ratchet(&mut self.make);
+            log::debug!("🔑 rotated sending key to epoch {}", self.sending.epoch);
+        }
+        let sealed = self.sending.cipher.seal(plaintext)?;
+        self.sending.bytes += plaintext.len() as u64;
+
+        let mut frame = Vec::with_capacity(EPOCH_LEN + sealed.len());
+        frame.extend_from_slice(&self.sending.epoch.to_be_bytes());
+        frame.extend_from_slice(&sealed);
+        Ok(frame)
+    }
+
+    fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, IpcError> {
+        let Some((epoch, sealed)) = ciphertext.split_first_chunk::<EPOCH_LEN>() else {
+            return Err(IpcError::Codec(
+                "frame too short to carry a key epoch".into(),
+            ));
+        };
+        let epoch = u32::from_be_bytes(*epoch);
+        if epoch < self.receiving.epoch {
+            return Err(IpcError::Codec(
+                format!("frame sealed with retired key epoch {epoch}").into(),
+            ));
+        }
+        if epoch - self.receiving.epoch > MAX_EPOCH_SKIP {
+            return Err(IpcError::Codec(
+                format!("frame claims implausible key epoch {epoch}").into(),
+            ));
+        }
+        if epoch == self.receiving.epoch {
+            return self.receiving.cipher.open(sealed);
+        }
+
+        // Only follow the peer to a new key once a frame sealed with it opens,
+        // so a forged epoch can't push the receiving side off the real chain
+        let mut key = self.receiving.key.clone();
+        for _ in self.receiving.epoch..epoch {
+            key = next_key(&key);
+        }
+        let mut cipher = (self.make)(&key);
+        let plaintext = cipher.open(sealed)?;
+        self.receiving = Direction {
+            epoch,
+            key,
+            cipher,
+            bytes: 0,
+            since: Instant::now(),
+        };
+        log::debug!("🔑 peer rotated to key epoch {epoch}");
+        Ok(plaintext)
+    }
+}
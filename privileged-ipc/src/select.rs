@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Waiting on several connections and servers at once
+//!
+//! A broker coordinating multiple helpers can use [`select`] to block until any
+//! of them has something to read, rather than dedicating a thread to each.
+
+use std::{
+    io,
+    os::fd::{AsFd, BorrowedFd},
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    poll::{PollFd, PollFlags, PollTimeout},
+};
+
+use crate::{Codec, IpcClient, IpcConnection, IpcServer, ServiceListener};
+
+/// A source that can be waited on by [`select`]
+pub trait Pollable {
+    /// The file descriptor to poll for readability
+    ///
+    /// Sources without one, such as connections over a [`StreamTransport`](crate::StreamTransport),
+    /// are only reported as readable while they have a message buffered.
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>>;
+
+    /// Returns true if a message is already buffered and can be read without waiting
+    fn has_pending(&self) -> bool {
+        false
+    }
+}
+
+/// Waits until at least one of `sources` is readable, returning the indices of those that are
+///
+/// A connection is readable when a complete message is buffered, data has arrived,
+/// or the peer has hung up; a server is readable when a client is waiting to be accepted.
+/// Passing `None` as the timeout blocks indefinitely, otherwise an empty list is
+/// returned once the timeout elapses.
+pub fn select(
+    sources: &mut [&mut dyn Pollable],
+    timeout: Option<Duration>,
+) -> io::Result<Vec<usize>> {
+    let pending = sources
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.has_pending())
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    // Buffered messages are ready right now, so only check the others without blocking
+    let timeout = if pending.is_empty() {
+        timeout
+    } else {
+        Some(Duration::ZERO)
+    };
+    let deadline = timeout.map(|t| Instant::now() + t);
+
+    let ready = loop {
+        let poll_timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX)
+            }
+            None => PollTimeout::NONE,
+        };
+        let (polled, mut fds): (Vec<_>, Vec<_>) = sources
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| Some((i, PollFd::new(s.poll_fd()?, PollFlags::POLLIN))))
+            .unzip();
+        match nix::poll::poll(&mut fds, poll_timeout) {
+            Ok(_) => {
+                break polled
+                    .into_iter()
+                    .zip(&fds)
+                    .filter(|(_, fd)| {
+                        fd.revents().is_some_and(|r| {
+                            r.intersects(
+                                PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR,
+                            )
+                        })
+                    })
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>()
+            }
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    Ok((0..sources.len())
+        .filter(|i| ready.contains(i) || pending.contains(i))
+        .collect())
+}
+
+impl<S, R, C: Codec> Pollable for IpcConnection<S, R, C> {
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.transport.poll_fd()
+    }
+
+    fn has_pending(&self) -> bool {
+        self.has_buffered_message()
+    }
+}
+
+impl<S, R, C: Codec> Pollable for IpcClient<S, R, C> {
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.connection.poll_fd()
+    }
+
+    fn has_pending(&self) -> bool {
+        self.connection.has_pending()
+    }
+}
+
+impl<S, R, C> Pollable for IpcServer<S, R, C> {
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.listener.as_fd())
+    }
+}
+
+impl Pollable for ServiceListener {
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.0.as_fd())
+    }
+}
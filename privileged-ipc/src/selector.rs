@@ -0,0 +1,177 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Receiving from whichever of several connections answers first
+//!
+//! A frontend talking to several helpers at once, such as moss and the installer,
+//! often just wants the next message from any of them. A [`Selector`] borrows the
+//! connections for as long as it is waited on, maps each one's messages into a
+//! common type, and tags them with the key the connection was added under. Unlike
+//! a [`Reactor`](crate::Reactor), it hands messages back rather than calling out,
+//! and the connections can be used to send again once it is dropped.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::{select, Codec, IpcConnection, IpcError, Pollable};
+
+/// Something that happened on one of the connections of a [`Selector`]
+#[derive(Debug)]
+pub enum Selected<K, M> {
+    /// The connection added under the key produced a message
+    Message(K, M),
+    /// The connection added under the key closed, removing it from the selector
+    ///
+    /// Carries the error that ended the connection, or `None` if the peer closed
+    /// it cleanly.
+    Closed(K, Option<IpcError>),
+    /// A message from the connection added under the key couldn't be decoded, or was too large
+    ///
+    /// Only that message was dropped, and the connection remains in the selector.
+    Skipped(K, IpcError),
+}
+
+/// Waits on several connections, returning messages tagged with the connection they came from
+pub struct Selector<'a, K, M> {
+    sources: Vec<(K, Box<dyn Source<M> + 'a>)>,
+    /// Where the next search for a message starts, so no connection can starve the others
+    next: usize,
+}
+
+impl<'a, K: Clone, M> Selector<'a, K, M> {
+    /// Creates an empty selector
+    pub fn new() -> Self {
+        Self {
+            sources: vec![],
+            next: 0,
+        }
+    }
+
+    /// Adds a connection under `key`, converting its messages with `map`
+    ///
+    /// Connections with different message types can be added to one selector by
+    /// mapping them into a common enum.
+    pub fn add<S, R, C>(
+        &mut self,
+        key: K,
+        connection: &'a mut IpcConnection<S, R, C>,
+        map: impl FnMut(R) -> M + 'a,
+    ) where
+        S: serde::Serialize + 'a,
+        R: serde::de::DeserializeOwned + 'a,
+        C: Codec + 'a,
+    {
+        self.sources
+            .push((key, Box::new(Mapped { connection, map })));
+    }
+
+    /// Returns the number of connections that haven't closed
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Returns true if every connection has closed, or none were added
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Waits for the next message from any connection, or for one to close
+    ///
+    /// Passing `None` as the timeout blocks until something happens. Returns
+    /// `None` once the timeout elapses, or straight away if no connections remain.
+    /// Fails if a connection's transport can't be polled.
+    pub fn recv(&mut self, timeout: Option<Duration>) -> io::Result<Option<Selected<K, M>>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        while !self.sources.is_empty() {
+            let ready = {
+                let mut pollables = self
+                    .sources
+                    .iter_mut()
+                    .map(|(_, source)| source.pollable())
+                    .collect::<Vec<_>>();
+                let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                select(&mut pollables, remaining)?
+            };
+            if ready.is_empty() {
+                return Ok(None);
+            }
+
+            let count = self.sources.len();
+            let start = self.next % count;
+            let mut ready = ready;
+            ready.sort_by_key(|&index| (index + count - start) % count);
+            for index in ready {
+                match self.sources[index].1.try_next() {
+                    Ok(Some(message)) => {
+                        self.next = index + 1;
+                        return Ok(Some(Selected::Message(
+                            self.sources[index].0.clone(),
+                            message,
+                        )));
+                    }
+                    // Only part of a message has arrived so far
+                    Ok(None) => {}
+                    Err(e) if self.sources[index].1.survives(&e) => {
+                        self.next = index + 1;
+                        return Ok(Some(Selected::Skipped(self.sources[index].0.clone(), e)));
+                    }
+                    Err(e) => {
+                        let (key, _) = self.sources.remove(index);
+                        self.next = index;
+                        let reason = match e {
+                            IpcError::ConnectionClosed => None,
+                            e => Some(e),
+                        };
+                        return Ok(Some(Selected::Closed(key, reason)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<K: Clone, M> Default for Selector<'_, K, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Type-erased view of a connection added to a selector
+trait Source<M> {
+    fn pollable(&mut self) -> &mut dyn Pollable;
+
+    /// Receives a message without blocking, failing once the connection has closed
+    fn try_next(&mut self) -> Result<Option<M>, IpcError>;
+
+    /// Returns true if the connection remains usable after `error`
+    fn survives(&self, error: &IpcError) -> bool;
+}
+
+struct Mapped<'a, S, R, C, F> {
+    connection: &'a mut IpcConnection<S, R, C>,
+    map: F,
+}
+
+impl<S, R, C, M, F> Source<M> for Mapped<'_, S, R, C, F>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+    F: FnMut(R) -> M,
+{
+    fn pollable(&mut self) -> &mut dyn Pollable {
+        self.connection
+    }
+
+    fn try_next(&mut self) -> Result<Option<M>, IpcError> {
+        Ok(self.connection.try_recv()?.map(&mut self.map))
+    }
+
+    fn survives(&self, error: &IpcError) -> bool {
+        self.connection.survives(error)
+    }
+}
@@ -0,0 +1,359 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Startup self-test for installed services
+//!
+//! Packaging scripts want to validate that a helper is installed correctly without
+//! driving a real client against it. A helper launched with `--self-test` can run
+//! [`IpcServer::self_test`] instead of serving, and print the report:
+//!
+//! ```ignore
+//! if std::env::args().any(|arg| arg == "--self-test") {
+//!     let report = IpcServer::<Response, Request>::self_test()
+//!         .request(Request::Version)
+//!         .response(Response::Version(1))
+//!         .policy("com.serpentos.moss.install")
+//!         .run();
+//!     println!("{}", serde_json::to_string(&report)?);
+//!     std::process::exit(if report.passed() { 0 } else { 1 });
+//! }
+//! ```
+
+use std::{
+    fs, io,
+    marker::PhantomData,
+    os::{
+        fd::AsFd,
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::{
+    credentials, AddressIdentifier, AddressScheme, Codec, Framing, IpcConnection, IpcServer,
+    PolkitActions,
+};
+
+/// Where polkit looks for action definitions
+const POLKIT_ACTIONS_DIR: &str = "/usr/share/polkit-1/actions";
+
+/// The checks to run for a service's self-test, created by [`IpcServer::self_test`]
+pub struct SelfTest<S, R, C> {
+    requests: Vec<R>,
+    responses: Vec<S>,
+    policies: Vec<String>,
+    require_root: bool,
+    framing: Framing,
+    _phantom: PhantomData<C>,
+}
+
+impl<S, R, C> IpcServer<S, R, C>
+where
+    S: serde::Serialize + serde::de::DeserializeOwned,
+    R: serde::Serialize + serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Prepares a self-test of this service's installation
+    ///
+    /// No listener is needed, so this works when the helper was launched directly
+    /// rather than by a client.
+    pub fn self_test() -> SelfTest<S, R, C> {
+        SelfTest {
+            requests: vec![],
+            responses: vec![],
+            policies: vec![],
+            require_root: false,
+            framing: Framing::default(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<S, R, C> SelfTest<S, R, C>
+where
+    S: serde::Serialize + serde::de::DeserializeOwned,
+    R: serde::Serialize + serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Adds a request that must survive a round trip through the codec
+    pub fn request(mut self, request: R) -> Self {
+        self.requests.push(request);
+        self
+    }
+
+    /// Adds a response that must survive a round trip through the codec
+    pub fn response(mut self, response: S) -> Self {
+        self.responses.push(response);
+        self
+    }
+
+    /// Requires a polkit action with the given id to be installed
+    pub fn policy(mut self, action: impl Into<String>) -> Self {
+        self.policies.push(action.into());
+        self
+    }
+
+    /// Requires every polkit action that requests of type `T` may need to be installed
+    pub fn actions_of<T: PolkitActions>(mut self) -> Self {
+        self.policies
+            .extend(T::ACTIONS.iter().map(|action| action.id.to_owned()));
+        self
+    }
+
+    /// Requires the self-test to run with root privileges
+    ///
+    /// Otherwise the effective user is only reported.
+    pub fn require_root(mut self) -> Self {
+        self.require_root = true;
+        self
+    }
+
+    /// Sets the framing used for codec round trips, which should match the server's
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Runs every check, returning the report
+    pub fn run(self) -> SelfTestReport {
+        let checks = vec![
+            self.check_codec(),
+            check_socket(),
+            self.check_privileges(),
+            self.check_policies(),
+        ];
+        SelfTestReport::new(checks)
+    }
+
+    /// Sends every sample over an in-process pair and checks it re-encodes identically
+    fn check_codec(&self) -> Check {
+        let samples = self.requests.len() + self.responses.len();
+        if samples == 0 {
+            return Check::skipped("codec", "no sample messages were given");
+        }
+        match self.round_trip() {
+            Ok(()) => Check::passed("codec", format!("{samples} message(s) round-tripped")),
+            Err(reason) => Check::failed("codec", reason),
+        }
+    }
+
+    fn round_trip(&self) -> Result<(), String> {
+        let (mut server, mut client) =
+            IpcConnection::<S, R, C>::pair().map_err(|e| e.to_string())?;
+        server.set_framing(self.framing);
+        client.set_framing(self.framing);
+
+        for (i, response) in self.responses.iter().enumerate() {
+            server
+                .send(response)
+                .map_err(|e| format!("response {i}: {e}"))?;
+            let received = client.recv().map_err(|e| format!("response {i}: {e}"))?;
+            compare(&client.codec, response, received.as_ref())
+                .map_err(|reason| format!("response {i}: {reason}"))?;
+        }
+        for (i, request) in self.requests.iter().enumerate() {
+            client
+                .send(request)
+                .map_err(|e| format!("request {i}: {e}"))?;
+            let received = server.recv().map_err(|e| format!("request {i}: {e}"))?;
+            compare(&server.codec, request, received.as_ref())
+                .map_err(|reason| format!("request {i}: {reason}"))?;
+        }
+        Ok(())
+    }
+
+    fn check_privileges(&self) -> Check {
+        let euid = nix::unistd::geteuid();
+        let detail = format!("running with effective uid {euid}");
+        if self.require_root && !euid.is_root() {
+            Check::failed("privileges", format!("{detail}, but root is required"))
+        } else {
+            Check::passed("privileges", detail)
+        }
+    }
+
+    fn check_policies(&self) -> Check {
+        if self.policies.is_empty() {
+            return Check::skipped("policy", "no polkit actions were given");
+        }
+        let installed = match installed_actions(Path::new(POLKIT_ACTIONS_DIR)) {
+            Ok(installed) => installed,
+            Err(e) => {
+                return Check::failed("policy", format!("can't read {POLKIT_ACTIONS_DIR}: {e}"))
+            }
+        };
+        let missing = self
+            .policies
+            .iter()
+            .filter(|action| !installed.iter().any(|id| id == *action))
+            .cloned()
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Check::passed(
+                "policy",
+                format!("{} polkit action(s) installed", self.policies.len()),
+            )
+        } else {
+            Check::failed(
+                "policy",
+                format!("missing polkit action(s): {}", missing.join(", ")),
+            )
+        }
+    }
+}
+
+/// Checks that a received message encodes the same as the one sent
+fn compare<T: Serialize>(codec: &impl Codec, sent: &T, received: Option<&T>) -> Result<(), String> {
+    let received = received.ok_or("connection closed before the message arrived")?;
+    let (mut expected, mut actual) = (vec![], vec![]);
+    codec
+        .encode(sent, &mut expected)
+        .map_err(|e| e.to_string())?;
+    codec
+        .encode(received, &mut actual)
+        .map_err(|e| e.to_string())?;
+    if expected != actual {
+        return Err("decoded message differs from the one sent".into());
+    }
+    Ok(())
+}
+
+/// Binds a fresh abstract socket and checks a connection to it carries credentials
+fn check_socket() -> Check {
+    let result = (|| -> io::Result<()> {
+        let address = AddressIdentifier::new(&AddressScheme::Anonymous).as_unix_address()?;
+        let listener = UnixListener::bind_addr(&address)?;
+        let _client = UnixStream::connect_addr(&address)?;
+        let (accepted, _) = listener.accept()?;
+        let peer = credentials::peer_credentials(accepted.as_fd())?;
+        if peer.pid != nix::unistd::getpid() {
+            return Err(io::Error::other(
+                "peer credentials don't match this process",
+            ));
+        }
+        Ok(())
+    })();
+    match result {
+        Ok(()) => Check::passed("socket", "bound, connected and verified peer credentials"),
+        Err(e) => Check::failed("socket", e.to_string()),
+    }
+}
+
+/// Collects the ids of the polkit actions defined in `dir`
+fn installed_actions(dir: &Path) -> io::Result<Vec<String>> {
+    let mut actions = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "policy") {
+            continue;
+        }
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        for tag in contents.split("<action").skip(1) {
+            let id = tag
+                .split_once("id=\"")
+                .and_then(|(_, rest)| rest.split_once('"'))
+                .map(|(id, _)| id.to_owned());
+            actions.extend(id);
+        }
+    }
+    Ok(actions)
+}
+
+/// Outcome of a single self-test check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    /// Nothing was configured for the check to test
+    Skipped,
+}
+
+/// The result of one self-test check
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// Short machine-readable name, such as `codec` or `policy`
+    pub name: &'static str,
+    pub status: CheckStatus,
+    /// Human readable explanation of the outcome
+    pub detail: String,
+}
+
+impl Check {
+    pub(crate) fn passed(name: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Passed, detail)
+    }
+
+    pub(crate) fn failed(name: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Failed, detail)
+    }
+
+    pub(crate) fn skipped(name: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Skipped, detail)
+    }
+
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Results of [`SelfTest::run`] or [`Preflight::run`](crate::Preflight::run), which serialize to a machine-readable report
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    checks: Vec<Check>,
+}
+
+impl SelfTestReport {
+    pub(crate) fn new(checks: Vec<Check>) -> Self {
+        Self { checks }
+    }
+
+    /// Returns true if no check failed
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Failed)
+    }
+
+    /// Returns the result of every check, in the order they ran
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+}
+
+impl Serialize for CheckStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            Self::Passed => "passed",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+        })
+    }
+}
+
+impl Serialize for Check {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut check = serializer.serialize_struct("Check", 3)?;
+        check.serialize_field("name", self.name)?;
+        check.serialize_field("status", &self.status)?;
+        check.serialize_field("detail", &self.detail)?;
+        check.end()
+    }
+}
+
+impl Serialize for SelfTestReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut report = serializer.serialize_struct("SelfTestReport", 2)?;
+        report.serialize_field("passed", &self.passed())?;
+        report.serialize_field("checks", &self.checks)?;
+        report.end()
+    }
+}
@@ -0,0 +1,256 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Handing messages to a writer thread
+//!
+//! Sending normally encodes and writes on the calling thread, which waits for as
+//! long as the peer takes to read. With a send queue, [`IpcConnection::send`]
+//! encodes the message and queues the frame, and a thread of the connection's
+//! own writes queued frames in order. A peer that falls behind fills the queue,
+//! at which point sending fails with [`IpcError::SendQueueFull`], or waits for
+//! room with [`IpcConnection::send_timeout`].
+//!
+//! Unlike [`WritePolicy::Buffer`](crate::WritePolicy::Buffer), the queue is
+//! bounded by a number of messages and gets written without further sends.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Write},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{transport, Codec, IpcConnection, IpcError};
+
+/// Frames waiting for the writer thread, shared with it
+#[derive(Debug)]
+pub(crate) struct SendQueue {
+    shared: Arc<Shared>,
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled whenever a frame is queued or written, or the queue is dropped
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    frames: VecDeque<Vec<u8>>,
+    /// Set while the writer holds a frame taken off the queue
+    writing: bool,
+    /// Set once the connection has dropped the queue
+    dropped: bool,
+    /// Set once the writer has stopped, along with the error that stopped it
+    failed: Option<Option<io::Error>>,
+}
+
+impl SendQueue {
+    /// Starts a thread writing queued frames to `writer`
+    fn spawn(writer: File, capacity: usize) -> io::Result<Self> {
+        let shared = Arc::new(Shared::default());
+        let thread_shared = Arc::clone(&shared);
+        thread::Builder::new()
+            .name("privileged-ipc-writer".into())
+            .spawn(move || write_queued(writer, &thread_shared))?;
+        Ok(Self { shared, capacity })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Queues `frame`, failing if the queue is full
+    pub(crate) fn push(&self, frame: &[u8]) -> Result<(), IpcError> {
+        let mut state = self.lock();
+        failed(&mut state)?;
+        if state.frames.len() >= self.capacity {
+            return Err(IpcError::SendQueueFull {
+                capacity: self.capacity,
+            });
+        }
+        state.frames.push_back(frame.to_vec());
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Waits until a frame can be queued or `deadline` passes, returning true if one can
+    fn wait_for_room(&self, deadline: Instant) -> Result<bool, IpcError> {
+        let mut state = self.lock();
+        loop {
+            failed(&mut state)?;
+            if state.frames.len() < self.capacity {
+                return Ok(true);
+            }
+            match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => state = self.wait(state, remaining),
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Waits until every queued frame was written or `deadline` passes, returning true if so
+    pub(crate) fn wait_idle(&self, deadline: Option<Instant>) -> Result<bool, IpcError> {
+        let mut state = self.lock();
+        loop {
+            failed(&mut state)?;
+            if state.frames.is_empty() && !state.writing {
+                return Ok(true);
+            }
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => remaining,
+                    _ => return Ok(false),
+                },
+                None => Duration::MAX,
+            };
+            state = self.wait(state, wait);
+        }
+    }
+
+    /// Returns true if nothing is queued or being written
+    pub(crate) fn is_idle(&self) -> bool {
+        let state = self.lock();
+        state.frames.is_empty() && !state.writing
+    }
+
+    /// Returns the number of frames not yet written, counting one being written
+    pub(crate) fn len(&self) -> usize {
+        let state = self.lock();
+        state.frames.len() + usize::from(state.writing)
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, State>, timeout: Duration) -> MutexGuard<'a, State> {
+        match self.shared.changed.wait_timeout(state, timeout) {
+            Ok((state, _)) => state,
+            Err(poisoned) => poisoned.into_inner().0,
+        }
+    }
+}
+
+impl Drop for SendQueue {
+    /// Lets the writer finish the queued frames and exit
+    fn drop(&mut self) {
+        self.lock().dropped = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Fails if the writer has stopped, reporting why the first time
+fn failed(state: &mut State) -> Result<(), IpcError> {
+    match state.failed.as_mut() {
+        None => Ok(()),
+        Some(error) => match error.take() {
+            Some(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(IpcError::Io(e)),
+            _ => Err(IpcError::ConnectionClosed),
+        },
+    }
+}
+
+/// Body of the writer thread, writing frames until the queue is dropped or a write fails
+fn write_queued(mut writer: File, shared: &Shared) {
+    let lock = || {
+        shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    };
+    let mut state = lock();
+    loop {
+        let Some(frame) = state.frames.pop_front() else {
+            if state.dropped {
+                return;
+            }
+            state = shared
+                .changed
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            continue;
+        };
+        state.writing = true;
+        drop(state);
+
+        let written = transport::suppress_sigpipe(|| writer.write_all(&frame));
+
+        state = lock();
+        state.writing = false;
+        if let Err(e) = written {
+            state.frames.clear();
+            state.failed = Some(Some(e));
+            shared.changed.notify_all();
+            return;
+        }
+        shared.changed.notify_all();
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Writes messages from a thread of their own, queueing up to `capacity` of them
+    ///
+    /// [`IpcConnection::send`] then only encodes and queues each message, and
+    /// fails with [`IpcError::SendQueueFull`] rather than waiting once
+    /// `capacity` are queued. Messages carrying [`Blob`](crate::Blob)s wait for
+    /// the queue to empty and are written directly. Needs a transport that can be
+    /// waited on for writing, such as a Unix socket or a pipe, and overrides the
+    /// [`WritePolicy`](crate::WritePolicy). A `capacity` of `0` waits for the
+    /// queue to empty and turns it off again.
+    ///
+    /// Dropping the connection leaves the thread to write whatever is still
+    /// queued, until the peer closes its end.
+    pub fn set_send_queue(&mut self, capacity: usize) -> Result<(), IpcError> {
+        if let Some(queue) = self.send_queue.take() {
+            if let Err(e) = queue.wait_idle(None) {
+                self.send_queue = Some(queue);
+                return Err(e);
+            }
+        }
+        if capacity == 0 {
+            return Ok(());
+        }
+        self.flush()?;
+        let fd = self.transport.write_fd().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "send queue not supported by this transport",
+            )
+        })?;
+        let writer = File::from(fd.try_clone_to_owned()?);
+        self.send_queue = Some(SendQueue::spawn(writer, capacity)?);
+        Ok(())
+    }
+
+    /// Returns the number of messages queued but not yet written, see [`IpcConnection::set_send_queue`]
+    pub fn queued_messages(&self) -> usize {
+        self.send_queue.as_ref().map_or(0, SendQueue::len)
+    }
+
+    /// Sends a message, waiting up to `timeout` for room in the send queue
+    ///
+    /// Fails with [`IpcError::SendQueueFull`] if there still isn't room by then.
+    /// Without a send queue this is the same as [`IpcConnection::send`].
+    pub fn send_timeout(&mut self, message: &S, timeout: Duration) -> Result<(), IpcError> {
+        let Some(queue) = self.send_queue.as_ref() else {
+            return self.send(message);
+        };
+        // Nothing else can queue frames in the meantime, so the room is still there
+        if !queue.wait_for_room(Instant::now() + timeout)? {
+            return Err(IpcError::SendQueueFull {
+                capacity: queue.capacity,
+            });
+        }
+        self.send(message)
+    }
+}
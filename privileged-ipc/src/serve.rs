@@ -0,0 +1,133 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Built-in multi-client serve loop for [`IpcServer`]
+
+use std::{
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{Codec, IpcConnection, IpcError, IpcServer};
+
+/// How often the serve loop checks whether shutdown was requested
+pub(crate) const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Handle used to stop a running [`IpcServer::serve_with`] loop
+///
+/// Handles are cheap to clone and may be moved into handlers or signal threads.
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    /// Requests that the serve loop stop accepting new clients
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true once shutdown has been requested
+    pub fn is_shutdown(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl<S, R, C> IpcServer<S, R, C>
+where
+    S: serde::Serialize + Send + 'static,
+    R: serde::de::DeserializeOwned + Send + 'static,
+    C: Codec + Send + 'static,
+{
+    /// Returns a handle that can be used to stop [`IpcServer::serve_with`]
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Accepts clients until shutdown is requested, handling each on its own thread
+    ///
+    /// `handler` is invoked for every message received from a client, and may reply
+    /// through the connection it is given. A handler error or a failure to receive
+    /// ends that client's session without affecting any other clients, while a session
+    /// the client ends is closed with [`IpcConnection::close`]. Once shutdown
+    /// is requested no new clients are accepted, and this returns after all
+    /// connected clients have finished.
+    pub fn serve_with<F>(&self, handler: F) -> Result<(), IpcError>
+    where
+        F: Fn(&mut IpcConnection<S, R, C>, R) -> Result<(), IpcError> + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let mut clients: Vec<thread::JoinHandle<()>> = vec![];
+
+        while !self.shutdown.is_shutdown() {
+            clients.retain(|c| !c.is_finished());
+
+            let mut connection = match self.accept_timeout(SHUTDOWN_POLL_INTERVAL) {
+                Ok(Some(connection)) => connection,
+                Ok(None) => continue,
+                Err(
+                    e @ (IpcError::UnauthorizedPeer { .. }
+                    | IpcError::ForeignClient { .. }
+                    | IpcError::TokenMismatch),
+                ) => {
+                    log::warn!(target: "privileged_ipc::audit", "🚨 {e}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            log::trace!("🔌 accepted client connection");
+
+            let handler = handler.clone();
+            clients.push(thread::spawn(move || {
+                match serve_client(&mut connection, handler.as_ref()) {
+                    // Acknowledges the client's close frame, if it sent one
+                    Ok(()) => {
+                        if let Err(e) = connection.close() {
+                            log::error!("💥 failed to close client session: {e}");
+                        }
+                    }
+                    Err(e) => {
+                        match e {
+                            // Under multi_client, the token is only checked once the client sends
+                            IpcError::TokenMismatch => {
+                                log::warn!(target: "privileged_ipc::audit", "🚨 {e}")
+                            }
+                            e => log::error!("💥 client session failed: {e}"),
+                        }
+                        // The peer may already be gone, which is fine
+                        let _ = connection.shutdown(Shutdown::Both);
+                    }
+                }
+            }));
+        }
+
+        for client in clients {
+            if client.join().is_err() {
+                log::error!("💥 client handler panicked");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Dispatches every message from one client to the handler until it disconnects
+fn serve_client<S, R, C, F>(
+    connection: &mut IpcConnection<S, R, C>,
+    handler: &F,
+) -> Result<(), IpcError>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+    F: Fn(&mut IpcConnection<S, R, C>, R) -> Result<(), IpcError>,
+{
+    while let Some(message) = connection.recv()? {
+        handler(connection, message)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Writing the serving loop of a helper as a list of handlers
+//!
+//! Most helpers accept their client, then receive each message, match it
+//! against the requests they know, send the replies and failures back and
+//! flush, until the client leaves. [`define_service!`](crate::define_service)
+//! writes that loop, leaving only which handler answers which request.
+
+/// What a handler of [`define_service!`](crate::define_service) answers with
+///
+/// A single message, several of them, or possibly none.
+pub trait IntoReplies<S> {
+    /// Returns the messages to send, in order
+    fn into_replies(self) -> Vec<S>;
+}
+
+impl<S> IntoReplies<S> for S {
+    fn into_replies(self) -> Vec<S> {
+        vec![self]
+    }
+}
+
+impl<S> IntoReplies<S> for Vec<S> {
+    fn into_replies(self) -> Vec<S> {
+        self
+    }
+}
+
+impl<S> IntoReplies<S> for Option<S> {
+    fn into_replies(self) -> Vec<S> {
+        self.into_iter().collect()
+    }
+}
+
+/// Accepts a client on an [`IpcServer`](crate::IpcServer) and answers its requests until it leaves
+///
+/// Each request is matched against the patterns in turn, guards included, and
+/// the handler expression of the first that matches is evaluated with its
+/// bindings. A handler gives a `Result` of anything implementing [`IntoReplies`], whose
+/// replies are sent and flushed. A handler error is passed to the `errors`
+/// function instead, whose reply is sent in their place, so the client hears
+/// about every failure while the session carries on. Handlers may fail with
+/// different error types when `errors` is a generic function.
+///
+/// The whole loop is an expression giving `Result<(), IpcError>`, which fails
+/// if the connection does. Once the client leaves, the connection is closed.
+///
+/// ```ignore
+/// privileged_ipc::define_service! {
+///     server: IpcServer::<Reply, Request>::new()?,
+///     errors: |e: io::Error| Reply::Failed(e.to_string()),
+///     Request::Mount { source, target } => mount(&source, &target),
+///     Request::ListMounts => list_mounts(),
+/// }
+/// ```
+#[macro_export]
+macro_rules! define_service {
+    (
+        server: $server:expr,
+        errors: $errors:expr,
+        $($pattern:pat $(if $guard:expr)? => $handler:expr),+ $(,)?
+    ) => {
+        (|| -> ::std::result::Result<(), $crate::IpcError> {
+            let server = $server;
+            let mut connection = server.accept()?;
+            while let ::std::option::Option::Some(message) = connection.recv()? {
+                let replies = match message {
+                    $(
+                        $pattern $(if $guard)? => match $handler {
+                            ::std::result::Result::Ok(replies) => {
+                                $crate::IntoReplies::into_replies(replies)
+                            }
+                            ::std::result::Result::Err(e) => ::std::vec![($errors)(e)],
+                        },
+                    )+
+                };
+                connection.send_all(&replies)?;
+                connection.flush()?;
+            }
+            connection.close()?;
+            ::std::result::Result::Ok(())
+        })()
+    };
+}
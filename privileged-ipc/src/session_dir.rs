@@ -0,0 +1,285 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Temporary directories scoped to a connection
+//!
+//! Artifacts too large for messages, such as disk images or package bundles, are
+//! better exchanged as files. A helper running as root can't simply agree on a
+//! path in `/tmp` with its client, as any other user could create or swap files
+//! there. A [`SessionDir`] is instead created by the helper inside a directory
+//! only it can write to, owned by the client's uid and private to it, and its
+//! path is sent to the client in a message.
+//!
+//! The client owns everything it puts in the directory, so the helper goes through
+//! [`SessionDir::open`] and [`SessionDir::create_file`], which never follow
+//! symbolic links, rather than the path. The directory and its contents are
+//! removed when the [`SessionDir`] is dropped, which for
+//! [`IpcConnection::session_dir`] is when the connection is. A helper that is
+//! killed leaves its directories behind, within the runtime directory that is
+//! emptied at boot.
+
+use std::{
+    ffi::{CStr, CString},
+    fs::{DirBuilder, File},
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd},
+        unix::fs::{DirBuilderExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use nix::unistd::{Gid, Uid};
+
+use crate::{endpoint, IpcConnection, IpcError, PeerCredentials};
+
+/// Nesting beyond which the contents of a session directory aren't removed
+const MAX_REMOVAL_DEPTH: usize = 64;
+
+/// A private directory owned by the peer of a connection, removed on drop
+#[derive(Debug)]
+pub struct SessionDir {
+    path: PathBuf,
+    name: CString,
+    parent: OwnedFd,
+    dir: OwnedFd,
+    owner: Uid,
+    group: Gid,
+}
+
+impl SessionDir {
+    /// Creates a directory within `parent` for the process with the given credentials
+    ///
+    /// `parent` is created with mode `0755` if needed, and must not be writable by
+    /// anyone but this process's user, so the directory can't be renamed or replaced.
+    /// Handing the directory to another user requires privileges.
+    pub fn create(parent: impl AsRef<Path>, peer: &PeerCredentials) -> io::Result<Self> {
+        let parent_path = parent.as_ref();
+        if !parent_path.exists() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o755)
+                .create(parent_path)?;
+        }
+        let parent = File::from(open_dir(libc::AT_FDCWD, &c_path(parent_path)?)?);
+        let metadata = parent.metadata()?;
+        if metadata.uid() != nix::unistd::geteuid().as_raw() || metadata.mode() & 0o022 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{parent_path:?} is writable by other users"),
+            ));
+        }
+
+        let name = format!("session-{}", uuid::Uuid::new_v4());
+        let c_name = CString::new(name.as_str()).expect("uuids contain no NUL bytes");
+        // SAFETY: `parent` is open and `c_name` is NUL-terminated
+        if unsafe { libc::mkdirat(parent.as_raw_fd(), c_name.as_ptr(), 0o700) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let dir = open_dir(parent.as_raw_fd(), &c_name)?;
+        let session = Self {
+            path: parent_path.join(name),
+            name: c_name,
+            parent: parent.into(),
+            dir,
+            owner: peer.uid,
+            group: peer.gid,
+        };
+        session.hand_over(session.dir.as_fd())?;
+        Ok(session)
+    }
+
+    /// Returns the path of the directory, to be sent to the peer
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens a regular file the peer put in the directory for reading
+    ///
+    /// Symbolic links and anything but regular files are refused.
+    pub fn open(&self, name: &str) -> io::Result<File> {
+        let name = entry_name(name)?;
+        // Opening without blocking keeps a FIFO from stalling the helper
+        let flags = libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_CLOEXEC | libc::O_NONBLOCK;
+        let file = File::from(open_at(self.dir.as_raw_fd(), &name, flags, 0)?);
+        if !file.metadata()?.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a regular file",
+            ));
+        }
+        // SAFETY: `file` is open, and clearing O_NONBLOCK leaves the other flags intact
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFL, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file)
+    }
+
+    /// Creates a new file in the directory for writing, owned by the peer
+    ///
+    /// Fails if anything already exists under `name`.
+    pub fn create_file(&self, name: &str) -> io::Result<File> {
+        let name = entry_name(name)?;
+        let flags =
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let file = open_at(self.dir.as_raw_fd(), &name, flags, 0o600)?;
+        self.hand_over(file.as_fd())?;
+        Ok(File::from(file))
+    }
+
+    /// Gives a file or directory to the peer, unless it already belongs to them
+    fn hand_over(&self, fd: BorrowedFd<'_>) -> io::Result<()> {
+        if self.owner == nix::unistd::geteuid() {
+            return Ok(());
+        }
+        // SAFETY: `fd` is open
+        if unsafe { libc::fchown(fd.as_raw_fd(), self.owner.as_raw(), self.group.as_raw()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsFd for SessionDir {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.dir.as_fd()
+    }
+}
+
+impl Drop for SessionDir {
+    fn drop(&mut self) {
+        let removed = remove_contents(self.dir.as_fd(), 0).and_then(|()| {
+            // SAFETY: `parent` is open and `name` is NUL-terminated
+            let ret = unsafe {
+                libc::unlinkat(
+                    self.parent.as_raw_fd(),
+                    self.name.as_ptr(),
+                    libc::AT_REMOVEDIR,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+        if let Err(e) = removed {
+            log::warn!("🧹 failed to remove session directory {:?}: {e}", self.path);
+        }
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Returns a private directory for exchanging files with the peer, creating it on first use
+    ///
+    /// Intended for the helper side: the directory is created under
+    /// `<runtime dir>/privileged-ipc`, owned by the peer's uid, and removed along
+    /// with everything in it when the connection is dropped. See [`SessionDir`].
+    pub fn session_dir(&mut self) -> Result<&SessionDir, IpcError> {
+        if self.session_dir.is_none() {
+            let peer = self.transport.peer_credentials()?;
+            let parent = endpoint::runtime_dir().join("privileged-ipc");
+            self.session_dir = Some(SessionDir::create(parent, &peer)?);
+        }
+        Ok(self
+            .session_dir
+            .as_ref()
+            .expect("session directory was just created"))
+    }
+}
+
+/// Checks that `name` refers to an entry directly within the directory
+fn entry_name(name: &str) -> io::Result<CString> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name:?} is not a plain file name"),
+        ));
+    }
+    CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn open_at(
+    dir: libc::c_int,
+    name: &CStr,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+) -> io::Result<OwnedFd> {
+    // SAFETY: `name` is NUL-terminated, and an invalid `dir` only makes this fail
+    let fd = unsafe { libc::openat(dir, name.as_ptr(), flags, mode as libc::c_uint) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just opened and is owned by nothing else
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn open_dir(dir: libc::c_int, name: &CStr) -> io::Result<OwnedFd> {
+    open_at(
+        dir,
+        name,
+        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+        0,
+    )
+}
+
+/// Removes everything within `dir` without following symbolic links
+fn remove_contents(dir: BorrowedFd<'_>, depth: usize) -> io::Result<()> {
+    if depth > MAX_REMOVAL_DEPTH {
+        return Err(io::Error::other("session directory is nested too deeply"));
+    }
+    for name in entries(dir)? {
+        // SAFETY: `dir` is open and `name` is NUL-terminated
+        if unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), 0) } == 0 {
+            continue;
+        }
+        let error = io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(libc::ENOENT) => continue,
+            Some(libc::EISDIR) => {}
+            _ => return Err(error),
+        }
+        let subdir = open_dir(dir.as_raw_fd(), &name)?;
+        remove_contents(subdir.as_fd(), depth + 1)?;
+        // SAFETY: as above
+        if unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), libc::AT_REMOVEDIR) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Lists the names of the entries in `dir`, other than `.` and `..`
+fn entries(dir: BorrowedFd<'_>) -> io::Result<Vec<CString>> {
+    let fd = dir.try_clone_to_owned()?;
+    // SAFETY: the stream takes ownership of the duplicated descriptor
+    let stream = unsafe { libc::fdopendir(fd.into_raw_fd()) };
+    if stream.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let mut names = vec![];
+    // SAFETY: `stream` is a valid directory stream until closed below, and each entry's
+    // name is NUL-terminated and copied before the next call to `readdir`
+    unsafe {
+        // The duplicate shares its offset with `dir`, which may already have been read
+        libc::rewinddir(stream);
+        loop {
+            let entry = libc::readdir(stream);
+            if entry.is_null() {
+                break;
+            }
+            let name = CStr::from_ptr((*entry).d_name.as_ptr());
+            if name != c"." && name != c".." {
+                names.push(name.to_owned());
+            }
+        }
+        libc::closedir(stream);
+    }
+    Ok(names)
+}
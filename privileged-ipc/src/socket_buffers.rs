@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Kernel socket buffer sizes
+//!
+//! The default buffers of a Unix socket hold a few hundred kilobytes, so a
+//! sender producing output faster than the other end reads it, such as a build
+//! streaming its log, stalls as soon as they fill. Larger buffers absorb those
+//! bursts. The kernel doubles requested sizes to allow for bookkeeping and
+//! applies limits of its own, so the getters report the sizes actually in effect.
+
+use std::{
+    io, mem,
+    os::fd::{AsRawFd, BorrowedFd},
+};
+
+use crate::{IpcConnection, IpcError, Transport};
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Requests a send buffer of `size` bytes for the underlying socket
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), IpcError> {
+        Ok(set_size(
+            socket_fd(&*self.transport)?,
+            libc::SO_SNDBUF,
+            size,
+        )?)
+    }
+
+    /// Requests a receive buffer of `size` bytes for the underlying socket
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), IpcError> {
+        Ok(set_size(
+            socket_fd(&*self.transport)?,
+            libc::SO_RCVBUF,
+            size,
+        )?)
+    }
+
+    /// Returns the size of the underlying socket's send buffer
+    pub fn send_buffer_size(&self) -> Result<usize, IpcError> {
+        Ok(size(socket_fd(&*self.transport)?, libc::SO_SNDBUF)?)
+    }
+
+    /// Returns the size of the underlying socket's receive buffer
+    pub fn recv_buffer_size(&self) -> Result<usize, IpcError> {
+        Ok(size(socket_fd(&*self.transport)?, libc::SO_RCVBUF)?)
+    }
+}
+
+/// Returns the socket behind a transport, failing for transports without one
+fn socket_fd(transport: &dyn Transport) -> io::Result<BorrowedFd<'_>> {
+    transport.poll_fd().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "socket buffers not supported by this transport",
+        )
+    })
+}
+
+fn set_size(fd: BorrowedFd<'_>, option: libc::c_int, size: usize) -> io::Result<()> {
+    let size = libc::c_int::try_from(size)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "buffer size too large"))?;
+    // SAFETY: `size` is valid for reads of the length passed
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &size as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn size(fd: BorrowedFd<'_>, option: libc::c_int) -> io::Result<usize> {
+    let mut size: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `size` and `len` are valid for writes and `len` matches the size of `size`
+    let ret = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &mut size as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Answering with a stream of messages, or all at once to a peer that can't take one
+//!
+//! A helper reporting progress sends many small messages, where a client built
+//! against an older protocol expects a single reply. [`IpcConnection::send_stream`]
+//! sends them one by one when the peer advertised [`Capabilities::STREAMING`],
+//! and otherwise folds them into one message, so both kinds of peer keep working
+//! with the same helper.
+
+use crate::{blob, Capabilities, Codec, IpcConnection, IpcError};
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Sends `messages` one at a time if the peer can take a stream, or else as the one message `aggregate` makes of them
+    ///
+    /// Streaming needs both sides to advertise [`Capabilities::STREAMING`] in
+    /// the handshake, see [`IpcConnection::set_capabilities`], so without one the
+    /// messages are always aggregated. Collecting them fails with
+    /// [`IpcError::MessageTooLarge`] as soon as they exceed the size limit between
+    /// them, as their aggregate couldn't be sent either, and nothing is sent.
+    ///
+    /// ```ignore
+    /// connection.send_stream(progress, |updates| Response::Progressed(updates))?;
+    /// ```
+    pub fn send_stream(
+        &mut self,
+        messages: impl IntoIterator<Item = S>,
+        aggregate: impl FnOnce(Vec<S>) -> S,
+    ) -> Result<(), IpcError> {
+        if self.capabilities().contains(Capabilities::STREAMING) {
+            for message in messages {
+                self.send(&message)?;
+            }
+            return Ok(());
+        }
+
+        let mut collected = vec![];
+        let mut size = 0;
+        for message in messages {
+            size += self.encoded_len(&message)?;
+            if size > self.max_message_size {
+                return Err(IpcError::MessageTooLarge {
+                    size,
+                    limit: self.max_message_size,
+                });
+            }
+            collected.push(message);
+        }
+        self.send(&aggregate(collected))
+    }
+
+    /// Returns the length of `message` once encoded, without framing
+    fn encoded_len(&self, message: &S) -> Result<usize, IpcError> {
+        let mut encoded = vec![];
+        let (result, _) = blob::collect_fds(|| self.codec.encode(message, &mut encoded));
+        result?;
+        Ok(encoded.len())
+    }
+}
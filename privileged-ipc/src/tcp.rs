@@ -0,0 +1,98 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! TCP transport, enabled by the `tcp` feature
+//!
+//! This lets the same typed protocol run between a local frontend and a helper
+//! that is only reachable over the network, such as one inside a container with
+//! a single exposed port.
+//!
+//! TCP connections are **unauthenticated**: anyone able to reach the port can
+//! connect, and there are no peer credentials to check. Only expose such a server
+//! on an isolated network, or combine it with an authentication layer such as a
+//! [`FrameCipher`](crate::FrameCipher) keyed with a shared secret.
+
+use std::{
+    io::{self, Read},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    os::fd::{AsFd, BorrowedFd},
+};
+
+use crate::{transport, Codec, IpcConnection, IpcError, IpcServer, Listener, Transport};
+
+impl Transport for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Read::read(self, buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        transport::send_nosignal(self.as_fd(), buf)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn try_write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        if !fds.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "passing file descriptors not supported by this transport",
+            ));
+        }
+        transport::try_send(self.as_fd(), buf)
+    }
+
+    fn write_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.as_fd())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.as_fd())
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Connects to an [`IpcServer`] listening on a TCP address
+    ///
+    /// The connection is unauthenticated, see the [module documentation](self).
+    pub fn connect_tcp(addr: impl ToSocketAddrs) -> Result<Self, IpcError> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S, R, C> IpcServer<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Creates a server accepting connections on a TCP address
+    ///
+    /// Connections are unauthenticated, see the [module documentation](self).
+    pub fn bind_tcp(addr: impl ToSocketAddrs) -> Result<Self, IpcError> {
+        Ok(Self::with_listener(Listener::Tcp(TcpListener::bind(addr)?)))
+    }
+
+    /// Returns the TCP address the server is listening on, if it is a TCP server
+    ///
+    /// Useful for finding the port chosen when binding to port 0.
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Transit timing of frames
+//!
+//! With timestamps enabled on both ends, see [`IpcConnection::set_timestamps`](crate::IpcConnection::set_timestamps),
+//! every frame carries the sender's monotonic clock reading. Comparing it with the
+//! receiver's clock separates time spent queued in the socket from time the peer
+//! took to produce a message, which helps tell "the helper is slow" apart from
+//! "nobody is reading the socket" when diagnosing sluggish frontends.
+
+use std::time::Duration;
+
+use crate::IpcError;
+
+/// Length of the timestamp preceding each timestamped payload
+const TIMESTAMP_LEN: usize = 8;
+
+/// Weight of each new sample in the moving average, as a divisor
+const AVERAGE_WEIGHT: i64 = 8;
+
+/// Estimates of clock skew and queueing delay for the frames received on a connection
+///
+/// Delays are measured against the fastest frame seen so far, so they don't depend
+/// on the peers' clocks agreeing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransitStats {
+    frames: u64,
+    min_offset: i64,
+    max_offset: i64,
+    last_offset: i64,
+    average_offset: i64,
+}
+
+impl TransitStats {
+    /// Number of timestamped frames received
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Estimated offset of the receiving clock from the sending one, in nanoseconds
+    ///
+    /// This is the smallest difference seen between a frame's receipt and its sending.
+    /// Peers on the same host share the monotonic clock, so this is just the
+    /// fastest transit time; otherwise it is dominated by the skew between the clocks.
+    pub fn clock_offset_ns(&self) -> Option<i64> {
+        (self.frames > 0).then_some(self.min_offset)
+    }
+
+    /// Time the most recent frame spent in transit beyond the fastest frame
+    pub fn last_delay(&self) -> Duration {
+        self.delay(self.last_offset)
+    }
+
+    /// Moving average of the time frames spend in transit beyond the fastest frame
+    pub fn average_delay(&self) -> Duration {
+        self.delay(self.average_offset)
+    }
+
+    /// Longest time a frame spent in transit beyond the fastest frame
+    pub fn max_delay(&self) -> Duration {
+        self.delay(self.max_offset)
+    }
+
+    /// Records a frame sent and received at the given clock readings
+    pub(crate) fn record(&mut self, sent: u64, received: u64) {
+        let offset = received.wrapping_sub(sent) as i64;
+        if self.frames == 0 {
+            self.min_offset = offset;
+            self.max_offset = offset;
+            self.average_offset = offset;
+        } else {
+            self.min_offset = self.min_offset.min(offset);
+            self.max_offset = self.max_offset.max(offset);
+            self.average_offset += (offset - self.average_offset) / AVERAGE_WEIGHT;
+        }
+        self.last_offset = offset;
+        self.frames += 1;
+    }
+
+    fn delay(&self, offset: i64) -> Duration {
+        Duration::from_nanos(offset.saturating_sub(self.min_offset).max(0) as u64)
+    }
+}
+
+/// Reads the monotonic clock, in nanoseconds
+pub(crate) fn monotonic_ns() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `now` is valid for writes, and CLOCK_MONOTONIC is always available
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+/// Prefixes `payload` with the current clock reading
+pub(crate) fn stamp(payload: &[u8]) -> Vec<u8> {
+    let mut stamped = Vec::with_capacity(TIMESTAMP_LEN + payload.len());
+    stamped.extend_from_slice(&monotonic_ns().to_be_bytes());
+    stamped.extend_from_slice(payload);
+    stamped
+}
+
+/// Splits a timestamped payload into the sender's clock reading and the payload
+pub(crate) fn split_stamp(payload: &[u8]) -> Result<(u64, &[u8]), IpcError> {
+    let (stamp, payload) = payload
+        .split_first_chunk::<TIMESTAMP_LEN>()
+        .ok_or_else(|| IpcError::Codec("frame too short to carry a timestamp".into()))?;
+    Ok((u64::from_be_bytes(*stamp), payload))
+}
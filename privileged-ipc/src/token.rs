@@ -0,0 +1,202 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! A secret token the launching client presents when connecting to its helper
+//!
+//! Checking who a peer runs as can't tell two processes of the same user apart,
+//! and socket permissions can't guard an abstract address at all. The client
+//! generates a random token and hands it to the helper through a pipe on its
+//! stdin, which unlike arguments and the environment isn't visible in `/proc`.
+//! Its first bytes on the connection are the token, and a helper calling
+//! [`IpcServer::set_connect_token`] refuses any client that doesn't present it.
+//!
+//! A server with a single client reads the token while accepting it, before it
+//! stops listening for others. One serving many clients can't wait on each as
+//! it arrives, so the token is read ahead of the client's first message instead.
+//!
+//! ```ignore
+//! let mut server = IpcServer::<Response, Request>::new()?;
+//! server.set_connect_token(read_spawn_key()?)?;
+//! ```
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    generate_key, wait_readable, AddressIdentifier, AddressScheme, Codec, IpcClient, IpcConnection,
+    IpcError, IpcServer, PathSocket, ServiceConnection, SocketExecutor, Transport, KEY_LEN,
+};
+
+/// How long a client has to present the token once it has been accepted
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl ServiceConnection {
+    /// Creates a new connection to a privileged service, presenting a fresh token when connecting
+    ///
+    /// The token is handed to the helper on its stdin, for it to fetch with
+    /// [`read_spawn_key`](crate::read_spawn_key) and pass to
+    /// [`IpcServer::set_connect_token`]. See [`ServiceConnection::new_with_scheme`].
+    pub fn new_with_token<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        scheme: &AddressScheme,
+    ) -> Result<Self, crate::Error> {
+        let token = generate_key()?;
+        let identity = AddressIdentifier::new(scheme);
+        let socket_addr = identity.as_unix_address()?;
+        let unix_socket = std::os::unix::net::UnixListener::bind_addr(&socket_addr)?;
+
+        log::trace!("🔌 setting server address to: @{identity}");
+
+        let mut connection =
+            Self::spawn::<T>(executable, args, unix_socket, &socket_addr, Some(&token))?;
+        Write::write_all(&mut connection.socket, &token)?;
+        Ok(connection)
+    }
+
+    /// Creates a new connection to a privileged service over a filesystem socket, presenting a fresh token
+    ///
+    /// See [`ServiceConnection::new_with_token`] and [`ServiceConnection::new_with_socket`].
+    pub fn new_with_socket_and_token<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        socket: &PathSocket,
+    ) -> Result<Self, crate::Error> {
+        let token = generate_key()?;
+        let mut connection = Self::new_with_socket_and_key::<T>(executable, args, socket, &token)?;
+        Write::write_all(&mut connection.socket, &token)?;
+        Ok(connection)
+    }
+}
+
+impl<S, R, C> IpcClient<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Creates a new IPC client connection that presents a fresh token when connecting
+    ///
+    /// See [`ServiceConnection::new_with_token`].
+    pub fn new_with_token<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        scheme: &AddressScheme,
+    ) -> Result<Self, IpcError> {
+        let connection = ServiceConnection::new_with_token::<T>(executable, args, scheme)?;
+        Ok(Self::from_service(connection))
+    }
+
+    /// Creates a new IPC client connection over a filesystem socket that presents a fresh token
+    ///
+    /// See [`ServiceConnection::new_with_socket_and_token`].
+    pub fn new_with_socket_and_token<T: SocketExecutor>(
+        executable: &str,
+        args: &[&str],
+        socket: &PathSocket,
+    ) -> Result<Self, IpcError> {
+        let connection =
+            ServiceConnection::new_with_socket_and_token::<T>(executable, args, socket)?;
+        Ok(Self::from_service(connection))
+    }
+}
+
+impl<S, R, C> IpcServer<S, R, C> {
+    /// Refuses clients that don't present `token` as soon as they connect
+    ///
+    /// A client that sends something else, or nothing within a few seconds, is
+    /// disconnected and reported as [`IpcError::TokenMismatch`], and the serve
+    /// loops carry on without it. Under [`IpcServer::set_multi_client`], clients
+    /// are accepted straight away and the token is checked when their first
+    /// message is received, which then fails instead.
+    ///
+    /// Fails unless the token is between 1 and [`KEY_LEN`] bytes long, as an
+    /// empty one would admit every client.
+    pub fn set_connect_token(&mut self, token: impl Into<Vec<u8>>) -> Result<(), IpcError> {
+        let token = token.into();
+        if token.is_empty() || token.len() > KEY_LEN {
+            return Err(IpcError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("connection tokens must be 1 to {KEY_LEN} bytes long"),
+            )));
+        }
+        self.token = Some(token);
+        Ok(())
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Takes the token the peer presents from the front of the buffer, returning false while it is incomplete
+    ///
+    /// Fails if the peer presented another token, or closed the connection
+    /// before presenting all of it, after which nothing more is received.
+    pub(crate) fn take_token(&mut self) -> Result<bool, IpcError> {
+        let Some(expected) = &self.connect_token else {
+            return Ok(true);
+        };
+        let available = self.buffer.len().min(expected.len());
+        let matches = matching(&self.buffer[..available], &expected[..available]);
+        if matches && available < expected.len() && !self.eof {
+            return Ok(false);
+        }
+        if !matches || available < expected.len() {
+            self.buffer.clear();
+            self.fds.clear();
+            self.eof = true;
+            self.settle_pool();
+            return Err(IpcError::TokenMismatch);
+        }
+        self.buffer.drain(..available);
+        self.take_fds(available);
+        self.settle_pool();
+        self.connect_token = None;
+        Ok(true)
+    }
+}
+
+/// Reads the token a client presents, failing unless it matches `expected`
+///
+/// Failing to read from the client refuses it too, rather than failing the
+/// server accepting it.
+pub(crate) fn verify(transport: &mut dyn Transport, expected: &[u8]) -> Result<(), IpcError> {
+    let mut presented = [0u8; KEY_LEN];
+    let presented = &mut presented[..expected.len()];
+    let deadline = Instant::now() + TOKEN_TIMEOUT;
+    let mut filled = 0;
+    while filled < presented.len() {
+        // Reading stops where the token ends, leaving the frames after it to the connection
+        if let Some(fd) = transport.poll_fd() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !wait_readable(fd, remaining).unwrap_or(false) {
+                return Err(IpcError::TokenMismatch);
+            }
+        }
+        match transport.read(&mut presented[filled..]) {
+            Ok(0) => return Err(IpcError::TokenMismatch),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return Err(IpcError::TokenMismatch),
+        }
+    }
+    match matching(presented, expected) {
+        true => Ok(()),
+        false => Err(IpcError::TokenMismatch),
+    }
+}
+
+/// Returns true if `presented` and `expected` are equal, in constant time so the token can't be guessed byte by byte
+fn matching(presented: &[u8], expected: &[u8]) -> bool {
+    let diff = presented
+        .iter()
+        .zip(expected)
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    diff == 0 && presented.len() == expected.len()
+}
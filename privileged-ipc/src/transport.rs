@@ -0,0 +1,490 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Byte streams that an [`IpcConnection`](crate::IpcConnection) can run over
+//!
+//! The typed message layer only needs to read and write bytes, so besides the
+//! Unix sockets used for spawned helpers it can run over pipes, TCP, or any other
+//! [`Read`] and [`Write`] pair via [`StreamTransport`].
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    mem,
+    net::Shutdown,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    ptr,
+};
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+use crate::{
+    blob, credentials,
+    file_transfer::{self, TransportWriter},
+    PeerCredentials, ServiceConnection,
+};
+
+/// A bidirectional byte stream carrying encoded messages
+///
+/// Only reading, writing and shutting down are required. Transports backed by a
+/// file descriptor should also provide [`Transport::poll_fd`] and
+/// [`Transport::set_nonblocking`], without which [`select`](crate::select) and
+/// [`IpcConnection::try_recv`](crate::IpcConnection::try_recv) are unavailable.
+pub trait Transport: Send {
+    /// Reads available bytes into `buf`, returning 0 once the peer has closed the stream
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes all of `buf` and flushes it to the peer
+    ///
+    /// Implementations must not raise `SIGPIPE` when the peer has gone away, and
+    /// should fail with [`io::ErrorKind::BrokenPipe`] instead.
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Shuts down the read side, the write side, or both
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()>;
+
+    /// Reads like [`Transport::read`], collecting any file descriptors passed with the bytes
+    fn read_with_fds(&mut self, buf: &mut [u8], _fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        self.read(buf)
+    }
+
+    /// Reads like [`Transport::read_with_fds`], also returning the credentials the kernel passed with the bytes
+    ///
+    /// Only Unix sockets with `SO_PASSCRED` set are passed credentials, as those
+    /// of [`ServiceConnection`]s are.
+    fn read_with_credentials(
+        &mut self,
+        buf: &mut [u8],
+        fds: &mut Vec<OwnedFd>,
+    ) -> io::Result<(usize, Option<PeerCredentials>)> {
+        Ok((self.read_with_fds(buf, fds)?, None))
+    }
+
+    /// Writes like [`Transport::write_all`], passing `fds` along with the bytes
+    ///
+    /// Only needs implementing by transports that can carry [`Blob`](crate::Blob)s.
+    fn write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<()> {
+        if !fds.is_empty() {
+            return Err(unsupported("passing file descriptors"));
+        }
+        self.write_all(buf)
+    }
+
+    /// Writes `len` bytes of `file` from `offset` without moving its position
+    ///
+    /// The default reads the file into memory in chunks, transports backed by a
+    /// socket can send it directly instead.
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        file_transfer::copy_file(&mut TransportWriter(self), file, offset, len)
+    }
+
+    /// Writes as much of `buf` as fits without blocking, passing `fds` with the first byte
+    ///
+    /// Returns how many bytes were written, failing with
+    /// [`io::ErrorKind::WouldBlock`] if none could be. Needed by the
+    /// [`WritePolicy`](crate::WritePolicy) variants that avoid blocking, along with
+    /// [`Transport::write_fd`].
+    fn try_write_with_fds(&mut self, _buf: &[u8], _fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        Err(unsupported("non-blocking writes"))
+    }
+
+    /// The file descriptor that becomes writable when the peer has read, if there is one
+    fn write_fd(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
+
+    /// Switches reads between blocking and non-blocking mode
+    fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+        Err(unsupported("non-blocking reads"))
+    }
+
+    /// The file descriptor that becomes readable when the peer sends data, if there is one
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
+
+    /// Credentials of the process on the other end, if the transport can tell
+    fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        Err(unsupported("peer credentials"))
+    }
+}
+
+impl Transport for UnixStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Read::read(self, buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        send_nosignal(self.as_fd(), buf)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+
+    fn read_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        blob::recv_with_fds(self.as_fd(), buf, fds)
+    }
+
+    fn read_with_credentials(
+        &mut self,
+        buf: &mut [u8],
+        fds: &mut Vec<OwnedFd>,
+    ) -> io::Result<(usize, Option<PeerCredentials>)> {
+        blob::recv_with_credentials(self.as_fd(), buf, fds)
+    }
+
+    fn write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<()> {
+        if fds.is_empty() || buf.is_empty() {
+            return Transport::write_all(self, buf);
+        }
+        let sent = blob::send_with_fds(self.as_fd(), buf, fds, 0)?;
+        Transport::write_all(self, &buf[sent..])
+    }
+
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        file_transfer::sendfile(self.as_fd(), file, offset, len)
+    }
+
+    fn try_write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        if fds.is_empty() {
+            return try_send(self.as_fd(), buf);
+        }
+        blob::send_with_fds(self.as_fd(), buf, fds, libc::MSG_DONTWAIT)
+    }
+
+    fn write_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.as_fd())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.as_fd())
+    }
+
+    fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        credentials::peer_credentials(self.as_fd())
+    }
+}
+
+impl Transport for ServiceConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Transport::read(&mut self.socket, buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        Transport::write_all(&mut self.socket, buf)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        Transport::shutdown(&mut self.socket, how)
+    }
+
+    fn read_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        Transport::read_with_fds(&mut self.socket, buf, fds)
+    }
+
+    fn read_with_credentials(
+        &mut self,
+        buf: &mut [u8],
+        fds: &mut Vec<OwnedFd>,
+    ) -> io::Result<(usize, Option<PeerCredentials>)> {
+        Transport::read_with_credentials(&mut self.socket, buf, fds)
+    }
+
+    fn write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<()> {
+        Transport::write_with_fds(&mut self.socket, buf, fds)
+    }
+
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        Transport::send_file(&mut self.socket, file, offset, len)
+    }
+
+    fn try_write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        Transport::try_write_with_fds(&mut self.socket, buf, fds)
+    }
+
+    fn write_fd(&self) -> Option<BorrowedFd<'_>> {
+        Transport::write_fd(&self.socket)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        Transport::set_nonblocking(&self.socket, nonblocking)
+    }
+
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        Transport::poll_fd(&self.socket)
+    }
+
+    fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        Transport::peer_credentials(&self.socket)
+    }
+}
+
+impl Transport for Box<dyn Transport> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (**self).write_all(buf)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        (**self).shutdown(how)
+    }
+
+    fn read_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        (**self).read_with_fds(buf, fds)
+    }
+
+    fn read_with_credentials(
+        &mut self,
+        buf: &mut [u8],
+        fds: &mut Vec<OwnedFd>,
+    ) -> io::Result<(usize, Option<PeerCredentials>)> {
+        (**self).read_with_credentials(buf, fds)
+    }
+
+    fn write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<()> {
+        (**self).write_with_fds(buf, fds)
+    }
+
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        (**self).send_file(file, offset, len)
+    }
+
+    fn try_write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        (**self).try_write_with_fds(buf, fds)
+    }
+
+    fn write_fd(&self) -> Option<BorrowedFd<'_>> {
+        (**self).write_fd()
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        (**self).set_nonblocking(nonblocking)
+    }
+
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        (**self).poll_fd()
+    }
+
+    fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        (**self).peer_credentials()
+    }
+}
+
+/// A pair of pipes, such as a helper's stdin and stdout
+///
+/// Pipes carry no peer credentials, and only their write side can be shut down.
+pub struct PipeTransport {
+    reader: File,
+    /// Dropped once the write side has been shut down, signalling EOF to the peer
+    writer: Option<File>,
+}
+
+impl PipeTransport {
+    /// Creates a transport reading from `reader` and writing to `writer`
+    pub fn new(reader: impl Into<OwnedFd>, writer: impl Into<OwnedFd>) -> Self {
+        Self {
+            reader: File::from(reader.into()),
+            writer: Some(File::from(writer.into())),
+        }
+    }
+}
+
+impl Transport for PipeTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => suppress_sigpipe(|| writer.write_all(buf).and_then(|_| writer.flush())),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        if how != Shutdown::Read {
+            self.writer.take();
+        }
+        Ok(())
+    }
+
+    fn try_write_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        if !fds.is_empty() {
+            return Err(unsupported("passing file descriptors"));
+        }
+        let Some(writer) = self.writer.as_mut() else {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        };
+        set_nonblocking(writer.as_raw_fd(), true)?;
+        let written = suppress_sigpipe(|| writer.write(buf));
+        set_nonblocking(writer.as_raw_fd(), false)?;
+        written
+    }
+
+    fn write_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.writer.as_ref().map(File::as_fd)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        set_nonblocking(self.reader.as_raw_fd(), nonblocking)
+    }
+
+    fn poll_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.reader.as_fd())
+    }
+}
+
+/// Adapts any reader and writer into a blocking [`Transport`]
+///
+/// Useful for in-process streams and other sources without a file descriptor.
+/// Such connections can't be polled, so only the blocking receive methods work.
+pub struct StreamTransport<R, W> {
+    reader: R,
+    writer: Option<W>,
+}
+
+impl<R, W> StreamTransport<R, W>
+where
+    R: Read + Send,
+    W: Write + Send,
+{
+    /// Creates a transport reading from `reader` and writing to `writer`
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer: Some(writer),
+        }
+    }
+}
+
+impl<R, W> Transport for StreamTransport<R, W>
+where
+    R: Read + Send,
+    W: Write + Send,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.write_all(buf).and_then(|_| writer.flush()),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// Dropping the writer is the only way to signal EOF, so the read side can't be shut down
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        if how != Shutdown::Read {
+            self.writer.take();
+        }
+        Ok(())
+    }
+}
+
+/// Writes all of `buf` to a socket with `MSG_NOSIGNAL`, so a closed peer can't raise `SIGPIPE`
+pub(crate) fn send_nosignal(socket: BorrowedFd<'_>, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        // SAFETY: the pointer and length describe `buf`
+        let sent = unsafe {
+            libc::send(
+                socket.as_raw_fd(),
+                buf.as_ptr().cast(),
+                buf.len(),
+                libc::MSG_NOSIGNAL,
+            )
+        };
+        match sent {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n if n > 0 => buf = &buf[n as usize..],
+            _ => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes as much of `buf` to a socket as fits without blocking, with `MSG_NOSIGNAL`
+pub(crate) fn try_send(socket: BorrowedFd<'_>, buf: &[u8]) -> io::Result<usize> {
+    loop {
+        // SAFETY: the pointer and length describe `buf`
+        let sent = unsafe {
+            libc::send(
+                socket.as_raw_fd(),
+                buf.as_ptr().cast(),
+                buf.len(),
+                libc::MSG_NOSIGNAL | libc::MSG_DONTWAIT,
+            )
+        };
+        if sent >= 0 {
+            return Ok(sent as usize);
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+/// Switches a file descriptor between blocking and non-blocking mode
+fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
+    let mut flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+    flags.set(OFlag::O_NONBLOCK, nonblocking);
+    fcntl(fd, FcntlArg::F_SETFL(flags))?;
+    Ok(())
+}
+
+/// Runs `write` with `SIGPIPE` blocked, discarding the signal if the write raised it
+///
+/// Pipes and `sendfile(2)` have no equivalent of `MSG_NOSIGNAL`, so this keeps a
+/// vanished peer from killing processes that haven't ignored the signal.
+pub(crate) fn suppress_sigpipe<T>(write: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    // SAFETY: the signal sets are initialised by sigemptyset and sigpending before use,
+    // and only the calling thread's mask is changed, and restored before returning
+    unsafe {
+        let mut sigpipe = mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut sigpipe);
+        libc::sigaddset(&mut sigpipe, libc::SIGPIPE);
+
+        // A SIGPIPE pending from elsewhere must still be delivered
+        let mut pending = mem::zeroed::<libc::sigset_t>();
+        libc::sigpending(&mut pending);
+        let already_pending = libc::sigismember(&pending, libc::SIGPIPE) == 1;
+
+        let mut previous = mem::zeroed::<libc::sigset_t>();
+        libc::pthread_sigmask(libc::SIG_BLOCK, &sigpipe, &mut previous);
+        let result = write();
+        if !already_pending && matches!(&result, Err(e) if e.kind() == io::ErrorKind::BrokenPipe) {
+            let immediately = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            libc::sigtimedwait(&sigpipe, ptr::null_mut(), &immediately);
+        }
+        libc::pthread_sigmask(libc::SIG_SETMASK, &previous, ptr::null_mut());
+        result
+    }
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{what} not supported by this transport"),
+    )
+}
@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! TypeScript declarations of the messages sent over connections
+//!
+//! Frontends written in TypeScript speak the same JSON as the Rust clients, and
+//! declaring the messages by hand lets the two drift apart. `#[derive(TypeScript)]`
+//! declares a type from its definition instead, following the serde attributes
+//! that shape its JSON: renames, `tag`, `content` and `untagged` enums, `flatten`,
+//! `skip` and `skip_serializing_if`. Attributes it can't follow, such as `with`,
+//! are rejected at compile time rather than declared wrongly.
+//!
+//! [`Declarations`] collects the declarations of some types and of every type
+//! they refer to, ready to be written to a `.d.ts` file.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize, TypeScript)]
+//! #[serde(tag = "type")]
+//! enum Request {
+//!     Mount { source: String, target: String },
+//!     Unmount { target: String },
+//! }
+//!
+//! let mut declarations = Declarations::new();
+//! declarations.add::<Request>();
+//! std::fs::write("messages.d.ts", declarations.to_string())?;
+//! ```
+//!
+//! Names are declared as they are in Rust, so two types of the same name in
+//! different modules have to be told apart with `#[typescript(rename = "...")]`.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+pub use privileged_ipc_macros::TypeScript;
+
+/// A type with a TypeScript counterpart, matching how serde_json writes it
+pub trait TypeScript {
+    /// Returns how the type is written where it is used, such as `string[]` or `Targeted<Request>`
+    fn ts_type() -> String;
+
+    /// Adds the declarations of this type and of the types it refers to
+    ///
+    /// Builtin types have nothing to declare.
+    fn declare(_declarations: &mut Declarations) {}
+}
+
+/// The declarations of a set of types, in the order they were added
+///
+/// Displays as the contents of a TypeScript module exporting each of them.
+#[derive(Debug, Default)]
+pub struct Declarations {
+    declared: Vec<(String, String)>,
+}
+
+impl Declarations {
+    /// Creates an empty set of declarations
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares `T` and every type it refers to
+    pub fn add<T: TypeScript + ?Sized>(&mut self) -> &mut Self {
+        T::declare(self);
+        self
+    }
+
+    /// Records the declaration of `name`, returning false if it was already declared
+    ///
+    /// Used by `#[derive(TypeScript)]`, which only declares the types a type
+    /// refers to the first time, so recursive types terminate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another type has already declared `name` differently, as
+    /// TypeScript only has one namespace for them.
+    pub fn insert(&mut self, name: &str, declaration: String) -> bool {
+        match self.declared.iter().find(|(declared, _)| declared == name) {
+            Some((_, existing)) if *existing == declaration => false,
+            Some(_) => panic!(
+                "two types are declared as `{name}` in TypeScript, \
+                 rename one with #[typescript(rename = \"...\")]"
+            ),
+            None => {
+                self.declared.push((name.to_owned(), declaration));
+                true
+            }
+        }
+    }
+
+    /// Returns true if nothing has been declared
+    pub fn is_empty(&self) -> bool {
+        self.declared.is_empty()
+    }
+}
+
+impl fmt::Display for Declarations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (_, declaration)) in self.declared.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{declaration}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps `ts_type` in parentheses if it is a union or intersection, for use inside an array
+fn grouped(ts_type: String) -> String {
+    match ts_type.contains(['|', '&']) {
+        true => format!("({ts_type})"),
+        false => ts_type,
+    }
+}
+
+macro_rules! builtin {
+    ($ts:literal: $($ty:ty),*) => {
+        $(impl TypeScript for $ty {
+            fn ts_type() -> String {
+                $ts.to_owned()
+            }
+        })*
+    };
+}
+
+builtin!("boolean": bool);
+builtin!("number": i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+builtin!("string": char, str, String, Path, PathBuf);
+builtin!("null": ());
+// Encoded as its index among the descriptors passed with the frame
+builtin!("number": crate::Descriptor);
+
+/// Types serialized as the type they hold
+macro_rules! transparent {
+    ($($ty:ident),*) => {
+        $(impl<T: TypeScript + ?Sized> TypeScript for $ty<T> {
+            fn ts_type() -> String {
+                T::ts_type()
+            }
+
+            fn declare(declarations: &mut Declarations) {
+                T::declare(declarations);
+            }
+        })*
+    };
+}
+
+transparent!(Box, Rc, Arc);
+
+impl<T: TypeScript + ?Sized> TypeScript for &T {
+    fn ts_type() -> String {
+        T::ts_type()
+    }
+
+    fn declare(declarations: &mut Declarations) {
+        T::declare(declarations);
+    }
+}
+
+impl<T: TypeScript + ToOwned + ?Sized> TypeScript for Cow<'_, T> {
+    fn ts_type() -> String {
+        T::ts_type()
+    }
+
+    fn declare(declarations: &mut Declarations) {
+        T::declare(declarations);
+    }
+}
+
+impl<T: TypeScript> TypeScript for Option<T> {
+    fn ts_type() -> String {
+        format!("{} | null", T::ts_type())
+    }
+
+    fn declare(declarations: &mut Declarations) {
+        T::declare(declarations);
+    }
+}
+
+/// Types serialized as arrays of their elements
+macro_rules! array {
+    ($($ty:ident),*) => {
+        $(impl<T: TypeScript> TypeScript for $ty<T> {
+            fn ts_type() -> String {
+                format!("{}[]", grouped(T::ts_type()))
+            }
+
+            fn declare(declarations: &mut Declarations) {
+                T::declare(declarations);
+            }
+        })*
+    };
+}
+
+array!(Vec, VecDeque, BTreeSet);
+
+impl<T: TypeScript, H> TypeScript for HashSet<T, H> {
+    fn ts_type() -> String {
+        format!("{}[]", grouped(T::ts_type()))
+    }
+
+    fn declare(declarations: &mut Declarations) {
+        T::declare(declarations);
+    }
+}
+
+impl<T: TypeScript> TypeScript for [T] {
+    fn ts_type() -> String {
+        format!("{}[]", grouped(T::ts_type()))
+    }
+
+    fn declare(declarations: &mut Declarations) {
+        T::declare(declarations);
+    }
+}
+
+impl<T: TypeScript, const N: usize> TypeScript for [T; N] {
+    fn ts_type() -> String {
+        format!("{}[]", grouped(T::ts_type()))
+    }
+
+    fn declare(declarations: &mut Declarations) {
+        T::declare(declarations);
+    }
+}
+
+impl<K: TypeScript, V: TypeScript> TypeScript for BTreeMap<K, V> {
+    fn ts_type() -> String {
+        format!("Record<{}, {}>", K::ts_type(), V::ts_type())
+    }
+
+    fn declare(declarations: &mut Declarations) {
+        K::declare(declarations);
+        V::declare(declarations);
+    }
+}
+
+impl<K: TypeScript, V: TypeScript, H> TypeScript for HashMap<K, V, H> {
+    fn ts_type() -> String {
+        format!("Record<{}, {}>", K::ts_type(), V::ts_type())
+    }
+
+    fn declare(declarations: &mut Declarations) {
+        K::declare(declarations);
+        V::declare(declarations);
+    }
+}
+
+macro_rules! tuple {
+    ($($ty:ident),*) => {
+        impl<$($ty: TypeScript),*> TypeScript for ($($ty,)*) {
+            fn ts_type() -> String {
+                let elements: &[String] = &[$($ty::ts_type()),*];
+                format!("[{}]", elements.join(", "))
+            }
+
+            fn declare(declarations: &mut Declarations) {
+                $($ty::declare(declarations);)*
+            }
+        }
+    };
+}
+
+tuple!(A);
+tuple!(A, B);
+tuple!(A, B, C);
+tuple!(A, B, C, D);
+tuple!(A, B, C, D, E);
+tuple!(A, B, C, D, E, F);
@@ -0,0 +1,219 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Helpers reporting what resources they use
+//!
+//! A frontend can only guess at what its backend costs, and a helper that leaks
+//! memory in production goes unnoticed until the system starts swapping. A
+//! helper that enables [`IpcConnection::set_usage_reports`] periodically tells
+//! its client its resident memory, CPU time and storage I/O, as read from
+//! `/proc/self`. The client keeps the latest report, see
+//! [`IpcConnection::peer_usage`], and can watch every report as it arrives.
+//!
+//! Reports are [control frames](crate::control) with a payload, so never reach
+//! the application, and need [`Framing::LengthPrefixed`] on both sides. Like
+//! heartbeats, they are sent while the helper receives or sends, so a helper busy
+//! for a long time without sending anything reports nothing in the meantime.
+
+use std::{
+    fs, io,
+    time::{Duration, Instant},
+};
+
+use nix::errno::Errno;
+
+use crate::{control, Codec, Framing, IpcConnection, IpcError};
+
+/// Size of the payload of a usage report
+pub(crate) const USAGE_LEN: usize = 32;
+
+/// Marks a counter the kernel doesn't provide
+const UNKNOWN: u64 = u64::MAX;
+
+/// Resources used by a process, as reported by the peer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// Resident memory, in bytes
+    pub rss: u64,
+    /// Time spent on a CPU, in user and kernel mode combined
+    pub cpu_time: Duration,
+    /// Bytes read from storage, unless the kernel doesn't account for I/O
+    pub read_bytes: Option<u64>,
+    /// Bytes written to storage, unless the kernel doesn't account for I/O
+    pub write_bytes: Option<u64>,
+}
+
+impl ResourceUsage {
+    /// Measures the resources used by this process
+    pub fn current() -> io::Result<Self> {
+        let statm = fs::read_to_string("/proc/self/statm")?;
+        let pages = statm
+            .split_whitespace()
+            .nth(1)
+            .and_then(|pages| pages.parse::<u64>().ok())
+            .ok_or_else(|| malformed("statm"))?;
+        // SAFETY: no pointers are involved
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+        // SAFETY: all zeroes is a valid rusage, which the kernel overwrites
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+        // SAFETY: `rusage` is valid for writes
+        Errno::result(unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut rusage) })?;
+        let time = |tv: libc::timeval| {
+            Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+        };
+
+        // Missing without task I/O accounting, and unreadable in some sandboxes
+        let io = fs::read_to_string("/proc/self/io").unwrap_or_default();
+        let counter = |name: &str| {
+            io.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.trim().parse().ok())
+        };
+
+        Ok(Self {
+            rss: pages.saturating_mul(page_size.max(0) as u64),
+            cpu_time: time(rusage.ru_utime) + time(rusage.ru_stime),
+            read_bytes: counter("read_bytes"),
+            write_bytes: counter("write_bytes"),
+        })
+    }
+
+    pub(crate) fn encode(&self) -> [u8; USAGE_LEN] {
+        let cpu_time = u64::try_from(self.cpu_time.as_nanos()).unwrap_or(UNKNOWN - 1);
+        let mut payload = [0u8; USAGE_LEN];
+        for (field, value) in payload.chunks_exact_mut(8).zip([
+            self.rss,
+            cpu_time,
+            self.read_bytes.unwrap_or(UNKNOWN),
+            self.write_bytes.unwrap_or(UNKNOWN),
+        ]) {
+            field.copy_from_slice(&value.to_be_bytes());
+        }
+        payload
+    }
+
+    pub(crate) fn decode(payload: &[u8; USAGE_LEN]) -> Self {
+        let mut fields = payload
+            .chunks_exact(8)
+            .map(|field| u64::from_be_bytes(field.try_into().unwrap_or_default()));
+        let mut next = || fields.next().unwrap_or_default();
+        let known = |value: u64| (value != UNKNOWN).then_some(value);
+        Self {
+            rss: next(),
+            cpu_time: Duration::from_nanos(next()),
+            read_bytes: known(next()),
+            write_bytes: known(next()),
+        }
+    }
+}
+
+fn malformed(file: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed /proc/self/{file}"),
+    )
+}
+
+/// Usage reporting settings and progress of a connection
+pub(crate) struct UsageReports {
+    interval: Duration,
+    /// When usage was last reported, if it has been
+    last_report: Option<Instant>,
+}
+
+impl<S, R, C> IpcConnection<S, R, C>
+where
+    S: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+    C: Codec,
+{
+    /// Reports this process's resource usage to the peer every `interval`, starting straight away
+    ///
+    /// Enabling reports switches the connection to [`Framing::LengthPrefixed`],
+    /// which the peer must use as well.
+    pub fn set_usage_reports(&mut self, interval: Duration) {
+        self.usage_reports = Some(UsageReports {
+            interval,
+            last_report: None,
+        });
+        self.framing = Framing::LengthPrefixed;
+    }
+
+    /// Stops reporting resource usage, which is the default
+    pub fn clear_usage_reports(&mut self) {
+        self.usage_reports = None;
+    }
+
+    /// Reports this process's resource usage to the peer now
+    pub fn report_usage(&mut self) -> Result<(), IpcError> {
+        let usage = ResourceUsage::current()?;
+        self.send_usage(&usage)
+    }
+
+    fn send_usage(&mut self, usage: &ResourceUsage) -> Result<(), IpcError> {
+        if self.framing != Framing::LengthPrefixed {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "usage reports need length-prefixed framing",
+            )
+            .into());
+        }
+        if let Some(reports) = self.usage_reports.as_mut() {
+            reports.last_report = Some(Instant::now());
+        }
+        let mut frame = control::USAGE.to_be_bytes().to_vec();
+        frame.extend_from_slice(&usage.encode());
+        self.write_frames(&frame, &[])
+    }
+
+    /// Reports resource usage if an interval has passed, returning the time until the next report
+    pub(crate) fn usage_tick(&mut self) -> Result<Option<Duration>, IpcError> {
+        let Some(reports) = self.usage_reports.as_ref() else {
+            return Ok(None);
+        };
+        let interval = reports.interval;
+        if let Some(last_report) = reports.last_report {
+            let due = last_report + interval;
+            let now = Instant::now();
+            if now < due {
+                return Ok(Some(due - now));
+            }
+        }
+        match ResourceUsage::current() {
+            Ok(usage) => self.send_usage(&usage)?,
+            // Measuring is best effort, unlike reaching the peer
+            Err(e) => {
+                log::debug!("failed to measure resource usage: {e}");
+                if let Some(reports) = self.usage_reports.as_mut() {
+                    reports.last_report = Some(Instant::now());
+                }
+            }
+        }
+        Ok(Some(interval))
+    }
+}
+
+impl<S, R, C> IpcConnection<S, R, C> {
+    /// Returns the resource usage the peer last reported, see [`IpcConnection::set_usage_reports`]
+    pub fn peer_usage(&self) -> Option<ResourceUsage> {
+        self.peer_usage
+    }
+
+    /// Calls `observer` with every resource usage report the peer sends
+    ///
+    /// Reports are handled while receiving, and the observer runs on the
+    /// receiving thread, so it shouldn't block.
+    pub fn set_usage_observer(&mut self, observer: impl FnMut(ResourceUsage) + Send + 'static) {
+        self.usage_observer = Some(Box::new(observer));
+    }
+
+    /// Records a usage report received from the peer
+    pub(crate) fn usage_reported(&mut self, usage: ResourceUsage) {
+        self.peer_usage = Some(usage);
+        if let Some(observer) = self.usage_observer.as_mut() {
+            observer(usage);
+        }
+    }
+}
@@ -0,0 +1,629 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Speaking varlink, so helpers can be introspected with systemd's tools
+//!
+//! Varlink exchanges JSON objects terminated by a NUL byte, carried here by
+//! [`Framing::NulTerminated`]. A client sends a [`VarlinkCall`] naming a method
+//! by its interface, and each call is answered with a [`VarlinkReply`] holding
+//! either the results or an error. Every service also implements the
+//! `org.varlink.service` interface, describing itself and the interfaces it
+//! provides.
+//!
+//! A [`VarlinkService`] answers that interface itself and routes every other
+//! call to the handler of its interface, and a [`VarlinkClient`] makes calls.
+//! `varlinkctl` connects to filesystem sockets, so to be inspected with it a
+//! helper binds its server with [`IpcServer::bind_path`]:
+//!
+//! ```ignore
+//! let server = IpcServer::bind_path(&PathSocket::new("/run/disks").name("io.serpentos.disks"))?;
+//! VarlinkService::new("Serpent OS", "disks", "1", "https://serpentos.com")
+//!     .interface(DESCRIPTION, |call| match call.member() {
+//!         "Mount" => mount(call.decode_parameters()?),
+//!         _ => Err(VarlinkError::method_not_found(&call.method)),
+//!     })
+//!     .serve_clients(&server)?;
+//! ```
+//!
+//! after which `varlinkctl info /run/disks/io.serpentos.disks` lists it. Upgraded
+//! connections aren't supported, and calls asking for more replies get just one.
+
+use std::fmt;
+
+use serde::{
+    de::{self, DeserializeOwned, IgnoredAny, MapAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{json, Value};
+
+use crate::{serve::SHUTDOWN_POLL_INTERVAL, Framing, IpcConnection, IpcError, IpcServer};
+
+/// The interface every varlink service implements
+pub const SERVICE_INTERFACE: &str = "org.varlink.service";
+
+/// Description of [`SERVICE_INTERFACE`], as given by the varlink specification
+const SERVICE_DESCRIPTION: &str = "\
+# The Varlink Service Interface is provided by every varlink service. It
+# describes the service and the interfaces it implements.
+interface org.varlink.service
+
+# Get a list of all the interfaces a service provides and information
+# about the implementation.
+method GetInfo() -> (
+  vendor: string,
+  product: string,
+  version: string,
+  url: string,
+  interfaces: []string
+)
+
+# Get the description of an interface that is implemented by this service.
+method GetInterfaceDescription(interface: string) -> (description: string)
+
+# The requested interface was not found.
+error InterfaceNotFound (interface: string)
+
+# The requested method was not found
+error MethodNotFound (method: string)
+
+# The interface defines the requested method, but the service does not
+# implement it.
+error MethodNotImplemented (method: string)
+
+# One of the passed parameters is invalid.
+error InvalidParameter (parameter: string)
+
+# Client is denied access
+error PermissionDenied ()
+
+# Method is expected to be called with 'more' set to true, but wasn't
+error ExpectedMore ()
+";
+
+/// A method call, as sent by a varlink client
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarlinkCall {
+    /// The fully qualified method name, such as `org.varlink.service.GetInfo`
+    pub method: String,
+    /// The arguments of the call, as an object
+    pub parameters: Value,
+    /// The client expects no reply
+    pub oneway: bool,
+    /// The client accepts several replies
+    pub more: bool,
+}
+
+impl VarlinkCall {
+    /// Calls `method` with `parameters`, expecting a single reply
+    pub fn new(method: impl Into<String>, parameters: Value) -> Self {
+        Self {
+            method: method.into(),
+            parameters,
+            oneway: false,
+            more: false,
+        }
+    }
+
+    /// Returns the interface the method belongs to
+    pub fn interface(&self) -> &str {
+        self.method
+            .rsplit_once('.')
+            .map_or("", |(interface, _)| interface)
+    }
+
+    /// Returns the method name within its interface, such as `GetInfo`
+    pub fn member(&self) -> &str {
+        self.method
+            .rsplit_once('.')
+            .map_or(&self.method, |(_, member)| member)
+    }
+
+    /// Decodes the parameters, failing with `InvalidParameter` if they don't fit `T`
+    pub fn decode_parameters<T: DeserializeOwned>(&self) -> Result<T, VarlinkError> {
+        T::deserialize(&self.parameters).map_err(|e| {
+            // serde names the offending field between backticks, where it can
+            let message = e.to_string();
+            let parameter = message.split('`').nth(1).unwrap_or("parameters").to_owned();
+            VarlinkError::invalid_parameter(parameter)
+        })
+    }
+}
+
+impl Serialize for VarlinkCall {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = 2 + usize::from(self.oneway) + usize::from(self.more);
+        let mut call = serializer.serialize_struct("VarlinkCall", len)?;
+        call.serialize_field("method", &self.method)?;
+        call.serialize_field("parameters", &self.parameters)?;
+        if self.oneway {
+            call.serialize_field("oneway", &true)?;
+        }
+        if self.more {
+            call.serialize_field("more", &true)?;
+        }
+        call.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for VarlinkCall {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(
+            "VarlinkCall",
+            &["method", "parameters", "oneway", "more"],
+            CallVisitor,
+        )
+    }
+}
+
+struct CallVisitor;
+
+impl<'de> Visitor<'de> for CallVisitor {
+    type Value = VarlinkCall;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a varlink call")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut call = VarlinkCall::new(String::new(), Value::Object(Default::default()));
+        let mut method = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "method" => method = Some(map.next_value()?),
+                "parameters" => call.parameters = map.next_value()?,
+                "oneway" => call.oneway = map.next_value()?,
+                "more" => call.more = map.next_value()?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        call.method = method.ok_or_else(|| de::Error::missing_field("method"))?;
+        Ok(call)
+    }
+}
+
+/// The answer to a [`VarlinkCall`], carrying either its results or an error
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarlinkReply {
+    /// The results of the call, or the parameters of the error
+    pub parameters: Value,
+    /// More replies to the same call follow
+    pub continues: bool,
+    /// The fully qualified name of the error the call failed with
+    pub error: Option<String>,
+}
+
+impl VarlinkReply {
+    /// Returns the reply to a call that succeeded or failed
+    pub fn from_result(result: Result<Value, VarlinkError>) -> Self {
+        match result {
+            Ok(parameters) => Self {
+                parameters,
+                continues: false,
+                error: None,
+            },
+            Err(e) => Self {
+                parameters: e.parameters,
+                continues: false,
+                error: Some(e.name),
+            },
+        }
+    }
+
+    /// Returns the results of the call, or the error it failed with
+    pub fn into_result(self) -> Result<Value, VarlinkError> {
+        match self.error {
+            None => Ok(self.parameters),
+            Some(name) => Err(VarlinkError::new(name, self.parameters)),
+        }
+    }
+}
+
+impl Serialize for VarlinkReply {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = 1 + usize::from(self.continues) + usize::from(self.error.is_some());
+        let mut reply = serializer.serialize_struct("VarlinkReply", len)?;
+        if let Some(error) = &self.error {
+            reply.serialize_field("error", error)?;
+        }
+        reply.serialize_field("parameters", &self.parameters)?;
+        if self.continues {
+            reply.serialize_field("continues", &true)?;
+        }
+        reply.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for VarlinkReply {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(
+            "VarlinkReply",
+            &["parameters", "continues", "error"],
+            ReplyVisitor,
+        )
+    }
+}
+
+struct ReplyVisitor;
+
+impl<'de> Visitor<'de> for ReplyVisitor {
+    type Value = VarlinkReply;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a varlink reply")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut reply = VarlinkReply::from_result(Ok(Value::Object(Default::default())));
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "parameters" => reply.parameters = map.next_value()?,
+                "continues" => reply.continues = map.next_value()?,
+                "error" => reply.error = map.next_value()?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(reply)
+    }
+}
+
+/// An error a varlink call failed with, named by the interface defining it
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarlinkError {
+    /// The fully qualified error name, such as `org.varlink.service.MethodNotFound`
+    pub name: String,
+    /// The parameters of the error, as an object
+    pub parameters: Value,
+}
+
+impl VarlinkError {
+    /// Returns the error `name` with `parameters`
+    pub fn new(name: impl Into<String>, parameters: Value) -> Self {
+        Self {
+            name: name.into(),
+            parameters,
+        }
+    }
+
+    fn service(name: &str, parameters: Value) -> Self {
+        Self::new(format!("{SERVICE_INTERFACE}.{name}"), parameters)
+    }
+
+    /// The service doesn't provide `interface`
+    pub fn interface_not_found(interface: impl Into<String>) -> Self {
+        Self::service(
+            "InterfaceNotFound",
+            json!({ "interface": interface.into() }),
+        )
+    }
+
+    /// The interface doesn't define `method`
+    pub fn method_not_found(method: impl Into<String>) -> Self {
+        Self::service("MethodNotFound", json!({ "method": method.into() }))
+    }
+
+    /// The interface defines `method`, but the service doesn't implement it
+    pub fn method_not_implemented(method: impl Into<String>) -> Self {
+        Self::service("MethodNotImplemented", json!({ "method": method.into() }))
+    }
+
+    /// The `parameter` of the call is missing or invalid
+    pub fn invalid_parameter(parameter: impl Into<String>) -> Self {
+        Self::service("InvalidParameter", json!({ "parameter": parameter.into() }))
+    }
+
+    /// The client isn't allowed to make the call
+    pub fn permission_denied() -> Self {
+        Self::service("PermissionDenied", json!({}))
+    }
+}
+
+impl fmt::Display for VarlinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        match &self.parameters {
+            Value::Object(parameters) if parameters.is_empty() => Ok(()),
+            Value::Null => Ok(()),
+            parameters => write!(f, " {parameters}"),
+        }
+    }
+}
+
+impl std::error::Error for VarlinkError {}
+
+/// How a varlink service describes itself, as returned by `org.varlink.service.GetInfo`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarlinkInfo {
+    /// Who ships the service
+    pub vendor: String,
+    /// What the service is part of
+    pub product: String,
+    /// The version of the service
+    pub version: String,
+    /// Where to find out more
+    pub url: String,
+    /// Every interface the service provides, including `org.varlink.service`
+    pub interfaces: Vec<String>,
+}
+
+impl VarlinkInfo {
+    fn to_value(&self) -> Value {
+        json!({
+            "vendor": self.vendor,
+            "product": self.product,
+            "version": self.version,
+            "url": self.url,
+            "interfaces": self.interfaces,
+        })
+    }
+
+    fn from_value(value: &Value) -> Result<Self, IpcError> {
+        let string = |name: &str| {
+            value[name]
+                .as_str()
+                .map(str::to_owned)
+                .ok_or_else(|| IpcError::ProtocolViolation(format!("GetInfo reply has no {name}")))
+        };
+        let interfaces = value["interfaces"]
+            .as_array()
+            .and_then(|interfaces| {
+                interfaces
+                    .iter()
+                    .map(|interface| interface.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .ok_or_else(|| {
+                IpcError::ProtocolViolation("GetInfo reply lists no interfaces".into())
+            })?;
+        Ok(Self {
+            vendor: string("vendor")?,
+            product: string("product")?,
+            version: string("version")?,
+            url: string("url")?,
+            interfaces,
+        })
+    }
+}
+
+type Handler = Box<dyn FnMut(&VarlinkCall) -> Result<Value, VarlinkError>>;
+
+/// An interface provided by a [`VarlinkService`]
+struct Interface {
+    name: String,
+    description: String,
+    handler: Handler,
+}
+
+/// Answers varlink calls, routing each to the handler of its interface
+pub struct VarlinkService {
+    vendor: String,
+    product: String,
+    version: String,
+    url: String,
+    interfaces: Vec<Interface>,
+}
+
+impl VarlinkService {
+    /// Describes a service that doesn't provide any interfaces yet
+    pub fn new(
+        vendor: impl Into<String>,
+        product: impl Into<String>,
+        version: impl Into<String>,
+        url: impl Into<String>,
+    ) -> Self {
+        Self {
+            vendor: vendor.into(),
+            product: product.into(),
+            version: version.into(),
+            url: url.into(),
+            interfaces: vec![],
+        }
+    }
+
+    /// Provides the interface `description`, whose calls are answered by `handler`
+    ///
+    /// The description is written in the varlink interface definition language
+    /// and returned as it is to clients introspecting the service, and the
+    /// interface is named by its `interface` line. The handler is given calls
+    /// to any method of the interface, and fails those it doesn't know with
+    /// [`VarlinkError::method_not_found`].
+    ///
+    /// Panics if the description names no interface.
+    pub fn interface<F>(mut self, description: impl Into<String>, handler: F) -> Self
+    where
+        F: FnMut(&VarlinkCall) -> Result<Value, VarlinkError> + 'static,
+    {
+        let description = description.into();
+        let name = interface_name(&description);
+        assert!(
+            !name.is_empty(),
+            "a varlink interface description starts with its `interface` line"
+        );
+        self.interfaces.push(Interface {
+            name: name.to_owned(),
+            description,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Returns how the service describes itself to clients
+    pub fn info(&self) -> VarlinkInfo {
+        VarlinkInfo {
+            vendor: self.vendor.clone(),
+            product: self.product.clone(),
+            version: self.version.clone(),
+            url: self.url.clone(),
+            interfaces: std::iter::once(SERVICE_INTERFACE.to_owned())
+                .chain(self.interfaces.iter().map(|i| i.name.clone()))
+                .collect(),
+        }
+    }
+
+    /// Answers `call`, with the result the client is sent unless the call is oneway
+    pub fn dispatch(&mut self, call: &VarlinkCall) -> Result<Value, VarlinkError> {
+        let interface = call.interface();
+        if interface == SERVICE_INTERFACE {
+            return match call.member() {
+                "GetInfo" => Ok(self.info().to_value()),
+                "GetInterfaceDescription" => {
+                    let wanted = call.parameters["interface"]
+                        .as_str()
+                        .ok_or_else(|| VarlinkError::invalid_parameter("interface"))?;
+                    let description = match wanted {
+                        SERVICE_INTERFACE => SERVICE_DESCRIPTION,
+                        _ => self
+                            .interfaces
+                            .iter()
+                            .find(|i| i.name == wanted)
+                            .map(|i| i.description.as_str())
+                            .ok_or_else(|| VarlinkError::interface_not_found(wanted))?,
+                    };
+                    Ok(json!({ "description": description }))
+                }
+                _ => Err(VarlinkError::method_not_found(&call.method)),
+            };
+        }
+        match self.interfaces.iter_mut().find(|i| i.name == interface) {
+            Some(provided) => (provided.handler)(call),
+            None => Err(VarlinkError::interface_not_found(interface)),
+        }
+    }
+
+    /// Answers calls on `connection` until the client leaves
+    ///
+    /// The connection is switched to [`Framing::NulTerminated`].
+    pub fn serve(
+        &mut self,
+        connection: &mut IpcConnection<VarlinkReply, VarlinkCall>,
+    ) -> Result<(), IpcError> {
+        connection.set_framing(Framing::NulTerminated);
+        while let Some(call) = connection.recv()? {
+            log::trace!("📞 varlink call to {}", call.method);
+            let result = self.dispatch(&call);
+            if call.oneway {
+                continue;
+            }
+            connection.send(&VarlinkReply::from_result(result))?;
+            connection.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Answers clients of `server` one after another, until shutdown is requested
+    ///
+    /// A client session that fails is logged and ends without affecting the
+    /// next. As with [`IpcServer::serve_with`], a client refused for running as
+    /// another user is audited and skipped.
+    pub fn serve_clients(
+        &mut self,
+        server: &IpcServer<VarlinkReply, VarlinkCall>,
+    ) -> Result<(), IpcError> {
+        while !server.shutdown.is_shutdown() {
+            let mut connection = match server.accept_timeout(SHUTDOWN_POLL_INTERVAL) {
+                Ok(Some(connection)) => connection,
+                Ok(None) => continue,
+                Err(
+                    e @ (IpcError::UnauthorizedPeer { .. }
+                    | IpcError::ForeignClient { .. }
+                    | IpcError::TokenMismatch),
+                ) => {
+                    log::warn!(target: "privileged_ipc::audit", "🚨 {e}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            match self.serve(&mut connection) {
+                Ok(()) => {
+                    if let Err(e) = connection.close() {
+                        log::error!("💥 failed to close varlink session: {e}");
+                    }
+                }
+                Err(e @ IpcError::TokenMismatch) => {
+                    log::warn!(target: "privileged_ipc::audit", "🚨 {e}")
+                }
+                Err(e) => log::error!("💥 varlink session failed: {e}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the name declared by the `interface` line of a description, or an empty string
+fn interface_name(description: &str) -> &str {
+    description
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .find_map(|line| line.strip_prefix("interface "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .unwrap_or_default()
+}
+
+/// Makes varlink calls over a connection, waiting for the reply to each
+pub struct VarlinkClient {
+    connection: IpcConnection<VarlinkCall, VarlinkReply>,
+}
+
+impl VarlinkClient {
+    /// Makes calls over `connection`, which is switched to [`Framing::NulTerminated`]
+    pub fn new(mut connection: IpcConnection<VarlinkCall, VarlinkReply>) -> Self {
+        connection.set_framing(Framing::NulTerminated);
+        Self { connection }
+    }
+
+    /// Connects to a varlink service listening on a filesystem socket
+    pub fn connect_path(path: impl AsRef<std::path::Path>) -> Result<Self, IpcError> {
+        Ok(Self::new(IpcConnection::connect_path(path)?))
+    }
+
+    /// Returns the underlying connection
+    pub fn into_inner(self) -> IpcConnection<VarlinkCall, VarlinkReply> {
+        self.connection
+    }
+
+    /// Calls `method` with `parameters`, returning its results
+    ///
+    /// An error reply fails with [`IpcError::Varlink`].
+    pub fn call(&mut self, method: &str, parameters: Value) -> Result<Value, IpcError> {
+        self.connection
+            .send(&VarlinkCall::new(method, parameters))?;
+        self.connection.flush()?;
+        let reply = self.connection.recv()?.ok_or(IpcError::ConnectionClosed)?;
+        reply.into_result().map_err(IpcError::Varlink)
+    }
+
+    /// Calls `method` with `parameters` without waiting for, or getting, a reply
+    pub fn call_oneway(&mut self, method: &str, parameters: Value) -> Result<(), IpcError> {
+        let mut call = VarlinkCall::new(method, parameters);
+        call.oneway = true;
+        self.connection.send(&call)?;
+        self.connection.flush()
+    }
+
+    /// Asks the service to describe itself
+    pub fn get_info(&mut self) -> Result<VarlinkInfo, IpcError> {
+        let info = self.call(&format!("{SERVICE_INTERFACE}.GetInfo"), json!({}))?;
+        VarlinkInfo::from_value(&info)
+    }
+
+    /// Asks the service for the description of `interface`
+    pub fn get_interface_description(&mut self, interface: &str) -> Result<String, IpcError> {
+        let reply = self.call(
+            &format!("{SERVICE_INTERFACE}.GetInterfaceDescription"),
+            json!({ "interface": interface }),
+        )?;
+        reply["description"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| {
+                IpcError::ProtocolViolation(
+                    "GetInterfaceDescription reply has no description".into(),
+                )
+            })
+    }
+}
@@ -0,0 +1,386 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    io,
+    os::fd::{FromRawFd, OwnedFd},
+};
+
+use nix::{
+    fcntl::{open, OFlag},
+    sys::stat::Mode,
+};
+use privileged_ipc::{
+    Action, Authorization, Descriptor, DirectExecutor, IpcClient, IpcError, PkexecExecutor,
+    PolkitActions,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::reply::{self, unexpected, Reply};
+
+/// Identifies a private mount namespace owned by the disks helper
+pub type NamespaceId = u32;
+
+/// Request types for the disks IPC
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "typescript",
+    derive(privileged_ipc::TypeScript),
+    typescript(rename = "DisksRequest")
+)]
+#[serde(tag = "type")]
+pub enum Request {
+    /// Create a private mount namespace, isolated from the host's mount table
+    CreateNamespace,
+    /// Unmount everything in a private namespace and discard it
+    DestroyNamespace { namespace: NamespaceId },
+    /// Mount a filesystem
+    Mount {
+        /// Namespace to mount into, or the host's mount table if `None`
+        namespace: Option<NamespaceId>,
+        source: String,
+        target: String,
+        fstype: String,
+        options: Vec<String>,
+    },
+    /// Unmount a filesystem
+    Unmount {
+        namespace: Option<NamespaceId>,
+        target: String,
+    },
+    /// Replace the partition table of a whole disk, destroying its contents
+    Partition {
+        device: String,
+        partitions: Vec<PartitionSpec>,
+    },
+    /// Create a filesystem on a block device
+    Format {
+        device: String,
+        fstype: String,
+        label: Option<String>,
+    },
+    /// Install and configure the bootloader for the system mounted at `root`
+    ConfigureBootloader {
+        root: String,
+        /// EFI system partition to install into
+        esp: String,
+    },
+    /// Describe the helper's protocol, answered with a readable reference to it
+    Describe,
+}
+
+/// The purpose of a partition, determining its type GUID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+#[serde(rename_all = "kebab-case")]
+pub enum PartitionType {
+    /// EFI system partition
+    Esp,
+    /// Extended boot loader partition
+    Xbootldr,
+    /// Linux root filesystem
+    Root,
+    /// Any other Linux filesystem
+    Linux,
+    /// Swap space
+    Swap,
+}
+
+/// A partition to create when partitioning a disk
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct PartitionSpec {
+    pub kind: PartitionType,
+    /// Size in bytes, or the remaining space if `None`
+    pub size: Option<u64>,
+    pub label: Option<String>,
+}
+
+/// Guards requests that mount a filesystem
+pub const MOUNT: Action = Action {
+    id: "com.serpentos.disks.mount",
+    description: "Mount filesystems",
+    message: "Authentication is required to mount a filesystem",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that mount a filesystem for the installer
+pub const MOUNT_PRIVATE: Action = Action {
+    id: "com.serpentos.disks.mount-private",
+    description: "Mount filesystems in a private namespace",
+    message: "Authentication is required to mount a filesystem for the installer",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that modify disks
+pub const FORMAT: Action = Action {
+    id: "com.serpentos.disks.format",
+    description: "Partition and format disks",
+    message: "Authentication is required to modify disks",
+    allow_active: Authorization::AuthAdmin,
+};
+
+/// Guards requests that configure the bootloader
+pub const BOOTLOADER: Action = Action {
+    id: "com.serpentos.disks.bootloader",
+    description: "Configure the bootloader",
+    message: "Authentication is required to configure the bootloader",
+    allow_active: Authorization::AuthAdmin,
+};
+
+impl PolkitActions for Request {
+    const ACTIONS: &'static [Action] = &[MOUNT, MOUNT_PRIVATE, FORMAT, BOOTLOADER];
+
+    fn action(&self) -> Option<&'static Action> {
+        match self {
+            // Private namespaces never touch the host's mount table
+            Request::CreateNamespace | Request::DestroyNamespace { .. } => Some(&MOUNT_PRIVATE),
+            Request::Mount { namespace, .. } | Request::Unmount { namespace, .. } => {
+                match namespace {
+                    Some(_) => Some(&MOUNT_PRIVATE),
+                    None => Some(&MOUNT),
+                }
+            }
+            Request::Partition { .. } | Request::Format { .. } => Some(&FORMAT),
+            Request::ConfigureBootloader { .. } => Some(&BOOTLOADER),
+            Request::Describe => None,
+        }
+    }
+}
+
+/// Renders the reference to the disks protocol that answers [`Request::Describe`]
+#[cfg(feature = "typescript")]
+pub fn reference() -> String {
+    crate::reference::<Request, Response>("disks", Request::ACTIONS).to_string()
+}
+
+/// Response types for the disks IPC
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "typescript",
+    derive(privileged_ipc::TypeScript),
+    typescript(rename = "DisksResponse")
+)]
+#[serde(tag = "type")]
+pub enum Response {
+    /// A private namespace was created
+    NamespaceCreated { namespace: NamespaceId },
+    /// The namespace and all of its mounts are gone
+    NamespaceDestroyed,
+    /// The filesystem was mounted
+    Mounted {
+        /// The root of the mounted tree, opened with `O_PATH`
+        dir: Descriptor,
+        /// Where the tree is mounted as seen from within its namespace, for diagnostics
+        path: String,
+    },
+    /// The filesystem was unmounted
+    Unmounted,
+    /// The disk was partitioned, listing the device of each partition in the order requested
+    Partitioned { devices: Vec<String> },
+    /// The filesystem was created
+    Formatted,
+    /// The bootloader was installed and configured
+    BootloaderConfigured,
+    /// A readable reference to the helper's protocol, as rendered by `reference()`
+    Description { reference: String },
+    /// Error response
+    Error { message: String },
+}
+
+impl Response {
+    /// Answers a mount request with the tree mounted at `path`
+    ///
+    /// The path means nothing outside the helper's namespace, so the client is
+    /// passed the mounted directory itself.
+    pub fn mounted(path: &str) -> io::Result<Self> {
+        let fd = open(
+            path,
+            OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        // SAFETY: open returned a new descriptor that nothing else owns
+        let dir = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Response::Mounted {
+            dir: Descriptor::new(dir),
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Reply for Response {
+    fn into_result(self) -> Result<Self, String> {
+        match self {
+            Response::Error { message } => Err(message),
+            response => Ok(response),
+        }
+    }
+}
+
+/// A filesystem mounted by the helper
+#[derive(Debug)]
+pub struct Mount {
+    /// The root of the mounted tree, opened with `O_PATH`
+    ///
+    /// Reach files within it with `openat(2)` and the like, as the tree may be
+    /// mounted in a namespace the client can't see.
+    pub dir: OwnedFd,
+    /// Where the tree is mounted as seen from within its namespace, for diagnostics only
+    pub path: String,
+}
+
+/// Client for interacting with the disks helper
+pub struct DisksClient {
+    client: IpcClient<Request, Response>,
+}
+
+impl DisksClient {
+    /// Creates a new DisksClient with privilege escalation
+    pub fn new_privileged() -> Result<Self, IpcError> {
+        Self::new_privileged_with_path("/usr/bin/disks")
+    }
+
+    /// Creates a new DisksClient with privilege escalation and custom helper path
+    pub fn new_privileged_with_path(disks_path: &str) -> Result<Self, IpcError> {
+        Ok(Self {
+            client: IpcClient::new::<PkexecExecutor>(disks_path, &["ipc"])?,
+        })
+    }
+
+    /// Creates a new DisksClient without privilege escalation
+    pub fn new_direct() -> Result<Self, IpcError> {
+        Self::new_direct_with_path("/usr/bin/disks")
+    }
+
+    /// Creates a new DisksClient without privilege escalation and custom helper path
+    pub fn new_direct_with_path(disks_path: &str) -> Result<Self, IpcError> {
+        Ok(Self {
+            client: IpcClient::new::<DirectExecutor>(disks_path, &["ipc"])?,
+        })
+    }
+
+    /// Creates a private mount namespace owned by the helper
+    ///
+    /// Mounts made within it, such as during an installation preview, don't
+    /// appear in the host's mount table and vanish when it is destroyed or the
+    /// helper exits.
+    pub fn create_namespace(&mut self) -> Result<NamespaceId, IpcError> {
+        match self.request(&Request::CreateNamespace)? {
+            Response::NamespaceCreated { namespace } => Ok(namespace),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Unmounts everything within a private namespace and discards it
+    pub fn destroy_namespace(&mut self, namespace: NamespaceId) -> Result<(), IpcError> {
+        match self.request(&Request::DestroyNamespace { namespace })? {
+            Response::NamespaceDestroyed => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Mounts `source` at `target`, within `namespace` if given
+    ///
+    /// Returns the mounted tree, which is reachable even when mounted within a
+    /// private namespace.
+    pub fn mount(
+        &mut self,
+        namespace: Option<NamespaceId>,
+        source: &str,
+        target: &str,
+        fstype: &str,
+        options: &[&str],
+    ) -> Result<Mount, IpcError> {
+        let request = Request::Mount {
+            namespace,
+            source: source.to_string(),
+            target: target.to_string(),
+            fstype: fstype.to_string(),
+            options: options.iter().map(|o| o.to_string()).collect(),
+        };
+        match self.request(&request)? {
+            Response::Mounted { dir, path } => Ok(Mount {
+                dir: dir.into_inner(),
+                path,
+            }),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Unmounts `target`, within `namespace` if given
+    pub fn unmount(
+        &mut self,
+        namespace: Option<NamespaceId>,
+        target: &str,
+    ) -> Result<(), IpcError> {
+        let request = Request::Unmount {
+            namespace,
+            target: target.to_string(),
+        };
+        match self.request(&request)? {
+            Response::Unmounted => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Replaces the partition table of `device`, destroying everything on it
+    ///
+    /// Returns the device path of each new partition, in the order given.
+    pub fn partition(
+        &mut self,
+        device: &str,
+        partitions: &[PartitionSpec],
+    ) -> Result<Vec<String>, IpcError> {
+        let request = Request::Partition {
+            device: device.to_string(),
+            partitions: partitions.to_vec(),
+        };
+        match self.request(&request)? {
+            Response::Partitioned { devices } => Ok(devices),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Creates a filesystem of type `fstype` on `device`
+    pub fn format(
+        &mut self,
+        device: &str,
+        fstype: &str,
+        label: Option<&str>,
+    ) -> Result<(), IpcError> {
+        let request = Request::Format {
+            device: device.to_string(),
+            fstype: fstype.to_string(),
+            label: label.map(str::to_string),
+        };
+        match self.request(&request)? {
+            Response::Formatted => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Installs and configures the bootloader for the system mounted at `root`
+    pub fn configure_bootloader(&mut self, root: &str, esp: &str) -> Result<(), IpcError> {
+        let request = Request::ConfigureBootloader {
+            root: root.to_string(),
+            esp: esp.to_string(),
+        };
+        match self.request(&request)? {
+            Response::BootloaderConfigured => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Asks the helper for a readable reference to the protocol it speaks
+    pub fn describe(&mut self) -> Result<String, IpcError> {
+        match self.request(&Request::Describe)? {
+            Response::Description { reference } => Ok(reference),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn request(&mut self, request: &Request) -> Result<Response, IpcError> {
+        reply::request(&mut self.client, request)
+    }
+}
@@ -0,0 +1,253 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Installation of a new system, coordinating the disks and moss helpers
+//!
+//! [`InstallerClient`] takes an [`InstallPlan`] through every step of an
+//! installation: partitioning and formatting the target disk, mounting the new
+//! filesystems, bootstrapping packages into them and configuring the bootloader.
+//! Progress from all of the helpers is reported through a single callback.
+
+use std::path::Path;
+
+use privileged_ipc::IpcError;
+
+use crate::{
+    disks::{DisksClient, PartitionSpec, PartitionType},
+    moss::{MossClient, Progress, TransactionOutcome, Warning},
+};
+
+/// A partition of the target disk and how the new system uses it
+#[derive(Debug, Clone)]
+pub struct Volume {
+    pub kind: PartitionType,
+    /// Size in bytes, or the remaining space if `None`
+    pub size: Option<u64>,
+    pub label: Option<String>,
+    /// Filesystem to create, or `None` to leave the partition unformatted
+    pub fstype: Option<String>,
+    /// Where the filesystem is mounted in the new system, such as `/` or `/boot`
+    pub mountpoint: Option<String>,
+}
+
+/// Everything needed to install a new system onto a disk
+#[derive(Debug, Clone)]
+pub struct InstallPlan {
+    /// Whole disk to install to, everything on it is destroyed
+    pub device: String,
+    /// Partitions to create, in order; one must be an ESP and one mounted at `/`
+    pub volumes: Vec<Volume>,
+    /// Packages to bootstrap the new system with
+    pub packages: Vec<String>,
+    /// Directory the new system is assembled under while installing
+    pub root: String,
+}
+
+/// A step of the installation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallStep {
+    /// Partitioning the target disk
+    Partition,
+    /// Creating a filesystem on the given partition
+    Format { device: String },
+    /// Mounting a filesystem at the given path
+    Mount { target: String },
+    /// Installing packages into the new system
+    Bootstrap,
+    /// Installing and configuring the bootloader
+    Bootloader,
+    /// Unmounting the filesystem at the given path
+    Unmount { target: String },
+}
+
+/// Progress of an installation
+#[derive(Debug)]
+pub enum InstallEvent<'a> {
+    /// A new step has started
+    Step(InstallStep),
+    /// Progress within the current step
+    Progress(&'a Progress),
+    /// A non-fatal issue within the current step
+    Warning(&'a Warning),
+}
+
+/// Client driving a complete installation through the disks and moss helpers
+pub struct InstallerClient {
+    disks: DisksClient,
+    moss: MossClient,
+}
+
+impl InstallerClient {
+    /// Creates a new InstallerClient from already connected helper clients
+    pub fn new(disks: DisksClient, moss: MossClient) -> Self {
+        Self { disks, moss }
+    }
+
+    /// Creates a new InstallerClient, launching both helpers with privilege escalation
+    pub fn new_privileged() -> Result<Self, IpcError> {
+        Ok(Self::new(
+            DisksClient::new_privileged()?,
+            MossClient::new_privileged()?,
+        ))
+    }
+
+    /// Creates a new InstallerClient, launching both helpers without privilege escalation
+    pub fn new_direct() -> Result<Self, IpcError> {
+        Ok(Self::new(
+            DisksClient::new_direct()?,
+            MossClient::new_direct()?,
+        ))
+    }
+
+    /// The disks helper client, for operations outside of an installation
+    pub fn disks(&mut self) -> &mut DisksClient {
+        &mut self.disks
+    }
+
+    /// The moss helper client, for operations outside of an installation
+    pub fn moss(&mut self) -> &mut MossClient {
+        &mut self.moss
+    }
+
+    /// Installs a new system according to `plan`
+    ///
+    /// `on_event` is called as each step starts and for every progress report.
+    /// Filesystems are always unmounted again before returning, and the bootloader
+    /// is only configured if bootstrapping completed. If bootstrapping is aborted or
+    /// held on conflicts, that outcome is returned and the disk is left partitioned.
+    pub fn install(
+        &mut self,
+        plan: &InstallPlan,
+        mut on_event: impl FnMut(InstallEvent<'_>),
+    ) -> Result<TransactionOutcome, IpcError> {
+        let esp = plan
+            .volumes
+            .iter()
+            .position(|v| v.kind == PartitionType::Esp)
+            .ok_or_else(|| invalid_plan("no EFI system partition"))?;
+        if !plan
+            .volumes
+            .iter()
+            .any(|v| v.mountpoint.as_deref() == Some("/"))
+        {
+            return Err(invalid_plan("nothing is mounted at /"));
+        }
+        if plan
+            .volumes
+            .iter()
+            .any(|v| v.mountpoint.is_some() && v.fstype.is_none())
+        {
+            return Err(invalid_plan("a mounted volume has no filesystem"));
+        }
+
+        on_event(InstallEvent::Step(InstallStep::Partition));
+        let specs = plan
+            .volumes
+            .iter()
+            .map(|v| PartitionSpec {
+                kind: v.kind,
+                size: v.size,
+                label: v.label.clone(),
+            })
+            .collect::<Vec<_>>();
+        let devices = self.disks.partition(&plan.device, &specs)?;
+        if devices.len() != plan.volumes.len() {
+            return Err(IpcError::ProtocolViolation(format!(
+                "requested {} partitions but {} were created",
+                plan.volumes.len(),
+                devices.len()
+            )));
+        }
+
+        for (volume, device) in plan.volumes.iter().zip(&devices) {
+            if let Some(fstype) = &volume.fstype {
+                on_event(InstallEvent::Step(InstallStep::Format {
+                    device: device.clone(),
+                }));
+                self.disks.format(device, fstype, volume.label.as_deref())?;
+            }
+        }
+
+        let mut mounted = vec![];
+        let result = self.populate(plan, &devices, &devices[esp], &mut mounted, &mut on_event);
+
+        // Unmount even on failure, so the disk isn't left busy
+        let unmounted = mounted.iter().rev().try_for_each(|target| {
+            on_event(InstallEvent::Step(InstallStep::Unmount {
+                target: target.clone(),
+            }));
+            self.disks.unmount(None, target)
+        });
+        let outcome = result?;
+        unmounted?;
+        Ok(outcome)
+    }
+
+    /// Mounts the new filesystems, bootstraps packages into them and configures the bootloader
+    ///
+    /// Every successfully mounted target is added to `mounted`, in mount order.
+    fn populate(
+        &mut self,
+        plan: &InstallPlan,
+        devices: &[String],
+        esp: &str,
+        mounted: &mut Vec<String>,
+        on_event: &mut impl FnMut(InstallEvent<'_>),
+    ) -> Result<TransactionOutcome, IpcError> {
+        let mut mounts = plan
+            .volumes
+            .iter()
+            .zip(devices)
+            .filter_map(|(volume, device)| {
+                Some((
+                    volume.mountpoint.as_deref()?,
+                    volume.fstype.as_deref()?,
+                    device,
+                ))
+            })
+            .collect::<Vec<_>>();
+        // Parents must be mounted before anything beneath them
+        mounts.sort_by_key(|(mountpoint, _, _)| Path::new(mountpoint).components().count());
+
+        for (mountpoint, fstype, device) in mounts {
+            let target = match mountpoint.trim_start_matches('/') {
+                "" => plan.root.clone(),
+                relative => Path::new(&plan.root)
+                    .join(relative)
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            on_event(InstallEvent::Step(InstallStep::Mount {
+                target: target.clone(),
+            }));
+            self.disks.mount(None, device, &target, fstype, &[])?;
+            mounted.push(target);
+        }
+
+        on_event(InstallEvent::Step(InstallStep::Bootstrap));
+        let packages = plan.packages.iter().map(String::as_str).collect::<Vec<_>>();
+        let outcome = self.moss.bootstrap(&plan.root, &packages, |progress| {
+            on_event(InstallEvent::Progress(progress))
+        });
+        for warning in self.moss.take_warnings() {
+            on_event(InstallEvent::Warning(&warning));
+        }
+        let outcome = outcome?;
+        if !matches!(outcome, TransactionOutcome::Completed) {
+            return Ok(outcome);
+        }
+
+        on_event(InstallEvent::Step(InstallStep::Bootloader));
+        self.disks.configure_bootloader(&plan.root, esp)?;
+        Ok(outcome)
+    }
+}
+
+/// Error for a plan that can't produce a bootable system
+fn invalid_plan(reason: &str) -> IpcError {
+    IpcError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("invalid install plan: {reason}"),
+    ))
+}
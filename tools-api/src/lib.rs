@@ -2,4 +2,50 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+pub mod disks;
+pub mod installer;
 pub mod moss;
+mod reply;
+pub mod services;
+pub mod target;
+pub mod timeline;
+pub mod users;
+
+/// Declares the messages of every helper for TypeScript frontends, as they are sent on the wire
+///
+/// Requests to the helpers that take a target are declared as
+/// `Targeted<MossRequest>` and so on, with the target flattened into them.
+#[cfg(feature = "typescript")]
+pub fn typescript_declarations() -> privileged_ipc::Declarations {
+    let mut declarations = privileged_ipc::Declarations::new();
+    declarations
+        .add::<disks::Request>()
+        .add::<disks::Response>()
+        .add::<target::Targeted<moss::Request>>()
+        .add::<moss::Response>()
+        .add::<target::Targeted<services::Request>>()
+        .add::<services::Response>()
+        .add::<target::Targeted<users::Request>>()
+        .add::<users::Response>();
+    declarations
+}
+
+/// Starts the reference to a helper's protocol, which each helper answers `Describe` with
+#[cfg(feature = "typescript")]
+fn reference<Req, Resp>(
+    name: &str,
+    actions: &[privileged_ipc::Action],
+) -> privileged_ipc::ProtocolReference
+where
+    Req: privileged_ipc::TypeScript,
+    Resp: privileged_ipc::TypeScript,
+{
+    privileged_ipc::ProtocolReference::new(name)
+        .requests::<Req>()
+        .responses::<Resp>()
+        .actions(actions)
+        .note(
+            "Each request is answered by one final response, or by an Error response if the \
+             helper failed to handle it.",
+        )
+}
@@ -2,30 +2,490 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use privileged_ipc::{DirectExecutor, IpcClient, IpcError, PkexecExecutor};
+use std::collections::VecDeque;
+
+use privileged_ipc::{
+    Action, Authorization, DirectExecutor, IpcClient, IpcError, PkexecExecutor, PolkitActions,
+};
 use serde_derive::{Deserialize, Serialize};
 
+use crate::{
+    reply::{self, unexpected, Reply},
+    target::{Target, Targeted},
+    timeline::Timeline,
+};
+
+/// Number of completed operation timelines retained by a client
+const MAX_TIMELINES: usize = 32;
+
+/// Number of unread warnings a client retains
+const MAX_WARNINGS: usize = 64;
+
 /// Basic request types for moss IPC
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "typescript",
+    derive(privileged_ipc::TypeScript),
+    typescript(rename = "MossRequest")
+)]
 #[serde(tag = "type")]
 pub enum Request {
     /// Ping request to test connection
     Ping,
+    /// Install the named packages
+    Install { packages: Vec<String> },
+    /// Remove the named packages
+    Remove { packages: Vec<String> },
+    /// Resume a transaction that stopped on conflicts, using the chosen resolutions
+    Resolve {
+        transaction: u64,
+        choices: Vec<Choice>,
+    },
+    /// Install the named packages into a new system assembled under `root`
+    Bootstrap { root: String, packages: Vec<String> },
+    /// List the repository signing keys that are currently trusted
+    ListKeys,
+    /// Trust a repository signing key, given in its exported form
+    ImportKey { key: String },
+    /// Stop trusting the signing key with the given fingerprint
+    RevokeKey { fingerprint: String },
+    /// Download the named packages into the cache without installing them
+    Fetch { packages: Vec<String> },
+    /// Report how much the package cache holds
+    CacheInfo,
+    /// Remove cached packages, optionally keeping those that are installed
+    CleanCache { keep_installed: bool },
+    /// Snapshot the system filesystems, using whichever backend the helper supports
+    CreateSnapshot { description: String },
+    /// List existing snapshots
+    ListSnapshots,
+    /// Restore the system to a snapshot
+    RestoreSnapshot { id: u64 },
+    /// Delete a snapshot
+    DeleteSnapshot { id: u64 },
+    /// List the transaction journal, oldest first
+    Journal,
+    /// Restore the snapshot taken before a journaled transaction, or the most recent one if `None`
+    Rollback { transaction: Option<u64> },
+    /// Download and write out an update to apply at next boot, for all updates if `packages` is empty
+    StageUpdate { packages: Vec<String> },
+    /// Check the staged update is complete and intact
+    VerifyStagedUpdate,
+    /// Arrange for the staged update to be applied at next boot
+    ScheduleUpdate,
+    /// Unschedule and discard the staged update
+    CancelUpdate,
+    /// Report the state of offline updates
+    UpdateStatus,
+    /// Describe the helper's protocol, answered with a readable reference to it
+    Describe,
+}
+
+/// Guards requests that install software
+pub const INSTALL: Action = Action {
+    id: "com.serpentos.moss.install",
+    description: "Install software",
+    message: "Authentication is required to install software",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that remove software
+pub const REMOVE: Action = Action {
+    id: "com.serpentos.moss.remove",
+    description: "Remove software",
+    message: "Authentication is required to remove software",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that install a new system
+pub const BOOTSTRAP: Action = Action {
+    id: "com.serpentos.moss.bootstrap",
+    description: "Bootstrap a new system",
+    message: "Authentication is required to install a new system",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that change which signing keys are trusted
+pub const MANAGE_TRUST: Action = Action {
+    id: "com.serpentos.moss.manage-trust",
+    description: "Manage trusted signing keys",
+    message: "Authentication is required to change which signing keys are trusted",
+    allow_active: Authorization::AuthAdmin,
+};
+
+/// Guards requests that download software
+pub const FETCH: Action = Action {
+    id: "com.serpentos.moss.fetch",
+    description: "Download software",
+    message: "Authentication is required to download software",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that clean the package cache
+pub const CLEAN_CACHE: Action = Action {
+    id: "com.serpentos.moss.clean-cache",
+    description: "Clean the package cache",
+    message: "Authentication is required to clean the package cache",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that manage system snapshots
+pub const MANAGE_SNAPSHOTS: Action = Action {
+    id: "com.serpentos.moss.manage-snapshots",
+    description: "Manage system snapshots",
+    message: "Authentication is required to manage system snapshots",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that roll back the system
+pub const ROLLBACK: Action = Action {
+    id: "com.serpentos.moss.rollback",
+    description: "Roll back the system",
+    message: "Authentication is required to roll back the system",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that manage offline updates
+pub const OFFLINE_UPDATE: Action = Action {
+    id: "com.serpentos.moss.offline-update",
+    description: "Manage offline updates",
+    message: "Authentication is required to manage offline updates",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+impl PolkitActions for Request {
+    const ACTIONS: &'static [Action] = &[
+        INSTALL,
+        REMOVE,
+        BOOTSTRAP,
+        MANAGE_TRUST,
+        FETCH,
+        CLEAN_CACHE,
+        MANAGE_SNAPSHOTS,
+        ROLLBACK,
+        OFFLINE_UPDATE,
+    ];
+
+    fn action(&self) -> Option<&'static Action> {
+        match self {
+            Request::Ping
+            | Request::ListKeys
+            | Request::CacheInfo
+            | Request::ListSnapshots
+            | Request::Journal
+            | Request::VerifyStagedUpdate
+            | Request::UpdateStatus
+            | Request::Describe => None,
+            Request::Install { .. } | Request::Resolve { .. } => Some(&INSTALL),
+            Request::Remove { .. } => Some(&REMOVE),
+            Request::Bootstrap { .. } => Some(&BOOTSTRAP),
+            Request::ImportKey { .. } | Request::RevokeKey { .. } => Some(&MANAGE_TRUST),
+            Request::Fetch { .. } => Some(&FETCH),
+            Request::CleanCache { .. } => Some(&CLEAN_CACHE),
+            Request::CreateSnapshot { .. } | Request::DeleteSnapshot { .. } => {
+                Some(&MANAGE_SNAPSHOTS)
+            }
+            Request::RestoreSnapshot { .. } | Request::Rollback { .. } => Some(&ROLLBACK),
+            Request::StageUpdate { .. } | Request::ScheduleUpdate | Request::CancelUpdate => {
+                Some(&OFFLINE_UPDATE)
+            }
+        }
+    }
+}
+
+/// Renders the reference to the moss protocol that answers [`Request::Describe`]
+#[cfg(feature = "typescript")]
+pub fn reference() -> String {
+    crate::reference::<Targeted<Request>, Response>("moss", Request::ACTIONS)
+        .note(
+            "Long running requests may be answered with any number of Progress, Stage and \
+             Warning responses before their final response.",
+        )
+        .to_string()
 }
 
 /// Basic response types for moss IPC
 #[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "typescript",
+    derive(privileged_ipc::TypeScript),
+    typescript(rename = "MossResponse")
+)]
 #[serde(tag = "type")]
 pub enum Response {
     /// Pong response to ping
     Pong,
+    /// The transaction was applied
+    TransactionComplete,
+    /// The transaction was abandoned at the client's request
+    TransactionAborted,
+    /// The transaction is on hold until the listed conflicts are resolved
+    Conflicts(ConflictReport),
+    /// The currently trusted signing keys
+    Keys { keys: Vec<SigningKey> },
+    /// The key was imported and is now trusted
+    KeyImported { fingerprint: String },
+    /// The key is no longer trusted
+    KeyRevoked,
+    /// Progress of a long running request, sent any number of times before its final response
+    Progress(Progress),
+    /// A long running request entered a new stage, sent before its final response
+    Stage { name: String },
+    /// A non-fatal issue, sent any number of times before the final response
+    Warning(Warning),
+    /// The packages were downloaded into the cache
+    Fetched { packages: Vec<String> },
+    /// Current contents of the package cache
+    Cache(CacheInfo),
+    /// The cache was cleaned, freeing the given number of bytes
+    CacheCleaned { reclaimed: u64 },
+    /// A snapshot was created
+    SnapshotCreated(Snapshot),
+    /// The existing snapshots
+    Snapshots { snapshots: Vec<Snapshot> },
+    /// A snapshot was restored, directly or by rolling back a transaction
+    Restored(RestoreOutcome),
+    /// The snapshot was deleted
+    SnapshotDeleted,
+    /// The transaction journal, oldest first
+    Journal { entries: Vec<JournalEntry> },
+    /// An update was staged and awaits scheduling
+    UpdateStaged(StagedUpdate),
+    /// The staged update was checked, listing any problems found
+    StagedUpdateVerified { problems: Vec<String> },
+    /// The staged update will be applied at next boot
+    UpdateScheduled,
+    /// The staged update was discarded
+    UpdateCancelled,
+    /// The state of offline updates
+    UpdateStatus(UpdateStatus),
+    /// A readable reference to the helper's protocol, as rendered by `reference()`
+    Description { reference: String },
     /// Error response
     Error { message: String },
 }
 
+impl Reply for Response {
+    fn into_result(self) -> Result<Self, String> {
+        match self {
+            Response::Error { message } => Err(message),
+            response => Ok(response),
+        }
+    }
+}
+
+/// Conflicts that stopped a transaction, awaiting a [`Request::Resolve`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct ConflictReport {
+    /// Identifies the held transaction when resolving it
+    pub transaction: u64,
+    /// Every conflict that needs a resolution
+    pub conflicts: Vec<ConflictEntry>,
+}
+
+/// A single conflict within a [`ConflictReport`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct ConflictEntry {
+    /// Identifies this conflict when choosing a resolution
+    pub id: u32,
+    /// What the conflict is
+    pub conflict: Conflict,
+}
+
+/// A problem preventing a transaction from being applied as requested
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+#[serde(tag = "kind")]
+pub enum Conflict {
+    /// More than one package provides the same file
+    File { path: String, packages: Vec<String> },
+    /// A package is held back because one of its dependencies can't be satisfied
+    DependencyHold {
+        package: String,
+        dependency: String,
+        reason: String,
+    },
+}
+
+/// How to resolve a conflict
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+#[serde(tag = "kind")]
+pub enum Resolution {
+    /// Let the named package own the conflicting file
+    PreferPackage { package: String },
+    /// Drop the named package from the transaction
+    SkipPackage { package: String },
+    /// Abandon the whole transaction
+    Abort,
+}
+
+/// The resolution chosen for one conflict
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct Choice {
+    /// The [`ConflictEntry::id`] being resolved
+    pub conflict: u32,
+    /// How to resolve it
+    pub resolution: Resolution,
+}
+
+/// A repository signing key trusted by moss
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct SigningKey {
+    /// Fingerprint uniquely identifying the key
+    pub fingerprint: String,
+    /// Human readable owner of the key
+    pub name: String,
+    /// Expiry time in seconds since the Unix epoch, if the key expires
+    pub expires: Option<u64>,
+}
+
+/// Progress report for a long running request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct Progress {
+    /// What is currently being worked on, such as a package name
+    pub item: String,
+    /// Units of work completed so far, typically bytes
+    pub completed: u64,
+    /// Total units of work, if known
+    pub total: Option<u64>,
+}
+
+/// A non-fatal issue encountered while handling a request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct Warning {
+    /// What the warning is about, such as a repository or package name
+    pub subject: Option<String>,
+    /// Human readable description, such as `skipped: unreachable`
+    pub message: String,
+}
+
+/// Summary of the package cache
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct CacheInfo {
+    /// Number of cached package archives
+    pub packages: u64,
+    /// Total size of the cache in bytes
+    pub size: u64,
+}
+
+/// A snapshot of the system filesystems
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct Snapshot {
+    /// Identifies the snapshot when restoring or deleting it
+    pub id: u64,
+    pub description: String,
+    /// Creation time in seconds since the Unix epoch
+    pub created: u64,
+    /// The transaction this snapshot was taken before, if taken automatically
+    pub transaction: Option<u64>,
+}
+
+/// Where a transaction recorded in the journal got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+#[serde(rename_all = "kebab-case")]
+pub enum JournalState {
+    /// The transaction is being applied, or was interrupted while being applied
+    Pending,
+    /// The transaction was applied
+    Applied,
+    /// Applying the transaction failed
+    Failed,
+    /// The transaction was undone by restoring its snapshot
+    RolledBack,
+}
+
+/// A transaction recorded in the journal
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct JournalEntry {
+    pub transaction: u64,
+    /// The operation that started the transaction, such as `install`
+    pub operation: String,
+    /// The snapshot taken before applying it, if the system supports snapshots
+    pub snapshot: Option<u64>,
+    pub state: JournalState,
+}
+
+/// Result of restoring a snapshot
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct RestoreOutcome {
+    /// The snapshot that was restored
+    pub snapshot: u64,
+    /// The restored state only takes effect after a reboot, as with root filesystem snapshots
+    pub reboot_required: bool,
+}
+
+/// An update written out to be applied offline
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct StagedUpdate {
+    /// Packages the update installs or upgrades
+    pub packages: Vec<String>,
+    /// Size of the staged payload in bytes
+    pub size: u64,
+}
+
+/// Where the offline update process currently is
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum UpdateState {
+    /// Nothing is staged
+    Idle,
+    /// An update is staged but won't be applied until scheduled
+    Staged { update: StagedUpdate },
+    /// An update will be applied at next boot
+    Scheduled { update: StagedUpdate },
+}
+
+/// How the most recent offline update went
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct UpdateResult {
+    pub succeeded: bool,
+    /// Reason for a failure, suitable for showing to the user
+    pub message: Option<String>,
+    /// When the update finished, in seconds since the Unix epoch
+    pub finished: u64,
+}
+
+/// Status of offline updates, as reported by [`MossClient::update_status`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct UpdateStatus {
+    pub state: UpdateState,
+    /// Outcome of the last offline update applied, if any
+    pub last_result: Option<UpdateResult>,
+}
+
+/// Result of a transaction request
+#[derive(Debug)]
+pub enum TransactionOutcome {
+    /// The transaction was applied
+    Completed,
+    /// The transaction was abandoned
+    Aborted,
+    /// The transaction is held until the conflicts are resolved with [`MossClient::resolve`]
+    Conflicts(ConflictReport),
+}
+
 /// Client for interacting with moss-ipc daemon
 pub struct MossClient {
-    client: IpcClient<Request, Response>,
+    client: IpcClient<Targeted<Request>, Response>,
+    timelines: VecDeque<Timeline>,
+    warnings: VecDeque<Warning>,
+    target: Option<Target>,
 }
 
 impl MossClient {
@@ -38,6 +498,9 @@ impl MossClient {
     pub fn new_privileged_with_path(moss_path: &str) -> Result<Self, IpcError> {
         Ok(Self {
             client: IpcClient::new::<PkexecExecutor>(moss_path, &["ipc"])?,
+            timelines: VecDeque::new(),
+            warnings: VecDeque::new(),
+            target: None,
         })
     }
 
@@ -50,24 +513,365 @@ impl MossClient {
     pub fn new_direct_with_path(moss_path: &str) -> Result<Self, IpcError> {
         Ok(Self {
             client: IpcClient::new::<DirectExecutor>(moss_path, &["ipc"])?,
+            timelines: VecDeque::new(),
+            warnings: VecDeque::new(),
+            target: None,
         })
     }
 
+    /// Directs subsequent requests at an alternate system, or the running system if `None`
+    ///
+    /// Packages are then installed into, removed from or queried in the target, such as
+    /// an image being built. The helper validates the target and confines each request to it.
+    pub fn set_target(&mut self, target: Option<Target>) {
+        self.target = target;
+    }
+
+    /// Returns the system requests are currently directed at, if not the running one
+    pub fn target(&self) -> Option<&Target> {
+        self.target.as_ref()
+    }
+
+    /// Returns the timelines of recently finished operations, oldest first
+    pub fn timelines(&self) -> impl Iterator<Item = &Timeline> {
+        self.timelines.iter()
+    }
+
+    /// Removes and returns all retained operation timelines
+    pub fn take_timelines(&mut self) -> Vec<Timeline> {
+        self.timelines.drain(..).collect()
+    }
+
+    /// Returns the warnings received since they were last taken, oldest first
+    ///
+    /// Only the most recent warnings are retained if they aren't taken.
+    pub fn warnings(&self) -> impl Iterator<Item = &Warning> {
+        self.warnings.iter()
+    }
+
+    /// Removes and returns all retained warnings
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        self.warnings.drain(..).collect()
+    }
+
     /// Sends a ping request to test the connection
     pub fn ping(&mut self) -> Result<(), IpcError> {
-        self.client.send(&Request::Ping)?;
-
-        // Read response
-        if let Some(response) = self.client.incoming()?.next() {
-            match response? {
-                Response::Pong => Ok(()),
-                Response::Error { message } => Err(IpcError::Io(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    message,
-                ))),
+        match self.request("ping", &Request::Ping)? {
+            Response::Pong => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Asks the helper for a readable reference to the protocol it speaks
+    pub fn describe(&mut self) -> Result<String, IpcError> {
+        match self.request("describe", &Request::Describe)? {
+            Response::Description { reference } => Ok(reference),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Installs the named packages
+    pub fn install(&mut self, packages: &[&str]) -> Result<TransactionOutcome, IpcError> {
+        let request = Request::Install {
+            packages: packages.iter().map(|p| p.to_string()).collect(),
+        };
+        self.transaction("install", &request)
+    }
+
+    /// Removes the named packages
+    pub fn remove(&mut self, packages: &[&str]) -> Result<TransactionOutcome, IpcError> {
+        let request = Request::Remove {
+            packages: packages.iter().map(|p| p.to_string()).collect(),
+        };
+        self.transaction("remove", &request)
+    }
+
+    /// Resumes a transaction held on conflicts, using one choice per conflict
+    ///
+    /// The helper may report further conflicts that arise from the chosen resolutions.
+    pub fn resolve(
+        &mut self,
+        report: &ConflictReport,
+        choices: Vec<Choice>,
+    ) -> Result<TransactionOutcome, IpcError> {
+        let request = Request::Resolve {
+            transaction: report.transaction,
+            choices,
+        };
+        self.transaction("resolve", &request)
+    }
+
+    /// Installs the named packages into a new system assembled under `root`
+    ///
+    /// `on_progress` is called for every progress report while installing.
+    pub fn bootstrap(
+        &mut self,
+        root: &str,
+        packages: &[&str],
+        mut on_progress: impl FnMut(&Progress),
+    ) -> Result<TransactionOutcome, IpcError> {
+        let request = Request::Bootstrap {
+            root: root.to_string(),
+            packages: packages.iter().map(|p| p.to_string()).collect(),
+        };
+        let response = self.request_with_progress("bootstrap", &request, &mut on_progress)?;
+        outcome(response)
+    }
+
+    /// Lists the trusted repository signing keys
+    pub fn list_keys(&mut self) -> Result<Vec<SigningKey>, IpcError> {
+        match self.request("list-keys", &Request::ListKeys)? {
+            Response::Keys { keys } => Ok(keys),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Imports and trusts a repository signing key, returning its fingerprint
+    pub fn import_key(&mut self, key: &str) -> Result<String, IpcError> {
+        let request = Request::ImportKey {
+            key: key.to_string(),
+        };
+        match self.request("import-key", &request)? {
+            Response::KeyImported { fingerprint } => Ok(fingerprint),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Revokes trust in the signing key with the given fingerprint
+    pub fn revoke_key(&mut self, fingerprint: &str) -> Result<(), IpcError> {
+        let request = Request::RevokeKey {
+            fingerprint: fingerprint.to_string(),
+        };
+        match self.request("revoke-key", &request)? {
+            Response::KeyRevoked => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Downloads the named packages into the cache without installing them
+    ///
+    /// `on_progress` is called for every progress report while downloading.
+    /// Returns the packages that were fetched.
+    pub fn fetch(
+        &mut self,
+        packages: &[&str],
+        mut on_progress: impl FnMut(&Progress),
+    ) -> Result<Vec<String>, IpcError> {
+        let request = Request::Fetch {
+            packages: packages.iter().map(|p| p.to_string()).collect(),
+        };
+        match self.request_with_progress("fetch", &request, &mut on_progress)? {
+            Response::Fetched { packages } => Ok(packages),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Reports the contents of the package cache
+    pub fn cache_info(&mut self) -> Result<CacheInfo, IpcError> {
+        match self.request("cache-info", &Request::CacheInfo)? {
+            Response::Cache(info) => Ok(info),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Cleans the package cache, returning the number of bytes reclaimed
+    ///
+    /// `on_progress` is called for every progress report while cleaning.
+    pub fn clean_cache(
+        &mut self,
+        keep_installed: bool,
+        mut on_progress: impl FnMut(&Progress),
+    ) -> Result<u64, IpcError> {
+        let request = Request::CleanCache { keep_installed };
+        match self.request_with_progress("clean-cache", &request, &mut on_progress)? {
+            Response::CacheCleaned { reclaimed } => Ok(reclaimed),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Snapshots the system filesystems
+    pub fn create_snapshot(&mut self, description: &str) -> Result<Snapshot, IpcError> {
+        let request = Request::CreateSnapshot {
+            description: description.to_string(),
+        };
+        match self.request("create-snapshot", &request)? {
+            Response::SnapshotCreated(snapshot) => Ok(snapshot),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Lists existing snapshots
+    pub fn snapshots(&mut self) -> Result<Vec<Snapshot>, IpcError> {
+        match self.request("list-snapshots", &Request::ListSnapshots)? {
+            Response::Snapshots { snapshots } => Ok(snapshots),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Restores the system to the snapshot with the given id
+    pub fn restore_snapshot(&mut self, id: u64) -> Result<RestoreOutcome, IpcError> {
+        match self.request("restore-snapshot", &Request::RestoreSnapshot { id })? {
+            Response::Restored(outcome) => Ok(outcome),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Deletes the snapshot with the given id
+    pub fn delete_snapshot(&mut self, id: u64) -> Result<(), IpcError> {
+        match self.request("delete-snapshot", &Request::DeleteSnapshot { id })? {
+            Response::SnapshotDeleted => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Lists the transaction journal, oldest first
+    pub fn journal(&mut self) -> Result<Vec<JournalEntry>, IpcError> {
+        match self.request("journal", &Request::Journal)? {
+            Response::Journal { entries } => Ok(entries),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Undoes a transaction by restoring the snapshot taken before it was applied
+    ///
+    /// With `None` the most recent transaction in the journal is rolled back, which is
+    /// typically the one that just failed to apply. Fails if no snapshot was taken for it.
+    pub fn rollback(&mut self, transaction: Option<u64>) -> Result<RestoreOutcome, IpcError> {
+        match self.request("rollback", &Request::Rollback { transaction })? {
+            Response::Restored(outcome) => Ok(outcome),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Downloads and writes out an update to apply at next boot
+    ///
+    /// An empty `packages` stages every available update. `on_progress` is called for
+    /// every progress report while staging. The update isn't applied until scheduled
+    /// with [`MossClient::schedule_update`].
+    pub fn stage_update(
+        &mut self,
+        packages: &[&str],
+        mut on_progress: impl FnMut(&Progress),
+    ) -> Result<StagedUpdate, IpcError> {
+        let request = Request::StageUpdate {
+            packages: packages.iter().map(|p| p.to_string()).collect(),
+        };
+        match self.request_with_progress("stage-update", &request, &mut on_progress)? {
+            Response::UpdateStaged(update) => Ok(update),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Checks the staged update is complete and intact, returning any problems found
+    pub fn verify_staged_update(&mut self) -> Result<Vec<String>, IpcError> {
+        match self.request("verify-staged-update", &Request::VerifyStagedUpdate)? {
+            Response::StagedUpdateVerified { problems } => Ok(problems),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Arranges for the staged update to be applied at next boot
+    ///
+    /// Rebooting is left to the caller, typically once the user confirms.
+    pub fn schedule_update(&mut self) -> Result<(), IpcError> {
+        match self.request("schedule-update", &Request::ScheduleUpdate)? {
+            Response::UpdateScheduled => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Unschedules and discards the staged update
+    pub fn cancel_update(&mut self) -> Result<(), IpcError> {
+        match self.request("cancel-update", &Request::CancelUpdate)? {
+            Response::UpdateCancelled => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Reports the state of offline updates, including how the last one went
+    pub fn update_status(&mut self) -> Result<UpdateStatus, IpcError> {
+        match self.request("update-status", &Request::UpdateStatus)? {
+            Response::UpdateStatus(status) => Ok(status),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn transaction(
+        &mut self,
+        operation: &str,
+        request: &Request,
+    ) -> Result<TransactionOutcome, IpcError> {
+        outcome(self.request(operation, request)?)
+    }
+
+    /// Sends a request and waits for its response, recording the operation's timeline
+    fn request(&mut self, operation: &str, request: &Request) -> Result<Response, IpcError> {
+        self.request_with_progress(operation, request, &mut |_| {})
+    }
+
+    /// Sends a request and waits for its final response, passing on any progress reports
+    fn request_with_progress(
+        &mut self,
+        operation: &str,
+        request: &Request,
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<Response, IpcError> {
+        let mut timeline = Timeline::new(operation);
+        let result = self.exchange(request, &mut timeline, on_progress);
+        self.finish(timeline, result.is_ok());
+        result
+    }
+
+    fn exchange(
+        &mut self,
+        request: &Request,
+        timeline: &mut Timeline,
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<Response, IpcError> {
+        self.client.send(&Targeted {
+            target: self.target.clone(),
+            request: request.clone(),
+        })?;
+
+        // Read responses until the final one arrives
+        loop {
+            match self.client.recv()? {
+                Some(Response::Progress(progress)) => {
+                    timeline.progress();
+                    on_progress(&progress);
+                }
+                Some(Response::Stage { name }) => timeline.stage(name),
+                Some(Response::Warning(warning)) => {
+                    if self.warnings.len() == MAX_WARNINGS {
+                        self.warnings.pop_front();
+                    }
+                    self.warnings.push_back(warning);
+                }
+                response => return reply::checked(response),
             }
+        }
+    }
+
+    /// Marks the timeline finished and retains it, discarding the oldest once the limit is reached
+    fn finish(&mut self, mut timeline: Timeline, succeeded: bool) {
+        if succeeded {
+            timeline.complete();
         } else {
-            Err(IpcError::ConnectionClosed)
+            timeline.fail();
         }
+        if self.timelines.len() == MAX_TIMELINES {
+            self.timelines.pop_front();
+        }
+        self.timelines.push_back(timeline);
+    }
+}
+
+/// Interprets the final response to a transaction
+fn outcome(response: Response) -> Result<TransactionOutcome, IpcError> {
+    match response {
+        Response::TransactionComplete => Ok(TransactionOutcome::Completed),
+        Response::TransactionAborted => Ok(TransactionOutcome::Aborted),
+        Response::Conflicts(report) => Ok(TransactionOutcome::Conflicts(report)),
+        response => Err(unexpected(response)),
     }
 }
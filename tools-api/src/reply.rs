@@ -0,0 +1,38 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Replies from the helpers, shared by their clients
+
+use std::{fmt, io};
+
+use privileged_ipc::{IpcClient, IpcError};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A helper's response type, one variant of which reports that a request failed
+pub(crate) trait Reply: fmt::Debug + DeserializeOwned {
+    /// Returns the helper's explanation if the response reports a failure
+    fn into_result(self) -> Result<Self, String>;
+}
+
+/// Sends `request` and waits for the helper's reply to it
+pub(crate) fn request<S: Serialize, R: Reply>(
+    client: &mut IpcClient<S, R>,
+    request: &S,
+) -> Result<R, IpcError> {
+    client.send(request)?;
+    checked(client.recv()?)
+}
+
+/// Turns a received reply into an error if the helper hung up or reported a failure
+pub(crate) fn checked<R: Reply>(reply: Option<R>) -> Result<R, IpcError> {
+    reply
+        .ok_or(IpcError::ConnectionClosed)?
+        .into_result()
+        .map_err(|message| IpcError::Io(io::Error::other(message)))
+}
+
+/// Error for a reply that doesn't answer the request that was sent
+pub(crate) fn unexpected(reply: impl fmt::Debug) -> IpcError {
+    IpcError::ProtocolViolation(format!("unexpected response: {reply:?}"))
+}
@@ -0,0 +1,282 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use privileged_ipc::{
+    Action, Authorization, DirectExecutor, IpcClient, IpcError, PkexecExecutor, PolkitActions,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    reply::{self, unexpected, Reply},
+    target::{Target, Targeted},
+};
+
+/// Request types for the service management IPC
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "typescript",
+    derive(privileged_ipc::TypeScript),
+    typescript(rename = "ServicesRequest")
+)]
+#[serde(tag = "type")]
+pub enum Request {
+    /// Start a unit
+    Start { unit: String },
+    /// Stop a unit
+    Stop { unit: String },
+    /// Restart a unit, starting it if it isn't running
+    Restart { unit: String },
+    /// Enable a unit to start at boot, and also start it if `now` is set
+    Enable { unit: String, now: bool },
+    /// Disable a unit from starting at boot, and also stop it if `now` is set
+    Disable { unit: String, now: bool },
+    /// Query the state of a unit
+    Status { unit: String },
+    /// Describe the helper's protocol, answered with a readable reference to it
+    Describe,
+}
+
+/// Guards requests that manage system services
+pub const MANAGE_UNITS: Action = Action {
+    id: "com.serpentos.services.manage-units",
+    description: "Start, stop or restart system services",
+    message: "Authentication is required to manage system services",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that change which services start at boot
+pub const MANAGE_UNIT_FILES: Action = Action {
+    id: "com.serpentos.services.manage-unit-files",
+    description: "Enable or disable system services",
+    message: "Authentication is required to change which services start at boot",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+impl PolkitActions for Request {
+    const ACTIONS: &'static [Action] = &[MANAGE_UNITS, MANAGE_UNIT_FILES];
+
+    fn action(&self) -> Option<&'static Action> {
+        match self {
+            Request::Status { .. } | Request::Describe => None,
+            Request::Start { .. } | Request::Stop { .. } | Request::Restart { .. } => {
+                Some(&MANAGE_UNITS)
+            }
+            Request::Enable { .. } | Request::Disable { .. } => Some(&MANAGE_UNIT_FILES),
+        }
+    }
+}
+
+/// Renders the reference to the services protocol that answers [`Request::Describe`]
+#[cfg(feature = "typescript")]
+pub fn reference() -> String {
+    crate::reference::<Targeted<Request>, Response>("services", Request::ACTIONS).to_string()
+}
+
+/// Response types for the service management IPC
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "typescript",
+    derive(privileged_ipc::TypeScript),
+    typescript(rename = "ServicesResponse")
+)]
+#[serde(tag = "type")]
+pub enum Response {
+    /// The job queued for a start, stop or restart has finished
+    Job { result: JobResult },
+    /// Unit files were enabled or disabled, with the resulting changes
+    UnitFilesChanged { changes: Vec<UnitFileChange> },
+    /// The state of a unit
+    Status(UnitStatus),
+    /// A readable reference to the helper's protocol, as rendered by `reference()`
+    Description { reference: String },
+    /// Error response
+    Error { message: String },
+}
+
+impl Reply for Response {
+    fn into_result(self) -> Result<Self, String> {
+        match self {
+            Response::Error { message } => Err(message),
+            response => Ok(response),
+        }
+    }
+}
+
+/// How a unit job finished, as reported by systemd
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+#[serde(rename_all = "kebab-case")]
+pub enum JobResult {
+    /// The job completed successfully
+    Done,
+    /// The job was cancelled before it finished
+    Canceled,
+    /// The job timed out
+    Timeout,
+    /// The job failed
+    Failed,
+    /// A job this one depended on failed
+    Dependency,
+    /// The job didn't apply to the unit's current state
+    Skipped,
+}
+
+/// The kind of change made to a unit file symlink
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeKind {
+    Symlink,
+    Unlink,
+}
+
+/// A symlink created or removed while enabling or disabling a unit
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct UnitFileChange {
+    pub kind: ChangeKind,
+    pub path: String,
+    /// Target of a created symlink
+    pub destination: Option<String>,
+}
+
+/// The state of a unit
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct UnitStatus {
+    pub unit: String,
+    /// Load state, such as `loaded` or `not-found`
+    pub load_state: String,
+    /// Active state, such as `active`, `inactive` or `failed`
+    pub active_state: String,
+    /// Sub state, such as `running` or `exited`
+    pub sub_state: String,
+    /// Enablement state, such as `enabled`, `disabled` or `static`
+    pub unit_file_state: String,
+}
+
+/// Client for interacting with the service management helper
+pub struct ServiceMgmtClient {
+    client: IpcClient<Targeted<Request>, Response>,
+    target: Option<Target>,
+}
+
+impl ServiceMgmtClient {
+    /// Creates a new ServiceMgmtClient with privilege escalation
+    pub fn new_privileged() -> Result<Self, IpcError> {
+        Self::new_privileged_with_path("/usr/bin/service-mgmt")
+    }
+
+    /// Creates a new ServiceMgmtClient with privilege escalation and custom helper path
+    pub fn new_privileged_with_path(helper_path: &str) -> Result<Self, IpcError> {
+        Ok(Self {
+            client: IpcClient::new::<PkexecExecutor>(helper_path, &["ipc"])?,
+            target: None,
+        })
+    }
+
+    /// Creates a new ServiceMgmtClient without privilege escalation
+    pub fn new_direct() -> Result<Self, IpcError> {
+        Self::new_direct_with_path("/usr/bin/service-mgmt")
+    }
+
+    /// Creates a new ServiceMgmtClient without privilege escalation and custom helper path
+    pub fn new_direct_with_path(helper_path: &str) -> Result<Self, IpcError> {
+        Ok(Self {
+            client: IpcClient::new::<DirectExecutor>(helper_path, &["ipc"])?,
+            target: None,
+        })
+    }
+
+    /// Directs subsequent requests at an alternate system, or the running system if `None`
+    ///
+    /// Only unit file changes make sense for a system that isn't running, much like
+    /// `systemctl --root`; the helper rejects start, stop and restart requests.
+    pub fn set_target(&mut self, target: Option<Target>) {
+        self.target = target;
+    }
+
+    /// Returns the system requests are currently directed at, if not the running one
+    pub fn target(&self) -> Option<&Target> {
+        self.target.as_ref()
+    }
+
+    /// Starts a unit, waiting for its job to finish
+    pub fn start(&mut self, unit: &str) -> Result<JobResult, IpcError> {
+        self.job(Request::Start {
+            unit: unit.to_string(),
+        })
+    }
+
+    /// Stops a unit, waiting for its job to finish
+    pub fn stop(&mut self, unit: &str) -> Result<JobResult, IpcError> {
+        self.job(Request::Stop {
+            unit: unit.to_string(),
+        })
+    }
+
+    /// Restarts a unit, waiting for its job to finish
+    pub fn restart(&mut self, unit: &str) -> Result<JobResult, IpcError> {
+        self.job(Request::Restart {
+            unit: unit.to_string(),
+        })
+    }
+
+    /// Enables a unit to start at boot, starting it straight away if `now` is set
+    pub fn enable(&mut self, unit: &str, now: bool) -> Result<Vec<UnitFileChange>, IpcError> {
+        self.unit_files(Request::Enable {
+            unit: unit.to_string(),
+            now,
+        })
+    }
+
+    /// Disables a unit from starting at boot, stopping it straight away if `now` is set
+    pub fn disable(&mut self, unit: &str, now: bool) -> Result<Vec<UnitFileChange>, IpcError> {
+        self.unit_files(Request::Disable {
+            unit: unit.to_string(),
+            now,
+        })
+    }
+
+    /// Queries the state of a unit
+    pub fn status(&mut self, unit: &str) -> Result<UnitStatus, IpcError> {
+        let request = Request::Status {
+            unit: unit.to_string(),
+        };
+        match self.request(&request)? {
+            Response::Status(status) => Ok(status),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn job(&mut self, request: Request) -> Result<JobResult, IpcError> {
+        match self.request(&request)? {
+            Response::Job { result } => Ok(result),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn unit_files(&mut self, request: Request) -> Result<Vec<UnitFileChange>, IpcError> {
+        match self.request(&request)? {
+            Response::UnitFilesChanged { changes } => Ok(changes),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Asks the helper for a readable reference to the protocol it speaks
+    pub fn describe(&mut self) -> Result<String, IpcError> {
+        match self.request(&Request::Describe)? {
+            Response::Description { reference } => Ok(reference),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn request(&mut self, request: &Request) -> Result<Response, IpcError> {
+        let request = Targeted {
+            target: self.target.clone(),
+            request: request.clone(),
+        };
+        reply::request(&mut self.client, &request)
+    }
+}
@@ -0,0 +1,161 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Alternate system roots for tool requests
+//!
+//! Clients may direct requests at a system other than the running one, such as a
+//! mounted installation target or a container rootfs being built. Requests are
+//! wrapped in [`Targeted`], and helpers use [`Target::open`] or [`Target::confine`]
+//! to keep every path they touch within the target.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    os::fd::{AsRawFd, FromRawFd},
+    path::{Component, Path, PathBuf},
+};
+
+use nix::{
+    fcntl::{openat2, OFlag, OpenHow, ResolveFlag},
+    sys::stat::Mode,
+};
+use serde_derive::{Deserialize, Serialize};
+
+/// Most symlinks followed while confining a path, as the kernel allows
+const MAX_SYMLINKS: usize = 40;
+
+/// A system for requests to operate on instead of the running one
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct Target {
+    /// Absolute path to the root directory of the target system
+    pub root: String,
+    /// Architecture of the target, if it differs from the host's
+    pub arch: Option<String>,
+}
+
+impl Target {
+    /// Targets the system rooted at `root`, with the host's architecture
+    pub fn new(root: impl Into<String>) -> Self {
+        Self {
+            root: root.into(),
+            arch: None,
+        }
+    }
+
+    /// Checks the root is an absolute path to an existing directory other than `/`
+    ///
+    /// Helpers should validate a target before acting on any request for it.
+    pub fn validate(&self) -> io::Result<()> {
+        let root = Path::new(&self.root);
+        if !root.is_absolute() {
+            return Err(invalid("target root must be an absolute path"));
+        }
+        if fs::canonicalize(root)? == Path::new("/") {
+            return Err(invalid("target root is the running system"));
+        }
+        if !root.is_dir() {
+            return Err(invalid("target root is not a directory"));
+        }
+        Ok(())
+    }
+
+    /// Opens `path`, as seen from within the target, with `flags`, creating files with `mode`
+    ///
+    /// The kernel resolves the path as if the target root were `/`, symlinks
+    /// included, so it can't be made to open anything outside the target, even
+    /// by swapping a symlink in halfway through. Helpers should prefer this over
+    /// [`Target::confine`] wherever they can work on a file descriptor. Needs
+    /// Linux 5.6 or later.
+    pub fn open(&self, path: impl AsRef<Path>, flags: OFlag, mode: Mode) -> io::Result<File> {
+        let root = File::open(&self.root)?;
+        let how = OpenHow::new()
+            .flags(flags | OFlag::O_CLOEXEC)
+            .mode(mode)
+            .resolve(ResolveFlag::RESOLVE_IN_ROOT | ResolveFlag::RESOLVE_NO_MAGICLINKS);
+        let fd = openat2(root.as_raw_fd(), path.as_ref(), how)?;
+        // SAFETY: openat2 returned a new descriptor that nothing else owns
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Resolves `path`, as seen from within the target, to a path on the host
+    ///
+    /// As with `chroot`, absolute paths are taken relative to the target root and
+    /// `..` can't climb above it, and symlinks are followed the same way, so that
+    /// one pointing at `/etc` leads to the target's `/etc`. Components that don't
+    /// exist yet are kept as given. The result has no symlinks left in it, but
+    /// one created in its place later would be followed by whatever uses it, so
+    /// [`Target::open`] is safer where untrusted processes can write to the target.
+    pub fn confine(&self, path: impl AsRef<Path>) -> io::Result<PathBuf> {
+        let root = fs::canonicalize(&self.root)?;
+        let mut confined = root.clone();
+        let mut pending = components(path.as_ref());
+        let mut followed = 0;
+        while let Some(name) = pending.pop() {
+            if name == ".." {
+                if confined != root {
+                    confined.pop();
+                }
+                continue;
+            }
+            confined.push(&name);
+            match fs::symlink_metadata(&confined) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    followed += 1;
+                    if followed > MAX_SYMLINKS {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "{} has too many levels of symlinks",
+                                path.as_ref().display()
+                            ),
+                        ));
+                    }
+                    let link = fs::read_link(&confined)?;
+                    confined.pop();
+                    if link.has_root() {
+                        confined.clone_from(&root);
+                    }
+                    pending.extend(components(&link));
+                }
+                Ok(_) => {}
+                // Nothing further along can be a symlink either
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(confined)
+    }
+}
+
+/// Returns the names and `..`s making up `path`, last first
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_owned()),
+            Component::ParentDir => Some("..".into()),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+/// A request along with the target it applies to
+///
+/// The target is flattened into the request on the wire and omitted when `None`,
+/// so helpers that predate targets still understand untargeted requests.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "typescript", derive(privileged_ipc::TypeScript))]
+pub struct Targeted<R> {
+    /// System the request applies to, or the running system if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+    #[serde(flatten)]
+    pub request: R,
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, reason)
+}
@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Per-operation timelines recorded by the tool clients
+//!
+//! Each operation issued by a client records when it was sent, when the first
+//! progress report arrived, every stage it passed through and when it finished.
+//! Frontends can use these for status displays and performance reporting.
+
+use std::time::{Duration, Instant};
+
+/// A notable point in the lifetime of an operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Milestone {
+    /// The request was sent to the helper
+    Sent,
+    /// The first progress report was received
+    FirstProgress,
+    /// The helper entered a named stage
+    Stage(String),
+    /// The operation completed successfully
+    Completed,
+    /// The operation failed
+    Failed,
+}
+
+/// A milestone along with the moment it was recorded
+#[derive(Debug, Clone)]
+pub struct TimelineEvent {
+    /// The milestone that was reached
+    pub milestone: Milestone,
+    /// When the milestone was recorded
+    pub at: Instant,
+}
+
+/// The recorded timeline of a single client operation
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    operation: String,
+    events: Vec<TimelineEvent>,
+}
+
+impl Timeline {
+    /// Starts a new timeline for the named operation, recording it as sent
+    pub fn new(operation: impl Into<String>) -> Self {
+        Self {
+            operation: operation.into(),
+            events: vec![TimelineEvent {
+                milestone: Milestone::Sent,
+                at: Instant::now(),
+            }],
+        }
+    }
+
+    /// Records a progress report, keeping only the first one
+    pub fn progress(&mut self) {
+        if !self.reached(&Milestone::FirstProgress) {
+            self.record(Milestone::FirstProgress);
+        }
+    }
+
+    /// Records entry into a named stage
+    pub fn stage(&mut self, name: impl Into<String>) {
+        self.record(Milestone::Stage(name.into()));
+    }
+
+    /// Records successful completion
+    pub fn complete(&mut self) {
+        self.record(Milestone::Completed);
+    }
+
+    /// Records failure
+    pub fn fail(&mut self) {
+        self.record(Milestone::Failed);
+    }
+
+    /// Name of the operation this timeline belongs to
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    /// All recorded events, in order
+    pub fn events(&self) -> &[TimelineEvent] {
+        &self.events
+    }
+
+    /// Returns true once the operation has completed or failed
+    pub fn is_finished(&self) -> bool {
+        self.reached(&Milestone::Completed) || self.reached(&Milestone::Failed)
+    }
+
+    /// Time between sending the request and first reaching `milestone`
+    pub fn elapsed(&self, milestone: &Milestone) -> Option<Duration> {
+        let sent = self.events.first()?.at;
+        self.events
+            .iter()
+            .find(|e| &e.milestone == milestone)
+            .map(|e| e.at.duration_since(sent))
+    }
+
+    /// Total duration of the operation, if it has finished
+    pub fn duration(&self) -> Option<Duration> {
+        if !self.is_finished() {
+            return None;
+        }
+        let sent = self.events.first()?.at;
+        self.events.last().map(|e| e.at.duration_since(sent))
+    }
+
+    fn reached(&self, milestone: &Milestone) -> bool {
+        self.events.iter().any(|e| &e.milestone == milestone)
+    }
+
+    fn record(&mut self, milestone: Milestone) {
+        self.events.push(TimelineEvent {
+            milestone,
+            at: Instant::now(),
+        });
+    }
+}
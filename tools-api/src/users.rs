@@ -0,0 +1,293 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use privileged_ipc::{
+    Action, Authorization, DirectExecutor, IpcClient, IpcError, PkexecExecutor, PolkitActions,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    reply::{self, unexpected, Reply},
+    target::{Target, Targeted},
+};
+
+/// Groups whose members gain administrative rights, and so need a stronger authorization
+const ADMIN_GROUPS: &[&str] = &["wheel", "sudo"];
+
+/// Request types for the user setup IPC
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "typescript",
+    derive(privileged_ipc::TypeScript),
+    typescript(rename = "UsersRequest")
+)]
+#[serde(tag = "type")]
+pub enum Request {
+    /// Create a local user account
+    CreateUser {
+        username: String,
+        full_name: Option<String>,
+        /// Login shell, or the system default if `None`
+        shell: Option<String>,
+    },
+    /// Set a user's password from an already hashed `crypt(3)` string
+    SetPasswordHash { username: String, hash: String },
+    /// Add a user to supplementary groups
+    AddToGroups {
+        username: String,
+        groups: Vec<String>,
+    },
+    /// Set the static hostname
+    SetHostname { hostname: String },
+    /// Set the system timezone, such as `Europe/London`
+    SetTimezone { timezone: String },
+    /// Describe the helper's protocol, answered with a readable reference to it
+    Describe,
+}
+
+/// Guards requests that create a user account
+pub const CREATE_USER: Action = Action {
+    id: "com.serpentos.users.create-user",
+    description: "Create user accounts",
+    message: "Authentication is required to create a user account",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that set a user's password
+pub const SET_PASSWORD: Action = Action {
+    id: "com.serpentos.users.set-password",
+    description: "Set user passwords",
+    message: "Authentication is required to set a user's password",
+    allow_active: Authorization::AuthAdmin,
+};
+
+/// Guards requests that change a user's groups
+pub const MANAGE_GROUPS: Action = Action {
+    id: "com.serpentos.users.manage-groups",
+    description: "Change group memberships",
+    message: "Authentication is required to change a user's groups",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that make a user an administrator
+pub const GRANT_ADMIN: Action = Action {
+    id: "com.serpentos.users.grant-admin",
+    description: "Grant administrative rights",
+    message: "Authentication is required to make a user an administrator",
+    allow_active: Authorization::AuthAdmin,
+};
+
+/// Guards requests that set the hostname
+pub const SET_HOSTNAME: Action = Action {
+    id: "com.serpentos.users.set-hostname",
+    description: "Set the hostname",
+    message: "Authentication is required to set the hostname",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+/// Guards requests that set the system timezone
+pub const SET_TIMEZONE: Action = Action {
+    id: "com.serpentos.users.set-timezone",
+    description: "Set the system timezone",
+    message: "Authentication is required to set the system timezone",
+    allow_active: Authorization::AuthAdminKeep,
+};
+
+impl PolkitActions for Request {
+    const ACTIONS: &'static [Action] = &[
+        CREATE_USER,
+        SET_PASSWORD,
+        MANAGE_GROUPS,
+        GRANT_ADMIN,
+        SET_HOSTNAME,
+        SET_TIMEZONE,
+    ];
+
+    fn action(&self) -> Option<&'static Action> {
+        match self {
+            Request::CreateUser { .. } => Some(&CREATE_USER),
+            Request::SetPasswordHash { .. } => Some(&SET_PASSWORD),
+            Request::AddToGroups { groups, .. }
+                if groups.iter().any(|g| ADMIN_GROUPS.contains(&g.as_str())) =>
+            {
+                Some(&GRANT_ADMIN)
+            }
+            Request::AddToGroups { .. } => Some(&MANAGE_GROUPS),
+            Request::SetHostname { .. } => Some(&SET_HOSTNAME),
+            Request::SetTimezone { .. } => Some(&SET_TIMEZONE),
+            Request::Describe => None,
+        }
+    }
+}
+
+/// Renders the reference to the users protocol that answers [`Request::Describe`]
+#[cfg(feature = "typescript")]
+pub fn reference() -> String {
+    crate::reference::<Targeted<Request>, Response>("users", Request::ACTIONS).to_string()
+}
+
+/// Response types for the user setup IPC
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "typescript",
+    derive(privileged_ipc::TypeScript),
+    typescript(rename = "UsersResponse")
+)]
+#[serde(tag = "type")]
+pub enum Response {
+    /// The user was created with the given uid
+    UserCreated { uid: u32 },
+    /// The password was set
+    PasswordSet,
+    /// The user was added to the groups
+    GroupsUpdated,
+    /// The hostname was set
+    HostnameSet,
+    /// The timezone was set
+    TimezoneSet,
+    /// A readable reference to the helper's protocol, as rendered by `reference()`
+    Description { reference: String },
+    /// Error response
+    Error { message: String },
+}
+
+impl Reply for Response {
+    fn into_result(self) -> Result<Self, String> {
+        match self {
+            Response::Error { message } => Err(message),
+            response => Ok(response),
+        }
+    }
+}
+
+/// Client for interacting with the user setup helper, as used by the firstboot wizard
+pub struct UsersClient {
+    client: IpcClient<Targeted<Request>, Response>,
+    target: Option<Target>,
+}
+
+impl UsersClient {
+    /// Creates a new UsersClient with privilege escalation
+    pub fn new_privileged() -> Result<Self, IpcError> {
+        Self::new_privileged_with_path("/usr/bin/user-setup")
+    }
+
+    /// Creates a new UsersClient with privilege escalation and custom helper path
+    pub fn new_privileged_with_path(helper_path: &str) -> Result<Self, IpcError> {
+        Ok(Self {
+            client: IpcClient::new::<PkexecExecutor>(helper_path, &["ipc"])?,
+            target: None,
+        })
+    }
+
+    /// Creates a new UsersClient without privilege escalation
+    pub fn new_direct() -> Result<Self, IpcError> {
+        Self::new_direct_with_path("/usr/bin/user-setup")
+    }
+
+    /// Creates a new UsersClient without privilege escalation and custom helper path
+    pub fn new_direct_with_path(helper_path: &str) -> Result<Self, IpcError> {
+        Ok(Self {
+            client: IpcClient::new::<DirectExecutor>(helper_path, &["ipc"])?,
+            target: None,
+        })
+    }
+
+    /// Directs subsequent requests at an alternate system, or the running system if `None`
+    ///
+    /// Accounts and settings are then written to the target, for example when setting up
+    /// a freshly installed system before its first boot.
+    pub fn set_target(&mut self, target: Option<Target>) {
+        self.target = target;
+    }
+
+    /// Returns the system requests are currently directed at, if not the running one
+    pub fn target(&self) -> Option<&Target> {
+        self.target.as_ref()
+    }
+
+    /// Creates a local user account, returning its uid
+    pub fn create_user(
+        &mut self,
+        username: &str,
+        full_name: Option<&str>,
+        shell: Option<&str>,
+    ) -> Result<u32, IpcError> {
+        let request = Request::CreateUser {
+            username: username.to_string(),
+            full_name: full_name.map(str::to_string),
+            shell: shell.map(str::to_string),
+        };
+        match self.request(&request)? {
+            Response::UserCreated { uid } => Ok(uid),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sets a user's password from a `crypt(3)` hash
+    ///
+    /// Hashing happens on the client, so the plaintext password never crosses the connection.
+    pub fn set_password_hash(&mut self, username: &str, hash: &str) -> Result<(), IpcError> {
+        let request = Request::SetPasswordHash {
+            username: username.to_string(),
+            hash: hash.to_string(),
+        };
+        match self.request(&request)? {
+            Response::PasswordSet => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Adds a user to supplementary groups
+    ///
+    /// Adding to an administrative group such as `wheel` requires its own authorization.
+    pub fn add_to_groups(&mut self, username: &str, groups: &[&str]) -> Result<(), IpcError> {
+        let request = Request::AddToGroups {
+            username: username.to_string(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        };
+        match self.request(&request)? {
+            Response::GroupsUpdated => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sets the static hostname
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<(), IpcError> {
+        let request = Request::SetHostname {
+            hostname: hostname.to_string(),
+        };
+        match self.request(&request)? {
+            Response::HostnameSet => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sets the system timezone
+    pub fn set_timezone(&mut self, timezone: &str) -> Result<(), IpcError> {
+        let request = Request::SetTimezone {
+            timezone: timezone.to_string(),
+        };
+        match self.request(&request)? {
+            Response::TimezoneSet => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Asks the helper for a readable reference to the protocol it speaks
+    pub fn describe(&mut self) -> Result<String, IpcError> {
+        match self.request(&Request::Describe)? {
+            Response::Description { reference } => Ok(reference),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn request(&mut self, request: &Request) -> Result<Response, IpcError> {
+        let request = Targeted {
+            target: self.target.clone(),
+            request: request.clone(),
+        };
+        reply::request(&mut self.client, &request)
+    }
+}